@@ -0,0 +1,113 @@
+/* C API for rusttext: load fastText or rusttext models, predict labels
+   and compute word and sentence vectors. */
+
+#ifndef RUSTTEXT_H
+#define RUSTTEXT_H
+
+/* Generated by cbindgen from src/lib.rs; do not edit. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// A loaded model, opaque to C.
+typedef struct RtModel RtModel;
+
+// A label predicted for a text, with its probability.
+typedef struct RtPrediction {
+  // NUL-terminated, owned by the enclosing `RtPredictions`.
+  char *label;
+  float prob;
+} RtPrediction;
+
+// Predicted labels, best first.
+typedef struct RtPredictions {
+  struct RtPrediction *items;
+  size_t len;
+} RtPredictions;
+
+// A word or sentence vector.
+typedef struct RtVector {
+  float *data;
+  size_t len;
+} RtVector;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// The message of the last failure on this thread, or NULL if there was
+// none. The string stays valid until the next failure on the thread.
+const char *rt_last_error(void);
+
+// Load the model at `path`: a rusttext model (`Model::save`), whose
+// matrices are memory-mapped so the file must not change while loaded,
+// or a fastText `.bin` or `.ftz` file. NULL on failure.
+//
+// # Safety
+//
+// `path` is NULL or a NUL-terminated string.
+struct RtModel *rt_load_model(const char *path);
+
+// # Safety
+//
+// `model` is NULL or was returned by `rt_load_model` and not freed yet.
+void rt_free_model(struct RtModel *model);
+
+// Size of the word and sentence vectors of `model`, 0 for NULL.
+//
+// # Safety
+//
+// `model` is NULL or a live model from `rt_load_model`.
+size_t rt_model_dim(const struct RtModel *model);
+
+// The `k` most probable labels of `text` with probability at least
+// `threshold`, best first; free with `rt_free_predictions`. NULL on
+// failure.
+//
+// # Safety
+//
+// `model` is NULL or a live model from `rt_load_model`, and `text` is
+// NULL or a NUL-terminated string.
+struct RtPredictions *rt_predict(const struct RtModel *model,
+                                 const char *text,
+                                 size_t k,
+                                 float threshold);
+
+// # Safety
+//
+// `predictions` is NULL or was returned by `rt_predict` and not freed
+// yet.
+void rt_free_predictions(struct RtPredictions *predictions);
+
+// Vector of `word`, built from its character n-grams if it is not in the
+// vocabulary; free with `rt_free_vector`. NULL on failure.
+//
+// # Safety
+//
+// `model` is NULL or a live model from `rt_load_model`, and `word` is
+// NULL or a NUL-terminated string.
+struct RtVector *rt_word_vector(const struct RtModel *model, const char *word);
+
+// Vector of `text`, as `Model::sentence_vector`; free with
+// `rt_free_vector`. NULL on failure.
+//
+// # Safety
+//
+// `model` is NULL or a live model from `rt_load_model`, and `text` is
+// NULL or a NUL-terminated string.
+struct RtVector *rt_sentence_vector(const struct RtModel *model, const char *text);
+
+// # Safety
+//
+// `vector` is NULL or was returned by `rt_word_vector` or
+// `rt_sentence_vector` and not freed yet.
+void rt_free_vector(struct RtVector *vector);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* RUSTTEXT_H */
@@ -0,0 +1,309 @@
+//! C API for inference with rusttext models, for services in Go, Java or
+//! C++ that embed the library directly. `include/rusttext.h` is generated
+//! from this file by cbindgen when the crate builds.
+//!
+//! Ownership rules:
+//!
+//! - Every pointer an `rt_*` function returns belongs to the caller, who
+//!   releases it exactly once with the matching `rt_free_*` function. Every
+//!   `rt_free_*` accepts NULL.
+//! - Strings passed in are borrowed for the duration of the call only and
+//!   must be NUL-terminated UTF-8.
+//! - On failure a function returns NULL, and `rt_last_error` describes the
+//!   failure until the next one on the same thread.
+//! - A model is never changed after loading, so one `RtModel` may be
+//!   queried from many threads at once.
+
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::mem;
+use std::os::raw::c_char;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+use rusttext_core::model::Model;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_error(message: &str) {
+    let message = CString::new(message.replace('\0', " ")).unwrap();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+/// Run `f`, turning an error or a panic into NULL, with the message kept
+/// for `rt_last_error`; a panic must not unwind into C.
+fn guard<T>(f: impl FnOnce() -> Result<*mut T, String>) -> *mut T {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(result)) => result,
+        Ok(Err(message)) => {
+            set_error(&message);
+            ptr::null_mut()
+        }
+        Err(_) => {
+            set_error("rusttext panicked");
+            ptr::null_mut()
+        }
+    }
+}
+
+unsafe fn to_str<'a>(string: *const c_char, what: &str) -> Result<&'a str, String> {
+    if string.is_null() {
+        return Err(format!("{} is NULL", what));
+    }
+    CStr::from_ptr(string)
+        .to_str()
+        .map_err(|_| format!("{} is not valid UTF-8", what))
+}
+
+unsafe fn to_model<'a>(model: *const RtModel) -> Result<&'a Model, String> {
+    model
+        .as_ref()
+        .map(|model| &model.inner)
+        .ok_or_else(|| String::from("model is NULL"))
+}
+
+/// A loaded model, opaque to C.
+pub struct RtModel {
+    inner: Model,
+}
+
+/// A label predicted for a text, with its probability.
+#[repr(C)]
+pub struct RtPrediction {
+    /// NUL-terminated, owned by the enclosing `RtPredictions`.
+    pub label: *mut c_char,
+    pub prob: f32,
+}
+
+/// Predicted labels, best first.
+#[repr(C)]
+pub struct RtPredictions {
+    pub items: *mut RtPrediction,
+    pub len: usize,
+}
+
+/// A word or sentence vector.
+#[repr(C)]
+pub struct RtVector {
+    pub data: *mut f32,
+    pub len: usize,
+}
+
+/// The heap pointer and length of `values`, given up to C until
+/// `reclaim` takes them back.
+fn release<T>(values: Vec<T>) -> (*mut T, usize) {
+    let mut values = values.into_boxed_slice();
+    let parts = (values.as_mut_ptr(), values.len());
+    mem::forget(values);
+    parts
+}
+
+unsafe fn reclaim<T>(data: *mut T, len: usize) -> Box<[T]> {
+    Box::from_raw(ptr::slice_from_raw_parts_mut(data, len))
+}
+
+fn to_vector(values: Vec<f32>) -> *mut RtVector {
+    let (data, len) = release(values);
+    Box::into_raw(Box::new(RtVector { data, len }))
+}
+
+/// The message of the last failure on this thread, or NULL if there was
+/// none. The string stays valid until the next failure on the thread.
+#[no_mangle]
+pub extern "C" fn rt_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| {
+        last.borrow()
+            .as_ref()
+            .map_or(ptr::null(), |message| message.as_ptr())
+    })
+}
+
+/// Load the model at `path`: a rusttext model (`Model::save`), whose
+/// matrices are memory-mapped so the file must not change while loaded,
+/// or a fastText `.bin` or `.ftz` file. NULL on failure.
+///
+/// # Safety
+///
+/// `path` is NULL or a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn rt_load_model(path: *const c_char) -> *mut RtModel {
+    guard(|| {
+        let path = to_str(path, "path")?;
+        let inner = Model::load_auto_mmap(path).map_err(|err| format!("{}: {}", path, err))?;
+        Ok(Box::into_raw(Box::new(RtModel { inner })))
+    })
+}
+
+/// # Safety
+///
+/// `model` is NULL or was returned by `rt_load_model` and not freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn rt_free_model(model: *mut RtModel) {
+    if !model.is_null() {
+        drop(Box::from_raw(model));
+    }
+}
+
+/// Size of the word and sentence vectors of `model`, 0 for NULL.
+///
+/// # Safety
+///
+/// `model` is NULL or a live model from `rt_load_model`.
+#[no_mangle]
+pub unsafe extern "C" fn rt_model_dim(model: *const RtModel) -> usize {
+    to_model(model).map_or(0, |model| model.args().dim)
+}
+
+/// The `k` most probable labels of `text` with probability at least
+/// `threshold`, best first; free with `rt_free_predictions`. NULL on
+/// failure.
+///
+/// # Safety
+///
+/// `model` is NULL or a live model from `rt_load_model`, and `text` is
+/// NULL or a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn rt_predict(
+    model: *const RtModel,
+    text: *const c_char,
+    k: usize,
+    threshold: f32,
+) -> *mut RtPredictions {
+    guard(|| {
+        let model = to_model(model)?;
+        let text = to_str(text, "text")?;
+        let items = model
+            .predict(text, k, threshold)
+            .into_iter()
+            .map(|(label, prob)| RtPrediction {
+                // labels are whitespace-separated tokens, so hold no NUL
+                label: CString::new(label).unwrap().into_raw(),
+                prob,
+            })
+            .collect();
+        let (items, len) = release(items);
+        Ok(Box::into_raw(Box::new(RtPredictions { items, len })))
+    })
+}
+
+/// # Safety
+///
+/// `predictions` is NULL or was returned by `rt_predict` and not freed
+/// yet.
+#[no_mangle]
+pub unsafe extern "C" fn rt_free_predictions(predictions: *mut RtPredictions) {
+    if predictions.is_null() {
+        return;
+    }
+    let predictions = Box::from_raw(predictions);
+    for item in reclaim(predictions.items, predictions.len).iter() {
+        drop(CString::from_raw(item.label));
+    }
+}
+
+/// Vector of `word`, built from its character n-grams if it is not in the
+/// vocabulary; free with `rt_free_vector`. NULL on failure.
+///
+/// # Safety
+///
+/// `model` is NULL or a live model from `rt_load_model`, and `word` is
+/// NULL or a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn rt_word_vector(
+    model: *const RtModel,
+    word: *const c_char,
+) -> *mut RtVector {
+    guard(|| {
+        let model = to_model(model)?;
+        Ok(to_vector(model.word_vector(to_str(word, "word")?)))
+    })
+}
+
+/// Vector of `text`, as `Model::sentence_vector`; free with
+/// `rt_free_vector`. NULL on failure.
+///
+/// # Safety
+///
+/// `model` is NULL or a live model from `rt_load_model`, and `text` is
+/// NULL or a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn rt_sentence_vector(
+    model: *const RtModel,
+    text: *const c_char,
+) -> *mut RtVector {
+    guard(|| {
+        let model = to_model(model)?;
+        Ok(to_vector(model.sentence_vector(to_str(text, "text")?)))
+    })
+}
+
+/// # Safety
+///
+/// `vector` is NULL or was returned by `rt_word_vector` or
+/// `rt_sentence_vector` and not freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn rt_free_vector(vector: *mut RtVector) {
+    if !vector.is_null() {
+        let vector = Box::from_raw(vector);
+        drop(reclaim(vector.data, vector.len));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusttext_core::args::Args;
+    use std::fs;
+    use std::slice;
+
+    #[test]
+    fn test_api() {
+        let dir = std::env::temp_dir();
+        let input = dir.join("rusttext_capi.txt");
+        let lines = "__label__fruit apple banana\n__label__letter xray zulu\n".repeat(100);
+        fs::write(&input, lines).unwrap();
+        let args = Args {
+            dim: 10,
+            bucket: 1000,
+            threads: 1,
+            ..Args::supervised()
+        };
+        let path = dir.join("rusttext_capi.model");
+        Model::train_supervised(&input, args)
+            .unwrap()
+            .save(&path)
+            .unwrap();
+
+        unsafe {
+            let path = CString::new(path.to_str().unwrap()).unwrap();
+            let model = rt_load_model(path.as_ptr());
+            assert!(!model.is_null());
+            assert_eq!(rt_model_dim(model), 10);
+
+            let text = CString::new("apple banana").unwrap();
+            let predictions = rt_predict(model, text.as_ptr(), 2, 0.0);
+            let items = slice::from_raw_parts((*predictions).items, (*predictions).len);
+            assert_eq!(items.len(), 2);
+            assert_eq!(
+                CStr::from_ptr(items[0].label).to_str(),
+                Ok("__label__fruit")
+            );
+            assert!(items[0].prob >= items[1].prob);
+            rt_free_predictions(predictions);
+
+            let vector = rt_word_vector(model, text.as_ptr());
+            assert_eq!((*vector).len, 10);
+            rt_free_vector(vector);
+
+            assert!(rt_sentence_vector(model, ptr::null()).is_null());
+            assert_eq!(CStr::from_ptr(rt_last_error()).to_str(), Ok("text is NULL"));
+            rt_free_model(model);
+
+            let missing = CString::new("/nonexistent/model.bin").unwrap();
+            assert!(rt_load_model(missing.as_ptr()).is_null());
+            rt_free_model(ptr::null_mut());
+        }
+    }
+}
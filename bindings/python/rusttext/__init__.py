@@ -1,3 +1,18 @@
-from .rusttext import foo
+# the extension returns NumPy arrays; fail here rather than on first use
+import numpy  # noqa: F401
 
-__all__ = ["foo"]
+from .rusttext import (
+    Model,
+    Vocabulary,
+    load_model,
+    train_supervised,
+    train_unsupervised,
+)
+
+__all__ = [
+    "Model",
+    "Vocabulary",
+    "load_model",
+    "train_supervised",
+    "train_unsupervised",
+]
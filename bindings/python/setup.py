@@ -5,6 +5,7 @@ setup(
     name="rusttext",
     version="0.0.1",
     packages=["rusttext"],
+    install_requires=["numpy"],
     rust_extensions=[RustExtension("rusttext", "Cargo.toml", debug=False)],
     include_package_data=True,
     zip_safe=False,
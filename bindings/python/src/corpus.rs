@@ -0,0 +1,43 @@
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use pyo3::prelude::*;
+use tempfile::NamedTempFile;
+
+/// A training corpus given from Python: a file path, or any iterable of
+/// strings. Training makes several passes over the corpus from several
+/// threads at once, reading it by offset, so an iterable is pulled once,
+/// item by item, into a new temporary file that is removed when the corpus
+/// is dropped. Each item is one line (one example); newlines inside an item
+/// are replaced with spaces.
+pub(crate) enum Corpus {
+    File(PathBuf),
+    Spooled(NamedTempFile),
+}
+
+impl Corpus {
+    pub(crate) fn from_py(input: &PyAny) -> PyResult<Corpus> {
+        if let Ok(path) = input.extract::<&str>() {
+            return Ok(Corpus::File(PathBuf::from(path)));
+        }
+        let spool = tempfile::Builder::new()
+            .prefix("rusttext-py-")
+            .suffix(".txt")
+            .tempfile()?;
+        let mut writer = BufWriter::new(spool.as_file());
+        for item in input.iter()? {
+            let line: &str = item?.extract()?;
+            writeln!(writer, "{}", line.replace('\n', " "))?;
+        }
+        writer.flush()?;
+        drop(writer);
+        Ok(Corpus::Spooled(spool))
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        match self {
+            Corpus::File(path) => path,
+            Corpus::Spooled(spool) => spool.path(),
+        }
+    }
+}
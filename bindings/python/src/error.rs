@@ -0,0 +1,12 @@
+use pyo3::exceptions::PyValueError;
+use pyo3::PyErr;
+use rusttext_core::RustTextError;
+
+/// Raise library errors as Python exceptions: I/O failures as `OSError`
+/// (`FileNotFoundError` and friends), everything else as `ValueError`.
+pub fn to_py_err(err: RustTextError) -> PyErr {
+    match err {
+        RustTextError::Io(err) => PyErr::from(err),
+        err => PyValueError::new_err(err.to_string()),
+    }
+}
@@ -1,14 +1,16 @@
 use pyo3::prelude::*;
-use pyo3::wrap_pyfunction;
 
-#[pyfunction]
-fn foo() -> PyResult<String> {
-    return Ok(String::from("foo"));
-}
+mod corpus;
+mod error;
+mod model;
+mod train;
+mod vocabulary;
 
 #[pymodule]
 fn rusttext(_py: Python<'_>, m: &PyModule) -> PyResult<()> {
-    m.add_function(wrap_pyfunction!(foo, m)?)?;
+    m.add_class::<vocabulary::Vocabulary>()?;
+    model::register(m)?;
+    train::register(m)?;
 
     Ok(())
 }
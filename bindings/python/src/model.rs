@@ -0,0 +1,327 @@
+use std::path::Path;
+
+use numpy::npyffi::{self, NPY_ARRAY_CARRAY_RO, PY_ARRAY_API};
+use numpy::{Element, PyArray1, PyArray2};
+use pyo3::class::{PyMappingProtocol, PyObjectProtocol, PySequenceProtocol};
+use pyo3::exceptions::{PyIndexError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyTuple;
+use pyo3::{wrap_pyfunction, AsPyPointer, PyNativeType};
+use rusttext_core::matrix::Matrix;
+use rusttext_core::model;
+use rusttext_core::word::EntryType;
+
+use crate::error::to_py_err;
+use crate::train::loss_name;
+
+pub(crate) fn register(m: &PyModule) -> PyResult<()> {
+    m.add_class::<Model>()?;
+    m.add_function(wrap_pyfunction!(load_model, m)?)?;
+    Ok(())
+}
+
+/// `(word, score)` pairs as the `(score, word)` the `fasttext` package
+/// returns.
+fn swap(scored: Vec<(String, f32)>) -> Vec<(f32, String)> {
+    scored
+        .into_iter()
+        .map(|(word, score)| (score, word))
+        .collect()
+}
+
+/// A read-only 2-D NumPy array over the data of `matrix`, a matrix of the
+/// model in `owner`, without copying. The array keeps `owner` alive, and
+/// a Python `Model` never changes its matrices, so the data outlives it.
+fn matrix_view<'py>(owner: &'py PyCell<Model>, matrix: &Matrix) -> &'py PyArray2<f32> {
+    let py = owner.py();
+    let mut dims = [
+        matrix.rows() as npyffi::npy_intp,
+        matrix.cols() as npyffi::npy_intp,
+    ];
+    unsafe {
+        let array = PY_ARRAY_API.PyArray_New(
+            PY_ARRAY_API.get_type_object(npyffi::NpyTypes::PyArray_Type),
+            2,
+            dims.as_mut_ptr(),
+            f32::npy_type() as i32,
+            std::ptr::null_mut(),
+            matrix.data().as_ptr() as *mut _,
+            0,
+            NPY_ARRAY_CARRAY_RO,
+            std::ptr::null_mut(),
+        );
+        // steals a reference to `owner`
+        pyo3::ffi::Py_INCREF(owner.as_ptr());
+        PY_ARRAY_API.PyArray_SetBaseObject(array as *mut npyffi::PyArrayObject, owner.as_ptr());
+        py.from_owned_ptr(array)
+    }
+}
+
+/// A trained model: word vectors or a text classifier.
+#[pyclass(module = "rusttext")]
+pub struct Model {
+    pub(crate) inner: model::Model,
+}
+
+impl Model {
+    /// The words or labels of the vocabulary, most frequent first, with
+    /// their counts as a NumPy array if `include_freq` is set.
+    fn entries(&self, py: Python, entry_type: EntryType, include_freq: bool) -> PyObject {
+        let (words, counts): (Vec<String>, Vec<i64>) = self
+            .inner
+            .vocabulary()
+            .words()
+            .iter()
+            .filter(|entry| entry.entry_type == entry_type)
+            .map(|entry| (entry.word.clone(), i64::from(entry.count)))
+            .unzip();
+        if include_freq {
+            (words, PyArray1::from_vec(py, counts)).into_py(py)
+        } else {
+            words.into_py(py)
+        }
+    }
+
+    fn count(&self, entry_type: EntryType) -> usize {
+        self.inner
+            .vocabulary()
+            .words()
+            .iter()
+            .filter(|entry| entry.entry_type == entry_type)
+            .count()
+    }
+}
+
+#[pymethods]
+impl Model {
+    /// Size of the word and sentence vectors.
+    fn get_dimension(&self) -> usize {
+        self.inner.args().dim
+    }
+
+    fn is_quantized(&self) -> bool {
+        self.inner.is_quantized()
+    }
+
+    /// Vector of `word` as a NumPy array, built from its character n-grams
+    /// if it is not in the vocabulary.
+    fn get_word_vector<'py>(&self, py: Python<'py>, word: &str) -> &'py PyArray1<f32> {
+        PyArray1::from_vec(py, self.inner.word_vector(word))
+    }
+
+    /// Vector of `text` as a NumPy array: for a classifier the averaged
+    /// input the classifier sees, otherwise the mean of the normalized
+    /// vectors of its words.
+    fn get_sentence_vector<'py>(&self, py: Python<'py>, text: &str) -> &'py PyArray1<f32> {
+        PyArray1::from_vec(py, self.inner.sentence_vector(text))
+    }
+
+    /// `get_sentence_vector` of every text, one row each of a 2-D NumPy
+    /// array, computed in parallel without holding the GIL.
+    fn get_sentence_vectors<'py>(
+        &self,
+        py: Python<'py>,
+        texts: Vec<String>,
+    ) -> PyResult<&'py PyArray2<f32>> {
+        let inner = &self.inner;
+        let matrix = py.allow_threads(|| {
+            let texts: Vec<&str> = texts.iter().map(String::as_str).collect();
+            inner.sentence_vectors(&texts)
+        });
+        PyArray1::from_slice(py, matrix.data()).reshape([matrix.rows(), matrix.cols()])
+    }
+
+    /// The input matrix, one row per word then per subword and word n-gram
+    /// bucket, as a read-only NumPy array sharing the model's memory.
+    /// Quantized models have none to give.
+    fn get_input_matrix(slf: &PyCell<Self>) -> PyResult<&PyArray2<f32>> {
+        let model = slf.borrow();
+        if model.inner.is_quantized() {
+            return Err(PyValueError::new_err("can't get a quantized matrix"));
+        }
+        Ok(matrix_view(slf, model.inner.input()))
+    }
+
+    /// The output matrix, one row per label for a classifier, as a
+    /// read-only NumPy array sharing the model's memory.
+    fn get_output_matrix(slf: &PyCell<Self>) -> PyResult<&PyArray2<f32>> {
+        let model = slf.borrow();
+        if model.inner.quant_output().is_some() {
+            return Err(PyValueError::new_err("can't get a quantized matrix"));
+        }
+        Ok(matrix_view(slf, model.inner.output()))
+    }
+
+    /// The words of the vocabulary, most frequent first; with
+    /// `include_freq`, a `(words, counts)` pair with the counts as a NumPy
+    /// array.
+    #[args(include_freq = false)]
+    fn get_words(&self, py: Python, include_freq: bool) -> PyObject {
+        self.entries(py, EntryType::Word, include_freq)
+    }
+
+    /// The labels of a classifier, as `get_words` gives the words.
+    #[args(include_freq = false)]
+    fn get_labels(&self, py: Python, include_freq: bool) -> PyObject {
+        self.entries(py, EntryType::Label, include_freq)
+    }
+
+    /// The words of the vocabulary, as `get_words()`.
+    #[getter]
+    fn words(&self, py: Python) -> PyObject {
+        self.entries(py, EntryType::Word, false)
+    }
+
+    /// The labels of a classifier, as `get_labels()`.
+    #[getter]
+    fn labels(&self, py: Python) -> PyObject {
+        self.entries(py, EntryType::Label, false)
+    }
+
+    /// Row `ind` of the input matrix (a word, or a subword or word n-gram
+    /// bucket) as a NumPy array, decoded if the model is quantized.
+    fn get_input_vector<'py>(&self, py: Python<'py>, ind: usize) -> PyResult<&'py PyArray1<f32>> {
+        let dim = self.inner.args().dim;
+        let mut vector = vec![0.0; dim];
+        match self.inner.quant_input() {
+            Some(matrix) if ind < matrix.rows() => matrix.add_row_to(&mut vector, ind, 1.0),
+            None if ind < self.inner.input().rows() => {
+                vector.copy_from_slice(self.inner.input().row(ind))
+            }
+            _ => return Err(PyIndexError::new_err(format!("no input row {}", ind))),
+        }
+        Ok(PyArray1::from_vec(py, vector))
+    }
+
+    /// The `k` most probable labels of `text` with probability at least
+    /// `threshold`, as `(labels, probs)`: a tuple of labels and a NumPy
+    /// array of probabilities, best first. Given a list of strings, returns
+    /// a list of label lists and a list of arrays, scored in parallel
+    /// without holding the GIL.
+    #[args(k = 1, threshold = "0.0")]
+    fn predict(&self, py: Python, text: &PyAny, k: usize, threshold: f32) -> PyResult<PyObject> {
+        if let Ok(text) = text.extract::<&str>() {
+            let (labels, probs): (Vec<String>, Vec<f32>) =
+                self.inner.predict(text, k, threshold).into_iter().unzip();
+            let labels = PyTuple::new(py, labels);
+            return Ok((labels, PyArray1::from_vec(py, probs)).into_py(py));
+        }
+        let texts: Vec<String> = text.extract()?;
+        let inner = &self.inner;
+        let predictions = py.allow_threads(|| {
+            let texts: Vec<&str> = texts.iter().map(String::as_str).collect();
+            inner.predict_batch(&texts, k, threshold)
+        });
+        let mut labels = Vec::with_capacity(predictions.len());
+        let mut probs = Vec::with_capacity(predictions.len());
+        for prediction in predictions {
+            let (names, scores): (Vec<String>, Vec<f32>) = prediction.into_iter().unzip();
+            labels.push(names);
+            probs.push(PyArray1::from_vec(py, scores));
+        }
+        Ok((labels, probs).into_py(py))
+    }
+
+    /// The `k` words closest to `word` by cosine similarity, as
+    /// `(score, word)` pairs, best first.
+    #[args(k = 10)]
+    fn get_nearest_neighbors(&self, py: Python, word: &str, k: usize) -> Vec<(f32, String)> {
+        let inner = &self.inner;
+        let neighbors = py.allow_threads(|| inner.nearest_neighbors(word, k));
+        swap(neighbors)
+    }
+
+    /// The `k` words closest to `wordA - wordB + wordC`, as fastText's
+    /// `get_analogies` computes them, as `(score, word)` pairs.
+    #[args(k = 10)]
+    #[allow(non_snake_case)]
+    fn get_analogies(
+        &self,
+        py: Python,
+        wordA: &str,
+        wordB: &str,
+        wordC: &str,
+        k: usize,
+    ) -> Vec<(f32, String)> {
+        let inner = &self.inner;
+        // `analogy(a, b, c)` is `b - a + c`
+        let answers = py.allow_threads(|| inner.analogy(wordB, wordA, wordC, k));
+        swap(answers)
+    }
+
+    /// Save the model to `path` in `format`: `"fasttext"` for fastText's
+    /// `.bin`, readable by the `fasttext` package, or `"native"` for
+    /// rusttext's own format, which keeps everything the model holds. By
+    /// default `.bin` and `.ftz` paths get fastText's format, others the
+    /// native one.
+    #[args(format = "None")]
+    fn save_model(&self, py: Python, path: &str, format: Option<&str>) -> PyResult<()> {
+        let format = format.unwrap_or_else(|| {
+            match Path::new(path).extension().and_then(|ext| ext.to_str()) {
+                Some("bin") | Some("ftz") => "fasttext",
+                _ => "native",
+            }
+        });
+        let inner = &self.inner;
+        match format {
+            "fasttext" => py.allow_threads(|| inner.save_fasttext(path)),
+            "native" => py.allow_threads(|| inner.save(path)),
+            _ => {
+                return Err(PyValueError::new_err(format!(
+                    "format must be fasttext or native, got {}",
+                    format
+                )))
+            }
+        }
+        .map_err(to_py_err)
+    }
+}
+
+#[pyproto]
+impl PyObjectProtocol for Model {
+    fn __repr__(&self) -> String {
+        let args = self.inner.args();
+        let mut repr = format!(
+            "<rusttext.Model dim={} words={}",
+            args.dim,
+            self.count(EntryType::Word)
+        );
+        if self.inner.is_supervised() {
+            repr += &format!(" labels={}", self.count(EntryType::Label));
+        }
+        repr += &format!(" loss={}", loss_name(args.loss));
+        if self.inner.is_quantized() {
+            repr += " quantized";
+        }
+        repr + ">"
+    }
+}
+
+#[pyproto]
+impl PySequenceProtocol for Model {
+    /// Whether `word` is a word of the vocabulary; labels are not.
+    fn __contains__(&self, word: &str) -> bool {
+        let vocabulary = self.inner.vocabulary();
+        vocabulary
+            .get_id(word)
+            .is_some_and(|id| vocabulary.words()[id as usize].entry_type == EntryType::Word)
+    }
+}
+
+#[pyproto]
+impl PyMappingProtocol for Model {
+    /// `model[word]` is `model.get_word_vector(word)`.
+    fn __getitem__(&self, word: &str) -> Py<PyArray1<f32>> {
+        Python::with_gil(|py| PyArray1::from_vec(py, self.inner.word_vector(word)).to_owned())
+    }
+}
+
+/// Load a model saved by `Model.save_model` in either format, or by
+/// fastText (`.bin` or `.ftz`).
+#[pyfunction]
+pub fn load_model(py: Python, path: &str) -> PyResult<Model> {
+    let inner = py
+        .allow_threads(|| model::Model::load_auto(path))
+        .map_err(to_py_err)?;
+    Ok(Model { inner })
+}
@@ -0,0 +1,200 @@
+use pyo3::exceptions::{PyTypeError, PyValueError};
+use pyo3::prelude::*;
+use std::sync::Mutex;
+
+use pyo3::types::PyDict;
+use pyo3::wrap_pyfunction;
+use rusttext_core::args::{Args, LossKind, WordModel};
+use rusttext_core::model;
+use rusttext_core::train::Progress;
+
+use crate::corpus::Corpus;
+use crate::error::to_py_err;
+use crate::model::Model;
+
+fn loss_kind(name: &str) -> PyResult<LossKind> {
+    match name {
+        "ns" => Ok(LossKind::NegativeSampling),
+        "hs" => Ok(LossKind::HierarchicalSoftmax),
+        "softmax" => Ok(LossKind::Softmax),
+        "ova" => Ok(LossKind::OneVsAll),
+        _ => Err(PyValueError::new_err(format!(
+            "loss must be one of ns, hs, softmax or ova, got {}",
+            name
+        ))),
+    }
+}
+
+/// The `loss` keyword argument naming `loss`.
+pub(crate) fn loss_name(loss: LossKind) -> &'static str {
+    match loss {
+        LossKind::NegativeSampling => "ns",
+        LossKind::HierarchicalSoftmax => "hs",
+        LossKind::Softmax => "softmax",
+        LossKind::OneVsAll => "ova",
+    }
+}
+
+fn word_model(name: &str) -> PyResult<WordModel> {
+    match name {
+        "skipgram" => Ok(WordModel::Skipgram),
+        "cbow" => Ok(WordModel::Cbow),
+        _ => Err(PyValueError::new_err(format!(
+            "model must be skipgram or cbow, got {}",
+            name
+        ))),
+    }
+}
+
+/// Override `args` with the keyword arguments of the `fasttext` package's
+/// training functions, under the same names (`wordNgrams`, `minCount`,
+/// ...), `model` only for unsupervised training. `verbose` is accepted for
+/// compatibility and ignored; anything else unknown is a `TypeError`, as
+/// for a Python function.
+pub(crate) fn apply_kwargs(
+    args: &mut Args,
+    supervised: bool,
+    kwargs: Option<&PyDict>,
+) -> PyResult<()> {
+    let kwargs = match kwargs {
+        Some(kwargs) => kwargs,
+        None => return Ok(()),
+    };
+    for (key, value) in kwargs.iter() {
+        let key: &str = key.extract()?;
+        match key {
+            "lr" => args.lr = value.extract()?,
+            "dim" => args.dim = value.extract()?,
+            "ws" => args.ws = value.extract()?,
+            "epoch" => args.epoch = value.extract()?,
+            "minCount" => args.min_count = value.extract()?,
+            "minCountLabel" => args.min_count_label = value.extract()?,
+            "minn" => args.min_n = value.extract()?,
+            "maxn" => args.max_n = value.extract()?,
+            "neg" => args.neg = value.extract()?,
+            "wordNgrams" => args.word_ngrams = value.extract()?,
+            "loss" => args.loss = loss_kind(value.extract()?)?,
+            "bucket" => args.bucket = value.extract()?,
+            "thread" => args.threads = value.extract()?,
+            "lrUpdateRate" => args.lr_update_rate = value.extract()?,
+            "t" => args.t = value.extract()?,
+            "label" => args.label_prefix = value.extract()?,
+            "seed" => args.seed = value.extract()?,
+            "pretrainedVectors" => {
+                let path: String = value.extract()?;
+                args.pretrained_vectors =
+                    Some(path).filter(|path| !path.is_empty()).map(Into::into);
+            }
+            "model" if !supervised => args.word_model = word_model(value.extract()?)?,
+            "verbose" => {
+                let _: u32 = value.extract()?;
+            }
+            _ => {
+                return Err(PyTypeError::new_err(format!(
+                    "unexpected keyword argument '{}'",
+                    key
+                )))
+            }
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn register(m: &PyModule) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(train_supervised, m)?)?;
+    m.add_function(wrap_pyfunction!(train_unsupervised, m)?)?;
+    Ok(())
+}
+
+/// The progress report handed to a Python training callback.
+fn progress_dict<'py>(py: Python<'py>, progress: &Progress) -> PyResult<&'py PyDict> {
+    let dict = PyDict::new(py);
+    dict.set_item("epoch", progress.epoch)?;
+    dict.set_item("progress", progress.percent)?;
+    dict.set_item("lr", progress.lr)?;
+    dict.set_item("loss", progress.loss)?;
+    dict.set_item("words_per_sec", progress.words_per_sec)?;
+    dict.set_item("tokens", progress.tokens)?;
+    dict.set_item("total_tokens", progress.total_tokens)?;
+    dict.set_item("eta", progress.eta.map(|eta| eta.as_secs_f64()))?;
+    Ok(dict)
+}
+
+/// Train on `input` with the GIL released, so other Python threads run
+/// meanwhile, calling `callback` (if any) with a progress dict under the
+/// GIL. Training cannot be interrupted: the first exception raised by the
+/// callback stops further calls and is re-raised once training ends.
+fn train(
+    py: Python,
+    input: &PyAny,
+    args: Args,
+    supervised: bool,
+    callback: Option<PyObject>,
+) -> PyResult<Model> {
+    let corpus = Corpus::from_py(input)?;
+    let failure: Mutex<Option<PyErr>> = Mutex::new(None);
+    let report = |progress: &Progress| {
+        let callback = match &callback {
+            Some(callback) => callback,
+            None => return,
+        };
+        let mut failure = failure.lock().unwrap();
+        if failure.is_some() {
+            return;
+        }
+        Python::with_gil(|py| {
+            let result =
+                progress_dict(py, progress).and_then(|dict| callback.call1(py, (dict,)).map(drop));
+            if let Err(err) = result {
+                *failure = Some(err);
+            }
+        });
+    };
+    let path = corpus.path();
+    let trained = py.allow_threads(|| {
+        if supervised {
+            model::Model::train_supervised_with(path, args, &report)
+        } else {
+            model::Model::train_unsupervised_with(path, args, &report)
+        }
+    });
+    if let Some(err) = failure.into_inner().unwrap() {
+        return Err(err);
+    }
+    let inner = trained.map_err(to_py_err)?;
+    Ok(Model { inner })
+}
+
+/// Train a text classifier on `input`, the path of a labelled file or an
+/// iterable of labelled lines, taking the `fasttext.train_supervised`
+/// keyword arguments with the same defaults. `callback`, if given, is
+/// called with a dict of `epoch`, `progress` (percent), `lr`, `loss`,
+/// `words_per_sec`, `tokens`, `total_tokens` and `eta` (seconds left, or
+/// `None`) as training goes.
+#[pyfunction(callback = "None", kwargs = "**")]
+pub fn train_supervised(
+    py: Python,
+    input: &PyAny,
+    callback: Option<PyObject>,
+    kwargs: Option<&PyDict>,
+) -> PyResult<Model> {
+    let mut args = Args::supervised();
+    apply_kwargs(&mut args, true, kwargs)?;
+    train(py, input, args, true, callback)
+}
+
+/// Learn word vectors from `input`, the path of a text file or an iterable
+/// of lines, taking the `fasttext.train_unsupervised` keyword arguments
+/// with the same defaults, `model="skipgram"` or `"cbow"` among them, and
+/// the `callback` of `train_supervised`.
+#[pyfunction(callback = "None", kwargs = "**")]
+pub fn train_unsupervised(
+    py: Python,
+    input: &PyAny,
+    callback: Option<PyObject>,
+    kwargs: Option<&PyDict>,
+) -> PyResult<Model> {
+    let mut args = Args::default();
+    apply_kwargs(&mut args, false, kwargs)?;
+    train(py, input, args, false, callback)
+}
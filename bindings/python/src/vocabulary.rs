@@ -0,0 +1,108 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufReader;
+
+use pyo3::class::PySequenceProtocol;
+use pyo3::prelude::*;
+use rusttext_core::args::Args;
+use rusttext_core::loader::{self, Filters};
+use rusttext_core::vocabulary;
+use rusttext_core::word::EntryType;
+
+use crate::error::to_py_err;
+
+/// Words and labels of a corpus with their counts, and the character
+/// n-gram settings used to hash subwords.
+#[pyclass(module = "rusttext")]
+pub struct Vocabulary {
+    pub(crate) inner: vocabulary::Vocabulary,
+}
+
+impl Vocabulary {
+    fn entries(&self, entry_type: EntryType) -> Vec<String> {
+        self.inner
+            .words()
+            .iter()
+            .filter(|entry| entry.entry_type == entry_type)
+            .map(|entry| entry.word.clone())
+            .collect()
+    }
+}
+
+#[pymethods]
+impl Vocabulary {
+    #[new]
+    #[args(min_n = 3, max_n = 6, bucket = 2000000, label_prefix = "\"__label__\"")]
+    fn new(min_n: usize, max_n: usize, bucket: u32, label_prefix: &str) -> PyResult<Self> {
+        let mut inner =
+            vocabulary::Vocabulary::new(Args::default().vocab_size, min_n, max_n, bucket)
+                .map_err(to_py_err)?;
+        inner.set_label_prefix(label_prefix);
+        Ok(Vocabulary { inner })
+    }
+
+    /// Count every whitespace-separated token of `line`.
+    fn add_line(&mut self, line: &str) -> PyResult<()> {
+        for token in line.split_whitespace() {
+            self.inner.add(token).map_err(to_py_err)?;
+        }
+        Ok(())
+    }
+
+    /// Count every token of every string of `lines`, any iterable (a list,
+    /// a generator, a pandas column), pulled one item at a time.
+    fn add_from(&mut self, lines: &PyAny) -> PyResult<()> {
+        for line in lines.iter()? {
+            self.add_line(line?.extract()?)?;
+        }
+        Ok(())
+    }
+
+    /// Count every token of the file at `path`, as `add_line` on each line,
+    /// with the GIL released.
+    fn build_from_file(&mut self, py: Python, path: &str) -> PyResult<()> {
+        let reader = BufReader::new(File::open(path)?);
+        let inner = &mut self.inner;
+        py.allow_threads(|| loader::read_from_reader(inner, reader, &Filters::new()))
+            .map_err(to_py_err)?;
+        Ok(())
+    }
+
+    /// Drop words seen fewer than `min_count` times and labels seen fewer
+    /// than `min_count_label` times, and sort the rest by count.
+    #[args(min_count_label = 1)]
+    fn threshold(&mut self, min_count: u32, min_count_label: u32) {
+        self.inner.threshold(min_count, min_count_label);
+    }
+
+    #[getter]
+    fn words(&self) -> Vec<String> {
+        self.entries(EntryType::Word)
+    }
+
+    #[getter]
+    fn labels(&self) -> Vec<String> {
+        self.entries(EntryType::Label)
+    }
+
+    /// Count of every word and label.
+    #[getter]
+    fn counts(&self) -> HashMap<String, u32> {
+        self.inner
+            .words()
+            .iter()
+            .map(|entry| (entry.word.clone(), entry.count))
+            .collect()
+    }
+}
+
+#[pyproto]
+impl PySequenceProtocol for Vocabulary {
+    fn __len__(&self) -> usize {
+        self.inner.size() as usize
+    }
+
+    fn __contains__(&self, word: &str) -> bool {
+        self.inner.get_id(word).is_some()
+    }
+}
@@ -0,0 +1,76 @@
+//! WebAssembly bindings for inference with rusttext models in the
+//! browser: load a model from the bytes of a file (`fetch` it, then
+//! `new Uint8Array(await response.arrayBuffer())`), then predict labels
+//! and compute word and sentence vectors. Build with `wasm-pack build`.
+
+use rusttext::{fasttext, model, native};
+use wasm_bindgen::prelude::*;
+
+fn to_js_err(err: rusttext::RustTextError) -> JsError {
+    JsError::new(&err.to_string())
+}
+
+/// A label predicted for a text, with its probability.
+#[wasm_bindgen(getter_with_clone)]
+pub struct Prediction {
+    pub label: String,
+    pub prob: f32,
+}
+
+/// A model loaded for inference: word vectors or a text classifier.
+#[wasm_bindgen]
+pub struct Model {
+    inner: model::Model,
+}
+
+#[wasm_bindgen]
+impl Model {
+    /// Read a model from the bytes of a rusttext model (`Model::save`) or
+    /// of a fastText `.bin` or `.ftz` file, telling them apart by their
+    /// first bytes. Quantized `.ftz` classifiers are the ones small enough
+    /// to ship to a browser.
+    #[wasm_bindgen(js_name = fromBytes)]
+    pub fn from_bytes(bytes: &[u8]) -> Result<Model, JsError> {
+        let inner = if bytes.starts_with(native::MAGIC) {
+            native::read_model(bytes)
+        } else {
+            fasttext::read_model(bytes)
+        }
+        .map_err(to_js_err)?;
+        Ok(Model { inner })
+    }
+
+    /// Size of the word and sentence vectors.
+    #[wasm_bindgen(getter)]
+    pub fn dim(&self) -> usize {
+        self.inner.args().dim
+    }
+
+    #[wasm_bindgen(getter, js_name = isQuantized)]
+    pub fn is_quantized(&self) -> bool {
+        self.inner.is_quantized()
+    }
+
+    /// The `k` most probable labels of `text` with probability at least
+    /// `threshold`, best first.
+    pub fn predict(&self, text: &str, k: usize, threshold: f32) -> Vec<Prediction> {
+        self.inner
+            .predict(text, k, threshold)
+            .into_iter()
+            .map(|(label, prob)| Prediction { label, prob })
+            .collect()
+    }
+
+    /// Vector of `word` as a `Float32Array`, built from its character
+    /// n-grams if it is not in the vocabulary.
+    #[wasm_bindgen(js_name = wordVector)]
+    pub fn word_vector(&self, word: &str) -> Vec<f32> {
+        self.inner.word_vector(word)
+    }
+
+    /// Vector of `text` as a `Float32Array`, as `Model::sentence_vector`.
+    #[wasm_bindgen(js_name = sentenceVector)]
+    pub fn sentence_vector(&self, text: &str) -> Vec<f32> {
+        self.inner.sentence_vector(text)
+    }
+}
@@ -0,0 +1,23 @@
+//! Loads a small fastText classifier (trained on lines of fruit and
+//! vehicle words, `dim` 4, with subwords) the way the browser does. Run
+//! with `wasm-pack test --node`.
+
+use rusttext_wasm::Model;
+use wasm_bindgen_test::wasm_bindgen_test;
+
+const CLASSIFIER: &[u8] = include_bytes!("fixtures/classifier.bin");
+
+#[wasm_bindgen_test]
+fn test_from_bytes() {
+    let model = Model::from_bytes(CLASSIFIER).unwrap();
+    assert_eq!(model.dim(), 4);
+    assert!(!model.is_quantized());
+
+    let predictions = model.predict("apple banana cherry", 1, 0.0);
+    assert_eq!(predictions[0].label, "__label__fruit");
+    let predictions = model.predict("truck wheel engine", 1, 0.0);
+    assert_eq!(predictions[0].label, "__label__vehicle");
+
+    assert_eq!(model.word_vector("bananas").len(), 4);
+    assert_eq!(model.sentence_vector("car grape").len(), 4);
+}
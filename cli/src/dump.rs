@@ -0,0 +1,122 @@
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+
+use clap::ValueEnum;
+use rusttext::args::{LossKind, WordModel};
+use rusttext::matrix::Matrix;
+use rusttext::model::Model;
+use rusttext::word::EntryType;
+use rusttext::RustTextError;
+
+use crate::load_model;
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum Part {
+    /// Training arguments, one `name value` per line
+    Args,
+    /// Vocabulary size, then `word count type` per entry
+    Dict,
+    /// Input matrix: `rows cols`, then one row of values per line
+    Input,
+    /// Output matrix: `rows cols`, then one row of values per line
+    Output,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct DumpArgs {
+    /// Model file (`.bin`, `.ftz` or a rusttext model)
+    model: PathBuf,
+    /// What to print
+    #[arg(value_enum)]
+    part: Part,
+}
+
+/// The arguments fastText's `dump` prints, under its names and values.
+fn dump_args<W: Write>(model: &Model, out: &mut W) -> io::Result<()> {
+    let args = model.args();
+    let loss = match args.loss {
+        LossKind::NegativeSampling => "ns",
+        LossKind::HierarchicalSoftmax => "hs",
+        LossKind::Softmax => "softmax",
+        LossKind::OneVsAll => "one-vs-all",
+    };
+    let kind = match args.word_model {
+        _ if model.is_supervised() => "sup",
+        WordModel::Skipgram => "sg",
+        WordModel::Cbow => "cbow",
+    };
+    writeln!(out, "dim {}", args.dim)?;
+    writeln!(out, "ws {}", args.ws)?;
+    writeln!(out, "epoch {}", args.epoch)?;
+    writeln!(out, "minCount {}", args.min_count)?;
+    writeln!(out, "neg {}", args.neg)?;
+    writeln!(out, "wordNgrams {}", args.word_ngrams)?;
+    writeln!(out, "loss {}", loss)?;
+    writeln!(out, "model {}", kind)?;
+    writeln!(out, "bucket {}", args.bucket)?;
+    writeln!(out, "minn {}", args.min_n)?;
+    writeln!(out, "maxn {}", args.max_n)?;
+    writeln!(out, "lrUpdateRate {}", args.lr_update_rate)?;
+    writeln!(out, "t {}", args.t)
+}
+
+fn dump_dict<W: Write>(model: &Model, out: &mut W) -> io::Result<()> {
+    let words = model.vocabulary().words();
+    writeln!(out, "{}", words.len())?;
+    for entry in words {
+        let kind = match entry.entry_type {
+            EntryType::Word => "word",
+            EntryType::Label => "label",
+        };
+        writeln!(out, "{} {} {}", entry.word, entry.count, kind)?;
+    }
+    Ok(())
+}
+
+fn dump_matrix<W: Write>(matrix: &Matrix, out: &mut W) -> io::Result<()> {
+    writeln!(out, "{} {}", matrix.rows(), matrix.cols())?;
+    for i in 0..matrix.rows() {
+        let row: Vec<String> = matrix.row(i).iter().map(f32::to_string).collect();
+        writeln!(out, "{}", row.join(" "))?;
+    }
+    Ok(())
+}
+
+/// Print part of a model, as `fasttext dump` does. The matrices of a
+/// quantized model are stored as codes, not values, so are refused.
+pub fn run(args: &DumpArgs) -> rusttext::Result<()> {
+    let model = load_model(&args.model)?;
+    let quantized = match args.part {
+        Part::Input => model.quant_input().is_some(),
+        Part::Output => model.quant_output().is_some(),
+        Part::Args | Part::Dict => false,
+    };
+    if quantized {
+        return Err(RustTextError::InvalidArgument(String::from(
+            "cannot dump the matrices of a quantized model",
+        )));
+    }
+    let stdout = io::stdout();
+    let mut out = BufWriter::new(stdout.lock());
+    match args.part {
+        Part::Args => dump_args(&model, &mut out)?,
+        Part::Dict => dump_dict(&model, &mut out)?,
+        Part::Input => dump_matrix(model.input(), &mut out)?,
+        Part::Output => dump_matrix(model.output(), &mut out)?,
+    }
+    out.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dump_matrix() {
+        let matrix = Matrix::from_vec(2, 3, vec![1.0, -0.5, 0.0, 2.0, 0.25, 3.0]).unwrap();
+        let mut out = Vec::new();
+        dump_matrix(&matrix, &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "2 3\n1 -0.5 0\n2 0.25 3\n");
+    }
+}
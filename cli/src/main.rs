@@ -0,0 +1,197 @@
+//! `rusttext`: the fastText command line on top of the rusttext library.
+//! Subcommands and flags follow fastText's, single-dash flags included
+//! (`rusttext supervised -input train.txt -output model`).
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::process;
+
+use clap::{Parser, Subcommand};
+use rusttext::model::Model;
+use rusttext::RustTextError;
+
+mod dump;
+mod predict;
+mod quantize;
+#[cfg(feature = "serve")]
+mod serve;
+mod train;
+mod vectors;
+
+#[derive(Parser)]
+#[command(
+    name = "rusttext",
+    version,
+    about = "Train and use fastText-style models"
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Train word vectors with skipgram
+    Skipgram(train::TrainArgs),
+    /// Train word vectors with CBOW
+    Cbow(train::TrainArgs),
+    /// Train a text classifier
+    Supervised(train::TrainArgs),
+    /// Print the most likely labels of each line
+    Predict(predict::PredictArgs),
+    /// Print the most likely labels of each line with their probabilities
+    PredictProb(predict::PredictArgs),
+    /// Evaluate a classifier on a labelled file
+    Test(predict::TestArgs),
+    /// Compress a model into a `.ftz` file
+    Quantize(quantize::QuantizeArgs),
+    /// Answer nearest-neighbour queries read from stdin
+    Nn(vectors::QueryArgs),
+    /// Answer word analogy queries read from stdin
+    Analogies(vectors::QueryArgs),
+    /// Print the vectors of words read from stdin or a file
+    PrintWordVectors(vectors::PrintArgs),
+    /// Print the vectors of lines read from stdin or a file
+    PrintSentenceVectors(vectors::PrintArgs),
+    /// Print a model's arguments, dictionary or matrices
+    Dump(dump::DumpArgs),
+    /// Serve predictions, vectors and neighbours over HTTP
+    #[cfg(feature = "serve")]
+    Serve(serve::ServeArgs),
+}
+
+/// fastText spells long flags with one dash (`-input`); turn those into
+/// clap's `--input`. Single letters (`-t`), negative numbers and `-` (stdin)
+/// are left alone.
+fn fasttext_flags<I: IntoIterator<Item = String>>(args: I) -> Vec<String> {
+    args.into_iter()
+        .map(|arg| {
+            let mut chars = arg.chars();
+            let long = chars.next() == Some('-')
+                && chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+                && chars.next().is_some();
+            if long {
+                format!("-{}", arg)
+            } else {
+                arg
+            }
+        })
+        .collect()
+}
+
+/// Load a model saved by rusttext (`Model::save`) or by fastText (`.bin`
+/// or `.ftz`), telling them apart by their first bytes.
+pub(crate) fn load_model(path: &Path) -> rusttext::Result<Model> {
+    open_model(path, false)
+}
+
+/// `load_model`, memory-mapping the matrices of a rusttext model when
+/// `mmap` is set (fastText files are always read into memory).
+pub(crate) fn open_model(path: &Path, mmap: bool) -> rusttext::Result<Model> {
+    if mmap {
+        Model::load_auto_mmap(path)
+    } else {
+        Model::load_auto(path)
+    }
+}
+
+/// `path` with `extension` appended, as fastText names its outputs
+/// (`model` gives `model.bin`).
+pub(crate) fn with_suffix(path: &Path, extension: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".");
+    name.push(extension);
+    PathBuf::from(name)
+}
+
+/// Lines of the file at `path`, or of stdin for `-`.
+pub(crate) fn open_input(path: &Path) -> rusttext::Result<Box<dyn BufRead>> {
+    if path == Path::new("-") {
+        Ok(Box::new(BufReader::new(io::stdin())))
+    } else {
+        Ok(Box::new(BufReader::new(File::open(path)?)))
+    }
+}
+
+fn run(command: Command) -> rusttext::Result<()> {
+    match command {
+        Command::Skipgram(args) => train::run(train::Kind::Skipgram, &args),
+        Command::Cbow(args) => train::run(train::Kind::Cbow, &args),
+        Command::Supervised(args) => train::run(train::Kind::Supervised, &args),
+        Command::Predict(args) => predict::predict(&args, false),
+        Command::PredictProb(args) => predict::predict(&args, true),
+        Command::Test(args) => predict::test(&args),
+        Command::Quantize(args) => quantize::run(&args),
+        Command::Nn(args) => vectors::nn(&args),
+        Command::Analogies(args) => vectors::analogies(&args),
+        Command::PrintWordVectors(args) => vectors::print_word_vectors(&args),
+        Command::PrintSentenceVectors(args) => vectors::print_sentence_vectors(&args),
+        Command::Dump(args) => dump::run(&args),
+        #[cfg(feature = "serve")]
+        Command::Serve(args) => serve::run(&args),
+    }
+}
+
+fn main() {
+    let cli = Cli::parse_from(fasttext_flags(std::env::args()));
+    match run(cli.command) {
+        // the reader went away (`rusttext dump ... | head`), as fine as EOF
+        Err(RustTextError::Io(err)) if err.kind() == io::ErrorKind::BrokenPipe => {}
+        Err(err) => {
+            eprintln!("error: {}", err);
+            process::exit(1);
+        }
+        Ok(()) => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_fasttext_flags() {
+        let args = strings(&[
+            "rusttext", "skipgram", "-input", "a.txt", "--dim", "10", "-t", "1e-4", "-lr", "-0.5",
+            "-",
+        ]);
+        assert_eq!(
+            fasttext_flags(args),
+            strings(&[
+                "rusttext", "skipgram", "--input", "a.txt", "--dim", "10", "-t", "1e-4", "--lr",
+                "-0.5", "-"
+            ])
+        );
+    }
+
+    #[test]
+    fn test_parse() {
+        let cli = Cli::try_parse_from(fasttext_flags(strings(&[
+            "rusttext",
+            "supervised",
+            "-input",
+            "train.txt",
+            "-output",
+            "model",
+            "-wordNgrams",
+            "2",
+            "-loss",
+            "ova",
+        ])))
+        .unwrap();
+        assert!(matches!(cli.command, Command::Supervised(_)));
+        assert!(
+            Cli::try_parse_from(strings(&["rusttext", "predict-prob", "m.bin", "-", "3"])).is_ok()
+        );
+        assert!(Cli::try_parse_from(strings(&["rusttext", "unknown"])).is_err());
+        assert_eq!(
+            with_suffix(Path::new("out/model.v1"), "bin"),
+            Path::new("out/model.v1.bin")
+        );
+    }
+}
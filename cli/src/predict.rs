@@ -0,0 +1,125 @@
+use std::io::{self, BufRead, BufWriter, Write};
+use std::path::PathBuf;
+
+use rusttext::eval::LabelMetrics;
+
+use crate::{load_model, open_input};
+
+#[derive(Debug, clap::Args)]
+pub struct PredictArgs {
+    /// Model file (`.bin`, `.ftz` or a rusttext model)
+    model: PathBuf,
+    /// Text to classify, one example per line; `-` reads stdin
+    input: PathBuf,
+    /// Labels to predict per line
+    #[arg(default_value_t = 1)]
+    k: usize,
+    /// Minimal probability of a predicted label
+    #[arg(default_value_t = 0.0)]
+    threshold: f32,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct TestArgs {
+    /// Model file (`.bin`, `.ftz` or a rusttext model)
+    model: PathBuf,
+    /// Labelled test file
+    input: PathBuf,
+    /// Labels to predict per example
+    #[arg(default_value_t = 1)]
+    k: usize,
+    /// Minimal probability of a predicted label
+    #[arg(default_value_t = 0.0)]
+    threshold: f32,
+    /// Print F1, precision and recall of every label first
+    #[arg(long = "label-metrics")]
+    label_metrics: bool,
+}
+
+/// Print the predicted labels of each input line, one line each, with
+/// their probabilities when `probabilities` is set.
+pub fn predict(args: &PredictArgs, probabilities: bool) -> rusttext::Result<()> {
+    let model = load_model(&args.model)?;
+    let stdout = io::stdout();
+    let mut out = BufWriter::new(stdout.lock());
+    for line in open_input(&args.input)?.lines() {
+        let predictions = model.predict(&line?, args.k, args.threshold);
+        let fields: Vec<String> = predictions
+            .into_iter()
+            .map(|(label, prob)| {
+                if probabilities {
+                    format!("{} {}", label, prob)
+                } else {
+                    label
+                }
+            })
+            .collect();
+        writeln!(out, "{}", fields.join(" "))?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// `value` to six decimals, or dashes when its denominator is zero.
+fn score(value: f32, defined: bool) -> String {
+    if defined {
+        format!("{:.6}", value)
+    } else {
+        String::from("--------")
+    }
+}
+
+/// One line of `fasttext test-label` output.
+fn label_line(metrics: &LabelMetrics) -> String {
+    format!(
+        "F1-Score : {}  Precision : {}  Recall : {}   {}",
+        score(metrics.f1, metrics.predicted + metrics.support > 0),
+        score(metrics.precision, metrics.predicted > 0),
+        score(metrics.recall, metrics.support > 0),
+        metrics.label
+    )
+}
+
+/// Print precision and recall at k on a labelled file, as `fasttext test`
+/// does, after the scores of each label with `--label-metrics`, as
+/// `fasttext test-label` does.
+pub fn test(args: &TestArgs) -> rusttext::Result<()> {
+    let model = load_model(&args.model)?;
+    if args.label_metrics {
+        let report = model.test_labels(&args.input, args.k, args.threshold)?;
+        for metrics in report.labels.iter() {
+            println!("{}", label_line(metrics));
+        }
+    }
+    let result = model.test(&args.input, args.k, args.threshold)?;
+    println!("N\t{}", result.n_examples);
+    println!("P@{}\t{:.3}", result.k, result.precision);
+    println!("R@{}\t{:.3}", result.k, result.recall);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_label_line() {
+        let mut metrics = LabelMetrics {
+            label: String::from("__label__a"),
+            support: 4,
+            predicted: 2,
+            correct: 2,
+            precision: 1.0,
+            recall: 0.5,
+            f1: 2.0 / 3.0,
+        };
+        assert_eq!(
+            label_line(&metrics),
+            "F1-Score : 0.666667  Precision : 1.000000  Recall : 0.500000   __label__a"
+        );
+        metrics.predicted = 0;
+        metrics.correct = 0;
+        metrics.precision = 0.0;
+        assert!(label_line(&metrics).contains("Precision : --------  Recall : 0.500000"));
+    }
+}
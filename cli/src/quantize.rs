@@ -0,0 +1,98 @@
+use std::path::PathBuf;
+
+use rusttext::quant::QuantArgs;
+use rusttext::RustTextError;
+
+use crate::{load_model, with_suffix};
+
+#[derive(Debug, clap::Args)]
+pub struct QuantizeArgs {
+    /// Model path without extension: reads `<output>.bin` and writes
+    /// `<output>.ftz`
+    #[arg(long)]
+    output: PathBuf,
+    /// Training file, needed by `-retrain`
+    #[arg(long)]
+    input: Option<PathBuf>,
+    /// Quantize each row's norm separately
+    #[arg(long)]
+    qnorm: bool,
+    /// Quantize the output matrix too
+    #[arg(long)]
+    qout: bool,
+    /// Keep only this many input rows, those with the largest norms; 0
+    /// keeps all
+    #[arg(long, default_value_t = 0)]
+    cutoff: usize,
+    /// After `-cutoff`, train the pruned model on `-input` again
+    #[arg(long)]
+    retrain: bool,
+    /// Epochs to retrain for
+    #[arg(long, default_value_t = 1)]
+    epoch: u32,
+    /// Dimensions per sub-quantizer
+    #[arg(long, default_value_t = 2)]
+    dsub: usize,
+}
+
+impl QuantizeArgs {
+    fn quant_args(&self) -> QuantArgs {
+        QuantArgs {
+            dsub: self.dsub,
+            qnorm: self.qnorm,
+            qout: self.qout,
+            // applied by `prune` beforehand, so retraining sees the cut
+            cutoff: 0,
+        }
+    }
+}
+
+/// Compress `<output>.bin` into `<output>.ftz` as fastText's `quantize`
+/// does: keep the `-cutoff` largest input rows, optionally retrain on
+/// `-input` to recover accuracy, then product-quantize.
+pub fn run(args: &QuantizeArgs) -> rusttext::Result<()> {
+    let retrain = match (&args.input, args.retrain) {
+        (Some(input), true) => Some(input),
+        (None, true) => {
+            return Err(RustTextError::InvalidArgument(String::from(
+                "-retrain needs the training file as -input",
+            )))
+        }
+        (_, false) => None,
+    };
+    let mut model = load_model(&with_suffix(&args.output, "bin"))?;
+    if args.cutoff > 0 && args.cutoff < model.input().rows() {
+        model.prune(args.cutoff)?;
+        if let Some(input) = retrain {
+            model.retrain(input, args.epoch)?;
+        }
+    }
+    model.quantize(&args.quant_args())?;
+    model.save_fasttext(with_suffix(&args.output, "ftz"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[derive(Parser)]
+    struct Wrapper {
+        #[command(flatten)]
+        args: QuantizeArgs,
+    }
+
+    #[test]
+    fn test_quant_args() {
+        let args = Wrapper::try_parse_from([
+            "quantize", "--output", "m", "--qnorm", "--cutoff", "1000", "--dsub", "4",
+        ])
+        .unwrap()
+        .args;
+        assert_eq!(args.cutoff, 1000);
+        assert!(!args.retrain);
+        let quant = args.quant_args();
+        assert_eq!((quant.dsub, quant.qnorm, quant.qout), (4, true, false));
+        assert_eq!(quant.cutoff, 0);
+    }
+}
@@ -0,0 +1,204 @@
+use std::io::{self, Read};
+use std::path::PathBuf;
+use std::thread;
+
+use rusttext::model::Model;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::open_model;
+
+/// Largest request body read; queries are a word or a few sentences.
+const MAX_BODY: u64 = 1 << 20;
+
+#[derive(Debug, clap::Args)]
+pub struct ServeArgs {
+    /// Model file (`.bin`, `.ftz` or a rusttext model, which is
+    /// memory-mapped)
+    model: PathBuf,
+    /// Address to listen on
+    #[arg(long, default_value = "127.0.0.1")]
+    host: String,
+    #[arg(long, default_value_t = 8080)]
+    port: u16,
+    /// Requests handled at once
+    #[arg(long, default_value_t = 4)]
+    threads: usize,
+}
+
+fn one() -> usize {
+    1
+}
+
+fn ten() -> usize {
+    10
+}
+
+#[derive(Deserialize)]
+struct PredictRequest {
+    text: String,
+    #[serde(default = "one")]
+    k: usize,
+    #[serde(default)]
+    threshold: f32,
+}
+
+/// Either the vector of one word or the sentence vector of a text.
+#[derive(Deserialize)]
+struct VectorRequest {
+    word: Option<String>,
+    text: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct NnRequest {
+    word: String,
+    #[serde(default = "ten")]
+    k: usize,
+}
+
+fn error(status: u16, message: &str) -> (u16, Value) {
+    (status, json!({ "error": message }))
+}
+
+fn parse<T: DeserializeOwned>(body: &str) -> Result<T, (u16, Value)> {
+    serde_json::from_str(body).map_err(|err| error(400, &err.to_string()))
+}
+
+fn scored(results: Vec<(String, f32)>, key: &str) -> Value {
+    let (names, scores): (Vec<String>, Vec<f32>) = results.into_iter().unzip();
+    json!({ key: names, "scores": scores })
+}
+
+/// Answer one request: a status and a JSON body. Every endpoint takes a
+/// JSON object by POST:
+///
+/// - `/predict` `{"text", "k"?, "threshold"?}` gives `{"labels", "probs"}`
+/// - `/vector` `{"word"}` or `{"text"}` gives `{"vector"}`
+/// - `/nn` `{"word", "k"?}` gives `{"words", "scores"}`
+fn handle(model: &Model, method: &Method, path: &str, body: &str) -> (u16, Value) {
+    let path = path.split('?').next().unwrap_or(path);
+    if !["/predict", "/vector", "/nn"].contains(&path) {
+        return error(404, "not found");
+    }
+    if *method != Method::Post {
+        return error(405, "use POST");
+    }
+    let response = match path {
+        "/predict" => parse::<PredictRequest>(body).map(|request| {
+            let predictions = model.predict(&request.text, request.k, request.threshold);
+            let (labels, probs): (Vec<String>, Vec<f32>) = predictions.into_iter().unzip();
+            json!({ "labels": labels, "probs": probs })
+        }),
+        "/vector" => {
+            parse::<VectorRequest>(body).and_then(|request| match (request.word, request.text) {
+                (Some(word), None) => Ok(json!({ "vector": model.word_vector(&word) })),
+                (None, Some(text)) => Ok(json!({ "vector": model.sentence_vector(&text) })),
+                _ => Err(error(400, "expected one of \"word\" or \"text\"")),
+            })
+        }
+        _ => parse::<NnRequest>(body).map(|request| {
+            let k = usize::min(request.k, model.vocabulary().n_words() as usize);
+            scored(model.nearest_neighbors(&request.word, k), "words")
+        }),
+    };
+    match response {
+        Ok(value) => (200, value),
+        Err(failure) => failure,
+    }
+}
+
+fn respond(model: &Model, mut request: Request) -> io::Result<()> {
+    let mut body = String::new();
+    let (status, value) = match request.as_reader().take(MAX_BODY).read_to_string(&mut body) {
+        Ok(_) => handle(model, request.method(), request.url(), &body),
+        Err(err) => error(400, &err.to_string()),
+    };
+    let content_type = Header::from_bytes("Content-Type", "application/json").unwrap();
+    request.respond(
+        Response::from_string(value.to_string())
+            .with_status_code(status)
+            .with_header(content_type),
+    )
+}
+
+/// Load the model once and answer requests on `--threads` threads until
+/// the process is stopped.
+pub fn run(args: &ServeArgs) -> rusttext::Result<()> {
+    let model = open_model(&args.model, true)?;
+    if !model.is_supervised() {
+        model.cache_word_vectors();
+    }
+    let address = format!("{}:{}", args.host, args.port);
+    let server = Server::http(&address).map_err(|err| io::Error::other(err.to_string()))?;
+    eprintln!("listening on http://{}", address);
+    thread::scope(|scope| {
+        for _ in 0..args.threads.max(1) {
+            scope.spawn(|| loop {
+                match server.recv() {
+                    Ok(request) => {
+                        if let Err(err) = respond(&model, request) {
+                            eprintln!("error: {}", err);
+                        }
+                    }
+                    Err(err) => eprintln!("error: {}", err),
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusttext::args::Args;
+    use std::io::Write;
+
+    fn train() -> Model {
+        let mut corpus = tempfile::NamedTempFile::new().unwrap();
+        let lines = "__label__fruit apple banana\n__label__letter xray zulu\n".repeat(100);
+        corpus.write_all(lines.as_bytes()).unwrap();
+        let args = Args {
+            dim: 10,
+            bucket: 1000,
+            threads: 1,
+            ..Args::supervised()
+        };
+        Model::train_supervised(corpus.path(), args).unwrap()
+    }
+
+    #[test]
+    fn test_handle() {
+        let model = train();
+
+        let (status, value) = handle(&model, &Method::Post, "/predict", r#"{"text": "apple"}"#);
+        assert_eq!(status, 200);
+        assert_eq!(value["labels"], json!(["__label__fruit"]));
+        assert_eq!(value["probs"].as_array().unwrap().len(), 1);
+
+        let (status, value) = handle(&model, &Method::Post, "/vector", r#"{"word": "zulu"}"#);
+        assert_eq!(status, 200);
+        assert_eq!(value["vector"].as_array().unwrap().len(), 10);
+
+        let (status, value) = handle(&model, &Method::Post, "/nn", r#"{"word": "apple", "k": 2}"#);
+        assert_eq!(status, 200);
+        assert_eq!(value["words"].as_array().unwrap().len(), 2);
+        let huge = format!(r#"{{"word": "apple", "k": {}}}"#, usize::MAX);
+        let (status, value) = handle(&model, &Method::Post, "/nn", &huge);
+        assert_eq!(status, 200);
+        // every other word
+        assert_eq!(value["words"].as_array().unwrap().len(), 4);
+
+        let bad = |method, path, body| handle(&model, &method, path, body).0;
+        assert_eq!(
+            bad(Method::Post, "/vector", r#"{"word": "a", "text": "b"}"#),
+            400
+        );
+        assert_eq!(bad(Method::Post, "/predict", "not json"), 400);
+        assert_eq!(bad(Method::Get, "/predict", ""), 405);
+        assert_eq!(bad(Method::Post, "/other", "{}"), 404);
+    }
+}
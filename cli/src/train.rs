@@ -0,0 +1,261 @@
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use clap::ValueEnum;
+use rusttext::args::{Args, LossKind, WordModel};
+use rusttext::model::Model;
+use rusttext::train::Progress;
+
+use crate::with_suffix;
+
+/// What a training subcommand trains.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    Skipgram,
+    Cbow,
+    Supervised,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum Loss {
+    /// Negative sampling
+    Ns,
+    /// Hierarchical softmax
+    Hs,
+    Softmax,
+    /// One-vs-all, for multi-label classification
+    Ova,
+}
+
+impl From<Loss> for LossKind {
+    fn from(loss: Loss) -> LossKind {
+        match loss {
+            Loss::Ns => LossKind::NegativeSampling,
+            Loss::Hs => LossKind::HierarchicalSoftmax,
+            Loss::Softmax => LossKind::Softmax,
+            Loss::Ova => LossKind::OneVsAll,
+        }
+    }
+}
+
+/// fastText's training flags. Unset ones come from `-config` if given, or
+/// else keep fastText's defaults for the kind of model trained
+/// (`Args::default` or `Args::supervised`).
+#[derive(Debug, clap::Args)]
+pub struct TrainArgs {
+    /// Training file
+    #[arg(long)]
+    input: PathBuf,
+    /// Output path, without extension; `.bin` and `.vec` are appended
+    #[arg(long)]
+    output: PathBuf,
+    /// JSON or TOML file of training arguments, overridden by the flags
+    #[arg(long)]
+    config: Option<PathBuf>,
+    /// Learning rate
+    #[arg(long)]
+    lr: Option<f32>,
+    /// Tokens between learning rate updates
+    #[arg(long = "lrUpdateRate")]
+    lr_update_rate: Option<u64>,
+    /// Size of the vectors
+    #[arg(long)]
+    dim: Option<usize>,
+    /// Size of the context window
+    #[arg(long)]
+    ws: Option<usize>,
+    /// Number of epochs
+    #[arg(long)]
+    epoch: Option<u32>,
+    /// Minimal number of word occurrences
+    #[arg(long = "minCount")]
+    min_count: Option<u32>,
+    /// Minimal number of label occurrences
+    #[arg(long = "minCountLabel")]
+    min_count_label: Option<u32>,
+    /// Number of negatives sampled
+    #[arg(long)]
+    neg: Option<usize>,
+    /// Max length of word n-grams
+    #[arg(long = "wordNgrams")]
+    word_ngrams: Option<usize>,
+    /// Loss function
+    #[arg(long, value_enum)]
+    loss: Option<Loss>,
+    /// Number of buckets
+    #[arg(long)]
+    bucket: Option<u32>,
+    /// Min length of character n-grams
+    #[arg(long)]
+    minn: Option<usize>,
+    /// Max length of character n-grams
+    #[arg(long)]
+    maxn: Option<usize>,
+    /// Number of threads
+    #[arg(long)]
+    thread: Option<usize>,
+    /// Sampling threshold
+    #[arg(short = 't')]
+    t: Option<f64>,
+    /// Labels prefix
+    #[arg(long)]
+    label: Option<String>,
+    /// Pretrained word vectors (`.vec`) for supervised learning
+    #[arg(long = "pretrainedVectors")]
+    pretrained_vectors: Option<PathBuf>,
+    /// Random seed
+    #[arg(long)]
+    seed: Option<u64>,
+    /// Verbosity level: 0 is silent, 2 shows progress
+    #[arg(long, default_value_t = 2)]
+    verbose: u8,
+}
+
+impl TrainArgs {
+    /// The library arguments for training a `kind` model.
+    pub fn to_args(&self, kind: Kind) -> rusttext::Result<Args> {
+        let mut args = match (&self.config, kind) {
+            (Some(config), _) => Args::load(config)?,
+            (None, Kind::Supervised) => Args::supervised(),
+            (None, Kind::Skipgram) | (None, Kind::Cbow) => Args::default(),
+        };
+        match kind {
+            Kind::Skipgram => args.word_model = WordModel::Skipgram,
+            Kind::Cbow => args.word_model = WordModel::Cbow,
+            Kind::Supervised => {}
+        }
+        macro_rules! set {
+            ($($flag:ident => $field:ident),*) => {
+                $(if let Some(value) = &self.$flag {
+                    args.$field = value.clone().into();
+                })*
+            };
+        }
+        set!(
+            lr => lr,
+            lr_update_rate => lr_update_rate,
+            dim => dim,
+            ws => ws,
+            epoch => epoch,
+            min_count => min_count,
+            min_count_label => min_count_label,
+            neg => neg,
+            word_ngrams => word_ngrams,
+            loss => loss,
+            bucket => bucket,
+            minn => min_n,
+            maxn => max_n,
+            thread => threads,
+            t => t,
+            label => label_prefix,
+            seed => seed
+        );
+        if self.pretrained_vectors.is_some() {
+            args.pretrained_vectors = self.pretrained_vectors.clone();
+        }
+        Ok(args)
+    }
+}
+
+/// `eta` as fastText prints it, `1h 5m 9s`.
+fn format_eta(eta: Option<Duration>) -> String {
+    match eta {
+        Some(eta) => {
+            let secs = eta.as_secs();
+            format!("{}h{:2}m{:2}s", secs / 3600, secs / 60 % 60, secs % 60)
+        }
+        None => String::from("--"),
+    }
+}
+
+fn report(progress: &Progress) {
+    eprint!(
+        "\rProgress: {:5.1}% words/sec: {:8.0} lr: {:9.6} avg.loss: {:9.6} ETA: {}",
+        progress.percent,
+        progress.words_per_sec,
+        progress.lr,
+        progress.loss,
+        format_eta(progress.eta)
+    );
+    let _ = io::stderr().flush();
+}
+
+/// Train a `kind` model on `-input` and save it as `<output>.bin` in
+/// fastText's format, with its word vectors in `<output>.vec`.
+pub fn run(kind: Kind, options: &TrainArgs) -> rusttext::Result<()> {
+    let args = options.to_args(kind)?;
+    let quiet = |_: &Progress| {};
+    let callback: &(dyn Fn(&Progress) + Sync) = if options.verbose > 1 { &report } else { &quiet };
+    let model = match kind {
+        Kind::Supervised => Model::train_supervised_with(&options.input, args, &callback),
+        Kind::Skipgram | Kind::Cbow => {
+            Model::train_unsupervised_with(&options.input, args, &callback)
+        }
+    }?;
+    if options.verbose > 1 {
+        eprintln!();
+    }
+    model.save_fasttext(with_suffix(&options.output, "bin"))?;
+    model.save_vectors(with_suffix(&options.output, "vec"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[derive(Parser)]
+    struct Wrapper {
+        #[command(flatten)]
+        args: TrainArgs,
+    }
+
+    fn parse(flags: &[&str]) -> TrainArgs {
+        let mut argv = vec!["train", "--input", "in.txt", "--output", "out"];
+        argv.extend_from_slice(flags);
+        Wrapper::try_parse_from(argv).unwrap().args
+    }
+
+    #[test]
+    fn test_to_args() {
+        let options = parse(&["--dim", "20", "--minn", "2", "--loss", "hs", "-t", "0.001"]);
+        let args = options.to_args(Kind::Supervised).unwrap();
+        assert_eq!(args.dim, 20);
+        assert_eq!(args.min_n, 2);
+        assert_eq!(args.loss, LossKind::HierarchicalSoftmax);
+        assert_eq!(args.t, 0.001);
+        // unset flags keep the supervised defaults
+        assert_eq!(args.lr, Args::supervised().lr);
+
+        let args = parse(&[]).to_args(Kind::Cbow).unwrap();
+        assert_eq!(args.word_model, WordModel::Cbow);
+        assert_eq!(args.min_n, Args::default().min_n);
+        assert!(Wrapper::try_parse_from(["train", "--input", "x"]).is_err());
+    }
+
+    #[test]
+    fn test_config() {
+        let path = std::env::temp_dir().join("rusttext_cli_config.toml");
+        std::fs::write(&path, "dim = 30\nepoch = 7\nlabel = \"#\"\n").unwrap();
+        let config = path.to_str().unwrap();
+        let args = parse(&["--config", config, "--epoch", "3"])
+            .to_args(Kind::Supervised)
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(args.dim, 30);
+        assert_eq!(args.label_prefix, "#");
+        // flags win over the file
+        assert_eq!(args.epoch, 3);
+        assert!(parse(&["--config", config])
+            .to_args(Kind::Supervised)
+            .is_err());
+    }
+
+    #[test]
+    fn test_format_eta() {
+        assert_eq!(format_eta(Some(Duration::from_secs(3909))), "1h 5m 9s");
+        assert_eq!(format_eta(Some(Duration::ZERO)), "0h 0m 0s");
+        assert_eq!(format_eta(None), "--");
+    }
+}
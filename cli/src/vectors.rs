@@ -0,0 +1,143 @@
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::PathBuf;
+
+use rusttext::vectors::{write_sentence_vectors, write_word_vectors};
+
+use crate::{load_model, open_input};
+
+#[derive(Debug, clap::Args)]
+pub struct QueryArgs {
+    /// Model file (`.bin`, `.ftz` or a rusttext model)
+    model: PathBuf,
+    /// Answers to print per query
+    #[arg(default_value_t = 10)]
+    k: usize,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct PrintArgs {
+    /// Model file (`.bin`, `.ftz` or a rusttext model)
+    model: PathBuf,
+    /// Text to read; `-` reads stdin
+    #[arg(default_value = "-")]
+    input: PathBuf,
+}
+
+/// Answer each line of `reader` with `answer`, writing `word score` rows
+/// and a blank line after each query, flushed so a caller on the other end
+/// of a pipe sees every answer before sending the next query. `prompt` is
+/// shown before each query; a line `answer` rejects is reported on stderr
+/// and skipped.
+fn repl<R, W, F>(reader: R, mut writer: W, prompt: Option<&str>, answer: F) -> rusttext::Result<()>
+where
+    R: BufRead,
+    W: Write,
+    F: Fn(&str) -> Result<Vec<(String, f32)>, String>,
+{
+    let mut lines = reader.lines();
+    loop {
+        if let Some(prompt) = prompt {
+            write!(writer, "{}", prompt)?;
+            writer.flush()?;
+        }
+        let line = match lines.next() {
+            Some(line) => line?,
+            None => break,
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        match answer(&line) {
+            Ok(results) => {
+                for (word, score) in results {
+                    writeln!(writer, "{} {}", word, score)?;
+                }
+                writeln!(writer)?;
+            }
+            Err(message) => eprintln!("{}", message),
+        }
+        writer.flush()?;
+    }
+    if prompt.is_some() {
+        writeln!(writer)?;
+    }
+    Ok(())
+}
+
+/// Load the model and its normalized word vectors once, then answer
+/// queries from stdin until it closes. Prompts are only shown to a
+/// terminal, so piped output holds nothing but answers.
+fn serve_queries<F>(args: &QueryArgs, prompt: &str, answer: F) -> rusttext::Result<()>
+where
+    F: Fn(&rusttext::model::Model, &str) -> Result<Vec<(String, f32)>, String>,
+{
+    let model = load_model(&args.model)?;
+    model.cache_word_vectors();
+    let stdin = io::stdin();
+    let prompt = if stdin.is_terminal() {
+        Some(prompt)
+    } else {
+        None
+    };
+    repl(stdin.lock(), io::stdout().lock(), prompt, |line| {
+        answer(&model, line)
+    })
+}
+
+/// The `k` nearest neighbours of each query word.
+pub fn nn(args: &QueryArgs) -> rusttext::Result<()> {
+    serve_queries(args, "Query word? ", |model, line| {
+        match line.split_whitespace().collect::<Vec<_>>()[..] {
+            [word] => Ok(model.nearest_neighbors(word, args.k)),
+            _ => Err(String::from("expected one word")),
+        }
+    })
+}
+
+/// Answers to each query triplet `A B C`, as in fastText: the words
+/// nearest to `A - B + C`, so `berlin germany france` should give `paris`.
+pub fn analogies(args: &QueryArgs) -> rusttext::Result<()> {
+    serve_queries(
+        args,
+        "Query triplet (A - B + C)? ",
+        |model, line| match line.split_whitespace().collect::<Vec<_>>()[..] {
+            [a, b, c] => Ok(model.analogy(b, a, c, args.k)),
+            _ => Err(String::from("expected three words: A B C")),
+        },
+    )
+}
+
+/// Print `word v1 v2 ...` for every whitespace-separated word of the
+/// input, a line at a time.
+pub fn print_word_vectors(args: &PrintArgs) -> rusttext::Result<()> {
+    let model = load_model(&args.model)?;
+    write_word_vectors(&model, open_input(&args.input)?, io::stdout().lock())?;
+    Ok(())
+}
+
+/// Print the sentence vector of every line of the input, a line at a time.
+pub fn print_sentence_vectors(args: &PrintArgs) -> rusttext::Result<()> {
+    let model = load_model(&args.model)?;
+    write_sentence_vectors(&model, open_input(&args.input)?, io::stdout().lock())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_repl() {
+        let answer = |line: &str| match line {
+            "bad" => Err(String::from("bad query")),
+            _ => Ok(vec![(line.to_uppercase(), 0.5)]),
+        };
+        let mut out = Vec::new();
+        repl("a\n\nbad\nb\n".as_bytes(), &mut out, None, answer).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "A 0.5\n\nB 0.5\n\n");
+
+        let mut out = Vec::new();
+        repl("a\n".as_bytes(), &mut out, Some("? "), answer).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "? A 0.5\n\n? \n");
+    }
+}
@@ -0,0 +1,42 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use rusttext::simd;
+
+fn values(n: usize, seed: f32) -> Vec<f32> {
+    (0..n).map(|i| ((i as f32 + seed) * 0.37).sin()).collect()
+}
+
+// The kernels against the plain loops they replaced.
+fn bench_kernels(c: &mut Criterion) {
+    let mut group = c.benchmark_group("simd");
+
+    for dim in [10, 100, 300].iter() {
+        let (x, y) = (values(*dim, 1.0), values(*dim, 2.0));
+        group.bench_with_input(BenchmarkId::new("dot", dim), dim, |b, _| {
+            b.iter(|| simd::dot(black_box(&x), black_box(&y)))
+        });
+        group.bench_with_input(BenchmarkId::new("dot_scalar", dim), dim, |b, _| {
+            b.iter(|| {
+                black_box(&x)
+                    .iter()
+                    .zip(black_box(&y).iter())
+                    .map(|(x, y)| x * y)
+                    .sum::<f32>()
+            })
+        });
+        let mut out = y.clone();
+        group.bench_with_input(BenchmarkId::new("axpy", dim), dim, |b, _| {
+            b.iter(|| simd::axpy(black_box(&mut out), 0.5, black_box(&x)))
+        });
+        group.bench_with_input(BenchmarkId::new("axpy_scalar", dim), dim, |b, _| {
+            b.iter(|| {
+                for (o, x) in black_box(&mut out).iter_mut().zip(black_box(&x).iter()) {
+                    *o += 0.5 * x;
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_kernels);
+criterion_main!(benches);
@@ -0,0 +1,56 @@
+use std::io::Write;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use rusttext::args::Args;
+use rusttext::model::Model;
+use rusttext::random::Rng;
+
+const N_LINES: usize = 2_000;
+const LINE_LENGTH: usize = 20;
+
+fn synthetic_corpus() -> std::path::PathBuf {
+    let mut rng = Rng::new(0);
+    let mut text = String::new();
+    for _ in 0..N_LINES {
+        for _ in 0..LINE_LENGTH {
+            // roughly Zipfian over a thousand word types
+            let rank = (1000.0f32.powf(rng.next_f32())) as usize;
+            text.push_str(&format!("w{} ", rank));
+        }
+        text.push('\n');
+    }
+
+    let path = std::env::temp_dir().join(format!("rusttext-bench-{}.txt", std::process::id()));
+    std::fs::File::create(&path)
+        .unwrap()
+        .write_all(text.as_bytes())
+        .unwrap();
+    path
+}
+
+fn bench_skipgram_threads(c: &mut Criterion) {
+    let path = synthetic_corpus();
+    let mut group = c.benchmark_group("skipgram");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements((N_LINES * (LINE_LENGTH + 1)) as u64));
+
+    for threads in [1, 2, 4].iter() {
+        let args = Args {
+            dim: 50,
+            epoch: 1,
+            min_count: 1,
+            bucket: 10_000,
+            vocab_size: 4096,
+            threads: *threads,
+            ..Args::default()
+        };
+        group.bench_with_input(BenchmarkId::from_parameter(threads), &args, |b, args| {
+            b.iter(|| Model::train_unsupervised(&path, args.clone()).unwrap())
+        });
+    }
+    group.finish();
+    std::fs::remove_file(&path).unwrap();
+}
+
+criterion_group!(benches, bench_skipgram_threads);
+criterion_main!(benches);
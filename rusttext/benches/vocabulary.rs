@@ -0,0 +1,47 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use rusttext::vocabulary::Vocabulary;
+
+const TABLE_SIZE: usize = 1 << 16;
+
+fn filled_vocab(load_factor: f64) -> (Vocabulary, Vec<String>) {
+    let n_words = (TABLE_SIZE as f64 * load_factor) as usize;
+    let words: Vec<String> = (0..n_words).map(|i| format!("word{}", i)).collect();
+
+    let mut vocab = Vocabulary::new(TABLE_SIZE, 0, 0, 1).unwrap();
+    for word in words.iter() {
+        vocab.add(word).unwrap();
+    }
+    (vocab, words)
+}
+
+fn bench_lookup(c: &mut Criterion) {
+    let mut group = c.benchmark_group("get_id");
+
+    for load_factor in [0.25, 0.5, 0.6, 0.75, 0.9, 0.95].iter() {
+        let (vocab, words) = filled_vocab(*load_factor);
+        let misses: Vec<String> = (0..words.len()).map(|i| format!("miss{}", i)).collect();
+
+        group.bench_with_input(BenchmarkId::new("hit", load_factor), &words, |b, words| {
+            let mut i = 0;
+            b.iter(|| {
+                i = (i + 1) % words.len();
+                black_box(vocab.get_id(&words[i]))
+            })
+        });
+        group.bench_with_input(
+            BenchmarkId::new("miss", load_factor),
+            &misses,
+            |b, misses| {
+                let mut i = 0;
+                b.iter(|| {
+                    i = (i + 1) % misses.len();
+                    black_box(vocab.get_id(&misses[i]))
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_lookup);
+criterion_main!(benches);
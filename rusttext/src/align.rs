@@ -0,0 +1,299 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+use crate::matrix::Matrix;
+use crate::model::Model;
+use crate::utils::jacobi_svd;
+use crate::vectors::{self, l2_norm};
+use crate::{Result, RustTextError};
+
+/// An orthogonal map from one embedding space onto another: a source
+/// vector `x` maps to `x W` for the `dim x dim` rotation `W`. Being
+/// orthogonal, it keeps norms and cosine similarities within the source
+/// space intact.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Alignment {
+    rotation: Matrix,
+}
+
+// Normalize the columns of `us` into the left singular vectors `U`. Columns
+// with a (near) zero singular value carry no direction, so they are
+// replaced by unit vectors orthogonal to the others, keeping `U`
+// orthogonal when the seed pairs don't span the space.
+fn left_singular_vectors(mut us: Vec<f64>, n: usize) -> Vec<f64> {
+    let norms: Vec<f64> = (0..n)
+        .map(|j| (0..n).map(|i| us[i * n + j].powi(2)).sum::<f64>().sqrt())
+        .collect();
+    let largest = norms.iter().cloned().fold(0.0, f64::max);
+    let mut done: Vec<usize> = Vec::with_capacity(n);
+    let mut pending = Vec::new();
+    for j in 0..n {
+        if norms[j] > 1e-9 * largest && largest > 0.0 {
+            for i in 0..n {
+                us[i * n + j] /= norms[j];
+            }
+            done.push(j);
+        } else {
+            pending.push(j);
+        }
+    }
+    // Gram-Schmidt over the standard basis for the missing columns
+    let mut basis = 0;
+    for j in pending {
+        loop {
+            let mut column = vec![0.0; n];
+            column[basis] = 1.0;
+            basis += 1;
+            for &k in done.iter() {
+                let dot: f64 = (0..n).map(|i| column[i] * us[i * n + k]).sum();
+                for (i, value) in column.iter_mut().enumerate() {
+                    *value -= dot * us[i * n + k];
+                }
+            }
+            let norm = column.iter().map(|x| x * x).sum::<f64>().sqrt();
+            if norm > 1e-6 {
+                for (i, value) in column.iter().enumerate() {
+                    us[i * n + j] = value / norm;
+                }
+                done.push(j);
+                break;
+            }
+        }
+    }
+    us
+}
+
+impl Alignment {
+    /// The orthogonal Procrustes solution: the rotation `W` minimizing
+    /// `|source W - target|` over paired rows, `W = U V^T` for the
+    /// singular value decomposition `U S V^T` of `source^T target`.
+    pub fn procrustes(source: &Matrix, target: &Matrix) -> Result<Alignment> {
+        if source.cols() != target.cols() {
+            return Err(RustTextError::DimensionMismatch {
+                expected: source.cols(),
+                found: target.cols(),
+            });
+        }
+        if source.rows() != target.rows() {
+            return Err(RustTextError::DimensionMismatch {
+                expected: source.rows(),
+                found: target.rows(),
+            });
+        }
+        let n = source.cols();
+        let mut cross = vec![0.0f64; n * n];
+        for row in 0..source.rows() {
+            let (x, y) = (source.row(row), target.row(row));
+            for i in 0..n {
+                for j in 0..n {
+                    cross[i * n + j] += f64::from(x[i]) * f64::from(y[j]);
+                }
+            }
+        }
+        let (us, v) = jacobi_svd(cross, n);
+        let u = left_singular_vectors(us, n);
+        let mut rotation = Matrix::new(n, n);
+        for i in 0..n {
+            for j in 0..n {
+                let value: f64 = (0..n).map(|k| u[i * n + k] * v[j * n + k]).sum();
+                rotation.row_mut(i)[j] = value as f32;
+            }
+        }
+        Ok(Alignment { rotation })
+    }
+
+    /// Align `source` onto `target` from a seed dictionary of (source word,
+    /// target word) translations, as in the MUSE and fastText alignment
+    /// recipes: Procrustes over the unit-normalized word vectors of each
+    /// pair. Pairs where either word has no vector are skipped.
+    pub fn fit(
+        source: &Model,
+        target: &Model,
+        dictionary: &[(String, String)],
+    ) -> Result<Alignment> {
+        let dim = source.args().dim;
+        if target.args().dim != dim {
+            return Err(RustTextError::DimensionMismatch {
+                expected: dim,
+                found: target.args().dim,
+            });
+        }
+        let (mut xs, mut ys) = (Vec::new(), Vec::new());
+        for (from, to) in dictionary.iter() {
+            let (x, y) = (source.word_vector(from), target.word_vector(to));
+            let (x_norm, y_norm) = (l2_norm(&x), l2_norm(&y));
+            if x_norm == 0.0 || y_norm == 0.0 {
+                continue;
+            }
+            xs.extend(x.iter().map(|v| v / x_norm));
+            ys.extend(y.iter().map(|v| v / y_norm));
+        }
+        if xs.is_empty() {
+            return Err(RustTextError::InvalidArgument(String::from(
+                "no dictionary pair has vectors in both models",
+            )));
+        }
+        let rows = xs.len() / dim;
+        Alignment::procrustes(
+            &Matrix::from_vec(rows, dim, xs)?,
+            &Matrix::from_vec(rows, dim, ys)?,
+        )
+    }
+
+    pub fn rotation(&self) -> &Matrix {
+        &self.rotation
+    }
+
+    /// `vector W`.
+    pub fn apply(&self, vector: &[f32]) -> Vec<f32> {
+        let mut out = vec![0.0; self.rotation.cols()];
+        for (i, &x) in vector.iter().enumerate() {
+            self.rotation.add_row_to(&mut out, i, x);
+        }
+        out
+    }
+
+    /// Rotate `model` into the target space in place. Every input row is
+    /// rotated, so word, subword and sentence vectors all follow; a
+    /// classifier's output rows are rotated too, leaving its predictions
+    /// unchanged. Compressed models can't be rotated.
+    pub fn apply_to(&self, model: &mut Model) -> Result<()> {
+        if model.is_quantized() {
+            return Err(RustTextError::InvalidArgument(String::from(
+                "cannot rotate a quantized model",
+            )));
+        }
+        if model.args().dim != self.rotation.rows() {
+            return Err(RustTextError::DimensionMismatch {
+                expected: self.rotation.rows(),
+                found: model.args().dim,
+            });
+        }
+        let dim = usize::max(model.args().dim, 1);
+        for matrix in [&mut model.input, &mut model.output].iter_mut() {
+            for row in matrix.data_mut().chunks_mut(dim) {
+                let rotated = self.apply(row);
+                row.copy_from_slice(&rotated);
+            }
+        }
+        model.reset_word_vectors();
+        Ok(())
+    }
+
+    /// The `k` target words nearest to source word `word` once mapped into
+    /// the target space, best first: word translation by nearest neighbour.
+    pub fn translate(
+        &self,
+        source: &Model,
+        target: &Model,
+        word: &str,
+        k: usize,
+    ) -> Vec<(String, f32)> {
+        vectors::nearest(target, &self.apply(&source.word_vector(word)), k, &[])
+    }
+}
+
+/// Read a bilingual dictionary in the MUSE format: one `source target`
+/// pair per line, separated by whitespace. Blank lines are skipped.
+pub fn read_dictionary<R: BufRead>(reader: R) -> Result<Vec<(String, String)>> {
+    let mut pairs = Vec::new();
+    for (number, line) in reader.lines().enumerate() {
+        let line = line?;
+        let mut words = line.split_whitespace();
+        match (words.next(), words.next(), words.next()) {
+            (None, _, _) => continue,
+            (Some(from), Some(to), None) => pairs.push((from.to_string(), to.to_string())),
+            _ => {
+                return Err(RustTextError::InvalidArgument(format!(
+                    "dictionary line {} is not a word pair",
+                    number + 1
+                )))
+            }
+        }
+    }
+    Ok(pairs)
+}
+
+/// `read_dictionary` from the file at `path`.
+pub fn read_dictionary_file<P: AsRef<Path>>(path: P) -> Result<Vec<(String, String)>> {
+    read_dictionary(BufReader::new(File::open(path)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vectors::tests::fixed;
+
+    // Rotation by `angle` in the plane of the first two axes, then a swap
+    // of the last two, as a row-major 3 x 3 matrix.
+    fn rotation(angle: f32) -> [f32; 9] {
+        let (c, s) = (angle.cos(), angle.sin());
+        [c, s, 0.0, 0.0, 0.0, 1.0, -s, c, 0.0]
+    }
+
+    fn rotate(vectors: &[f32], w: &[f32; 9]) -> Vec<f32> {
+        vectors
+            .chunks(3)
+            .flat_map(|x| (0..3).map(move |j| (0..3).map(|i| x[i] * w[i * 3 + j]).sum::<f32>()))
+            .collect()
+    }
+
+    fn assert_orthogonal(rotation: &Matrix) {
+        for i in 0..3 {
+            for j in 0..3 {
+                let dot: f32 = (0..3)
+                    .map(|k| rotation.row(i)[k] * rotation.row(j)[k])
+                    .sum();
+                let expected = if i == j { 1.0 } else { 0.0 };
+                assert!((dot - expected).abs() < 1e-5);
+            }
+        }
+    }
+
+    #[test]
+    fn test_fit_recovers_rotation() {
+        let vectors = vec![
+            1.0, 0.2, -0.3, 0.1, 1.0, 0.5, -0.4, 0.3, 1.0, 0.7, -0.7, 0.1,
+        ];
+        let w = rotation(0.7);
+        let source = fixed(&["one", "two", "three", "four"], vectors.clone());
+        let target = fixed(&["uno", "dos", "tres", "cuatro"], rotate(&vectors, &w));
+        let dictionary = read_dictionary("one uno\n\ntwo dos\nthree tres\n".as_bytes()).unwrap();
+
+        let alignment = Alignment::fit(&source, &target, &dictionary).unwrap();
+        assert_orthogonal(alignment.rotation());
+        for (found, expected) in alignment.rotation().data().iter().zip(w.iter()) {
+            assert!((found - expected).abs() < 1e-4);
+        }
+        let translated = alignment.translate(&source, &target, "four", 1);
+        assert_eq!(translated[0].0, "cuatro");
+        assert!(translated[0].1 > 0.999);
+
+        let mut rotated = fixed(&["one", "two", "three", "four"], vectors);
+        alignment.apply_to(&mut rotated).unwrap();
+        for (x, y) in rotated
+            .word_vector("four")
+            .iter()
+            .zip(target.word_vector("cuatro"))
+        {
+            assert!((x - y).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn test_fit_underdetermined() {
+        let source = fixed(&["a", "b"], vec![1.0, 0.0, 0.0, 0.0, 1.0, 0.0]);
+        let target = fixed(&["x", "y"], vec![0.0, 0.0, 1.0, 1.0, 0.0, 0.0]);
+        let pairs = vec![(String::from("a"), String::from("x"))];
+
+        let alignment = Alignment::fit(&source, &target, &pairs).unwrap();
+        assert_orthogonal(alignment.rotation());
+        let mapped = alignment.apply(&[1.0, 0.0, 0.0]);
+        assert!((mapped[2] - 1.0).abs() < 1e-5);
+
+        let unknown = vec![(String::from("zzz"), String::from("x"))];
+        assert!(Alignment::fit(&source, &target, &unknown).is_err());
+        assert!(read_dictionary("a b c\n".as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,237 @@
+use rayon::prelude::*;
+
+use crate::matrix::Matrix;
+use crate::model::Model;
+use crate::random::Rng;
+use crate::vectors;
+use crate::{Result, RustTextError};
+
+/// Result of a k-means run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Clusters {
+    /// Cluster of each clustered row (word id, for `kmeans`).
+    pub assignments: Vec<usize>,
+    /// One row per cluster.
+    pub centroids: Matrix,
+    /// The word whose normalized vector is closest to each centroid; `None`
+    /// for a zero centroid, or if the model has no words.
+    pub nearest_words: Vec<Option<String>>,
+}
+
+impl Clusters {
+    /// Number of rows assigned to each cluster.
+    pub fn sizes(&self) -> Vec<usize> {
+        let mut sizes = vec![0; self.centroids.rows()];
+        for &cluster in self.assignments.iter() {
+            sizes[cluster] += 1;
+        }
+        sizes
+    }
+}
+
+fn distance(left: &[f32], right: &[f32]) -> f32 {
+    left.iter()
+        .zip(right.iter())
+        .map(|(l, r)| (l - r) * (l - r))
+        .sum()
+}
+
+fn nearest_centroid(centroids: &Matrix, row: &[f32]) -> (usize, f32) {
+    let mut best = (0, f32::INFINITY);
+    for i in 0..centroids.rows() {
+        let d = distance(row, centroids.row(i));
+        if d < best.1 {
+            best = (i, d);
+        }
+    }
+    best
+}
+
+// k-means++ seeding: each further centroid is a row drawn with probability
+// proportional to its squared distance from the nearest centroid so far.
+fn init_centroids(rows: &Matrix, k: usize, rng: &mut Rng) -> Matrix {
+    let mut centroids = Matrix::new(k, rows.cols());
+    let first = rng.below(rows.rows());
+    centroids.row_mut(0).copy_from_slice(rows.row(first));
+    let mut distances: Vec<f32> = (0..rows.rows())
+        .map(|i| distance(rows.row(i), centroids.row(0)))
+        .collect();
+
+    for c in 1..k {
+        let total: f32 = distances.iter().sum();
+        let mut chosen = rng.below(rows.rows());
+        if total > 0.0 {
+            let mut target = rng.next_f32() * total;
+            for (i, &d) in distances.iter().enumerate() {
+                if target < d {
+                    chosen = i;
+                    break;
+                }
+                target -= d;
+            }
+        }
+        centroids.row_mut(c).copy_from_slice(rows.row(chosen));
+        for (i, d) in distances.iter_mut().enumerate() {
+            *d = d.min(distance(rows.row(i), centroids.row(c)));
+        }
+    }
+    centroids
+}
+
+/// Lloyd's k-means over the rows of `rows` for at most `iters` rounds,
+/// stopping early once no assignment changes. Seeded by `seed`; clusters
+/// that empty out keep their previous centroid.
+pub fn kmeans_rows(
+    rows: &Matrix,
+    k: usize,
+    iters: usize,
+    seed: u64,
+) -> Result<(Vec<usize>, Matrix)> {
+    if k == 0 || k > rows.rows() {
+        return Err(RustTextError::InvalidArgument(format!(
+            "cannot make {} clusters from {} rows",
+            k,
+            rows.rows()
+        )));
+    }
+    let mut rng = Rng::new(seed);
+    let mut centroids = init_centroids(rows, k, &mut rng);
+    let mut assignments = vec![usize::MAX; rows.rows()];
+
+    for _ in 0..iters {
+        let next: Vec<usize> = (0..rows.rows())
+            .into_par_iter()
+            .map(|i| nearest_centroid(&centroids, rows.row(i)).0)
+            .collect();
+        if next == assignments {
+            break;
+        }
+        assignments = next;
+
+        let mut sums = Matrix::new(k, rows.cols());
+        let mut sizes = vec![0usize; k];
+        for (i, &cluster) in assignments.iter().enumerate() {
+            sums.add_row(rows.row(i), cluster, 1.0);
+            sizes[cluster] += 1;
+        }
+        for (cluster, &size) in sizes.iter().enumerate() {
+            if size > 0 {
+                sums.scale_row(cluster, 1.0 / size as f32);
+                centroids
+                    .row_mut(cluster)
+                    .copy_from_slice(sums.row(cluster));
+            }
+        }
+    }
+    if assignments.first() == Some(&usize::MAX) {
+        assignments = (0..rows.rows())
+            .map(|i| nearest_centroid(&centroids, rows.row(i)).0)
+            .collect();
+    }
+    Ok((assignments, centroids))
+}
+
+/// Cluster arbitrary vectors of the model's dimension, such as the rows of
+/// `Model::sentence_vectors`, labelling each cluster with the word nearest
+/// its centroid. Seeded by the model's `args.seed`.
+pub fn kmeans_vectors(model: &Model, vectors: &Matrix, k: usize, iters: usize) -> Result<Clusters> {
+    if vectors.cols() != model.args().dim {
+        return Err(RustTextError::DimensionMismatch {
+            expected: model.args().dim,
+            found: vectors.cols(),
+        });
+    }
+    let (assignments, centroids) = kmeans_rows(vectors, k, iters, model.args().seed)?;
+    let nearest_words = (0..centroids.rows())
+        .map(|i| {
+            vectors::nearest(model, centroids.row(i), 1, &[])
+                .into_iter()
+                .next()
+                .map(|(word, _)| word)
+        })
+        .collect();
+    Ok(Clusters {
+        assignments,
+        centroids,
+        nearest_words,
+    })
+}
+
+/// Cluster the model's words by their normalized vectors, for topic
+/// discovery. `assignments` is indexed by word id.
+pub fn kmeans(model: &Model, k: usize, iters: usize) -> Result<Clusters> {
+    kmeans_vectors(model, model.normalized_word_vectors(), k, iters)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kmeans_rows() {
+        #[rustfmt::skip]
+        let rows = Matrix::from_vec(6, 2, vec![
+            0.0, 0.1,
+            10.0, 10.0,
+            0.1, 0.0,
+            10.1, 9.9,
+            -0.1, 0.0,
+            9.9, 10.1,
+        ])
+        .unwrap();
+
+        let (assignments, centroids) = kmeans_rows(&rows, 2, 10, 3).unwrap();
+        assert_eq!(assignments[0], assignments[2]);
+        assert_eq!(assignments[0], assignments[4]);
+        assert_eq!(assignments[1], assignments[3]);
+        assert_ne!(assignments[0], assignments[1]);
+        let big = centroids.row(assignments[1]);
+        assert!((big[0] - 10.0).abs() < 1e-5 && (big[1] - 10.0).abs() < 1e-5);
+
+        assert_eq!(kmeans_rows(&rows, 2, 10, 3).unwrap().0, assignments);
+        assert!(kmeans_rows(&rows, 0, 10, 3).is_err());
+        assert!(kmeans_rows(&rows, 7, 10, 3).is_err());
+        // zero iterations still assigns every row to its nearest seed
+        assert_eq!(kmeans_rows(&rows, 6, 0, 3).unwrap().0.len(), 6);
+    }
+
+    #[test]
+    fn test_kmeans_words() {
+        let model = crate::vectors::tests::fixed(
+            &["cat", "dog", "apple", "pear", "mouse"],
+            vec![1.0, 0.1, 0.9, 0.0, 0.0, 1.0, 0.1, 0.9, 0.8, 0.2],
+        );
+        let clusters = kmeans(&model, 2, 20).unwrap();
+        let id = |word: &str| model.vocabulary().get_id(word).unwrap() as usize;
+        let cluster = |word: &str| clusters.assignments[id(word)];
+        assert_eq!(cluster("cat"), cluster("dog"));
+        assert_eq!(cluster("cat"), cluster("mouse"));
+        assert_eq!(cluster("apple"), cluster("pear"));
+        assert_ne!(cluster("cat"), cluster("apple"));
+        assert_eq!(clusters.nearest_words.len(), 2);
+        assert_eq!(clusters.sizes()[cluster("apple")], 2);
+        let fruit = clusters.nearest_words[cluster("apple")].as_deref();
+        assert!(fruit == Some("apple") || fruit == Some("pear"));
+
+        // a zero centroid has no nearest word but keeps its place
+        #[rustfmt::skip]
+        let degenerate = Matrix::from_vec(4, 2, vec![
+            0.0, 0.0,
+            1.0, 0.1,
+            0.0, 0.0,
+            0.9, 0.0,
+        ])
+        .unwrap();
+        let clusters = kmeans_vectors(&model, &degenerate, 2, 5).unwrap();
+        let (zero, cat) = (clusters.assignments[0], clusters.assignments[1]);
+        assert_ne!(zero, cat);
+        assert_eq!(clusters.nearest_words[zero], None);
+        assert_eq!(clusters.nearest_words[cat].as_deref(), Some("cat"));
+
+        let wrong = Matrix::new(3, 4);
+        assert!(matches!(
+            kmeans_vectors(&model, &wrong, 2, 5),
+            Err(RustTextError::DimensionMismatch { .. })
+        ));
+    }
+}
@@ -0,0 +1,456 @@
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashSet};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::matrix::Matrix;
+use crate::random::Rng;
+use crate::{Result, RustTextError};
+
+const MAGIC: &[u8; 4] = b"RTXH";
+/// Index format version written by `write_index`. Version 2 added the
+/// vector dimension; version 1 indexes must be rebuilt.
+pub const VERSION: u32 = 2;
+
+/// Settings of an `HnswIndex`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct HnswArgs {
+    /// Links per node on the upper layers; the bottom layer keeps twice as
+    /// many. More links give better recall for more memory.
+    pub m: usize,
+    /// Candidates considered when linking a new node; higher builds a
+    /// better graph, more slowly.
+    pub ef_construction: usize,
+    /// Candidates considered per query (at least `k`); the recall/speed
+    /// trade-off at search time.
+    pub ef_search: usize,
+    /// Seed for the random layer assignment.
+    pub seed: u64,
+}
+
+impl Default for HnswArgs {
+    fn default() -> HnswArgs {
+        HnswArgs {
+            m: 16,
+            ef_construction: 100,
+            ef_search: 64,
+            seed: 0,
+        }
+    }
+}
+
+// A node with its similarity to the query, ordered by similarity, then
+// lower rows first.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Scored(pub(crate) f32, pub(crate) u32);
+
+impl PartialEq for Scored {
+    fn eq(&self, other: &Scored) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Scored {}
+
+impl PartialOrd for Scored {
+    fn partial_cmp(&self, other: &Scored) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Scored {
+    fn cmp(&self, other: &Scored) -> Ordering {
+        self.0.total_cmp(&other.0).then(other.1.cmp(&self.1))
+    }
+}
+
+/// A hierarchical navigable small world graph (Malkov and Yashunin, 2016)
+/// over the rows of a matrix of unit vectors, for approximate
+/// maximum-cosine search in roughly logarithmic time. The index holds only
+/// the graph: the vectors it was built from are passed to every search.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HnswIndex {
+    args: HnswArgs,
+    /// Dimension of the vectors the index was built from.
+    dim: usize,
+    /// Neighbours of every node on each of its layers, bottom layer first.
+    links: Vec<Vec<Vec<u32>>>,
+    entry: u32,
+}
+
+fn similarity(vectors: &Matrix, query: &[f32], node: u32) -> f32 {
+    vectors.dot_row(query, node as usize)
+}
+
+impl HnswIndex {
+    /// Build the index over every row of `vectors`, which should be
+    /// normalized so that dot products are cosine similarities.
+    pub fn build(vectors: &Matrix, args: &HnswArgs) -> Result<HnswIndex> {
+        if args.m < 2 || args.ef_construction == 0 {
+            return Err(RustTextError::InvalidArgument(format!(
+                "HNSW needs m of at least 2 and a positive ef_construction, got {} and {}",
+                args.m, args.ef_construction
+            )));
+        }
+        let mut index = HnswIndex {
+            args: *args,
+            dim: vectors.cols(),
+            links: Vec::with_capacity(vectors.rows()),
+            entry: 0,
+        };
+        let mut rng = Rng::new(args.seed);
+        let scale = 1.0 / (args.m as f64).ln();
+        for node in 0..vectors.rows() as u32 {
+            // 1 - u is in (0, 1], keeping the logarithm finite
+            let u = 1.0 - f64::from(rng.next_f32());
+            let level = (-u.ln() * scale) as usize;
+            index.insert(vectors, node, level);
+        }
+        Ok(index)
+    }
+
+    pub fn args(&self) -> &HnswArgs {
+        &self.args
+    }
+
+    pub fn dim(&self) -> usize {
+        self.dim
+    }
+
+    /// Number of indexed vectors.
+    pub fn len(&self) -> usize {
+        self.links.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.links.is_empty()
+    }
+
+    // Check that a deserialized graph can be searched: every node is on the
+    // bottom layer, the entry point is on the top one, and every link is to
+    // a node on the link's layer.
+    fn validate(&self) -> Result<()> {
+        let format_error = |message: &str| Err(RustTextError::ModelFormat(String::from(message)));
+        if self.is_empty() {
+            return Ok(());
+        }
+        let layers = |node: u32| self.links.get(node as usize).map_or(0, Vec::len);
+        let top = layers(self.entry);
+        if top == 0 {
+            return format_error("HNSW entry point is not an indexed node");
+        }
+        for node in self.links.iter() {
+            if node.is_empty() || node.len() > top {
+                return format_error("HNSW node is outside the entry point's layers");
+            }
+            for (layer, links) in node.iter().enumerate() {
+                if links.iter().any(|&other| layers(other) <= layer) {
+                    return format_error("HNSW link is to a node missing from its layer");
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn top(&self) -> usize {
+        self.links[self.entry as usize].len() - 1
+    }
+
+    fn max_links(&self, layer: usize) -> usize {
+        if layer == 0 {
+            2 * self.args.m
+        } else {
+            self.args.m
+        }
+    }
+
+    fn insert(&mut self, vectors: &Matrix, node: u32, level: usize) {
+        self.links.push(vec![Vec::new(); level + 1]);
+        if node == 0 {
+            return;
+        }
+        let query = vectors.row(node as usize);
+        let top = self.top();
+        let mut entries = vec![self.entry];
+        for layer in (level + 1..=top).rev() {
+            entries = self.greedy(vectors, query, entries[0], layer);
+        }
+        for layer in (0..=usize::min(level, top)).rev() {
+            let found =
+                self.search_layer(vectors, query, &entries, self.args.ef_construction, layer);
+            let max_links = self.max_links(layer);
+            let neighbours: Vec<u32> = found.iter().take(self.args.m).map(|s| s.1).collect();
+            for &neighbour in neighbours.iter() {
+                let links = &mut self.links[neighbour as usize][layer];
+                links.push(node);
+                if links.len() > max_links {
+                    let row = vectors.row(neighbour as usize);
+                    let mut scored: Vec<Scored> = links
+                        .iter()
+                        .map(|&other| Scored(similarity(vectors, row, other), other))
+                        .collect();
+                    scored.sort_unstable_by(|left, right| right.cmp(left));
+                    *links = scored.iter().take(max_links).map(|s| s.1).collect();
+                }
+            }
+            self.links[node as usize][layer] = neighbours;
+            entries = found.iter().map(|s| s.1).collect();
+        }
+        if level > top {
+            self.entry = node;
+        }
+    }
+
+    // Walk `layer` from `start` to the node most similar to `query`.
+    fn greedy(&self, vectors: &Matrix, query: &[f32], start: u32, layer: usize) -> Vec<u32> {
+        let mut best = Scored(similarity(vectors, query, start), start);
+        loop {
+            let current = best;
+            for &neighbour in self.links[current.1 as usize][layer].iter() {
+                let scored = Scored(similarity(vectors, query, neighbour), neighbour);
+                if scored > best {
+                    best = scored;
+                }
+            }
+            if best.1 == current.1 {
+                return vec![best.1];
+            }
+        }
+    }
+
+    // The `ef` nodes of `layer` most similar to `query` reachable from
+    // `entries`, best first.
+    fn search_layer(
+        &self,
+        vectors: &Matrix,
+        query: &[f32],
+        entries: &[u32],
+        ef: usize,
+        layer: usize,
+    ) -> Vec<Scored> {
+        let mut visited: HashSet<u32> = entries.iter().copied().collect();
+        let mut candidates = BinaryHeap::new();
+        let mut found = BinaryHeap::new();
+        for &entry in entries.iter() {
+            let scored = Scored(similarity(vectors, query, entry), entry);
+            candidates.push(scored);
+            found.push(Reverse(scored));
+        }
+        while found.len() > ef {
+            found.pop();
+        }
+        while let Some(candidate) = candidates.pop() {
+            if found.len() >= ef && candidate < found.peek().unwrap().0 {
+                break;
+            }
+            for &neighbour in self.links[candidate.1 as usize][layer].iter() {
+                if !visited.insert(neighbour) {
+                    continue;
+                }
+                let scored = Scored(similarity(vectors, query, neighbour), neighbour);
+                if found.len() < ef || scored > found.peek().unwrap().0 {
+                    candidates.push(scored);
+                    found.push(Reverse(scored));
+                    if found.len() > ef {
+                        found.pop();
+                    }
+                }
+            }
+        }
+        let mut found: Vec<Scored> = found.into_iter().map(|scored| scored.0).collect();
+        found.sort_unstable_by(|left, right| right.cmp(left));
+        found
+    }
+
+    /// The (approximately) `k` rows of `vectors` with the largest dot
+    /// product with `query`, as (row, similarity) pairs, best first.
+    /// `vectors` must be the matrix the index was built from.
+    pub fn search(&self, vectors: &Matrix, query: &[f32], k: usize) -> Vec<(usize, f32)> {
+        if self.is_empty() || k == 0 {
+            return Vec::new();
+        }
+        let mut entries = vec![self.entry];
+        for layer in (1..=self.top()).rev() {
+            entries = self.greedy(vectors, query, entries[0], layer);
+        }
+        let ef = usize::max(self.args.ef_search, k);
+        self.search_layer(vectors, query, &entries, ef, 0)
+            .into_iter()
+            .take(k)
+            .map(|scored| (scored.1 as usize, scored.0))
+            .collect()
+    }
+}
+
+/// Write `index` as a magic number and `VERSION` followed by its bincode
+/// encoding.
+pub fn write_index<W: Write>(writer: W, index: &HnswIndex) -> Result<()> {
+    let mut writer = BufWriter::new(writer);
+    writer.write_all(MAGIC)?;
+    writer.write_all(&VERSION.to_le_bytes())?;
+    bincode::serialize_into(&mut writer, index)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Read an index written by `write_index`.
+pub fn read_index<R: Read>(mut reader: R) -> Result<HnswIndex> {
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(RustTextError::ModelFormat(String::from(
+            "not a rusttext HNSW index",
+        )));
+    }
+    let mut version = [0u8; 4];
+    reader.read_exact(&mut version)?;
+    let version = u32::from_le_bytes(version);
+    if version > VERSION {
+        return Err(RustTextError::ModelFormat(format!(
+            "HNSW index version {} is newer than the supported {}",
+            version, VERSION
+        )));
+    }
+    if version < VERSION {
+        return Err(RustTextError::ModelFormat(format!(
+            "HNSW index version {} has no dimension; rebuild it",
+            version
+        )));
+    }
+    let index: HnswIndex = bincode::deserialize_from(reader)?;
+    index.validate()?;
+    Ok(index)
+}
+
+/// Where the index of the model saved at `path` is kept: alongside it,
+/// with a `.hnsw` extension.
+pub fn index_path<P: AsRef<Path>>(path: P) -> PathBuf {
+    path.as_ref().with_extension("hnsw")
+}
+
+/// `write_index` to a new file at `path`.
+pub fn save<P: AsRef<Path>>(index: &HnswIndex, path: P) -> Result<()> {
+    write_index(File::create(path)?, index)
+}
+
+/// `read_index` from the file at `path`.
+pub fn load<P: AsRef<Path>>(path: P) -> Result<HnswIndex> {
+    read_index(BufReader::new(File::open(path)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Random unit vectors.
+    fn points(n: usize, dim: usize) -> Matrix {
+        let mut rng = Rng::new(3);
+        let mut matrix = Matrix::new(n, dim);
+        for i in 0..n {
+            let row = matrix.row_mut(i);
+            for value in row.iter_mut() {
+                *value = rng.uniform(-1.0, 1.0);
+            }
+            let norm = row.iter().map(|x| x * x).sum::<f32>().sqrt();
+            row.iter_mut().for_each(|x| *x /= norm);
+        }
+        matrix
+    }
+
+    fn exact(vectors: &Matrix, query: &[f32], k: usize) -> Vec<usize> {
+        let mut scores: Vec<(usize, f32)> = (0..vectors.rows())
+            .map(|i| (i, vectors.dot_row(query, i)))
+            .collect();
+        scores.sort_by(|left, right| right.1.total_cmp(&left.1));
+        scores.into_iter().take(k).map(|(i, _)| i).collect()
+    }
+
+    #[test]
+    fn test_recall() {
+        let vectors = points(2000, 8);
+        let index = HnswIndex::build(&vectors, &HnswArgs::default()).unwrap();
+        assert_eq!(index.len(), 2000);
+
+        let mut hits = 0;
+        for q in 0..50 {
+            let query = vectors.row(q * 37);
+            let found = index.search(&vectors, query, 10);
+            assert_eq!(found[0], (q * 37, vectors.dot_row(query, q * 37)));
+            assert!(found.windows(2).all(|pair| pair[0].1 >= pair[1].1));
+            let expected = exact(&vectors, query, 10);
+            hits += found.iter().filter(|(i, _)| expected.contains(i)).count();
+        }
+        assert!(hits >= 475, "recall {} of 500", hits);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let vectors = points(100, 4);
+        let args = HnswArgs {
+            m: 4,
+            ..HnswArgs::default()
+        };
+        let index = HnswIndex::build(&vectors, &args).unwrap();
+        let mut bytes = Vec::new();
+        write_index(&mut bytes, &index).unwrap();
+        assert_eq!(read_index(&bytes[..]).unwrap(), index);
+
+        let mut broken = index.clone();
+        broken.links[7][0].push(100);
+        let mut broken_bytes = Vec::new();
+        write_index(&mut broken_bytes, &broken).unwrap();
+        broken.links[7][0].pop();
+        broken.entry = 100;
+        let mut entry_bytes = Vec::new();
+        write_index(&mut entry_bytes, &broken).unwrap();
+        for bytes in [&broken_bytes, &entry_bytes].iter() {
+            assert!(matches!(
+                read_index(&bytes[..]),
+                Err(RustTextError::ModelFormat(_))
+            ));
+        }
+
+        bytes[0] = b'X';
+        assert!(read_index(&bytes[..]).is_err());
+        assert!(HnswIndex::build(&vectors, &HnswArgs { m: 1, ..args }).is_err());
+        assert!(HnswIndex::build(&Matrix::new(0, 4), &args)
+            .unwrap()
+            .search(&vectors, vectors.row(0), 3)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_model_index() {
+        let words = ["a", "b", "c", "d", "e"];
+        let vectors = vec![1.0, 0.0, 0.9, 0.1, 0.0, 1.0, 0.1, 0.9, -1.0, 0.0];
+        let mut model = crate::vectors::tests::fixed(&words, vectors);
+        let exact = model.nearest_neighbors("a", 2);
+        assert_eq!(exact[0].0, "b");
+
+        model.build_index(&HnswArgs::default()).unwrap();
+        assert_eq!(model.nearest_neighbors("a", 2), exact);
+        let path = index_path(
+            std::env::temp_dir().join(format!("rusttext-ann-{}.bin", std::process::id())),
+        );
+        model.save_index(&path).unwrap();
+        let index = model.index().cloned();
+        model.set_index(None).unwrap();
+        model.load_index(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(model.index().cloned(), index);
+
+        assert!(model
+            .set_index(Some(
+                HnswIndex::build(&Matrix::new(1, 2), &HnswArgs::default()).unwrap()
+            ))
+            .is_err());
+        assert!(model
+            .set_index(Some(
+                HnswIndex::build(&Matrix::new(5, 3), &HnswArgs::default()).unwrap()
+            ))
+            .is_err());
+    }
+}
@@ -0,0 +1,554 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::loader::CorpusStats;
+use crate::vocabulary::{Vocabulary, MAX_VOCAB_SIZE};
+use crate::word::{self, EntryType};
+use crate::{Result, RustTextError};
+
+/// Which output layer objective to train with; see `loss`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LossKind {
+    #[serde(alias = "ns")]
+    NegativeSampling,
+    #[serde(alias = "softmax")]
+    Softmax,
+    /// Independent binary classifiers per label, for multi-label data.
+    #[serde(alias = "ova")]
+    OneVsAll,
+    /// Binary decisions along a Huffman tree of the outputs; much faster
+    /// than a softmax with many labels.
+    #[serde(alias = "hs")]
+    HierarchicalSoftmax,
+}
+
+/// How unsupervised training learns word vectors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WordModel {
+    /// Each word predicts the words around it.
+    #[serde(alias = "skipgram")]
+    Skipgram,
+    /// The mean of the words around each word predicts it (continuous
+    /// bag of words); faster than skipgram, a little weaker on rare words.
+    #[serde(alias = "cbow")]
+    Cbow,
+}
+
+/// How the learning rate moves from `Args::lr` towards zero over training.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LrSchedule {
+    /// Linear decay to zero, as in fastText.
+    Linear,
+    Constant,
+    /// Half a cosine period from `lr` down to zero.
+    Cosine,
+}
+
+impl LrSchedule {
+    /// The learning rate at `progress`, the fraction of training tokens
+    /// processed so far.
+    pub fn lr(&self, base: f32, progress: f32) -> f32 {
+        let progress = progress.clamp(0.0, 1.0);
+        match self {
+            LrSchedule::Linear => base * (1.0 - progress),
+            LrSchedule::Constant => base,
+            LrSchedule::Cosine => base * 0.5 * (1.0 + (std::f32::consts::PI * progress).cos()),
+        }
+    }
+}
+
+/// Per-label weights that scale each supervised example's update by the
+/// weight of the label it is trained towards.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ClassWeights {
+    Uniform,
+    /// Inverse label frequency, `n_examples / (n_labels * count)`, so every
+    /// label carries the same total weight.
+    Auto,
+    /// Weights keyed by full label (prefix included); other labels get 1.
+    Custom(HashMap<String, f32>),
+}
+
+impl ClassWeights {
+    /// One weight per label, in the vocabulary's label order.
+    pub fn resolve(&self, vocab: &Vocabulary) -> Result<Vec<f32>> {
+        let labels: Vec<(&str, u32)> = vocab
+            .words()
+            .iter()
+            .filter(|entry| entry.entry_type == EntryType::Label)
+            .map(|entry| (entry.word.as_str(), entry.count))
+            .collect();
+
+        match self {
+            ClassWeights::Uniform => Ok(vec![1.0; labels.len()]),
+            ClassWeights::Auto => {
+                let total: f32 = labels.iter().map(|(_, count)| *count as f32).sum();
+                Ok(labels
+                    .iter()
+                    .map(|(_, count)| total / (labels.len() as f32 * *count as f32))
+                    .collect())
+            }
+            ClassWeights::Custom(weights) => {
+                for label in weights.keys() {
+                    if !labels.iter().any(|(name, _)| name == label) {
+                        return Err(RustTextError::UnknownLabel(label.clone()));
+                    }
+                }
+                Ok(labels
+                    .iter()
+                    .map(|(name, _)| weights.get(*name).copied().unwrap_or(1.0))
+                    .collect())
+            }
+        }
+    }
+}
+
+/// Training hyperparameters. Defaults follow fastText's unsupervised
+/// settings.
+///
+/// Args can be kept in JSON or TOML files (`Args::load`, `Args::save`).
+/// Settings missing from a file keep their defaults, and fastText's flag
+/// names (`minCount`, `wordNgrams`, `minn`, ...) are accepted alongside the
+/// field names.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Args {
+    pub dim: usize,
+    pub ws: usize,
+    pub epoch: u32,
+    pub lr: f32,
+    #[serde(alias = "minCount")]
+    pub min_count: u32,
+    #[serde(alias = "minCountLabel")]
+    pub min_count_label: u32,
+    pub neg: usize,
+    #[serde(alias = "wordNgrams")]
+    pub word_ngrams: usize,
+    #[serde(alias = "minn")]
+    pub min_n: usize,
+    #[serde(alias = "maxn")]
+    pub max_n: usize,
+    pub bucket: u32,
+    #[serde(alias = "label")]
+    pub label_prefix: String,
+    /// Sub-sampling threshold for frequent words.
+    pub t: f64,
+    /// Slots in the vocabulary lookup table; see `Vocabulary::new`.
+    pub vocab_size: usize,
+    pub loss: LossKind,
+    /// Objective of unsupervised training; classifiers ignore it.
+    pub word_model: WordModel,
+    /// Training threads, each updating the shared matrices lock-free.
+    #[serde(alias = "thread")]
+    pub threads: usize,
+    pub lr_schedule: LrSchedule,
+    /// Tokens each thread processes between refreshes of its learning rate
+    /// from the shared progress counter.
+    #[serde(alias = "lrUpdateRate")]
+    pub lr_update_rate: u64,
+    /// Seeds every random draw in training: matrix initialisation, negative
+    /// and frequent-word sampling, window sizes and label choice. With
+    /// `threads: 1` training is fully deterministic for a given seed.
+    pub seed: u64,
+    /// Where to write checkpoints during training; see `Model::resume`.
+    pub checkpoint: Option<PathBuf>,
+    /// Tokens processed between checkpoints.
+    pub checkpoint_interval: u64,
+    /// Labelled file scored after every epoch of supervised training; the
+    /// best-scoring epoch's model is kept.
+    pub validation_file: Option<PathBuf>,
+    /// Epochs without a better validation score before training stops
+    /// early; 0 never stops early.
+    pub patience: u32,
+    /// Probability mass moved from the true labels to all labels in the
+    /// softmax and one-vs-all losses, in `[0, 1)`.
+    pub label_smoothing: f32,
+    pub class_weights: ClassWeights,
+    /// `.vec` file whose vectors initialise the input rows of matching
+    /// words; its dimension must equal `dim`.
+    #[serde(alias = "pretrainedVectors")]
+    pub pretrained_vectors: Option<PathBuf>,
+    /// Keep the input matrix fixed during supervised training, so only the
+    /// classifier (output matrix) is learned.
+    pub freeze_embeddings: bool,
+}
+
+impl Default for Args {
+    fn default() -> Args {
+        Args {
+            dim: 100,
+            ws: 5,
+            epoch: 5,
+            lr: 0.05,
+            min_count: 5,
+            min_count_label: 0,
+            neg: 5,
+            word_ngrams: 1,
+            min_n: 3,
+            max_n: 6,
+            bucket: 2_000_000,
+            label_prefix: String::from("__label__"),
+            t: 1e-4,
+            vocab_size: 30_000_000,
+            loss: LossKind::NegativeSampling,
+            word_model: WordModel::Skipgram,
+            threads: 12,
+            lr_schedule: LrSchedule::Linear,
+            lr_update_rate: 100,
+            seed: 0,
+            checkpoint: None,
+            checkpoint_interval: 10_000_000,
+            validation_file: None,
+            patience: 0,
+            label_smoothing: 0.0,
+            class_weights: ClassWeights::Uniform,
+            pretrained_vectors: None,
+            freeze_embeddings: false,
+        }
+    }
+}
+
+impl Args {
+    /// fastText's defaults for text classification: a softmax over labels,
+    /// no subwords, and a higher learning rate.
+    pub fn supervised() -> Args {
+        Args {
+            lr: 0.1,
+            min_count: 1,
+            min_n: 0,
+            max_n: 0,
+            loss: LossKind::Softmax,
+            ..Args::default()
+        }
+    }
+
+    /// Pick dim/epoch/lr/min_count from the shape of the corpus.
+    ///
+    /// Labelled corpora get fastText's supervised settings (no subwords,
+    /// higher learning rate), scaled so that small datasets see more epochs
+    /// and smaller embeddings. Unlabelled corpora keep the skipgram defaults
+    /// but lower `min_count` and raise `epoch` when there is too little text
+    /// for rare words to accumulate useful counts.
+    pub fn auto_defaults(stats: &CorpusStats) -> Args {
+        let mut args = Args::default();
+
+        if stats.is_supervised() {
+            args = Args::supervised();
+            args.dim = match stats.n_lines {
+                n if n < 10_000 => 10,
+                n if n < 1_000_000 => 50,
+                _ => 100,
+            };
+            args.epoch = match stats.n_lines {
+                n if n < 10_000 => 25,
+                n if n < 100_000 => 10,
+                _ => 5,
+            };
+            // short documents give few updates per example; push harder
+            if stats.avg_line_length() < 20.0 {
+                args.lr = 0.5;
+            }
+            // many classes spread the gradient thin over the output matrix
+            if stats.n_labels > 100 && args.dim < 50 {
+                args.dim = 50;
+            }
+        } else {
+            args.min_count = match stats.n_tokens {
+                n if n < 1_000_000 => 1,
+                n if n < 100_000_000 => 3,
+                _ => 5,
+            };
+            args.epoch = match stats.n_tokens {
+                n if n < 1_000_000 => 20,
+                n if n < 10_000_000 => 10,
+                _ => 5,
+            };
+            args.dim = match stats.n_tokens {
+                n if n < 1_000_000 => 50,
+                n if n < 1_000_000_000 => 100,
+                _ => 300,
+            };
+        }
+
+        args
+    }
+
+    /// Check every setting against its valid range, naming the first one
+    /// out of range. Training calls this before reading the corpus.
+    pub fn validate(&self) -> Result<()> {
+        let invalid = |message: String| Err(RustTextError::InvalidArgument(message));
+        let positive = [
+            ("dim", self.dim as u64),
+            ("ws", self.ws as u64),
+            ("epoch", u64::from(self.epoch)),
+            ("word_ngrams", self.word_ngrams as u64),
+            ("lr_update_rate", self.lr_update_rate),
+            ("checkpoint_interval", self.checkpoint_interval),
+        ];
+        for (name, value) in positive.iter() {
+            if *value == 0 {
+                return invalid(format!("{} must be positive, got 0", name));
+            }
+        }
+        if !(self.lr.is_finite() && self.lr >= 0.0) {
+            return invalid(format!("lr must be non-negative, got {}", self.lr));
+        }
+        if self.loss == LossKind::NegativeSampling && self.neg == 0 {
+            return invalid(String::from(
+                "neg must be positive with negative sampling, got 0",
+            ));
+        }
+        if !(self.t.is_finite() && self.t > 0.0) {
+            return invalid(format!("t must be positive, got {}", self.t));
+        }
+        if self.label_prefix.is_empty() {
+            return invalid(String::from("label_prefix must not be empty"));
+        }
+        if !(0.0..1.0).contains(&self.label_smoothing) {
+            return invalid(format!(
+                "label_smoothing must be in [0, 1), got {}",
+                self.label_smoothing
+            ));
+        }
+        if let ClassWeights::Custom(weights) = &self.class_weights {
+            for (label, weight) in weights {
+                if !(weight.is_finite() && *weight >= 0.0) {
+                    return invalid(format!(
+                        "class weight of {} must be non-negative, got {}",
+                        label, weight
+                    ));
+                }
+            }
+        }
+        if self.vocab_size == 0 || self.vocab_size > MAX_VOCAB_SIZE {
+            return invalid(format!(
+                "vocab_size must be between 1 and {}, got {}",
+                MAX_VOCAB_SIZE, self.vocab_size
+            ));
+        }
+        word::validate_subword_params(self.min_n, self.max_n, self.bucket)
+    }
+
+    pub fn from_json(json: &str) -> Result<Args> {
+        serde_json::from_str(json).map_err(|err| RustTextError::InvalidArgument(err.to_string()))
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string_pretty(self)
+            .map_err(|err| RustTextError::InvalidArgument(err.to_string()))
+    }
+
+    pub fn from_toml(toml: &str) -> Result<Args> {
+        toml::from_str(toml).map_err(|err| RustTextError::InvalidArgument(err.to_string()))
+    }
+
+    pub fn to_toml(&self) -> Result<String> {
+        toml::to_string(self).map_err(|err| RustTextError::InvalidArgument(err.to_string()))
+    }
+
+    /// Read a `.json` or `.toml` file, chosen by extension. The result is
+    /// not validated; see `validate`.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Args> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)?;
+        let args = match Format::of(path)? {
+            Format::Json => Args::from_json(&text),
+            Format::Toml => Args::from_toml(&text),
+        };
+        args.map_err(|err| match err {
+            RustTextError::InvalidArgument(message) => {
+                RustTextError::InvalidArgument(format!("{}: {}", path.display(), message))
+            }
+            err => err,
+        })
+    }
+
+    /// Write a `.json` or `.toml` file, chosen by extension.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let text = match Format::of(path)? {
+            Format::Json => self.to_json()?,
+            Format::Toml => self.to_toml()?,
+        };
+        fs::write(path, text)?;
+        Ok(())
+    }
+}
+
+enum Format {
+    Json,
+    Toml,
+}
+
+impl Format {
+    fn of(path: &Path) -> Result<Format> {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => Ok(Format::Json),
+            Some("toml") => Ok(Format::Toml),
+            _ => Err(RustTextError::InvalidArgument(format!(
+                "{}: expected a .json or .toml file",
+                path.display()
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_auto_defaults_small_supervised() {
+        let stats = CorpusStats {
+            n_lines: 5_000,
+            n_tokens: 50_000,
+            n_unique_words: 8_000,
+            n_labels: 4,
+        };
+        let args = Args::auto_defaults(&stats);
+
+        assert_eq!(args.dim, 10);
+        assert_eq!(args.epoch, 25);
+        assert_eq!(args.lr, 0.5);
+        assert_eq!(args.min_count, 1);
+        assert_eq!((args.min_n, args.max_n), (0, 0));
+        assert_eq!(args.loss, LossKind::Softmax);
+    }
+
+    #[test]
+    fn test_auto_defaults_large_unsupervised() {
+        let stats = CorpusStats {
+            n_lines: 10_000_000,
+            n_tokens: 500_000_000,
+            n_unique_words: 4_000_000,
+            n_labels: 0,
+        };
+        let args = Args::auto_defaults(&stats);
+
+        assert_eq!(args.dim, 100);
+        assert_eq!(args.epoch, 5);
+        assert_eq!(args.min_count, 5);
+        assert_eq!(args.lr, Args::default().lr);
+    }
+
+    #[test]
+    fn test_lr_schedule() {
+        assert_eq!(LrSchedule::Linear.lr(0.1, 0.25), 0.075);
+        assert_eq!(LrSchedule::Linear.lr(0.1, 1.5), 0.0);
+        assert_eq!(LrSchedule::Constant.lr(0.1, 0.9), 0.1);
+        assert_eq!(LrSchedule::Cosine.lr(0.1, 0.0), 0.1);
+        assert!((LrSchedule::Cosine.lr(0.1, 0.5) - 0.05).abs() < 1e-6);
+        assert!(LrSchedule::Cosine.lr(0.1, 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_class_weights() {
+        let mut vocab = Vocabulary::new(101, 0, 0, 1).unwrap();
+        for token in ["__label__a", "__label__a", "__label__a", "__label__b"].iter() {
+            vocab.add(token).unwrap();
+        }
+
+        assert_eq!(
+            ClassWeights::Uniform.resolve(&vocab).unwrap(),
+            vec![1.0, 1.0]
+        );
+        let auto = ClassWeights::Auto.resolve(&vocab).unwrap();
+        assert!((auto[0] - 4.0 / 6.0).abs() < 1e-6 && (auto[1] - 2.0).abs() < 1e-6);
+
+        let mut custom = HashMap::new();
+        custom.insert(String::from("__label__b"), 5.0);
+        assert_eq!(
+            ClassWeights::Custom(custom.clone())
+                .resolve(&vocab)
+                .unwrap(),
+            vec![1.0, 5.0]
+        );
+        custom.insert(String::from("__label__c"), 1.0);
+        assert!(ClassWeights::Custom(custom).resolve(&vocab).is_err());
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(Args::default().validate().is_ok());
+        assert!(Args::supervised().validate().is_ok());
+
+        let message = |args: Args| match args.validate() {
+            Err(RustTextError::InvalidArgument(message)) => message,
+            other => panic!("{:?}", other),
+        };
+        assert_eq!(
+            message(Args {
+                dim: 0,
+                ..Args::default()
+            }),
+            "dim must be positive, got 0"
+        );
+        assert_eq!(
+            message(Args {
+                lr: -0.1,
+                ..Args::default()
+            }),
+            "lr must be non-negative, got -0.1"
+        );
+        assert!(message(Args {
+            label_smoothing: 1.0,
+            ..Args::supervised()
+        })
+        .starts_with("label_smoothing"));
+        // softmax draws no negatives
+        assert!(Args {
+            neg: 0,
+            ..Args::supervised()
+        }
+        .validate()
+        .is_ok());
+        assert!(matches!(
+            Args {
+                min_n: 5,
+                max_n: 3,
+                ..Args::default()
+            }
+            .validate(),
+            Err(RustTextError::InvalidSubwordParams(_))
+        ));
+    }
+
+    #[test]
+    fn test_serde_round_trip() {
+        let mut weights = HashMap::new();
+        weights.insert(String::from("__label__a"), 2.0);
+        let args = Args {
+            dim: 16,
+            checkpoint: Some(PathBuf::from("ckpt.bin")),
+            class_weights: ClassWeights::Custom(weights),
+            ..Args::supervised()
+        };
+        assert_eq!(Args::from_json(&args.to_json().unwrap()).unwrap(), args);
+        assert_eq!(Args::from_toml(&args.to_toml().unwrap()).unwrap(), args);
+
+        let dir = std::env::temp_dir();
+        for name in ["rusttext_args.json", "rusttext_args.toml"].iter() {
+            let path = dir.join(name);
+            args.save(&path).unwrap();
+            assert_eq!(Args::load(&path).unwrap(), args);
+        }
+        assert!(Args::load(dir.join("rusttext_args.yaml")).is_err());
+    }
+
+    #[test]
+    fn test_partial_config() {
+        let args = Args::from_toml("dim = 50\nwordNgrams = 2\nminn = 2\nloss = \"hs\"\n").unwrap();
+        assert_eq!(args.dim, 50);
+        assert_eq!(args.word_ngrams, 2);
+        assert_eq!(args.min_n, 2);
+        assert_eq!(args.loss, LossKind::HierarchicalSoftmax);
+        assert_eq!(args.epoch, Args::default().epoch);
+
+        let args = Args::from_json(r#"{"epoch": 10, "thread": 4}"#).unwrap();
+        assert_eq!((args.epoch, args.threads), (10, 4));
+        assert!(Args::from_json(r#"{"dim": "big"}"#).is_err());
+    }
+}
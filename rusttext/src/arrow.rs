@@ -0,0 +1,195 @@
+//! Arrow record batches and Parquet files of a vocabulary and its vectors,
+//! for joining embeddings against feature tables in Spark, Polars and the
+//! like. Enabled by the `arrow` feature.
+
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+
+use ::arrow::array::{
+    ArrayRef, FixedSizeListArray, Float32Array, ListBuilder, StringArray, UInt32Array,
+    UInt32Builder,
+};
+use ::arrow::datatypes::{DataType, Field, Schema};
+use ::arrow::record_batch::RecordBatch;
+use parquet::arrow::ArrowWriter;
+
+use crate::io::ExportFilter;
+use crate::model::Model;
+use crate::vocabulary::Vocabulary;
+use crate::word::EntryType;
+use crate::Result;
+
+fn entry_type(entry_type: &EntryType) -> &'static str {
+    match entry_type {
+        EntryType::Word => "word",
+        EntryType::Label => "label",
+    }
+}
+
+/// The vocabulary as one row per entry, in id order: `id` (`UInt32`),
+/// `word` and `type` (`Utf8`, `word` or `label`), `count` (`UInt32`), and
+/// `subwords` (`List<UInt32>`), the input-matrix rows of the entry's
+/// character n-grams, empty for labels.
+pub fn vocabulary_batch(vocab: &Vocabulary) -> Result<RecordBatch> {
+    let words = vocab.words();
+    let mut subwords = ListBuilder::new(UInt32Builder::new());
+    let mut features = Vec::new();
+    for (id, entry) in words.iter().enumerate() {
+        features.clear();
+        if entry.entry_type == EntryType::Word {
+            vocab.word_features(id as u32, &mut features);
+        }
+        let own_row = usize::from(!vocab.char_only() && !features.is_empty());
+        subwords.values().append_slice(&features[own_row..]);
+        subwords.append(true);
+    }
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(UInt32Array::from_iter_values(0..words.len() as u32)),
+        Arc::new(StringArray::from_iter_values(
+            words.iter().map(|entry| entry.word.as_str()),
+        )),
+        Arc::new(StringArray::from_iter_values(
+            words.iter().map(|entry| entry_type(&entry.entry_type)),
+        )),
+        Arc::new(UInt32Array::from_iter_values(
+            words.iter().map(|entry| entry.count),
+        )),
+        Arc::new(subwords.finish()),
+    ];
+    Ok(RecordBatch::try_from_iter(
+        ["id", "word", "type", "count", "subwords"]
+            .iter()
+            .zip(columns),
+    )?)
+}
+
+/// Vectors of the entries `filter` selects, most frequent first, as `word`
+/// (`Utf8`) and `vector` (`FixedSizeList<Float32>` of the model
+/// dimension). Words get their `Model::word_vector`, a classifier's labels
+/// their output matrix row.
+pub fn vectors_batch(model: &Model, filter: &ExportFilter) -> Result<RecordBatch> {
+    let vocab = model.vocabulary();
+    let dim = model.args().dim;
+    let ids = filter.select(vocab);
+    let mut values = Vec::with_capacity(ids.len() * dim);
+    for &id in ids.iter() {
+        let entry = &vocab.words()[id as usize];
+        match entry.entry_type {
+            EntryType::Label if model.supervised => {
+                values.extend(model.output_row((id - vocab.n_words()) as usize))
+            }
+            _ => values.extend(model.word_vector(&entry.word)),
+        }
+    }
+    let item = Arc::new(Field::new("item", DataType::Float32, false));
+    let vectors = FixedSizeListArray::try_new(
+        Arc::clone(&item),
+        dim as i32,
+        Arc::new(Float32Array::from(values)),
+        None,
+    )?;
+    let schema = Schema::new(vec![
+        Field::new("word", DataType::Utf8, false),
+        Field::new("vector", DataType::FixedSizeList(item, dim as i32), false),
+    ]);
+    let words = ids
+        .iter()
+        .map(|&id| vocab.words()[id as usize].word.as_str());
+    Ok(RecordBatch::try_new(
+        Arc::new(schema),
+        vec![
+            Arc::new(StringArray::from_iter_values(words)),
+            Arc::new(vectors),
+        ],
+    )?)
+}
+
+/// Write `batch` as a Parquet file with the default writer settings.
+pub fn write_parquet<W: Write + Send>(writer: W, batch: &RecordBatch) -> Result<()> {
+    let mut writer = ArrowWriter::try_new(writer, batch.schema(), None)?;
+    writer.write(batch)?;
+    writer.close()?;
+    Ok(())
+}
+
+/// `vocabulary_batch` of the model's vocabulary as Parquet at `path`.
+pub fn save_vocabulary<P: AsRef<Path>>(model: &Model, path: P) -> Result<()> {
+    write_parquet(File::create(path)?, &vocabulary_batch(model.vocabulary())?)
+}
+
+/// `vectors_batch` as Parquet at `path`. Returns the number of vectors
+/// written.
+pub fn save_vectors<P: AsRef<Path>>(
+    model: &Model,
+    path: P,
+    filter: &ExportFilter,
+) -> Result<usize> {
+    let batch = vectors_batch(model, filter)?;
+    write_parquet(File::create(path)?, &batch)?;
+    Ok(batch.num_rows())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vectors::tests::fixed;
+    use ::arrow::array::{Array, AsArray};
+    use ::arrow::datatypes::{Float32Type, UInt32Type};
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    #[test]
+    fn test_vocabulary_batch() {
+        let mut vocab = Vocabulary::new(101, 2, 3, 50).unwrap();
+        for word in ["ab", "ab", "xyz", "__label__l"].iter() {
+            vocab.add(word).unwrap();
+        }
+        vocab.threshold(1, 1);
+        let batch = vocabulary_batch(&vocab).unwrap();
+
+        assert_eq!(batch.num_rows(), 3);
+        let words = batch.column(1).as_string::<i32>();
+        let types = batch.column(2).as_string::<i32>();
+        assert_eq!(words.value(0), "ab");
+        assert_eq!(types.value(2), "label");
+        assert_eq!(batch.column(3).as_primitive::<UInt32Type>().value(0), 2);
+
+        let subwords = batch.column(4).as_list::<i32>();
+        let mut expected = Vec::new();
+        vocab.word_features(1, &mut expected);
+        let rows = subwords.value(1);
+        assert_eq!(rows.as_primitive::<UInt32Type>().values(), &expected[1..]);
+        assert!(!rows.is_empty());
+        assert_eq!(subwords.value(2).len(), 0);
+    }
+
+    #[test]
+    fn test_save_vectors() {
+        let model = fixed(&["x", "y"], vec![1.0, -2.5, 0.25, 3.0]);
+        let path =
+            std::env::temp_dir().join(format!("rusttext-arrow-{}.parquet", std::process::id()));
+
+        assert_eq!(
+            save_vectors(&model, &path, &ExportFilter::default()).unwrap(),
+            2
+        );
+        let file = File::open(&path).unwrap();
+        let mut reader = ParquetRecordBatchReaderBuilder::try_new(file)
+            .unwrap()
+            .build()
+            .unwrap();
+        let batch = reader.next().unwrap().unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let words = batch.column(0).as_string::<i32>();
+        let vectors = batch.column(1).as_fixed_size_list();
+        for row in 0..batch.num_rows() {
+            let vector = vectors.value(row);
+            assert_eq!(
+                vector.as_primitive::<Float32Type>().values().to_vec(),
+                model.word_vector(words.value(row))
+            );
+        }
+    }
+}
@@ -0,0 +1,283 @@
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::args::Args;
+use crate::loss::State;
+use crate::model::Model;
+use crate::random::Rng;
+use crate::train;
+use crate::{Result, RustTextError};
+
+/// What an autotune search maximizes on the validation file.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AutotuneMetric {
+    /// Micro-averaged f1 over all labels.
+    F1,
+    /// f1 of a single label, given in full (prefix included).
+    LabelF1(String),
+}
+
+/// Settings for `Model::autotune`. The defaults follow fastText: five
+/// minutes, overall f1, one prediction per example.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Autotune {
+    /// Labelled file every candidate model is scored on.
+    pub validation_file: PathBuf,
+    /// No new trial starts once this much time has passed; the trial in
+    /// progress runs to completion.
+    pub duration: Duration,
+    pub metric: AutotuneMetric,
+    /// Labels predicted per validation example when scoring.
+    pub k: usize,
+    /// Stop after this many trials even if time remains.
+    pub max_trials: Option<usize>,
+}
+
+impl Autotune {
+    pub fn new<P: AsRef<Path>>(validation_file: P) -> Autotune {
+        Autotune {
+            validation_file: validation_file.as_ref().to_path_buf(),
+            duration: Duration::from_secs(300),
+            metric: AutotuneMetric::F1,
+            k: 1,
+            max_trials: None,
+        }
+    }
+}
+
+// Standard normal draw (Box-Muller).
+fn gauss(rng: &mut Rng) -> f64 {
+    let u1 = 1.0 - f64::from(rng.next_f32());
+    let u2 = f64::from(rng.next_f32());
+    (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()
+}
+
+// Move `value` by a normal step whose spread shrinks linearly from
+// `sigma.0` to `sigma.1` as the search progresses (`t` in `[0, 1]`). On a
+// log scale the step is in powers of two.
+fn perturb(
+    value: f64,
+    range: (f64, f64),
+    sigma: (f64, f64),
+    log_scale: bool,
+    t: f64,
+    rng: &mut Rng,
+) -> f64 {
+    let step = gauss(rng) * (sigma.0 - (sigma.0 - sigma.1) * t);
+    let value = if log_scale {
+        value * step.exp2()
+    } else {
+        value + step
+    };
+    value.clamp(range.0, range.1)
+}
+
+// A neighbour of `best` for the next trial, searching the same parameters
+// (and ranges) as fastText's autotune.
+fn sample_args(best: &Args, t: f64, rng: &mut Rng) -> Args {
+    let mut args = best.clone();
+    args.epoch = perturb(
+        f64::from(best.epoch),
+        (1.0, 100.0),
+        (2.8, 2.5),
+        true,
+        t,
+        rng,
+    )
+    .round() as u32;
+    args.lr = perturb(f64::from(best.lr), (0.01, 5.0), (1.9, 1.0), true, t, rng) as f32;
+    args.dim = perturb(best.dim as f64, (1.0, 1000.0), (1.4, 0.3), true, t, rng).round() as usize;
+    args.word_ngrams = perturb(
+        best.word_ngrams as f64,
+        (1.0, 5.0),
+        (4.3, 2.4),
+        false,
+        t,
+        rng,
+    )
+    .round() as usize;
+    args.min_n = perturb(best.min_n as f64, (0.0, 6.0), (1.5, 0.5), false, t, rng).round() as usize;
+    args.max_n = if args.min_n == 0 {
+        0
+    } else {
+        usize::min(args.min_n + rng.below(4), 6)
+    };
+    if args.word_ngrams > 1 || args.min_n > 0 {
+        let bucket = if best.bucket == 0 {
+            Args::default().bucket
+        } else {
+            best.bucket
+        };
+        args.bucket = perturb(
+            f64::from(bucket),
+            (10_000.0, 10_000_000.0),
+            (2.0, 1.5),
+            true,
+            t,
+            rng,
+        )
+        .round() as u32;
+    }
+    args
+}
+
+// Score `model` on the labelled file at `path`.
+fn score(model: &Model, path: &Path, metric: &AutotuneMetric, k: usize) -> Result<f32> {
+    let vocab = model.vocabulary();
+    let target = match metric {
+        AutotuneMetric::F1 => None,
+        AutotuneMetric::LabelF1(label) => match vocab.get_id(label) {
+            Some(id) if id >= vocab.n_words() => Some(id - vocab.n_words()),
+            _ => return Err(RustTextError::UnknownLabel(label.clone())),
+        },
+    };
+    let counts: Vec<u32> = vocab.words()[vocab.n_words() as usize..]
+        .iter()
+        .map(|entry| entry.count)
+        .collect();
+    let loss = train::build_loss(model.args(), &counts, &mut Rng::new(model.args().seed))?;
+    let mut state = State::new(model.input().cols(), model.output().rows());
+
+    let (mut true_positives, mut predicted, mut gold) = (0u64, 0u64, 0u64);
+    for (features, labels) in train::read_examples(vocab, path)?.iter() {
+        let top = train::top_k(
+            model.input(),
+            model.output(),
+            loss.as_ref(),
+            features,
+            k,
+            &mut state,
+        );
+        let counted = |id: &&u32| target.is_none_or(|target| **id == target);
+        predicted += top.iter().filter(counted).count() as u64;
+        gold += labels.iter().filter(counted).count() as u64;
+        true_positives += top
+            .iter()
+            .filter(counted)
+            .filter(|id| labels.contains(id))
+            .count() as u64;
+    }
+    if true_positives == 0 {
+        return Ok(0.0);
+    }
+    let precision = true_positives as f32 / predicted as f32;
+    let recall = true_positives as f32 / gold as f32;
+    Ok(2.0 * precision * recall / (precision + recall))
+}
+
+/// Random search around the best arguments found so far, starting from
+/// `args` and narrowing as the time budget runs out. Returns the model of
+/// the best-scoring trial, trained with the arguments in its `args()`.
+pub(crate) fn search(corpus: &Path, args: Args, autotune: &Autotune) -> Result<Model> {
+    if autotune.k == 0 {
+        return Err(RustTextError::InvalidArgument(String::from(
+            "autotune needs at least one prediction per example",
+        )));
+    }
+    let start = Instant::now();
+    let mut rng = Rng::new(args.seed);
+    let mut best: Option<(f32, Model)> = None;
+    let mut trials = 0;
+
+    let mut trial_args = args;
+    loop {
+        let model = Model::train_supervised(corpus, trial_args)?;
+        let trial_score = score(
+            &model,
+            &autotune.validation_file,
+            &autotune.metric,
+            autotune.k,
+        )?;
+        trace_info!(
+            trial = trials,
+            score = trial_score,
+            epoch = model.args().epoch,
+            lr = model.args().lr,
+            dim = model.args().dim,
+            "autotune trial"
+        );
+        trials += 1;
+        let (best_score, best_model) = match best {
+            Some((best_score, best_model)) if best_score >= trial_score => (best_score, best_model),
+            _ => (trial_score, model),
+        };
+
+        let elapsed = start.elapsed();
+        let out_of_trials = autotune
+            .max_trials
+            .is_some_and(|max_trials| trials >= max_trials);
+        if elapsed >= autotune.duration || out_of_trials {
+            return Ok(best_model);
+        }
+        let t = (elapsed.as_secs_f64() / autotune.duration.as_secs_f64()).min(1.0);
+        trial_args = sample_args(best_model.args(), t, &mut rng);
+        best = Some((best_score, best_model));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::tests::corpus_factory;
+
+    #[test]
+    fn test_sample_args_in_range() {
+        let mut rng = Rng::new(3);
+        let mut args = Args::supervised();
+        for i in 0..200 {
+            args = sample_args(&args, i as f64 / 200.0, &mut rng);
+            assert!((1..=100).contains(&args.epoch));
+            assert!((0.01..=5.0).contains(&args.lr));
+            assert!((1..=1000).contains(&args.dim));
+            assert!((1..=5).contains(&args.word_ngrams));
+            assert!(args.min_n <= args.max_n && args.max_n <= 6);
+            if args.word_ngrams > 1 || args.min_n > 0 {
+                assert!(args.bucket >= 10_000);
+            }
+        }
+    }
+
+    #[test]
+    fn test_autotune() {
+        let mut text = String::new();
+        for _ in 0..50 {
+            text.push_str("__label__fruit apple banana cherry\n");
+            text.push_str("__label__letter xray yankee zulu\n");
+        }
+        let corpus = corpus_factory(&text);
+        let validation = corpus_factory("__label__fruit banana apple\n__label__letter zulu xray\n");
+        let args = Args {
+            dim: 5,
+            epoch: 1,
+            lr: 0.01,
+            vocab_size: 101,
+            threads: 1,
+            ..Args::supervised()
+        };
+        let autotune = Autotune {
+            max_trials: Some(6),
+            ..Autotune::new(&validation)
+        };
+
+        let model = Model::autotune(corpus.path(), args.clone(), &autotune).unwrap();
+        let baseline = Model::train_supervised(corpus.path(), args).unwrap();
+        let tuned = score(&model, validation.path(), &AutotuneMetric::F1, 1).unwrap();
+        assert!(tuned >= score(&baseline, validation.path(), &AutotuneMetric::F1, 1).unwrap());
+        assert!(score(
+            &model,
+            validation.path(),
+            &AutotuneMetric::LabelF1(String::from("__label__fruit")),
+            1
+        )
+        .is_ok());
+        assert!(matches!(
+            score(
+                &model,
+                validation.path(),
+                &AutotuneMetric::LabelF1(String::from("__label__veg")),
+                1
+            ),
+            Err(RustTextError::UnknownLabel(_))
+        ));
+    }
+}
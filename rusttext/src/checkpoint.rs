@@ -0,0 +1,452 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Take, Write};
+use std::path::{Path, PathBuf};
+
+use crate::args::{Args, ClassWeights, LossKind, LrSchedule, WordModel};
+use crate::io::read_f32s;
+use crate::matrix::{Matrix, SharedMatrix};
+use crate::{Result, RustTextError};
+
+const MAGIC: &[u8; 4] = b"RTCK";
+const VERSION: u32 = 2;
+
+/// A snapshot of a training run: the arguments and corpus it was started
+/// with, how many tokens had been processed, and both matrices. The
+/// vocabulary is not stored; it is rebuilt from the corpus on resume, which
+/// gives the same ids as long as the file is unchanged.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Checkpoint {
+    pub args: Args,
+    pub corpus: PathBuf,
+    pub supervised: bool,
+    pub processed: u64,
+    pub input: Matrix,
+    pub output: Matrix,
+}
+
+fn format_error(message: String) -> RustTextError {
+    RustTextError::ModelFormat(message)
+}
+
+fn write_u8<W: Write>(writer: &mut W, value: u8) -> Result<()> {
+    writer.write_all(&[value])?;
+    Ok(())
+}
+
+fn write_u32<W: Write>(writer: &mut W, value: u32) -> Result<()> {
+    writer.write_all(&value.to_le_bytes())?;
+    Ok(())
+}
+
+fn write_u64<W: Write>(writer: &mut W, value: u64) -> Result<()> {
+    writer.write_all(&value.to_le_bytes())?;
+    Ok(())
+}
+
+fn write_str<W: Write>(writer: &mut W, value: &str) -> Result<()> {
+    write_u64(writer, value.len() as u64)?;
+    writer.write_all(value.as_bytes())?;
+    Ok(())
+}
+
+fn read_bytes<R: Read, const N: usize>(reader: &mut R) -> Result<[u8; N]> {
+    let mut bytes = [0; N];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn read_u8<R: Read>(reader: &mut R) -> Result<u8> {
+    Ok(read_bytes::<R, 1>(reader)?[0])
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> Result<u32> {
+    Ok(u32::from_le_bytes(read_bytes(reader)?))
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> Result<u64> {
+    Ok(u64::from_le_bytes(read_bytes(reader)?))
+}
+
+// Reads past the checkpoint's reader are limited to the file's length, so
+// a claimed size can be checked against the bytes left before allocating.
+fn read_string<R: Read>(reader: &mut Take<R>) -> Result<String> {
+    let len = read_u64(reader)?;
+    if len > reader.limit() {
+        return Err(format_error(format!(
+            "{}-byte string does not fit in the {} bytes left in the checkpoint",
+            len,
+            reader.limit()
+        )));
+    }
+    let mut bytes = vec![0; len as usize];
+    reader.read_exact(&mut bytes)?;
+    Ok(String::from_utf8(bytes)?)
+}
+
+fn write_path<W: Write>(writer: &mut W, path: Option<&Path>) -> Result<()> {
+    match path {
+        Some(path) => {
+            write_u8(writer, 1)?;
+            write_str(writer, &path.to_string_lossy())
+        }
+        None => write_u8(writer, 0),
+    }
+}
+
+fn read_path<R: Read>(reader: &mut Take<R>) -> Result<Option<PathBuf>> {
+    Ok(match read_u8(reader)? {
+        0 => None,
+        _ => Some(PathBuf::from(read_string(reader)?)),
+    })
+}
+
+/// A matrix to write to a checkpoint, a row at a time: either a plain
+/// matrix or one that training threads are still updating, whose rows are
+/// copied out one by one without borrowing the whole matrix.
+pub(crate) trait Rows {
+    fn shape(&self) -> (usize, usize);
+    fn copy_row(&self, i: usize, out: &mut [f32]);
+}
+
+impl Rows for Matrix {
+    fn shape(&self) -> (usize, usize) {
+        (self.rows(), self.cols())
+    }
+
+    fn copy_row(&self, i: usize, out: &mut [f32]) {
+        out.copy_from_slice(self.row(i));
+    }
+}
+
+impl Rows for SharedMatrix<'_> {
+    fn shape(&self) -> (usize, usize) {
+        (self.rows(), self.cols())
+    }
+
+    fn copy_row(&self, i: usize, out: &mut [f32]) {
+        SharedMatrix::copy_row(self, out, i);
+    }
+}
+
+fn write_matrix<W: Write>(writer: &mut W, matrix: &dyn Rows) -> Result<()> {
+    let (rows, cols) = matrix.shape();
+    write_u64(writer, rows as u64)?;
+    write_u64(writer, cols as u64)?;
+    let mut row = vec![0.0; cols];
+    for i in 0..rows {
+        matrix.copy_row(i, &mut row);
+        for value in row.iter() {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+fn read_matrix<R: Read>(reader: &mut Take<R>) -> Result<Matrix> {
+    let rows = read_u64(reader)?;
+    let cols = read_u64(reader)?;
+    let remaining = reader.limit();
+    let size = rows
+        .checked_mul(cols)
+        .filter(|&n| n.checked_mul(4).is_some_and(|bytes| bytes <= remaining))
+        .ok_or_else(|| {
+            format_error(format!(
+                "{}x{} matrix does not fit in the {} bytes left in the checkpoint",
+                rows, cols, remaining
+            ))
+        })?;
+    Matrix::from_vec(
+        rows as usize,
+        cols as usize,
+        read_f32s(reader, size as usize)?,
+    )
+}
+
+fn write_args<W: Write>(writer: &mut W, args: &Args) -> Result<()> {
+    write_u64(writer, args.dim as u64)?;
+    write_u64(writer, args.ws as u64)?;
+    write_u32(writer, args.epoch)?;
+    write_u32(writer, args.lr.to_bits())?;
+    write_u32(writer, args.min_count)?;
+    write_u32(writer, args.min_count_label)?;
+    write_u64(writer, args.neg as u64)?;
+    write_u64(writer, args.word_ngrams as u64)?;
+    write_u64(writer, args.min_n as u64)?;
+    write_u64(writer, args.max_n as u64)?;
+    write_u32(writer, args.bucket)?;
+    write_str(writer, &args.label_prefix)?;
+    write_u64(writer, args.t.to_bits())?;
+    write_u64(writer, args.vocab_size as u64)?;
+    write_u8(
+        writer,
+        match args.loss {
+            LossKind::NegativeSampling => 0,
+            LossKind::Softmax => 1,
+            LossKind::OneVsAll => 2,
+            LossKind::HierarchicalSoftmax => 3,
+        },
+    )?;
+    write_u8(
+        writer,
+        match args.word_model {
+            WordModel::Skipgram => 0,
+            WordModel::Cbow => 1,
+        },
+    )?;
+    write_u64(writer, args.threads as u64)?;
+    write_u8(
+        writer,
+        match args.lr_schedule {
+            LrSchedule::Linear => 0,
+            LrSchedule::Constant => 1,
+            LrSchedule::Cosine => 2,
+        },
+    )?;
+    write_u64(writer, args.lr_update_rate)?;
+    write_u64(writer, args.seed)?;
+    write_path(writer, args.checkpoint.as_deref())?;
+    write_u64(writer, args.checkpoint_interval)?;
+    write_path(writer, args.validation_file.as_deref())?;
+    write_u32(writer, args.patience)?;
+    write_u32(writer, args.label_smoothing.to_bits())?;
+    match &args.class_weights {
+        ClassWeights::Uniform => write_u8(writer, 0)?,
+        ClassWeights::Auto => write_u8(writer, 1)?,
+        ClassWeights::Custom(weights) => {
+            write_u8(writer, 2)?;
+            write_u64(writer, weights.len() as u64)?;
+            let mut labels: Vec<&String> = weights.keys().collect();
+            labels.sort();
+            for label in labels {
+                write_str(writer, label)?;
+                write_u32(writer, weights[label].to_bits())?;
+            }
+        }
+    }
+    write_path(writer, args.pretrained_vectors.as_deref())?;
+    write_u8(writer, args.freeze_embeddings as u8)?;
+    Ok(())
+}
+
+fn read_args<R: Read>(reader: &mut Take<R>) -> Result<Args> {
+    Ok(Args {
+        dim: read_u64(reader)? as usize,
+        ws: read_u64(reader)? as usize,
+        epoch: read_u32(reader)?,
+        lr: f32::from_bits(read_u32(reader)?),
+        min_count: read_u32(reader)?,
+        min_count_label: read_u32(reader)?,
+        neg: read_u64(reader)? as usize,
+        word_ngrams: read_u64(reader)? as usize,
+        min_n: read_u64(reader)? as usize,
+        max_n: read_u64(reader)? as usize,
+        bucket: read_u32(reader)?,
+        label_prefix: read_string(reader)?,
+        t: f64::from_bits(read_u64(reader)?),
+        vocab_size: read_u64(reader)? as usize,
+        loss: match read_u8(reader)? {
+            0 => LossKind::NegativeSampling,
+            1 => LossKind::Softmax,
+            2 => LossKind::OneVsAll,
+            3 => LossKind::HierarchicalSoftmax,
+            other => {
+                return Err(format_error(format!(
+                    "unknown loss {} in checkpoint",
+                    other
+                )))
+            }
+        },
+        word_model: match read_u8(reader)? {
+            0 => WordModel::Skipgram,
+            1 => WordModel::Cbow,
+            other => {
+                return Err(format_error(format!(
+                    "unknown word model {} in checkpoint",
+                    other
+                )))
+            }
+        },
+        threads: read_u64(reader)? as usize,
+        lr_schedule: match read_u8(reader)? {
+            0 => LrSchedule::Linear,
+            1 => LrSchedule::Constant,
+            2 => LrSchedule::Cosine,
+            other => {
+                return Err(format_error(format!(
+                    "unknown lr schedule {} in checkpoint",
+                    other
+                )))
+            }
+        },
+        lr_update_rate: read_u64(reader)?,
+        seed: read_u64(reader)?,
+        checkpoint: read_path(reader)?,
+        checkpoint_interval: read_u64(reader)?,
+        validation_file: read_path(reader)?,
+        patience: read_u32(reader)?,
+        label_smoothing: f32::from_bits(read_u32(reader)?),
+        class_weights: match read_u8(reader)? {
+            0 => ClassWeights::Uniform,
+            1 => ClassWeights::Auto,
+            2 => {
+                let mut weights = HashMap::new();
+                for _ in 0..read_u64(reader)? {
+                    let label = read_string(reader)?;
+                    weights.insert(label, f32::from_bits(read_u32(reader)?));
+                }
+                ClassWeights::Custom(weights)
+            }
+            other => {
+                return Err(format_error(format!(
+                    "unknown class weights {} in checkpoint",
+                    other
+                )))
+            }
+        },
+        pretrained_vectors: read_path(reader)?,
+        freeze_embeddings: read_u8(reader)? != 0,
+    })
+}
+
+/// Write a checkpoint to `path` atomically: the data goes to a temporary
+/// file next to it, which is then renamed over `path`, so a crash mid-write
+/// leaves the previous checkpoint intact.
+pub(crate) fn write(
+    path: &Path,
+    args: &Args,
+    corpus: &Path,
+    supervised: bool,
+    processed: u64,
+    input: &dyn Rows,
+    output: &dyn Rows,
+) -> Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+
+    let mut writer = BufWriter::new(File::create(&tmp)?);
+    writer.write_all(MAGIC)?;
+    write_u32(&mut writer, VERSION)?;
+    write_args(&mut writer, args)?;
+    write_str(&mut writer, &corpus.to_string_lossy())?;
+    write_u8(&mut writer, supervised as u8)?;
+    write_u64(&mut writer, processed)?;
+    write_matrix(&mut writer, input)?;
+    write_matrix(&mut writer, output)?;
+    writer
+        .into_inner()
+        .map_err(|err| err.into_error())?
+        .sync_all()?;
+
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+impl Checkpoint {
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        write(
+            path.as_ref(),
+            &self.args,
+            &self.corpus,
+            self.supervised,
+            self.processed,
+            &self.input,
+            &self.output,
+        )
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Checkpoint> {
+        let file = File::open(path)?;
+        let len = file.metadata()?.len();
+        let mut reader = BufReader::new(file).take(len);
+        if &read_bytes::<_, 4>(&mut reader)? != MAGIC {
+            return Err(format_error(String::from("not a rusttext checkpoint")));
+        }
+        let version = read_u32(&mut reader)?;
+        if version != VERSION {
+            return Err(format_error(format!(
+                "unsupported checkpoint version {}",
+                version
+            )));
+        }
+        let args = read_args(&mut reader)?;
+        let corpus = PathBuf::from(read_string(&mut reader)?);
+        let supervised = read_u8(&mut reader)? != 0;
+        let processed = read_u64(&mut reader)?;
+        let input = read_matrix(&mut reader)?;
+        let output = read_matrix(&mut reader)?;
+        Ok(Checkpoint {
+            args,
+            corpus,
+            supervised,
+            processed,
+            input,
+            output,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let path =
+            std::env::temp_dir().join(format!("rusttext-checkpoint-{}.bin", std::process::id()));
+        let checkpoint = Checkpoint {
+            args: Args {
+                checkpoint: Some(path.clone()),
+                validation_file: Some(PathBuf::from("valid.txt")),
+                patience: 3,
+                pretrained_vectors: Some(PathBuf::from("wiki.vec")),
+                label_smoothing: 0.1,
+                class_weights: ClassWeights::Custom(
+                    vec![(String::from("__label__x"), 2.5)]
+                        .into_iter()
+                        .collect(),
+                ),
+                loss: LossKind::OneVsAll,
+                lr_schedule: LrSchedule::Cosine,
+                ..Args::supervised()
+            },
+            corpus: PathBuf::from("corpus.txt"),
+            supervised: true,
+            processed: 12_345,
+            input: Matrix::from_vec(2, 3, vec![0.5, -1.0, 2.0, 0.0, 1e-7, -3.25]).unwrap(),
+            output: Matrix::new(1, 3),
+        };
+
+        checkpoint.save(&path).unwrap();
+        let loaded = Checkpoint::load(&path).unwrap();
+        assert_eq!(loaded, checkpoint);
+
+        // the input matrix header sits before 24 bytes of input values and
+        // the 28-byte output matrix
+        let mut bytes = std::fs::read(&path).unwrap();
+        let rows = bytes.len() - 68;
+        bytes[rows..rows + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+        std::fs::write(&path, &bytes).unwrap();
+        assert!(matches!(
+            Checkpoint::load(&path),
+            Err(RustTextError::ModelFormat(_))
+        ));
+        bytes[rows..rows + 8].copy_from_slice(&(1u64 << 40).to_le_bytes());
+        std::fs::write(&path, &bytes).unwrap();
+        assert!(matches!(
+            Checkpoint::load(&path),
+            Err(RustTextError::ModelFormat(_))
+        ));
+        // the label prefix's length follows 68 bytes of fixed-size
+        // arguments
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes[76..84].copy_from_slice(&u64::MAX.to_le_bytes());
+        std::fs::write(&path, &bytes).unwrap();
+        assert!(matches!(
+            Checkpoint::load(&path),
+            Err(RustTextError::ModelFormat(_))
+        ));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
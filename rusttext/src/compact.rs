@@ -0,0 +1,325 @@
+use std::cmp::Ordering;
+
+use crate::vocabulary::Vocabulary;
+use crate::word::{EntryType, WordEntry};
+use crate::Result;
+
+const BLOCK_SIZE: usize = 16;
+
+/// Read-only vocabulary for inference.
+///
+/// Words are sorted and front-coded in blocks of `BLOCK_SIZE`: the first word
+/// of each block is stored whole and the rest as a shared-prefix length plus
+/// suffix, all in one byte buffer. Entry ids keep the order of the source
+/// `Vocabulary`, so model rows line up; subwords are not stored and should be
+/// recomputed from `min_n`/`max_n`/`bucket` and the markers when needed.
+pub struct CompactVocabulary {
+    data: Vec<u8>,
+    block_offsets: Vec<usize>,
+    sorted_to_id: Vec<u32>,
+    id_to_sorted: Vec<u32>,
+    counts: Vec<u32>,
+    labels: Vec<u64>,
+    min_n: usize,
+    max_n: usize,
+    bucket: u32,
+    bow: String,
+    eow: String,
+}
+
+fn write_varint(data: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        data.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    data.push(value as u8);
+}
+
+fn read_varint(data: &[u8], pos: &mut usize) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = data[*pos];
+        *pos += 1;
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte < 0x80 {
+            return value;
+        }
+        shift += 7;
+    }
+}
+
+fn shared_prefix(left: &[u8], right: &[u8]) -> usize {
+    left.iter()
+        .zip(right.iter())
+        .take_while(|(l, r)| l == r)
+        .count()
+}
+
+impl CompactVocabulary {
+    pub fn from_vocabulary(vocab: &Vocabulary) -> CompactVocabulary {
+        let words = vocab.words();
+        let mut sorted_to_id: Vec<u32> = (0..words.len() as u32).collect();
+        sorted_to_id
+            .sort_by(|&left, &right| words[left as usize].word.cmp(&words[right as usize].word));
+
+        let mut id_to_sorted = vec![0; words.len()];
+        for (rank, &id) in sorted_to_id.iter().enumerate() {
+            id_to_sorted[id as usize] = rank as u32;
+        }
+
+        let mut data = Vec::new();
+        let mut block_offsets = Vec::new();
+        let mut previous: &[u8] = &[];
+        for (rank, &id) in sorted_to_id.iter().enumerate() {
+            let word = words[id as usize].word.as_bytes();
+            if rank % BLOCK_SIZE == 0 {
+                block_offsets.push(data.len());
+                write_varint(&mut data, word.len());
+                data.extend_from_slice(word);
+            } else {
+                let prefix = shared_prefix(previous, word);
+                write_varint(&mut data, prefix);
+                write_varint(&mut data, word.len() - prefix);
+                data.extend_from_slice(&word[prefix..]);
+            }
+            previous = word;
+        }
+        data.shrink_to_fit();
+
+        let mut labels = vec![0u64; words.len().div_ceil(64)];
+        for (id, entry) in words.iter().enumerate() {
+            if entry.entry_type == EntryType::Label {
+                labels[id / 64] |= 1 << (id % 64);
+            }
+        }
+
+        CompactVocabulary {
+            data,
+            block_offsets,
+            sorted_to_id,
+            id_to_sorted,
+            counts: words.iter().map(|entry| entry.count).collect(),
+            labels,
+            min_n: vocab.min_n(),
+            max_n: vocab.max_n(),
+            bucket: vocab.bucket(),
+            bow: String::from(vocab.bow()),
+            eow: String::from(vocab.eow()),
+        }
+    }
+
+    // the uncompressed first word of the block starting at `offset`
+    fn head_at(&self, offset: usize) -> &[u8] {
+        let mut pos = offset;
+        let len = read_varint(&self.data, &mut pos);
+        &self.data[pos..pos + len]
+    }
+
+    // Decode words of `block` in order, calling `visit` with each word's rank
+    // and bytes until it returns false.
+    fn scan_block<F>(&self, block: usize, mut visit: F)
+    where
+        F: FnMut(usize, &[u8]) -> bool,
+    {
+        let mut pos = self.block_offsets[block];
+        let mut word = Vec::new();
+        let first = block * BLOCK_SIZE;
+        let last = usize::min(first + BLOCK_SIZE, self.sorted_to_id.len());
+
+        for rank in first..last {
+            if rank == first {
+                let len = read_varint(&self.data, &mut pos);
+                word.extend_from_slice(&self.data[pos..pos + len]);
+                pos += len;
+            } else {
+                let prefix = read_varint(&self.data, &mut pos);
+                let len = read_varint(&self.data, &mut pos);
+                word.truncate(prefix);
+                word.extend_from_slice(&self.data[pos..pos + len]);
+                pos += len;
+            }
+            if !visit(rank, &word) {
+                return;
+            }
+        }
+    }
+
+    pub fn get_id(&self, word: &str) -> Option<u32> {
+        let target = word.as_bytes();
+        // last block whose head is <= target
+        let block = match self
+            .block_offsets
+            .binary_search_by(|&offset| self.head_at(offset).cmp(target))
+        {
+            Ok(block) => return Some(self.sorted_to_id[block * BLOCK_SIZE]),
+            Err(0) => return None,
+            Err(insert) => insert - 1,
+        };
+
+        let mut found = None;
+        self.scan_block(block, |rank, candidate| match candidate.cmp(target) {
+            Ordering::Less => true,
+            Ordering::Equal => {
+                found = Some(self.sorted_to_id[rank]);
+                false
+            }
+            Ordering::Greater => false,
+        });
+        found
+    }
+
+    /// The word with entry id `id`, if there is one; an error if its bytes
+    /// aren't valid UTF-8.
+    pub fn get_word(&self, id: u32) -> Result<Option<String>> {
+        let rank = match self.id_to_sorted.get(id as usize) {
+            Some(&rank) => rank as usize,
+            None => return Ok(None),
+        };
+        let mut found = None;
+        self.scan_block(rank / BLOCK_SIZE, |candidate_rank, word| {
+            if candidate_rank == rank {
+                found = Some(String::from_utf8(word.to_vec()));
+                return false;
+            }
+            true
+        });
+        Ok(found.transpose()?)
+    }
+
+    // every entry in id order, without subwords
+    pub(crate) fn entries(&self) -> Vec<WordEntry> {
+        let mut entries: Vec<Option<WordEntry>> = vec![None; self.len()];
+        for block in 0..self.block_offsets.len() {
+            self.scan_block(block, |rank, word| {
+                let id = self.sorted_to_id[rank];
+                entries[id as usize] = Some(WordEntry {
+                    word: String::from_utf8_lossy(word).into_owned(),
+                    entry_type: self.entry_type(id),
+                    count: self.count(id),
+                    subwords: Vec::new(),
+                });
+                true
+            });
+        }
+        entries.into_iter().flatten().collect()
+    }
+
+    pub fn count(&self, id: u32) -> u32 {
+        self.counts[id as usize]
+    }
+
+    pub fn entry_type(&self, id: u32) -> EntryType {
+        let id = id as usize;
+        match self.labels[id / 64] & (1 << (id % 64)) {
+            0 => EntryType::Word,
+            _ => EntryType::Label,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.counts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.counts.is_empty()
+    }
+
+    pub fn min_n(&self) -> usize {
+        self.min_n
+    }
+
+    pub fn max_n(&self) -> usize {
+        self.max_n
+    }
+
+    pub fn bucket(&self) -> u32 {
+        self.bucket
+    }
+
+    pub fn bow(&self) -> &str {
+        &self.bow
+    }
+
+    pub fn eow(&self) -> &str {
+        &self.eow
+    }
+
+    /// Approximate heap footprint in bytes.
+    pub fn memory_bytes(&self) -> usize {
+        self.data.capacity()
+            + self.block_offsets.capacity() * std::mem::size_of::<usize>()
+            + (self.sorted_to_id.capacity() + self.id_to_sorted.capacity() + self.counts.capacity())
+                * 4
+            + self.labels.capacity() * 8
+    }
+}
+
+impl From<&Vocabulary> for CompactVocabulary {
+    fn from(vocab: &Vocabulary) -> CompactVocabulary {
+        CompactVocabulary::from_vocabulary(vocab)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_vocab() -> Vocabulary {
+        let mut vocab = Vocabulary::new(1024, 3, 6, 100).unwrap();
+        for i in 0..200 {
+            let word = format!("prefix{:03}", i);
+            for _ in 0..(i % 3 + 1) {
+                vocab.add(&word).unwrap();
+            }
+        }
+        vocab.add("__label__x").unwrap();
+        vocab.add("naive").unwrap();
+        vocab.add("nail").unwrap();
+        vocab
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let vocab = test_vocab();
+        let compact = CompactVocabulary::from(&vocab);
+
+        assert_eq!(compact.len(), vocab.words().len());
+        for (id, entry) in vocab.words().iter().enumerate() {
+            let id = id as u32;
+            assert_eq!(compact.get_id(&entry.word), Some(id));
+            assert_eq!(
+                compact.get_word(id).unwrap().as_deref(),
+                Some(entry.word.as_str())
+            );
+            assert_eq!(compact.count(id), entry.count);
+            assert_eq!(compact.entry_type(id), entry.entry_type);
+        }
+        assert_eq!(compact.head_at(compact.block_offsets[0]), b"__label__x");
+    }
+
+    #[test]
+    fn test_missing() {
+        let compact = CompactVocabulary::from(&test_vocab());
+
+        assert_eq!(compact.get_id("aaa"), None);
+        assert_eq!(compact.get_id("prefix0005"), None);
+        assert_eq!(compact.get_id("zzz"), None);
+        assert_eq!(compact.get_word(10_000).unwrap(), None);
+    }
+
+    #[test]
+    fn test_smaller_than_entries() {
+        let vocab = test_vocab();
+        let compact = CompactVocabulary::from(&vocab);
+
+        let entry_bytes: usize = vocab
+            .words()
+            .iter()
+            .map(|entry| {
+                std::mem::size_of_val(entry) + entry.word.capacity() + entry.subwords.capacity() * 4
+            })
+            .sum();
+        assert!(compact.memory_bytes() * 4 < entry_bytes);
+    }
+}
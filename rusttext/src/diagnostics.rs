@@ -0,0 +1,260 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::vocabulary::Vocabulary;
+use crate::word::EntryType;
+
+/// Occupancy of the subword bucket space for a vocabulary.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BucketStats {
+    pub bucket: u32,
+    /// Distinct character n-grams across all word entries.
+    pub n_ngrams: usize,
+    /// Buckets holding at least one n-gram.
+    pub n_used_buckets: usize,
+    /// Buckets holding two or more distinct n-grams.
+    pub n_colliding_buckets: usize,
+    /// Largest number of distinct n-grams sharing a single bucket.
+    pub max_bucket_load: usize,
+    /// The most crowded buckets, largest first, with the n-grams they hold.
+    pub top_collisions: Vec<(u32, Vec<String>)>,
+}
+
+impl BucketStats {
+    /// Fraction of buckets in use.
+    pub fn fill_rate(&self) -> f64 {
+        if self.bucket == 0 {
+            return 0.0;
+        }
+        self.n_used_buckets as f64 / f64::from(self.bucket)
+    }
+
+    /// Fraction of distinct n-grams that share their bucket with another.
+    pub fn collision_rate(&self) -> f64 {
+        if self.n_ngrams == 0 {
+            return 0.0;
+        }
+        let n_unique = self.n_used_buckets - self.n_colliding_buckets;
+        (self.n_ngrams - n_unique) as f64 / self.n_ngrams as f64
+    }
+}
+
+/// Hash every distinct character n-gram of the vocabulary's words into the
+/// bucket space and summarise the resulting occupancy, keeping the `top_k`
+/// most crowded buckets.
+pub fn bucket_stats(vocab: &Vocabulary, top_k: usize) -> BucketStats {
+    let mut ngrams: HashSet<String> = HashSet::new();
+    for entry in vocab.words() {
+        if entry.entry_type == EntryType::Word {
+            ngrams.extend(entry.parse_subwords(
+                vocab.min_n(),
+                vocab.max_n(),
+                vocab.bow(),
+                vocab.eow(),
+            ));
+        }
+    }
+
+    let mut buckets: HashMap<u32, Vec<String>> = HashMap::new();
+    if vocab.bucket() > 0 {
+        for ngram in ngrams.iter() {
+            let id = vocab.hasher().bucket(ngram, vocab.bucket());
+            buckets.entry(id).or_default().push(ngram.clone());
+        }
+    }
+
+    let mut top_collisions: Vec<(u32, Vec<String>)> = buckets
+        .iter()
+        .filter(|(_, members)| members.len() > 1)
+        .map(|(id, members)| {
+            let mut members = members.clone();
+            members.sort();
+            (*id, members)
+        })
+        .collect();
+    top_collisions
+        .sort_by(|left, right| right.1.len().cmp(&left.1.len()).then(left.0.cmp(&right.0)));
+    let n_colliding_buckets = top_collisions.len();
+    top_collisions.truncate(top_k);
+
+    BucketStats {
+        bucket: vocab.bucket(),
+        n_ngrams: ngrams.len(),
+        n_used_buckets: buckets.len(),
+        n_colliding_buckets,
+        max_bucket_load: buckets
+            .values()
+            .map(|members| members.len())
+            .max()
+            .unwrap_or(0),
+        top_collisions,
+    }
+}
+
+const PERCENTILES: [f64; 5] = [50.0, 75.0, 90.0, 95.0, 99.0];
+const COVERAGE_POINTS: [usize; 6] = [1_000, 10_000, 50_000, 100_000, 500_000, 1_000_000];
+
+/// Token frequency distribution of a vocabulary's words, plus label counts.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrequencyReport {
+    /// Total occurrences of word (non-label) entries.
+    pub n_tokens: u64,
+    pub n_words: usize,
+    /// Words seen exactly once.
+    pub n_hapax: usize,
+    /// Per-word count at each percentile of the word distribution, e.g.
+    /// `(90.0, 3)` means 90% of words occur at most 3 times.
+    pub percentiles: Vec<(f64, u32)>,
+    /// Labels with their counts, most frequent first.
+    pub label_counts: Vec<(String, u32)>,
+    // running token total over words sorted by descending count
+    cumulative: Vec<u64>,
+}
+
+impl FrequencyReport {
+    /// Fraction of word tokens covered by the `top_n` most frequent words.
+    pub fn coverage(&self, top_n: usize) -> f64 {
+        if self.n_tokens == 0 || top_n == 0 {
+            return 0.0;
+        }
+        let top_n = usize::min(top_n, self.cumulative.len());
+        self.cumulative[top_n - 1] as f64 / self.n_tokens as f64
+    }
+
+    /// Smallest number of most-frequent words covering `fraction` of tokens.
+    pub fn words_for_coverage(&self, fraction: f64) -> usize {
+        let target = (fraction * self.n_tokens as f64).ceil() as u64;
+        match self.cumulative.binary_search(&target) {
+            Ok(i) | Err(i) => usize::min(i + 1, self.cumulative.len()),
+        }
+    }
+
+    /// Coverage at the standard vocabulary sizes that fit this vocabulary.
+    pub fn coverage_curve(&self) -> Vec<(usize, f64)> {
+        COVERAGE_POINTS
+            .iter()
+            .filter(|&&top_n| top_n < self.n_words)
+            .chain(std::iter::once(&self.n_words))
+            .map(|&top_n| (top_n, self.coverage(top_n)))
+            .collect()
+    }
+}
+
+impl fmt::Display for FrequencyReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "tokens: {}", self.n_tokens)?;
+        writeln!(f, "words: {}", self.n_words)?;
+        writeln!(f, "hapax: {}", self.n_hapax)?;
+        for (percentile, count) in self.percentiles.iter() {
+            writeln!(f, "p{}: {}", percentile, count)?;
+        }
+        for (top_n, coverage) in self.coverage_curve() {
+            writeln!(
+                f,
+                "top {} words cover {:.1}% of tokens",
+                top_n,
+                coverage * 100.0
+            )?;
+        }
+        for (label, count) in self.label_counts.iter() {
+            writeln!(f, "{}: {}", label, count)?;
+        }
+        Ok(())
+    }
+}
+
+/// Summarise how word counts are distributed, to help choose `min_count`
+/// and a vocabulary size before training.
+pub fn frequency_report(vocab: &Vocabulary) -> FrequencyReport {
+    let mut counts: Vec<u32> = Vec::new();
+    let mut label_counts: Vec<(String, u32)> = Vec::new();
+    for entry in vocab.words() {
+        match entry.entry_type {
+            EntryType::Word => counts.push(entry.count),
+            EntryType::Label => label_counts.push((entry.word.clone(), entry.count)),
+        }
+    }
+    counts.sort_unstable_by(|left, right| right.cmp(left));
+    label_counts.sort_by(|left, right| right.1.cmp(&left.1).then(left.0.cmp(&right.0)));
+
+    let mut cumulative = Vec::with_capacity(counts.len());
+    let mut total: u64 = 0;
+    for count in counts.iter() {
+        total += u64::from(*count);
+        cumulative.push(total);
+    }
+
+    // nearest-rank percentiles over ascending counts
+    let percentiles = PERCENTILES
+        .iter()
+        .map(|&percentile| {
+            let count = if counts.is_empty() {
+                0
+            } else {
+                let rank = ((percentile / 100.0) * counts.len() as f64).ceil() as usize;
+                counts[counts.len() - usize::max(rank, 1)]
+            };
+            (percentile, count)
+        })
+        .collect();
+
+    FrequencyReport {
+        n_tokens: total,
+        n_words: counts.len(),
+        n_hapax: counts.iter().filter(|&&count| count == 1).count(),
+        percentiles,
+        label_counts,
+        cumulative,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bucket_stats() {
+        // "rust" with 2..3-grams hashes to [0, 9, 2, 7, 7] in 10 buckets
+        let mut vocab = Vocabulary::new(11, 2, 3, 10).unwrap();
+        vocab.set_markers("", "");
+        vocab.add("rust").unwrap();
+        vocab.add("__label__x").unwrap();
+
+        let stats = vocab.bucket_stats(5);
+
+        assert_eq!(stats.n_ngrams, 5);
+        assert_eq!(stats.n_used_buckets, 4);
+        assert_eq!(stats.n_colliding_buckets, 1);
+        assert_eq!(stats.max_bucket_load, 2);
+        assert_eq!(
+            stats.top_collisions,
+            vec![(7, vec![String::from("rus"), String::from("ust")])]
+        );
+        assert_eq!(stats.fill_rate(), 0.4);
+        assert_eq!(stats.collision_rate(), 0.4);
+    }
+
+    #[test]
+    fn test_frequency_report() {
+        let mut vocab = Vocabulary::new(101, 0, 0, 10).unwrap();
+        for (word, count) in [("a", 6), ("b", 2), ("c", 1), ("d", 1), ("__label__x", 3)].iter() {
+            for _ in 0..*count {
+                vocab.add(word).unwrap();
+            }
+        }
+
+        let report = vocab.frequency_report();
+
+        assert_eq!(report.n_tokens, 10);
+        assert_eq!(report.n_words, 4);
+        assert_eq!(report.n_hapax, 2);
+        assert_eq!(report.percentiles[0], (50.0, 1));
+        assert_eq!(report.percentiles[4], (99.0, 6));
+        assert_eq!(report.label_counts, vec![(String::from("__label__x"), 3)]);
+        assert_eq!(report.coverage(1), 0.6);
+        assert_eq!(report.coverage(10), 1.0);
+        assert_eq!(report.words_for_coverage(0.8), 2);
+        assert_eq!(report.words_for_coverage(0.85), 3);
+        assert_eq!(report.coverage_curve(), vec![(4, 1.0)]);
+    }
+}
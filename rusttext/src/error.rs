@@ -0,0 +1,65 @@
+use std::num::{ParseFloatError, ParseIntError};
+use std::string::FromUtf8Error;
+
+use thiserror::Error;
+
+/// Everything that can go wrong in rusttext, by kind, so callers can match
+/// on the failure instead of parsing messages.
+#[derive(Debug, Error)]
+pub enum RustTextError {
+    #[error("invalid subword parameters: {0}")]
+    InvalidSubwordParams(String),
+    #[error("vocabulary is full ({capacity} slots)")]
+    VocabularyFull { capacity: usize },
+    /// A setting outside its valid range, or settings that conflict.
+    #[error("invalid argument: {0}")]
+    InvalidArgument(String),
+    #[error("unknown label {0}")]
+    UnknownLabel(String),
+    /// A training corpus that cannot produce a model, e.g. one without labels
+    /// for supervised training.
+    #[error("unusable corpus: {0}")]
+    Corpus(String),
+    #[error("dimension mismatch: expected {expected}, found {found}")]
+    DimensionMismatch { expected: usize, found: usize },
+    /// A malformed or unsupported model, vector or checkpoint file.
+    #[error("bad model format: {0}")]
+    ModelFormat(String),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Regex(#[from] regex::Error),
+    #[cfg(feature = "arrow")]
+    #[error(transparent)]
+    Arrow(#[from] arrow::error::ArrowError),
+    #[cfg(feature = "arrow")]
+    #[error(transparent)]
+    Parquet(#[from] parquet::errors::ParquetError),
+    /// A training thread panicked.
+    #[error("training failed: {0}")]
+    Training(String),
+}
+
+impl From<ParseIntError> for RustTextError {
+    fn from(err: ParseIntError) -> RustTextError {
+        RustTextError::ModelFormat(err.to_string())
+    }
+}
+
+impl From<ParseFloatError> for RustTextError {
+    fn from(err: ParseFloatError) -> RustTextError {
+        RustTextError::ModelFormat(err.to_string())
+    }
+}
+
+impl From<FromUtf8Error> for RustTextError {
+    fn from(err: FromUtf8Error) -> RustTextError {
+        RustTextError::ModelFormat(err.to_string())
+    }
+}
+
+impl From<bincode::Error> for RustTextError {
+    fn from(err: bincode::Error) -> RustTextError {
+        RustTextError::ModelFormat(err.to_string())
+    }
+}
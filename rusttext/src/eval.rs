@@ -0,0 +1,778 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+use rayon::prelude::*;
+
+use crate::args::LossKind;
+use crate::model::Model;
+use crate::predict::{self, Scratch};
+use crate::train;
+use crate::word;
+use crate::{Result, RustTextError};
+
+/// Scores of a classifier on a labelled test file, as `fasttext test`
+/// prints them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TestResult {
+    /// Labelled examples evaluated.
+    pub n_examples: usize,
+    /// Predictions per example.
+    pub k: usize,
+    /// Correct predictions over all predictions made (P@k).
+    pub precision: f32,
+    /// Correct predictions over all gold labels (R@k).
+    pub recall: f32,
+}
+
+impl TestResult {
+    /// Harmonic mean of precision and recall; 0 if both are.
+    pub fn f1(&self) -> f32 {
+        f1(self.precision, self.recall)
+    }
+}
+
+/// Scores of one label over a test file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LabelMetrics {
+    pub label: String,
+    /// Examples carrying the label.
+    pub support: usize,
+    /// Examples the label was predicted for.
+    pub predicted: usize,
+    /// Examples the label was rightly predicted for.
+    pub correct: usize,
+    pub precision: f32,
+    pub recall: f32,
+    pub f1: f32,
+}
+
+/// Counts of single-label test examples by gold label (rows) and most
+/// probable predicted label (columns), both in the model's label order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfusionMatrix {
+    labels: Vec<String>,
+    counts: Vec<usize>,
+}
+
+impl ConfusionMatrix {
+    pub fn labels(&self) -> &[String] {
+        &self.labels
+    }
+
+    /// Examples labelled `gold` whose best prediction was `predicted`, by
+    /// label index.
+    pub fn get(&self, gold: usize, predicted: usize) -> usize {
+        self.counts[gold * self.labels.len() + predicted]
+    }
+
+    /// Best predictions of the examples labelled `gold`, by predicted
+    /// label index.
+    pub fn row(&self, gold: usize) -> &[usize] {
+        let n = self.labels.len();
+        &self.counts[gold * n..(gold + 1) * n]
+    }
+}
+
+/// Per-label scores of a classifier on a test file, as `fasttext
+/// test-label` prints them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LabelReport {
+    /// One entry per label of the model, in label order, including labels
+    /// never seen in the test file.
+    pub labels: Vec<LabelMetrics>,
+    /// Set when every test example has exactly one label.
+    pub confusion: Option<ConfusionMatrix>,
+}
+
+impl LabelReport {
+    /// Scores of `label`, if the model has it.
+    pub fn get(&self, label: &str) -> Option<&LabelMetrics> {
+        self.labels.iter().find(|metrics| metrics.label == label)
+    }
+}
+
+fn ratio(part: usize, whole: usize) -> f32 {
+    if whole == 0 {
+        0.0
+    } else {
+        part as f32 / whole as f32
+    }
+}
+
+fn f1(precision: f32, recall: f32) -> f32 {
+    if precision + recall == 0.0 {
+        0.0
+    } else {
+        2.0 * precision * recall / (precision + recall)
+    }
+}
+
+fn supervised(model: &Model) -> Result<()> {
+    if model.supervised {
+        Ok(())
+    } else {
+        Err(RustTextError::InvalidArgument(String::from(
+            "testing needs a supervised model",
+        )))
+    }
+}
+
+/// Predict the `k` most probable labels with probability at least
+/// `threshold` for every labelled line of the file at `path`, in parallel,
+/// and score them against the line's labels. Lines are read as for
+/// training; those without a label are skipped.
+pub fn test<P: AsRef<Path>>(
+    model: &Model,
+    path: P,
+    k: usize,
+    threshold: f32,
+) -> Result<TestResult> {
+    supervised(model)?;
+    let examples = train::read_examples(&model.vocab, path.as_ref())?;
+    let (correct, predicted, gold) = examples
+        .par_iter()
+        .map_init(Scratch::default, |scratch, (features, labels)| {
+            let top = predict::rank(model, features, k, threshold, scratch);
+            let correct = top
+                .iter()
+                .filter(|(i, _)| labels.contains(&(*i as u32)))
+                .count();
+            (correct, top.len(), labels.len())
+        })
+        .reduce(
+            || (0, 0, 0),
+            |left, right| (left.0 + right.0, left.1 + right.1, left.2 + right.2),
+        );
+    Ok(TestResult {
+        n_examples: examples.len(),
+        k,
+        precision: ratio(correct, predicted),
+        recall: ratio(correct, gold),
+    })
+}
+
+// Per-label counts of correct, predicted and gold labels, and the
+// confusion matrix counts, summed over examples.
+#[derive(Clone)]
+struct Tally {
+    correct: Vec<usize>,
+    predicted: Vec<usize>,
+    gold: Vec<usize>,
+    confusion: Vec<usize>,
+}
+
+impl Tally {
+    fn new(n: usize) -> Tally {
+        Tally {
+            correct: vec![0; n],
+            predicted: vec![0; n],
+            gold: vec![0; n],
+            confusion: vec![0; n * n],
+        }
+    }
+
+    fn merge(mut self, other: Tally) -> Tally {
+        for (mine, theirs) in [
+            (&mut self.correct, &other.correct),
+            (&mut self.predicted, &other.predicted),
+            (&mut self.gold, &other.gold),
+            (&mut self.confusion, &other.confusion),
+        ] {
+            mine.iter_mut()
+                .zip(theirs.iter())
+                .for_each(|(m, t)| *m += t);
+        }
+        self
+    }
+}
+
+/// `test` broken down by label: the precision, recall and F1 of each
+/// label over the `k` predictions per example with probability at least
+/// `threshold`, and for single-label test files a confusion matrix of gold
+/// labels against the most probable prediction (threshold aside).
+pub fn test_labels<P: AsRef<Path>>(
+    model: &Model,
+    path: P,
+    k: usize,
+    threshold: f32,
+) -> Result<LabelReport> {
+    supervised(model)?;
+    let examples = train::read_examples(&model.vocab, path.as_ref())?;
+    let vocab = &model.vocab;
+    let names: Vec<String> = (vocab.n_words()..vocab.n_words() + vocab.n_labels())
+        .map(|id| vocab.word(id).unwrap_or_default().into_owned())
+        .collect();
+    let n = names.len();
+    let tally = examples
+        .par_iter()
+        .fold(
+            || (Tally::new(n), Scratch::default()),
+            |(mut tally, mut scratch), (features, labels)| {
+                let top = predict::rank(model, features, usize::max(k, 1), 0.0, &mut scratch);
+                if let (Some(&(best, _)), [gold]) = (top.first(), labels.as_slice()) {
+                    tally.confusion[*gold as usize * n + best] += 1;
+                }
+                for &(i, _) in top.iter().take(k).filter(|(_, p)| *p >= threshold) {
+                    tally.predicted[i] += 1;
+                    if labels.contains(&(i as u32)) {
+                        tally.correct[i] += 1;
+                    }
+                }
+                for &label in labels.iter() {
+                    tally.gold[label as usize] += 1;
+                }
+                (tally, scratch)
+            },
+        )
+        .map(|(tally, _)| tally)
+        .reduce(|| Tally::new(n), Tally::merge);
+
+    let single_label = examples.iter().all(|(_, labels)| labels.len() == 1);
+    let labels = names
+        .iter()
+        .enumerate()
+        .map(|(i, label)| {
+            let precision = ratio(tally.correct[i], tally.predicted[i]);
+            let recall = ratio(tally.correct[i], tally.gold[i]);
+            LabelMetrics {
+                label: label.clone(),
+                support: tally.gold[i],
+                predicted: tally.predicted[i],
+                correct: tally.correct[i],
+                precision,
+                recall,
+                f1: f1(precision, recall),
+            }
+        })
+        .collect();
+    Ok(LabelReport {
+        labels,
+        confusion: if single_label {
+            Some(ConfusionMatrix {
+                labels: names,
+                counts: tally.confusion,
+            })
+        } else {
+            None
+        },
+    })
+}
+
+/// What `calibrate` picks each label's threshold for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CalibrationTarget {
+    /// The threshold with the best F1 for the label.
+    F1,
+    /// The lowest threshold whose precision for the label reaches the
+    /// given value, so recall is as high as that precision allows.
+    Precision(f32),
+}
+
+// The threshold for one label from the (probability, is gold) pair of
+// every example.
+fn label_threshold(mut scored: Vec<(f32, bool)>, target: CalibrationTarget) -> f32 {
+    let positives = scored.iter().filter(|(_, gold)| *gold).count();
+    if positives == 0 {
+        return 0.0;
+    }
+    scored.sort_by(|left, right| right.0.total_cmp(&left.0));
+    let (mut best, mut best_score) = (f32::INFINITY, f32::NEG_INFINITY);
+    let (mut correct, mut predicted) = (0, 0);
+    for (i, &(score, gold)) in scored.iter().enumerate() {
+        predicted += 1;
+        correct += usize::from(gold);
+        // only cut between distinct probabilities
+        if scored.get(i + 1).is_some_and(|next| next.0 == score) {
+            continue;
+        }
+        let precision = ratio(correct, predicted);
+        match target {
+            CalibrationTarget::F1 => {
+                let f1 = f1(precision, ratio(correct, positives));
+                if f1 > best_score {
+                    best = score;
+                    best_score = f1;
+                }
+            }
+            CalibrationTarget::Precision(target) if precision >= target => best = score,
+            CalibrationTarget::Precision(_) => {}
+        }
+    }
+    best
+}
+
+/// Per-label probability thresholds for a one-vs-all classifier, swept on
+/// the labelled file at `path`: for each label, the cut over its
+/// probabilities on every example that best meets `target`. Labels absent
+/// from the file get 0, leaving them to the caller's threshold; labels
+/// that can't reach a `Precision` target get infinity and are never
+/// predicted. Any thresholds the model already has are ignored.
+pub fn calibrate<P: AsRef<Path>>(
+    model: &Model,
+    path: P,
+    target: CalibrationTarget,
+) -> Result<Vec<f32>> {
+    supervised(model)?;
+    if model.args.loss != LossKind::OneVsAll {
+        return Err(RustTextError::InvalidArgument(String::from(
+            "threshold calibration needs a one-vs-all model",
+        )));
+    }
+    let examples = train::read_examples(&model.vocab, path.as_ref())?;
+    let scores: Vec<Vec<f32>> = examples
+        .par_iter()
+        .map_init(Scratch::default, |scratch, (features, _)| {
+            predict::label_scores(model, features, scratch)
+        })
+        .collect();
+    let n = model.vocab.n_labels() as usize;
+    Ok((0..n)
+        .into_par_iter()
+        .map(|label| {
+            let scored = examples
+                .iter()
+                .zip(scores.iter())
+                .filter(|((features, _), _)| !features.is_empty())
+                .map(|((_, labels), scores)| (scores[label], labels.contains(&(label as u32))))
+                .collect();
+            label_threshold(scored, target)
+        })
+        .collect())
+}
+
+/// Examples and length of one label of a labelled corpus.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LabelStats {
+    pub label: String,
+    /// Examples carrying the label.
+    pub examples: usize,
+    /// Mean tokens per example, labels left out.
+    pub avg_tokens: f64,
+}
+
+/// Two labels found on the same examples.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LabelPair {
+    pub first: String,
+    pub second: String,
+    pub examples: usize,
+}
+
+/// A text labelled differently in different examples, which pulls a
+/// classifier in opposite directions.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Conflict {
+    /// The text, labels left out and tokens joined by single spaces.
+    pub text: String,
+    /// Every label set the text was given, each sorted, in order of first
+    /// appearance.
+    pub labels: Vec<Vec<String>>,
+}
+
+/// Texts of one corpus found in another; see `DatasetReport::overlap`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Overlap {
+    /// Examples of the other corpus whose text is in this one.
+    pub shared: usize,
+    /// Of those, examples with labels the text never has here.
+    pub relabelled: usize,
+    /// `shared` over the other corpus's examples.
+    pub fraction: f32,
+}
+
+// Examples of one text, and the hashes of the label sets given to it.
+#[derive(Debug, Clone, PartialEq, Default)]
+struct TextStats {
+    examples: usize,
+    label_sets: Vec<u64>,
+}
+
+/// The shape of a labelled corpus and the data problems that most often
+/// pass for classifier bugs: rare labels, labels that always come
+/// together, texts labelled inconsistently, and (with `overlap`) test
+/// texts that are also in the training data. See `dataset_report`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DatasetReport {
+    /// Lines with at least one label.
+    pub n_examples: usize,
+    /// Lines without a label, which training skips.
+    pub n_unlabelled: usize,
+    /// Mean tokens per example, labels left out.
+    pub avg_tokens: f64,
+    /// Every label, most examples first.
+    pub labels: Vec<LabelStats>,
+    /// Labels that share examples, most shared first.
+    pub co_occurrence: Vec<LabelPair>,
+    /// Examples whose text appeared in an earlier example.
+    pub n_duplicates: usize,
+    /// Texts given different labels in different examples.
+    pub conflicts: Vec<Conflict>,
+    texts: HashMap<u64, TextStats>,
+}
+
+impl DatasetReport {
+    /// How many examples of `other`, typically a validation or test file,
+    /// have a text that is also in this corpus. Shared texts inflate test
+    /// scores; relabelled ones cap them.
+    pub fn overlap(&self, other: &DatasetReport) -> Overlap {
+        let (mut shared, mut relabelled) = (0, 0);
+        for (hash, theirs) in other.texts.iter() {
+            if let Some(ours) = self.texts.get(hash) {
+                shared += theirs.examples;
+                if theirs
+                    .label_sets
+                    .iter()
+                    .any(|set| !ours.label_sets.contains(set))
+                {
+                    relabelled += theirs.examples;
+                }
+            }
+        }
+        Overlap {
+            shared,
+            relabelled,
+            fraction: shared as f32 / usize::max(other.n_examples, 1) as f32,
+        }
+    }
+}
+
+/// Read the labelled corpus at `path`, where labels are the tokens
+/// starting with `label_prefix` as in training, and summarize it without a
+/// model: examples and length per label, label co-occurrence, and repeated
+/// texts, flagging those labelled inconsistently. Texts are compared by
+/// their tokens, so whitespace and label placement don't matter.
+pub fn dataset_report<P: AsRef<Path>>(path: P, label_prefix: &str) -> Result<DatasetReport> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut ids: HashMap<String, usize> = HashMap::new();
+    let mut names: Vec<String> = Vec::new();
+    let (mut examples, mut tokens) = (Vec::<usize>::new(), Vec::<usize>::new());
+    let mut pairs: HashMap<(usize, usize), usize> = HashMap::new();
+    let mut texts: HashMap<u64, TextStats> = HashMap::new();
+    // every distinct label set by hash, and each conflicting text
+    let mut label_sets: HashMap<u64, Vec<String>> = HashMap::new();
+    let mut conflicts: Vec<(u64, String)> = Vec::new();
+    let mut report = DatasetReport {
+        n_examples: 0,
+        n_unlabelled: 0,
+        avg_tokens: 0.0,
+        labels: Vec::new(),
+        co_occurrence: Vec::new(),
+        n_duplicates: 0,
+        conflicts: Vec::new(),
+        texts: HashMap::new(),
+    };
+    let mut total_tokens = 0;
+
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 0 {
+        let (labels, words): (Vec<&str>, Vec<&str>) = line
+            .split_whitespace()
+            .partition(|token| token.starts_with(label_prefix));
+        if labels.is_empty() {
+            report.n_unlabelled += usize::from(!words.is_empty());
+            line.clear();
+            continue;
+        }
+        report.n_examples += 1;
+        total_tokens += words.len();
+
+        let mut label_ids: Vec<usize> = labels
+            .iter()
+            .map(|&label| {
+                *ids.entry(String::from(label)).or_insert_with(|| {
+                    names.push(String::from(label));
+                    examples.push(0);
+                    tokens.push(0);
+                    names.len() - 1
+                })
+            })
+            .collect();
+        label_ids.sort_unstable();
+        label_ids.dedup();
+        for (i, &first) in label_ids.iter().enumerate() {
+            examples[first] += 1;
+            tokens[first] += words.len();
+            for &second in &label_ids[i + 1..] {
+                *pairs.entry((first, second)).or_insert(0) += 1;
+            }
+        }
+
+        let mut label_set: Vec<&str> = label_ids.iter().map(|&id| names[id].as_str()).collect();
+        label_set.sort_unstable();
+        let set_hash = word::fnv_hash64(&label_set.join(" "));
+        label_sets
+            .entry(set_hash)
+            .or_insert_with(|| label_set.iter().map(|&label| String::from(label)).collect());
+        let text = words.join(" ");
+        let text_hash = word::fnv_hash64(&text);
+        let stats = texts.entry(text_hash).or_default();
+        if stats.examples > 0 {
+            report.n_duplicates += 1;
+        }
+        stats.examples += 1;
+        if !stats.label_sets.contains(&set_hash) {
+            stats.label_sets.push(set_hash);
+            if stats.label_sets.len() == 2 {
+                conflicts.push((text_hash, text));
+            }
+        }
+        line.clear();
+    }
+
+    report.avg_tokens = total_tokens as f64 / usize::max(report.n_examples, 1) as f64;
+    report.labels = names
+        .iter()
+        .zip(examples.iter().zip(tokens.iter()))
+        .map(|(label, (&examples, &tokens))| LabelStats {
+            label: label.clone(),
+            examples,
+            avg_tokens: tokens as f64 / examples as f64,
+        })
+        .collect();
+    report
+        .labels
+        .sort_by_key(|stats| std::cmp::Reverse(stats.examples));
+    report.co_occurrence = pairs
+        .into_iter()
+        .map(|((first, second), examples)| LabelPair {
+            first: names[first].clone(),
+            second: names[second].clone(),
+            examples,
+        })
+        .collect();
+    report.co_occurrence.sort_by(|left, right| {
+        (right.examples, &left.first, &left.second).cmp(&(
+            left.examples,
+            &right.first,
+            &right.second,
+        ))
+    });
+    report.conflicts = conflicts
+        .into_iter()
+        .map(|(hash, text)| Conflict {
+            text,
+            labels: texts[&hash]
+                .label_sets
+                .iter()
+                .map(|set| label_sets[set].clone())
+                .collect(),
+        })
+        .collect();
+    report.texts = texts;
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args::Args;
+    use crate::io::tests::corpus_factory;
+
+    // A classifier trained on 100 copies of `lines`.
+    fn train(lines: &str) -> Model {
+        let corpus = corpus_factory(&lines.repeat(100));
+        let args = Args {
+            dim: 10,
+            vocab_size: 101,
+            threads: 1,
+            ..Args::supervised()
+        };
+        Model::train_supervised(corpus.path(), args).unwrap()
+    }
+
+    #[test]
+    fn test_test() {
+        let model = train(
+            "__label__fruit apple banana cherry\n\
+             __label__letter xray yankee zulu\n",
+        );
+        let corpus = corpus_factory(
+            "__label__fruit banana apple\n\
+             __label__letter __label__fruit zulu cherry\n\
+             no label here\n\
+             __label__letter yankee\n",
+        );
+        let test_path = corpus.path();
+
+        let top1 = model.test(test_path, 1, 0.0).unwrap();
+        assert_eq!(top1.n_examples, 3);
+        assert_eq!(top1.precision, 1.0);
+        assert_eq!(top1.recall, 0.75);
+        let top2 = model.test(test_path, 2, 0.0).unwrap();
+        assert_eq!(top2.precision, 4.0 / 6.0);
+        assert_eq!(top2.recall, 1.0);
+        assert!((top2.f1() - 0.8).abs() < 1e-6);
+        // nothing reaches the threshold, so nothing is predicted
+        let none = model.test(test_path, 1, 1.1).unwrap();
+        assert_eq!((none.precision, none.recall, none.f1()), (0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn test_rejects_unsupervised() {
+        let model = crate::vectors::tests::fixed(&["a"], vec![1.0]);
+        assert!(test(&model, "missing.txt", 1, 0.0).is_err());
+        assert!(test_labels(&model, "missing.txt", 1, 0.0).is_err());
+    }
+
+    #[test]
+    fn test_test_labels() {
+        let model = train(
+            "__label__fruit apple banana cherry\n\
+             __label__letter xray yankee zulu\n\
+             __label__color red green blue\n",
+        );
+        let corpus =
+            corpus_factory("__label__fruit banana\n__label__fruit zulu\n__label__letter xray\n");
+        let path = corpus.path();
+
+        let report = model.test_labels(path, 1, 0.0).unwrap();
+        assert_eq!(report.labels.len(), 3);
+        let fruit = report.get("__label__fruit").unwrap();
+        assert_eq!((fruit.support, fruit.predicted, fruit.correct), (2, 1, 1));
+        assert_eq!((fruit.precision, fruit.recall), (1.0, 0.5));
+        assert!((fruit.f1 - 2.0 / 3.0).abs() < 1e-6);
+        let letter = report.get("__label__letter").unwrap();
+        assert_eq!(
+            (letter.support, letter.predicted, letter.correct),
+            (1, 2, 1)
+        );
+        let color = report.get("__label__color").unwrap();
+        assert_eq!((color.support, color.f1), (0, 0.0));
+        assert!(report.get("__label__missing").is_none());
+
+        let confusion = report.confusion.unwrap();
+        let index = |label: &str| confusion.labels().iter().position(|l| l == label).unwrap();
+        let (fruit, letter) = (index("__label__fruit"), index("__label__letter"));
+        assert_eq!(confusion.get(fruit, fruit), 1);
+        assert_eq!(confusion.get(fruit, letter), 1);
+        assert_eq!(confusion.row(letter).iter().sum::<usize>(), 1);
+
+        std::fs::write(path, "__label__fruit __label__color apple red\n").unwrap();
+        assert!(model.test_labels(path, 1, 0.0).unwrap().confusion.is_none());
+    }
+
+    #[test]
+    fn test_label_threshold() {
+        let scored = vec![
+            (0.9, true),
+            (0.8, false),
+            (0.7, true),
+            (0.7, true),
+            (0.4, false),
+            (0.3, false),
+            (0.3, false),
+            (0.2, true),
+        ];
+        // 0.7 keeps three of four positives for one false positive
+        assert_eq!(label_threshold(scored.clone(), CalibrationTarget::F1), 0.7);
+        assert_eq!(
+            label_threshold(scored.clone(), CalibrationTarget::Precision(1.0)),
+            0.9
+        );
+        assert_eq!(
+            label_threshold(scored.clone(), CalibrationTarget::Precision(0.75)),
+            0.7
+        );
+        assert_eq!(
+            label_threshold(
+                vec![(0.9, false), (0.5, true)],
+                CalibrationTarget::Precision(0.9)
+            ),
+            f32::INFINITY
+        );
+        assert_eq!(
+            label_threshold(vec![(0.9, false)], CalibrationTarget::F1),
+            0.0
+        );
+    }
+
+    #[test]
+    fn test_calibrate() {
+        let lines = "__label__fruit apple banana cherry\n\
+                     __label__letter xray yankee zulu\n";
+        let corpus = corpus_factory(lines);
+        let path = corpus.path();
+        let softmax = train(lines);
+        assert!(calibrate(&softmax, path, CalibrationTarget::F1).is_err());
+
+        let train_corpus = corpus_factory(&lines.repeat(100));
+        let args = Args {
+            dim: 10,
+            vocab_size: 101,
+            threads: 1,
+            loss: LossKind::OneVsAll,
+            ..Args::supervised()
+        };
+        let mut model = Model::train_supervised(train_corpus.path(), args).unwrap();
+        model.calibrate(path, CalibrationTarget::F1).unwrap();
+        assert_eq!(model.label_thresholds().unwrap().len(), 2);
+        // each label's threshold is its probability on its own example, so
+        // the other label is no longer predicted for it
+        assert_eq!(model.predict("apple banana cherry", 2, 0.0).len(), 1);
+        assert!(model.set_label_thresholds(Some(vec![0.5])).is_err());
+        model.set_label_thresholds(None).unwrap();
+        assert_eq!(model.predict("apple banana cherry", 2, 0.0).len(), 2);
+    }
+
+    #[test]
+    fn test_dataset_report() {
+        let train = corpus_factory(
+            "__label__fruit apple banana\n\
+             __label__fruit __label__sweet cherry  pie\n\
+             no label here\n\
+             \n\
+             __label__sweet __label__fruit cherry pie\n\
+             apple __label__letter banana\n\
+             __label__letter xray yankee zulu\n",
+        );
+        let valid = corpus_factory(
+            "__label__letter xray yankee zulu\n\
+             __label__fruit apple banana\n\
+             __label__fruit kiwi\n",
+        );
+        let report = dataset_report(train.path(), "__label__").unwrap();
+        let other = dataset_report(valid.path(), "__label__").unwrap();
+
+        assert_eq!(report.n_examples, 5);
+        assert_eq!(report.n_unlabelled, 1);
+        assert_eq!(report.avg_tokens, 11.0 / 5.0);
+        assert_eq!(
+            report.labels[0],
+            LabelStats {
+                label: String::from("__label__fruit"),
+                examples: 3,
+                avg_tokens: 2.0,
+            }
+        );
+        assert_eq!(report.labels[1].label, "__label__sweet");
+        assert_eq!(report.labels[2].examples, 2);
+        assert_eq!(
+            report.co_occurrence,
+            vec![LabelPair {
+                first: String::from("__label__fruit"),
+                second: String::from("__label__sweet"),
+                examples: 2,
+            }]
+        );
+        // label order within a line doesn't make a conflict
+        assert_eq!(report.n_duplicates, 2);
+        assert_eq!(
+            report.conflicts,
+            vec![Conflict {
+                text: String::from("apple banana"),
+                labels: vec![
+                    vec![String::from("__label__fruit")],
+                    vec![String::from("__label__letter")],
+                ],
+            }]
+        );
+
+        let overlap = report.overlap(&other);
+        assert_eq!(overlap.shared, 2);
+        assert_eq!(overlap.relabelled, 0);
+        assert!((overlap.fraction - 2.0 / 3.0).abs() < 1e-6);
+        assert_eq!(other.overlap(&report).relabelled, 2);
+    }
+}
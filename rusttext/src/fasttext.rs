@@ -0,0 +1,778 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use crate::args::{Args, LossKind, WordModel};
+use crate::io::read_f32s;
+use crate::matrix::Matrix;
+use crate::model::Model;
+use crate::quant::{CompressedMatrix, ProductQuantizer, QuantMatrix, KSUB};
+use crate::vocabulary::Vocabulary;
+use crate::word::{self, EntryType, WordEntry};
+use crate::{Result, RustTextError};
+
+/// First word of every fastText `.bin` file.
+pub const MAGIC: i32 = 793_712_314;
+/// The `.bin` version fastText writes; version 11 files are also read.
+pub const VERSION: i32 = 12;
+
+// fastText's enum values for `-loss` and `-model`.
+const LOSS_HS: i32 = 1;
+const LOSS_NS: i32 = 2;
+const LOSS_SOFTMAX: i32 = 3;
+const LOSS_OVA: i32 = 4;
+const MODEL_CBOW: i32 = 1;
+const MODEL_SG: i32 = 2;
+const MODEL_SUP: i32 = 3;
+
+fn format_error(message: String) -> RustTextError {
+    RustTextError::ModelFormat(message)
+}
+
+fn read_bytes<R: Read, const N: usize>(reader: &mut R) -> Result<[u8; N]> {
+    let mut bytes = [0; N];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn read_bool<R: Read>(reader: &mut R) -> Result<bool> {
+    Ok(read_bytes::<R, 1>(reader)?[0] != 0)
+}
+
+fn read_i32<R: Read>(reader: &mut R) -> Result<i32> {
+    Ok(i32::from_le_bytes(read_bytes(reader)?))
+}
+
+fn read_i64<R: Read>(reader: &mut R) -> Result<i64> {
+    Ok(i64::from_le_bytes(read_bytes(reader)?))
+}
+
+fn read_f64<R: Read>(reader: &mut R) -> Result<f64> {
+    Ok(f64::from_le_bytes(read_bytes(reader)?))
+}
+
+// A size field, which fastText stores signed.
+fn read_size<R: Read>(reader: &mut R, wide: bool, what: &str) -> Result<usize> {
+    let value = if wide {
+        read_i64(reader)?
+    } else {
+        i64::from(read_i32(reader)?)
+    };
+    usize::try_from(value).map_err(|_| format_error(format!("negative {}: {}", what, value)))
+}
+
+fn read_u8s<R: Read>(reader: &mut R, n: usize) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    reader.take(n as u64).read_to_end(&mut bytes)?;
+    if bytes.len() != n {
+        return Err(format_error(String::from("model file is truncated")));
+    }
+    Ok(bytes)
+}
+
+// A NUL-terminated word. Invalid UTF-8 is replaced rather than rejected, so
+// one odd entry in a large published vocabulary doesn't stop the load.
+fn read_word<R: BufRead>(reader: &mut R) -> Result<String> {
+    let mut bytes = Vec::new();
+    reader.read_until(0, &mut bytes)?;
+    if bytes.pop() != Some(0) {
+        return Err(format_error(String::from("model file is truncated")));
+    }
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+// The saved arguments, and whether the model is a classifier.
+fn read_args<R: Read>(reader: &mut R, version: i32) -> Result<(Args, bool)> {
+    let dim = read_size(reader, false, "dim")?;
+    let ws = read_size(reader, false, "ws")?;
+    let epoch = read_size(reader, false, "epoch")? as u32;
+    let min_count = read_size(reader, false, "minCount")? as u32;
+    let neg = read_size(reader, false, "neg")?;
+    let word_ngrams = read_size(reader, false, "wordNgrams")?;
+    let loss = match read_i32(reader)? {
+        LOSS_HS => LossKind::HierarchicalSoftmax,
+        LOSS_NS => LossKind::NegativeSampling,
+        LOSS_SOFTMAX => LossKind::Softmax,
+        LOSS_OVA => LossKind::OneVsAll,
+        other => return Err(format_error(format!("unknown loss {}", other))),
+    };
+    let (supervised, word_model) = match read_i32(reader)? {
+        MODEL_CBOW => (false, WordModel::Cbow),
+        MODEL_SG => (false, WordModel::Skipgram),
+        MODEL_SUP => (true, WordModel::Skipgram),
+        other => return Err(format_error(format!("unknown model type {}", other))),
+    };
+    let bucket = read_size(reader, false, "bucket")? as u32;
+    let min_n = read_size(reader, false, "minn")?;
+    let mut max_n = read_size(reader, false, "maxn")?;
+    let lr_update_rate = read_size(reader, false, "lrUpdateRate")? as u64;
+    let t = read_f64(reader)?;
+    // version 11 classifiers predate subwords in supervised mode
+    if version == 11 && supervised {
+        max_n = 0;
+    }
+
+    let base = if supervised {
+        Args::supervised()
+    } else {
+        Args::default()
+    };
+    let args = Args {
+        dim,
+        ws,
+        epoch,
+        min_count,
+        neg,
+        word_ngrams,
+        loss,
+        word_model,
+        bucket,
+        min_n,
+        max_n,
+        lr_update_rate,
+        t,
+        ..base
+    };
+    Ok((args, supervised))
+}
+
+fn read_vocabulary<R: BufRead>(reader: &mut R, args: &mut Args) -> Result<Vocabulary> {
+    let size = read_size(reader, false, "dictionary size")?;
+    let n_words = read_size(reader, false, "word count")?;
+    let n_labels = read_size(reader, false, "label count")?;
+    let n_tokens = read_size(reader, true, "token count")? as u64;
+    let pruned_size = read_i64(reader)?;
+
+    let mut words = Vec::new();
+    for _ in 0..size {
+        let word = read_word(reader)?;
+        let count = u32::try_from(read_i64(reader)?).unwrap_or(u32::MAX);
+        let entry_type = match read_bytes::<R, 1>(reader)?[0] {
+            0 => EntryType::Word,
+            1 => EntryType::Label,
+            other => return Err(format_error(format!("unknown entry type {}", other))),
+        };
+        words.push(WordEntry {
+            word,
+            entry_type,
+            count,
+            subwords: Vec::new(),
+        });
+    }
+    // negative means the dictionary was never pruned
+    let pruned = if pruned_size >= 0 {
+        let mut pruned = HashMap::new();
+        for _ in 0..pruned_size {
+            let bucket = read_size(reader, false, "pruned bucket")? as u32;
+            let index = read_size(reader, false, "pruned index")? as u32;
+            pruned.insert(bucket, index);
+        }
+        Some(pruned)
+    } else {
+        None
+    };
+
+    // the lookup table only needs to be comfortably larger than the entries
+    args.vocab_size = usize::max(2 * size, 1024);
+    let mut vocab = Vocabulary::new(args.vocab_size, args.min_n, args.max_n, args.bucket)?;
+    vocab.set_label_prefix(&args.label_prefix);
+    vocab.set_word_ngrams(args.word_ngrams);
+    vocab.set_entries(words, n_tokens, args.threads)?;
+    vocab.set_pruned_buckets(pruned);
+    if vocab.n_words() as usize != n_words || vocab.n_labels() as usize != n_labels {
+        return Err(format_error(format!(
+            "dictionary header says {} words and {} labels, found {} and {}",
+            n_words,
+            n_labels,
+            vocab.n_words(),
+            vocab.n_labels()
+        )));
+    }
+    Ok(vocab)
+}
+
+fn read_dense<R: Read>(reader: &mut R) -> Result<Matrix> {
+    let rows = read_size(reader, true, "matrix rows")?;
+    let cols = read_size(reader, true, "matrix columns")?;
+    let n = rows
+        .checked_mul(cols)
+        .ok_or_else(|| format_error(format!("matrix of {} x {} is too large", rows, cols)))?;
+    Matrix::from_vec(rows, cols, read_f32s(reader, n)?)
+}
+
+fn read_product_quantizer<R: Read>(reader: &mut R) -> Result<ProductQuantizer> {
+    let dim = read_size(reader, false, "quantizer dim")?;
+    let nsubq = read_size(reader, false, "quantizer nsubq")?;
+    let dsub = read_size(reader, false, "quantizer dsub")?;
+    let lastdsub = read_size(reader, false, "quantizer lastdsub")?;
+    let centroids = read_f32s(reader, dim * KSUB)?;
+    let pq = ProductQuantizer::from_centroids(dim, dsub, centroids)?;
+    if pq.nsubq() != nsubq || pq.lastdsub() != lastdsub {
+        return Err(format_error(format!(
+            "inconsistent quantizer: {} dimensions in {} groups of {} (last {})",
+            dim, nsubq, dsub, lastdsub
+        )));
+    }
+    Ok(pq)
+}
+
+fn read_quantized<R: Read>(reader: &mut R) -> Result<QuantMatrix> {
+    let qnorm = read_bool(reader)?;
+    let rows = read_size(reader, true, "matrix rows")?;
+    let cols = read_size(reader, true, "matrix columns")?;
+    let code_size = read_size(reader, false, "code size")?;
+    let codes = read_u8s(reader, code_size)?;
+    let pq = read_product_quantizer(reader)?;
+    let norms = if qnorm {
+        let norm_codes = read_u8s(reader, rows)?;
+        Some((read_product_quantizer(reader)?, norm_codes))
+    } else {
+        None
+    };
+    if pq.dim() != cols {
+        return Err(RustTextError::DimensionMismatch {
+            expected: cols,
+            found: pq.dim(),
+        });
+    }
+    QuantMatrix::from_codes(rows, pq, codes, norms)
+}
+
+// A matrix stored dense, or product-quantized if `quantized`, as the dense
+// placeholder and compressed form `Model` keeps.
+fn read_matrix<R: Read>(
+    reader: &mut R,
+    quantized: bool,
+    dim: usize,
+) -> Result<(Matrix, Option<CompressedMatrix>)> {
+    if quantized {
+        let matrix = read_quantized(reader)?;
+        Ok((Matrix::new(0, dim), Some(CompressedMatrix::Product(matrix))))
+    } else {
+        Ok((read_dense(reader)?, None))
+    }
+}
+
+/// Read a model in fastText's `.bin` format: classifiers and word vector
+/// models alike, with dense or product-quantized matrices (as written by
+/// `fasttext quantize`), including pruned dictionaries. The result
+/// predicts and looks up vectors like a model trained here; hashing and
+/// subword extraction follow fastText, so out-of-vocabulary words get the
+/// same vectors. fastText doesn't save the learning rate or the label
+/// prefix, which keep their defaults.
+pub fn read_model<R: BufRead>(mut reader: R) -> Result<Model> {
+    if read_i32(&mut reader)? != MAGIC {
+        return Err(format_error(String::from("not a fastText model")));
+    }
+    let version = read_i32(&mut reader)?;
+    if version != 11 && version != VERSION {
+        return Err(format_error(format!(
+            "unsupported fastText model version {}",
+            version
+        )));
+    }
+    let (mut args, supervised) = read_args(&mut reader, version)?;
+    let vocab = read_vocabulary(&mut reader, &mut args)?;
+
+    let quant_input = read_bool(&mut reader)?;
+    if !quant_input && vocab.pruned_buckets().is_some() {
+        return Err(format_error(String::from(
+            "pruned dictionary with a dense input matrix",
+        )));
+    }
+    let input = read_matrix(&mut reader, quant_input, args.dim)?;
+    let quant_output = read_bool(&mut reader)?;
+    let output = read_matrix(&mut reader, quant_output, args.dim)?;
+    Model::from_parts(args, vocab, supervised, input, output)
+}
+
+/// `read_model` from the file at `path`.
+pub fn load<P: AsRef<Path>>(path: P) -> Result<Model> {
+    read_model(BufReader::new(File::open(path)?))
+}
+
+fn unsupported(message: &str) -> RustTextError {
+    RustTextError::InvalidArgument(format!("cannot save in fastText format: {}", message))
+}
+
+fn write_i32<W: Write>(writer: &mut W, value: i32) -> Result<()> {
+    writer.write_all(&value.to_le_bytes())?;
+    Ok(())
+}
+
+fn write_i64<W: Write>(writer: &mut W, value: i64) -> Result<()> {
+    writer.write_all(&value.to_le_bytes())?;
+    Ok(())
+}
+
+// A size field as a signed 32-bit int.
+fn write_size<W: Write, T>(writer: &mut W, value: T, what: &str) -> Result<()>
+where
+    i32: TryFrom<T>,
+{
+    let value = i32::try_from(value).map_err(|_| unsupported(&format!("{} is too large", what)))?;
+    write_i32(writer, value)
+}
+
+fn write_f32s<W: Write>(writer: &mut W, values: &[f32]) -> Result<()> {
+    for value in values.iter() {
+        writer.write_all(&value.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+fn write_args<W: Write>(writer: &mut W, model: &Model) -> Result<()> {
+    let args = model.args();
+    let vocab = model.vocabulary();
+    write_size(writer, args.dim, "dim")?;
+    write_size(writer, args.ws, "ws")?;
+    write_size(writer, args.epoch, "epoch")?;
+    write_size(writer, args.min_count, "min_count")?;
+    write_size(writer, args.neg, "neg")?;
+    write_size(writer, vocab.word_ngrams(), "word_ngrams")?;
+    write_i32(
+        writer,
+        match args.loss {
+            LossKind::HierarchicalSoftmax => LOSS_HS,
+            LossKind::NegativeSampling => LOSS_NS,
+            LossKind::Softmax => LOSS_SOFTMAX,
+            LossKind::OneVsAll => LOSS_OVA,
+        },
+    )?;
+    write_i32(
+        writer,
+        match (model.supervised, args.word_model) {
+            (true, _) => MODEL_SUP,
+            (false, WordModel::Skipgram) => MODEL_SG,
+            (false, WordModel::Cbow) => MODEL_CBOW,
+        },
+    )?;
+    // the vocabulary's subword settings are the ones its buckets follow
+    write_size(writer, vocab.bucket(), "bucket")?;
+    write_size(writer, vocab.min_n(), "min_n")?;
+    write_size(writer, vocab.max_n(), "max_n")?;
+    write_size(writer, args.lr_update_rate, "lr_update_rate")?;
+    writer.write_all(&args.t.to_le_bytes())?;
+    Ok(())
+}
+
+type PrunedBuckets = Vec<(u32, u32)>;
+
+// The entries to save and the pruned-bucket map (original bucket, index),
+// sorted by index. fastText has no notion of rows that read as zero, so if
+// the input matrix was quantized with a cutoff, words and buckets outside
+// it are pruned from the dictionary instead, as fastText's own
+// `quantize -cutoff` does. Dropped buckets then leave the feature mean
+// instead of adding zero to it, so scores can shift slightly.
+fn saved_dictionary(model: &Model) -> Result<(Vec<&WordEntry>, Option<PrunedBuckets>)> {
+    let vocab = model.vocabulary();
+    let n_words = vocab.n_words();
+    let mut pruned: Option<PrunedBuckets> = vocab.pruned_buckets().map(|pruned| {
+        let mut pruned: Vec<(u32, u32)> = pruned.iter().map(|(&b, &i)| (b, i)).collect();
+        pruned.sort_by_key(|&(_, index)| index);
+        pruned
+    });
+    let kept = match model.quant_input() {
+        Some(CompressedMatrix::Product(matrix)) => matrix.kept_rows(),
+        _ => None,
+    };
+    let kept = match kept {
+        Some(kept) => kept,
+        None => return Ok((vocab.words().iter().collect(), pruned)),
+    };
+    if !model.supervised {
+        return Err(unsupported(
+            "only classifiers can be quantized with a cutoff",
+        ));
+    }
+
+    let words = vocab
+        .words()
+        .iter()
+        .enumerate()
+        .filter(|(id, entry)| {
+            entry.entry_type == EntryType::Label || kept.binary_search(&(*id as u32)).is_ok()
+        })
+        .map(|(_, entry)| entry)
+        .collect();
+    let original = |index: u32| match &pruned {
+        Some(pruned) => pruned[index as usize].0,
+        None => index,
+    };
+    let buckets = kept
+        .iter()
+        .filter(|&&row| row >= n_words)
+        .enumerate()
+        .map(|(index, &row)| (original(row - n_words), index as u32))
+        .collect();
+    pruned = Some(buckets);
+    Ok((words, pruned))
+}
+
+fn write_vocabulary<W: Write>(writer: &mut W, model: &Model) -> Result<()> {
+    let vocab = model.vocabulary();
+    let (words, pruned) = saved_dictionary(model)?;
+    let n_words = words
+        .iter()
+        .filter(|entry| entry.entry_type == EntryType::Word)
+        .count();
+    write_size(writer, words.len(), "vocabulary size")?;
+    write_size(writer, n_words, "vocabulary size")?;
+    write_size(writer, words.len() - n_words, "label count")?;
+    write_i64(writer, vocab.n_tokens() as i64)?;
+    write_i64(
+        writer,
+        pruned.as_ref().map_or(-1, |pruned| pruned.len() as i64),
+    )?;
+    for entry in words.iter() {
+        if entry.word.as_bytes().contains(&0) {
+            return Err(unsupported(&format!(
+                "{:?} contains a NUL byte",
+                entry.word
+            )));
+        }
+        writer.write_all(entry.word.as_bytes())?;
+        writer.write_all(&[0])?;
+        write_i64(writer, i64::from(entry.count))?;
+        writer.write_all(&[match entry.entry_type {
+            EntryType::Word => 0,
+            EntryType::Label => 1,
+        }])?;
+    }
+    for &(bucket, index) in pruned.iter().flatten() {
+        write_size(writer, bucket, "bucket")?;
+        write_size(writer, index, "bucket index")?;
+    }
+    Ok(())
+}
+
+fn write_dense<W: Write>(writer: &mut W, matrix: &Matrix) -> Result<()> {
+    write_i64(writer, matrix.rows() as i64)?;
+    write_i64(writer, matrix.cols() as i64)?;
+    write_f32s(writer, matrix.data())
+}
+
+fn write_product_quantizer<W: Write>(writer: &mut W, pq: &ProductQuantizer) -> Result<()> {
+    write_size(writer, pq.dim(), "quantizer dim")?;
+    write_size(writer, pq.nsubq(), "quantizer nsubq")?;
+    write_size(writer, pq.dsub(), "quantizer dsub")?;
+    write_size(writer, pq.lastdsub(), "quantizer lastdsub")?;
+    write_f32s(writer, pq.centroids())
+}
+
+// Only the stored rows are written, so after a cutoff the row count is
+// that of the pruned dictionary.
+fn write_quantized<W: Write>(writer: &mut W, matrix: &QuantMatrix) -> Result<()> {
+    let rows = matrix.codes().len() / matrix.pq().nsubq();
+    writer.write_all(&[matrix.qnorm() as u8])?;
+    write_i64(writer, rows as i64)?;
+    write_i64(writer, matrix.cols() as i64)?;
+    write_size(writer, matrix.codes().len(), "code size")?;
+    writer.write_all(matrix.codes())?;
+    write_product_quantizer(writer, matrix.pq())?;
+    if let Some((npq, norm_codes)) = matrix.norm_codes() {
+        writer.write_all(norm_codes)?;
+        write_product_quantizer(writer, npq)?;
+    }
+    Ok(())
+}
+
+fn write_matrix<W: Write>(
+    writer: &mut W,
+    dense: &Matrix,
+    compressed: Option<&CompressedMatrix>,
+) -> Result<()> {
+    match compressed {
+        None => {
+            writer.write_all(&[0])?;
+            write_dense(writer, dense)
+        }
+        Some(CompressedMatrix::Product(matrix)) => {
+            writer.write_all(&[1])?;
+            write_quantized(writer, matrix)
+        }
+        Some(CompressedMatrix::Int8(_)) => Err(unsupported("int8 matrices have no equivalent")),
+    }
+}
+
+/// Write `model` in fastText's `.bin` format, readable by the fastText
+/// binary, the official Python package and `read_model`. Dense and
+/// product-quantized models are supported; the vocabulary must use
+/// fastText's hash and word markers and give words their own rows.
+/// Unsupervised models are saved as skipgram models.
+pub fn write_model<W: Write>(writer: W, model: &Model) -> Result<()> {
+    let vocab = model.vocabulary();
+    // fastText always hashes with its 32-bit FNV variant
+    if vocab.hasher().hash_mode() != Some(word::HashMode::Fnv32) {
+        return Err(unsupported("the vocabulary uses a different hash function"));
+    }
+    if vocab.bow() != word::BOW || vocab.eow() != word::EOW {
+        return Err(unsupported("the vocabulary uses custom word markers"));
+    }
+    if vocab.char_only() {
+        return Err(unsupported("character-only vocabularies have no word rows"));
+    }
+    if vocab.pruned_buckets().is_some() && model.quant_input().is_none() {
+        return Err(unsupported(
+            "fastText only reads pruned models once quantized",
+        ));
+    }
+
+    let mut writer = BufWriter::new(writer);
+    write_i32(&mut writer, MAGIC)?;
+    write_i32(&mut writer, VERSION)?;
+    write_args(&mut writer, model)?;
+    write_vocabulary(&mut writer, model)?;
+    write_matrix(&mut writer, model.input(), model.quant_input())?;
+    write_matrix(&mut writer, model.output(), model.quant_output())?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// `write_model` to a new file at `path`.
+pub fn save<P: AsRef<Path>>(model: &Model, path: P) -> Result<()> {
+    write_model(File::create(path)?, model)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::tests::corpus_factory;
+
+    fn i32s(out: &mut Vec<u8>, values: &[i32]) {
+        for value in values.iter() {
+            out.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+
+    fn f32s(out: &mut Vec<u8>, values: &[f32]) {
+        for value in values.iter() {
+            out.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+
+    // A fastText file with the given args tail (loss, model, bucket, minn,
+    // maxn) and dictionary, without matrices.
+    fn header(dim: i32, settings: [i32; 5], entries: &[(&str, i64, u8)], n_words: i32) -> Vec<u8> {
+        let mut out = Vec::new();
+        i32s(&mut out, &[MAGIC, VERSION]);
+        i32s(&mut out, &[dim, 5, 5, 1, 5, 1]);
+        i32s(&mut out, &settings);
+        i32s(&mut out, &[100]);
+        out.extend_from_slice(&1e-4f64.to_le_bytes());
+
+        let n_labels = entries.len() as i32 - n_words;
+        i32s(&mut out, &[entries.len() as i32, n_words, n_labels]);
+        out.extend_from_slice(&100i64.to_le_bytes());
+        out.extend_from_slice(&(-1i64).to_le_bytes());
+        for (word, count, entry_type) in entries.iter() {
+            out.extend_from_slice(word.as_bytes());
+            out.push(0);
+            out.extend_from_slice(&count.to_le_bytes());
+            out.push(*entry_type);
+        }
+        out
+    }
+
+    fn dense(out: &mut Vec<u8>, rows: usize, cols: usize, values: &[f32]) {
+        out.push(0);
+        out.extend_from_slice(&(rows as i64).to_le_bytes());
+        out.extend_from_slice(&(cols as i64).to_le_bytes());
+        f32s(out, values);
+    }
+
+    #[test]
+    fn test_read_word_vectors() {
+        // skipgram with 2 words (EOS included), minn 3 / maxn 3, 5 buckets
+        let mut bytes = header(
+            2,
+            [LOSS_NS, MODEL_SG, 5, 3, 3],
+            &[(word::EOS, 7, 0), ("ab", 3, 0)],
+            2,
+        );
+        let input: Vec<f32> = (0..14).map(|i| i as f32).collect();
+        dense(&mut bytes, 7, 2, &input);
+        dense(&mut bytes, 2, 2, &[0.5; 4]);
+
+        let model = read_model(&bytes[..]).unwrap();
+        assert!(!model.supervised);
+        assert_eq!(model.args().loss, LossKind::NegativeSampling);
+        assert_eq!((model.args().min_n, model.args().bucket), (3, 5));
+        assert_eq!(model.vocabulary().get_id("ab"), Some(1));
+        // EOS has no subwords, so its vector is its own row
+        assert_eq!(model.word_vector(word::EOS), vec![0.0, 1.0]);
+
+        // "ab" averages its row and the buckets of "<ab" and "ab>"
+        let buckets: Vec<u32> = model
+            .vocabulary()
+            .get_subwords("ab")
+            .into_iter()
+            .map(|(_, bucket)| bucket)
+            .collect();
+        let mut expected = input[2..4].to_vec();
+        for &bucket in buckets.iter() {
+            let row = 2 + bucket as usize;
+            expected[0] += input[2 * row];
+            expected[1] += input[2 * row + 1];
+        }
+        let vector = model.word_vector("ab");
+        for (v, e) in vector.iter().zip(expected.iter()) {
+            assert!((v - e / 3.0).abs() < 1e-5, "{:?} vs {:?}", vector, expected);
+        }
+    }
+
+    #[test]
+    fn test_read_quantized_classifier() {
+        // two labels with hierarchical softmax, input rows quantized with
+        // one sub-quantizer of two dimensions
+        let mut bytes = header(
+            2,
+            [LOSS_HS, MODEL_SUP, 0, 0, 0],
+            &[("hi", 4, 0), ("__label__a", 3, 1), ("__label__b", 1, 1)],
+            1,
+        );
+        bytes.push(1);
+        bytes.push(0);
+        bytes.extend_from_slice(&1i64.to_le_bytes());
+        bytes.extend_from_slice(&2i64.to_le_bytes());
+        i32s(&mut bytes, &[1]);
+        bytes.push(3);
+        i32s(&mut bytes, &[2, 1, 2, 2]);
+        let centroids: Vec<f32> = (0..2 * KSUB).map(|i| i as f32).collect();
+        f32s(&mut bytes, &centroids);
+        dense(&mut bytes, 2, 2, &[1.0, 0.0, 0.0, 0.0]);
+
+        let model = read_model(&bytes[..]).unwrap();
+        assert!(model.supervised && model.is_quantized());
+        assert_eq!(model.args().loss, LossKind::HierarchicalSoftmax);
+        // code 3 decodes to centroid (6, 7)
+        assert_eq!(model.word_vector("hi"), vec![6.0, 7.0]);
+        // the root's right branch is the frequent label, taken with
+        // probability sigmoid(6)
+        let predictions = model.predict("hi", 2, 0.0);
+        assert_eq!(predictions[0].0, "__label__a");
+        assert!((predictions[0].1 - crate::utils::sigmoid(6.0)).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_read_model_errors() {
+        assert!(matches!(
+            read_model(&[0u8; 8][..]),
+            Err(RustTextError::ModelFormat(_))
+        ));
+
+        let mut bytes = header(2, [LOSS_NS, MODEL_SG, 0, 0, 0], &[("a", 1, 0)], 1);
+        dense(&mut bytes, 3, 2, &[0.0; 6]);
+        dense(&mut bytes, 1, 2, &[0.0; 2]);
+        assert!(matches!(
+            read_model(&bytes[..]),
+            Err(RustTextError::ModelFormat(_))
+        ));
+        // truncated
+        assert!(read_model(&bytes[..bytes.len() - 3]).is_err());
+    }
+
+    fn round_trip(model: &Model) -> Model {
+        let mut bytes = Vec::new();
+        write_model(&mut bytes, model).unwrap();
+        read_model(&bytes[..]).unwrap()
+    }
+
+    #[test]
+    fn test_write_model_bit_exact() {
+        let mut bytes = header(
+            2,
+            [LOSS_NS, MODEL_SG, 5, 3, 3],
+            &[(word::EOS, 7, 0), ("ab", 3, 0), ("é", 1, 0)],
+            3,
+        );
+        dense(&mut bytes, 8, 2, &[0.25; 16]);
+        dense(&mut bytes, 3, 2, &[-1.0; 6]);
+
+        let mut written = Vec::new();
+        write_model(&mut written, &read_model(&bytes[..]).unwrap()).unwrap();
+        assert_eq!(written, bytes);
+    }
+
+    #[test]
+    fn test_save_classifier() {
+        let mut text = String::new();
+        for i in 0..100 {
+            text.push_str(&format!("__label__fruit apple banana cherry f{}\n", i % 40));
+            text.push_str(&format!("__label__letter xray yankee zulu l{}\n", i % 40));
+        }
+        let corpus = corpus_factory(&text);
+        let args = Args {
+            dim: 10,
+            word_ngrams: 2,
+            bucket: 1000,
+            vocab_size: 1001,
+            threads: 1,
+            loss: LossKind::HierarchicalSoftmax,
+            ..Args::supervised()
+        };
+        let mut model = Model::train_supervised(corpus.path(), args).unwrap();
+
+        let loaded = round_trip(&model);
+        assert_eq!(loaded.args().loss, LossKind::HierarchicalSoftmax);
+        assert_eq!(loaded.args().word_ngrams, 2);
+        for text in ["apple banana", "zulu f3", "unseen words"].iter() {
+            assert_eq!(loaded.predict(text, 2, 0.0), model.predict(text, 2, 0.0));
+        }
+
+        // a cutoff becomes a pruned dictionary
+        model
+            .quantize(&crate::quant::QuantArgs {
+                cutoff: 300,
+                qnorm: true,
+                ..Default::default()
+            })
+            .unwrap();
+        let loaded = round_trip(&model);
+        assert!(loaded.vocabulary().pruned_buckets().is_some());
+        assert!(loaded.vocabulary().n_words() <= model.vocabulary().n_words());
+        for text in ["apple banana", "zulu yankee"].iter() {
+            assert_eq!(
+                loaded.predict(text, 1, 0.0)[0].0,
+                model.predict(text, 1, 0.0)[0].0
+            );
+        }
+        assert_eq!(
+            round_trip(&loaded).predict("apple", 2, 0.0),
+            loaded.predict("apple", 2, 0.0)
+        );
+
+        model.quant_input = None;
+        model.input = Matrix::new(0, 10);
+        model.quantize_int8().unwrap();
+        assert!(matches!(
+            write_model(Vec::new(), &model),
+            Err(RustTextError::InvalidArgument(_))
+        ));
+    }
+
+    #[test]
+    fn test_save_word_vectors() {
+        let corpus = corpus_factory(&"the quick brown fox jumps over the lazy dog\n".repeat(20));
+        let args = Args {
+            dim: 8,
+            epoch: 1,
+            min_count: 1,
+            bucket: 500,
+            vocab_size: 1001,
+            threads: 1,
+            ..Args::default()
+        };
+        let model = Model::train_unsupervised(corpus.path(), args).unwrap();
+
+        let loaded = round_trip(&model);
+        assert!(!loaded.supervised);
+        assert_eq!(loaded.input(), model.input());
+        assert_eq!(loaded.output(), model.output());
+        for word in ["fox", "foxes", "</s>"].iter() {
+            assert_eq!(loaded.word_vector(word), model.word_vector(word));
+        }
+    }
+}
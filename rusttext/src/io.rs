@@ -0,0 +1,804 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::matrix::Matrix;
+use crate::vocabulary::Vocabulary;
+use crate::word::EntryType;
+use crate::{Result, RustTextError};
+
+// Floats read per chunk by `read_f32s`, so a corrupt size can't make us
+// allocate the whole claimed matrix up front.
+const CHUNK: usize = 1 << 16;
+
+/// Read `n` little-endian `f32`s, as stored in binary model files.
+pub(crate) fn read_f32s<R: Read>(reader: &mut R, n: usize) -> Result<Vec<f32>> {
+    let mut data = Vec::new();
+    let mut buffer = vec![0; 4 * usize::min(n, CHUNK)];
+    while data.len() < n {
+        let bytes = &mut buffer[..4 * usize::min(n - data.len(), CHUNK)];
+        reader.read_exact(bytes)?;
+        data.extend(
+            bytes
+                .chunks_exact(4)
+                .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])),
+        );
+    }
+    Ok(data)
+}
+
+/// How a corpus file is compressed, told by its first bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Compression {
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    fn detect(file: &mut File) -> Result<Option<Compression>> {
+        let mut magic = Vec::with_capacity(4);
+        Read::by_ref(file).take(4).read_to_end(&mut magic)?;
+        file.seek(SeekFrom::Start(0))?;
+        Ok(if magic.starts_with(&[0x1f, 0x8b]) {
+            Some(Compression::Gzip)
+        } else if magic == [0x28, 0xb5, 0x2f, 0xfd] {
+            Some(Compression::Zstd)
+        } else {
+            None
+        })
+    }
+
+    /// Decompressed lines of `reader`.
+    #[cfg(feature = "compression")]
+    pub(crate) fn decode<'a, R: Read + Send + 'a>(
+        self,
+        reader: R,
+    ) -> Result<Box<dyn BufRead + Send + 'a>> {
+        Ok(match self {
+            Compression::Gzip => {
+                Box::new(BufReader::new(flate2::read::MultiGzDecoder::new(reader)))
+            }
+            Compression::Zstd => Box::new(BufReader::new(zstd::Decoder::new(reader)?)),
+        })
+    }
+
+    #[cfg(not(feature = "compression"))]
+    pub(crate) fn decode<'a, R: Read + Send + 'a>(
+        self,
+        _reader: R,
+    ) -> Result<Box<dyn BufRead + Send + 'a>> {
+        Err(RustTextError::Corpus(format!(
+            "reading {:?}-compressed corpora needs the compression feature",
+            self
+        )))
+    }
+}
+
+/// Open the training corpus at `path`, with the compression its first
+/// bytes show, if any. Training never loads the corpus; see `CorpusLines`.
+pub(crate) fn open_corpus(path: &Path) -> Result<(File, Option<Compression>)> {
+    let mut file = File::open(path)?;
+    let compression = Compression::detect(&mut file)?;
+    Ok((file, compression))
+}
+
+/// The lines of a training corpus that one of `threads` training threads
+/// reads, a line at a time and over and over, so memory use doesn't grow
+/// with the corpus. A plain file is split into byte ranges, each thread
+/// starting at its own and wrapping around at the end. A gzip or zstd file
+/// can't be entered midway, so every thread decompresses all of it with a
+/// fresh decoder each pass and keeps every `threads`-th line. Lines
+/// starting at the (decompressed) byte offsets in `skip` are passed over.
+pub(crate) struct CorpusLines<'a> {
+    path: &'a Path,
+    reader: Lines<'a>,
+    thread: usize,
+    threads: usize,
+    line: usize,
+    skip: &'a [u64],
+    offset: u64,
+}
+
+enum Lines<'a> {
+    Plain(BufReader<File>),
+    Compressed(Compression, Box<dyn BufRead + Send + 'a>),
+}
+
+impl<'a> CorpusLines<'a> {
+    pub(crate) fn open(
+        path: &'a Path,
+        thread: usize,
+        threads: usize,
+        skip: &'a [u64],
+    ) -> Result<CorpusLines<'a>> {
+        let (mut file, compression) = open_corpus(path)?;
+        let mut offset = 0;
+        let reader = match compression {
+            Some(compression) => Lines::Compressed(compression, compression.decode(file)?),
+            None => {
+                offset = file.metadata()?.len() * thread as u64 / threads as u64;
+                file.seek(SeekFrom::Start(offset))?;
+                let mut reader = BufReader::new(file);
+                if offset > 0 {
+                    // finish the line the offset landed in
+                    offset += reader.read_line(&mut String::new())? as u64;
+                }
+                Lines::Plain(reader)
+            }
+        };
+        Ok(CorpusLines {
+            path,
+            reader,
+            thread,
+            threads,
+            line: 0,
+            skip,
+            offset,
+        })
+    }
+
+    /// Replace `line` with this thread's next line and return its length,
+    /// or 0 at the end of the corpus, having gone back to its start.
+    pub(crate) fn read_line(&mut self, line: &mut String) -> Result<usize> {
+        loop {
+            line.clear();
+            let n = match &mut self.reader {
+                Lines::Plain(reader) => {
+                    let n = reader.read_line(line)?;
+                    if n == 0 {
+                        reader.seek(SeekFrom::Start(0))?;
+                    }
+                    n
+                }
+                Lines::Compressed(compression, reader) => {
+                    let n = reader.read_line(line)?;
+                    if n == 0 {
+                        let (file, _) = open_corpus(self.path)?;
+                        *reader = compression.decode(file)?;
+                        self.line = 0;
+                    }
+                    n
+                }
+            };
+            if n == 0 {
+                self.offset = 0;
+                return Ok(0);
+            }
+            let start = self.offset;
+            self.offset += n as u64;
+            if let Lines::Compressed(..) = self.reader {
+                self.line += 1;
+                if (self.line - 1) % self.threads != self.thread {
+                    continue;
+                }
+            }
+            if self.skip.binary_search(&start).is_err() {
+                return Ok(n);
+            }
+        }
+    }
+}
+
+/// A reader that hashes the bytes read through it with 64-bit FNV-1a, so
+/// the vocabulary pass over a corpus also fingerprints it.
+pub(crate) struct HashingReader<R> {
+    inner: R,
+    hash: u64,
+}
+
+impl<R: Read> HashingReader<R> {
+    pub(crate) fn new(inner: R) -> HashingReader<R> {
+        HashingReader {
+            inner,
+            hash: 14695981039346656037,
+        }
+    }
+
+    /// Hash of everything read so far.
+    pub(crate) fn hash(&self) -> u64 {
+        self.hash
+    }
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        for &byte in &buf[..n] {
+            self.hash ^= u64::from(byte);
+            self.hash = self.hash.wrapping_mul(1099511628211);
+        }
+        Ok(n)
+    }
+}
+
+/// Which vocabulary entries to include when exporting vectors. The default
+/// keeps every word and drops labels.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ExportFilter {
+    /// Skip entries seen fewer than this many times.
+    pub min_count: u32,
+    /// Keep only the N most frequent entries that pass the other filters.
+    pub top_n: Option<usize>,
+    pub include_labels: bool,
+}
+
+impl ExportFilter {
+    /// Entry ids passing the filter, most frequent first.
+    pub fn select(&self, vocab: &Vocabulary) -> Vec<u32> {
+        let words = vocab.words();
+        let mut ids: Vec<u32> = (0..words.len() as u32)
+            .filter(|&id| {
+                let entry = &words[id as usize];
+                entry.count >= self.min_count
+                    && (self.include_labels || entry.entry_type == EntryType::Word)
+            })
+            .collect();
+        ids.sort_by(|&left, &right| words[right as usize].count.cmp(&words[left as usize].count));
+        if let Some(top_n) = self.top_n {
+            ids.truncate(top_n);
+        }
+        ids
+    }
+}
+
+/// Stream vectors in `.vec` text format: a `count dim` header, then one
+/// `word v_1 ... v_dim` line per selected entry. Rows are fetched one at a
+/// time through `row`, so nothing beyond the selected ids is materialised.
+/// Returns the number of rows written.
+pub fn write_vectors<W, F, R>(
+    writer: W,
+    vocab: &Vocabulary,
+    dim: usize,
+    filter: &ExportFilter,
+    row: F,
+) -> Result<usize>
+where
+    W: Write,
+    F: Fn(u32) -> R,
+    R: AsRef<[f32]>,
+{
+    let ids = filter.select(vocab);
+    let mut writer = BufWriter::new(writer);
+
+    writeln!(writer, "{} {}", ids.len(), dim)?;
+    for &id in ids.iter() {
+        let vector = row(id);
+        let vector = vector.as_ref();
+        if vector.len() != dim {
+            return Err(RustTextError::DimensionMismatch {
+                expected: dim,
+                found: vector.len(),
+            });
+        }
+        write!(writer, "{}", vocab.words()[id as usize].word)?;
+        for value in vector.iter() {
+            write!(writer, " {}", value)?;
+        }
+        writeln!(writer)?;
+    }
+    writer.flush()?;
+    Ok(ids.len())
+}
+
+/// Write vectors for the TensorBoard Embedding Projector: one
+/// tab-separated vector per line to `vectors`, and a `word`, `count`,
+/// `type` header then one matching line per entry to `metadata`, with
+/// type `word` or `label`. Entries are chosen by `filter` and fetched
+/// through `row` as in `write_vectors`. Returns the number of rows written.
+pub fn write_projector<V, M, F, R>(
+    vectors: V,
+    metadata: M,
+    vocab: &Vocabulary,
+    dim: usize,
+    filter: &ExportFilter,
+    row: F,
+) -> Result<usize>
+where
+    V: Write,
+    M: Write,
+    F: Fn(u32) -> R,
+    R: AsRef<[f32]>,
+{
+    let ids = filter.select(vocab);
+    let mut vectors = BufWriter::new(vectors);
+    let mut metadata = BufWriter::new(metadata);
+
+    writeln!(metadata, "word\tcount\ttype")?;
+    for &id in ids.iter() {
+        let vector = row(id);
+        let vector = vector.as_ref();
+        if vector.len() != dim {
+            return Err(RustTextError::DimensionMismatch {
+                expected: dim,
+                found: vector.len(),
+            });
+        }
+        let values: Vec<String> = vector.iter().map(f32::to_string).collect();
+        writeln!(vectors, "{}", values.join("\t"))?;
+        let entry = &vocab.words()[id as usize];
+        let entry_type = match entry.entry_type {
+            EntryType::Word => "word",
+            EntryType::Label => "label",
+        };
+        writeln!(metadata, "{}\t{}\t{}", entry.word, entry.count, entry_type)?;
+    }
+    vectors.flush()?;
+    metadata.flush()?;
+    Ok(ids.len())
+}
+
+/// Parse vectors in `.vec` text format, returning the words in file order
+/// and a matrix whose rows line up with them.
+pub fn read_vectors<R: BufRead>(reader: R) -> Result<(Vec<String>, Matrix)> {
+    let mut lines = reader.lines();
+    let header = match lines.next() {
+        Some(line) => line?,
+        None => {
+            return Err(RustTextError::ModelFormat(String::from(
+                "empty vector file",
+            )))
+        }
+    };
+    let mut fields = header.split_whitespace();
+    let (count, dim): (usize, usize) = match (fields.next(), fields.next(), fields.next()) {
+        (Some(count), Some(dim), None) => (count.parse()?, dim.parse()?),
+        _ => {
+            return Err(RustTextError::ModelFormat(format!(
+                "bad vector file header: {}",
+                header
+            )))
+        }
+    };
+
+    if count.checked_mul(dim).is_none() {
+        return Err(RustTextError::ModelFormat(format!(
+            "bad vector file header: {}",
+            header
+        )));
+    }
+
+    // grown row by row, so a header can't claim more than the file holds
+    let mut words = Vec::new();
+    let mut data = Vec::new();
+    for (i, line) in lines.enumerate().take(count) {
+        let line = line?;
+        let mut fields = line.split_whitespace();
+        let word = match fields.next() {
+            Some(word) => word,
+            None => {
+                return Err(RustTextError::ModelFormat(format!(
+                    "empty line {} in vector file",
+                    i + 2
+                )))
+            }
+        };
+        let before = data.len();
+        for field in fields {
+            data.push(field.parse::<f32>()?);
+        }
+        if data.len() - before != dim {
+            return Err(RustTextError::ModelFormat(format!(
+                "vector for {} has dimension {}, expected {}",
+                word,
+                data.len() - before,
+                dim
+            )));
+        }
+        words.push(word.to_string());
+    }
+    if words.len() != count {
+        return Err(RustTextError::ModelFormat(format!(
+            "vector file has {} rows, header says {}",
+            words.len(),
+            count
+        )));
+    }
+    Ok((words, Matrix::from_vec(count, dim, data)?))
+}
+
+/// `read_vectors` from the `.vec` file at `path`, as written by fastText,
+/// word2vec's text output or `Model::save_vectors`.
+pub fn read_vec_file<P: AsRef<Path>>(path: P) -> Result<(Vec<String>, Matrix)> {
+    read_vectors(BufReader::new(File::open(path)?))
+}
+
+/// Parse GloVe's headerless text format: one `word v_1 ... v_dim` line per
+/// word, with `dim` taken from the first line. The vector is read from the
+/// end of each line, so words containing spaces (as in the 840B-token
+/// release) are kept whole.
+pub fn read_glove<R: BufRead>(reader: R) -> Result<(Vec<String>, Matrix)> {
+    let mut words = Vec::new();
+    let mut data = Vec::new();
+    let mut dim = None;
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            continue;
+        }
+        let dim = *dim.get_or_insert_with(|| line.split(' ').count() - 1);
+        let mut fields = line.rsplitn(dim + 1, ' ');
+        let before = data.len();
+        for field in fields.by_ref().take(dim) {
+            data.push(field.parse::<f32>()?);
+        }
+        data[before..].reverse();
+        match fields.next() {
+            Some(word) if !word.is_empty() && data.len() - before == dim => {
+                words.push(word.to_string())
+            }
+            _ => {
+                return Err(RustTextError::ModelFormat(format!(
+                    "line {} does not hold a word and {} values",
+                    i + 1,
+                    dim
+                )))
+            }
+        }
+    }
+    match dim {
+        Some(dim) if dim > 0 => {
+            let matrix = Matrix::from_vec(words.len(), dim, data)?;
+            Ok((words, matrix))
+        }
+        _ => Err(RustTextError::ModelFormat(String::from(
+            "no vectors in GloVe file",
+        ))),
+    }
+}
+
+/// `read_glove` from the file at `path`.
+pub fn read_glove_file<P: AsRef<Path>>(path: P) -> Result<(Vec<String>, Matrix)> {
+    read_glove(BufReader::new(File::open(path)?))
+}
+
+/// Stream vectors in word2vec's binary format: a `count dim` text header,
+/// then per selected entry the word, a space, `dim` little-endian `f32`s
+/// and a newline. Otherwise as `write_vectors`.
+pub fn write_word2vec<W, F, R>(
+    writer: W,
+    vocab: &Vocabulary,
+    dim: usize,
+    filter: &ExportFilter,
+    row: F,
+) -> Result<usize>
+where
+    W: Write,
+    F: Fn(u32) -> R,
+    R: AsRef<[f32]>,
+{
+    let ids = filter.select(vocab);
+    let mut writer = BufWriter::new(writer);
+
+    writeln!(writer, "{} {}", ids.len(), dim)?;
+    for &id in ids.iter() {
+        let vector = row(id);
+        let vector = vector.as_ref();
+        if vector.len() != dim {
+            return Err(RustTextError::DimensionMismatch {
+                expected: dim,
+                found: vector.len(),
+            });
+        }
+        write!(writer, "{} ", vocab.words()[id as usize].word)?;
+        for value in vector.iter() {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+        writeln!(writer)?;
+    }
+    writer.flush()?;
+    Ok(ids.len())
+}
+
+/// Largest vector size `read_word2vec` accepts from a header.
+pub const MAX_WORD2VEC_DIM: usize = 1 << 16;
+
+/// Parse vectors in word2vec's binary format, as written by word2vec with
+/// `-binary 1` (e.g. the GoogleNews vectors) or `write_word2vec`. The
+/// newline after each vector is optional, and invalid UTF-8 in words is
+/// replaced rather than rejected.
+pub fn read_word2vec<R: BufRead>(mut reader: R) -> Result<(Vec<String>, Matrix)> {
+    let mut header = String::new();
+    reader.read_line(&mut header)?;
+    let mut fields = header.split_whitespace();
+    let (count, dim): (usize, usize) = match (fields.next(), fields.next(), fields.next()) {
+        (Some(count), Some(dim), None) => (count.parse()?, dim.parse()?),
+        _ => {
+            return Err(RustTextError::ModelFormat(format!(
+                "bad word2vec header: {}",
+                header.trim_end()
+            )))
+        }
+    };
+
+    if dim > MAX_WORD2VEC_DIM || count.checked_mul(dim).is_none() {
+        return Err(RustTextError::ModelFormat(format!(
+            "bad word2vec header: {}",
+            header.trim_end()
+        )));
+    }
+
+    let mut words = Vec::new();
+    let mut data = Vec::new();
+    let mut bytes = vec![0; 4 * dim];
+    for _ in 0..count {
+        let mut word = Vec::new();
+        reader.read_until(b' ', &mut word)?;
+        if word.pop() != Some(b' ') {
+            return Err(RustTextError::ModelFormat(format!(
+                "word2vec file has {} rows, header says {}",
+                words.len(),
+                count
+            )));
+        }
+        let start = word.iter().position(|b| !b.is_ascii_whitespace());
+        let word = &word[start.unwrap_or(word.len())..];
+        reader.read_exact(&mut bytes)?;
+        data.extend(
+            bytes
+                .chunks_exact(4)
+                .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])),
+        );
+        words.push(String::from_utf8_lossy(word).into_owned());
+    }
+    Ok((words, Matrix::from_vec(count, dim, data)?))
+}
+
+/// `read_word2vec` from the file at `path`.
+pub fn read_word2vec_file<P: AsRef<Path>>(path: P) -> Result<(Vec<String>, Matrix)> {
+    read_word2vec(BufReader::new(File::open(path)?))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use tempfile::NamedTempFile;
+
+    // A temporary corpus file holding `text`, deleted when dropped.
+    pub(crate) fn corpus_factory(text: &str) -> NamedTempFile {
+        let mut corpus = NamedTempFile::new().unwrap();
+        corpus.write_all(text.as_bytes()).unwrap();
+        corpus.flush().unwrap();
+        corpus
+    }
+
+    fn test_vocab() -> Vocabulary {
+        let mut vocab = Vocabulary::new(101, 0, 0, 10).unwrap();
+        for (word, count) in [("a", 1), ("b", 3), ("c", 2), ("__label__x", 5)].iter() {
+            for _ in 0..*count {
+                vocab.add(word).unwrap();
+            }
+        }
+        vocab
+    }
+
+    #[test]
+    fn test_write_vectors() {
+        let vocab = test_vocab();
+        let mut out = Vec::new();
+
+        let written = write_vectors(&mut out, &vocab, 2, &ExportFilter::default(), |id| {
+            vec![id as f32, 0.5]
+        })
+        .unwrap();
+
+        assert_eq!(written, 3);
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "3 2\nb 1 0.5\nc 2 0.5\na 0 0.5\n"
+        );
+    }
+
+    #[test]
+    fn test_write_vectors_filtered() {
+        let vocab = test_vocab();
+        let filter = ExportFilter {
+            min_count: 2,
+            top_n: Some(2),
+            include_labels: true,
+        };
+        let mut out = Vec::new();
+
+        write_vectors(&mut out, &vocab, 1, &filter, |id| [id as f32]).unwrap();
+
+        assert_eq!(String::from_utf8(out).unwrap(), "2 1\n__label__x 3\nb 1\n");
+    }
+
+    #[test]
+    fn test_write_vectors_bad_dim() {
+        let vocab = test_vocab();
+        let result = write_vectors(Vec::new(), &vocab, 3, &ExportFilter::default(), |_| [0.0]);
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_write_projector() {
+        let vocab = test_vocab();
+        let filter = ExportFilter {
+            include_labels: true,
+            ..Default::default()
+        };
+        let (mut vectors, mut metadata) = (Vec::new(), Vec::new());
+
+        let written = write_projector(&mut vectors, &mut metadata, &vocab, 2, &filter, |id| {
+            vec![id as f32, -0.5]
+        })
+        .unwrap();
+
+        assert_eq!(written, 4);
+        assert_eq!(
+            String::from_utf8(vectors).unwrap(),
+            "3\t-0.5\n1\t-0.5\n2\t-0.5\n0\t-0.5\n"
+        );
+        assert_eq!(
+            String::from_utf8(metadata).unwrap(),
+            "word\tcount\ttype\n__label__x\t5\tlabel\nb\t3\tword\nc\t2\tword\na\t1\tword\n"
+        );
+    }
+
+    #[test]
+    fn test_export_projector() {
+        let model = crate::vectors::tests::fixed(&["x", "y"], vec![1.0, -2.5, 0.25, 3.0]);
+        let dir = std::env::temp_dir().join(format!("rusttext-projector-{}", std::process::id()));
+
+        assert_eq!(model.export_projector(&dir).unwrap(), 2);
+        let vectors = std::fs::read_to_string(dir.join("vectors.tsv")).unwrap();
+        let metadata = std::fs::read_to_string(dir.join("metadata.tsv")).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        for (vector, entry) in vectors.lines().zip(metadata.lines().skip(1)) {
+            let word = entry.split('\t').next().unwrap();
+            let values: Vec<f32> = vector.split('\t').map(|v| v.parse().unwrap()).collect();
+            assert_eq!(values, model.word_vector(word));
+        }
+    }
+
+    #[test]
+    fn test_read_vectors() {
+        let text = "2 3\nfoo 1 2 3\nbar -0.5 0 1e-3\n";
+        let (words, matrix) = read_vectors(text.as_bytes()).unwrap();
+
+        assert_eq!(words, vec!["foo", "bar"]);
+        assert_eq!(matrix.row(1), &[-0.5, 0.0, 1e-3]);
+
+        assert!(read_vectors("2 3\nfoo 1 2 3\n".as_bytes()).is_err());
+        assert!(read_vectors("1 3\nfoo 1 2\n".as_bytes()).is_err());
+        assert!(read_vectors("1\nfoo 1\n".as_bytes()).is_err());
+        // headers claiming more than the file holds fail without allocating
+        let huge = format!("{} 2\nfoo 1 2\n", usize::MAX / 4);
+        assert!(read_vectors(huge.as_bytes()).is_err());
+        let overflow = format!("{0} {0}\nfoo 1 2\n", usize::MAX);
+        assert!(read_vectors(overflow.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_vec_file_round_trip() {
+        let model = crate::vectors::tests::fixed(&["x", "y"], vec![1.0, -2.5, 0.25, 3.0]);
+        let path = std::env::temp_dir().join(format!("rusttext-io-{}.vec", std::process::id()));
+
+        model.save_vectors(&path).unwrap();
+        let (words, matrix) = read_vec_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(matrix.cols(), 2);
+        for (i, word) in words.iter().enumerate() {
+            assert_eq!(matrix.row(i), model.word_vector(word).as_slice());
+        }
+        let mut sorted = words.clone();
+        sorted.sort();
+        assert_eq!(sorted, vec!["x", "y"]);
+        assert!(read_vec_file(&path).is_err());
+    }
+
+    #[test]
+    fn test_word2vec_round_trip() {
+        let vocab = test_vocab();
+        let mut out = Vec::new();
+
+        let written = write_word2vec(&mut out, &vocab, 2, &ExportFilter::default(), |id| {
+            vec![id as f32, -0.5]
+        })
+        .unwrap();
+        assert_eq!(written, 3);
+        assert!(out.starts_with(b"3 2\nb "));
+
+        let (words, matrix) = read_word2vec(&out[..]).unwrap();
+        assert_eq!(words, vec!["b", "c", "a"]);
+        assert_eq!(matrix.row(1), &[2.0, -0.5]);
+
+        // rows without the trailing newline, as some writers produce
+        let mut packed = b"2 1\nx ".to_vec();
+        packed.extend_from_slice(&1.5f32.to_le_bytes());
+        packed.extend_from_slice(b"y ");
+        packed.extend_from_slice(&(-2f32).to_le_bytes());
+        let (words, matrix) = read_word2vec(&packed[..]).unwrap();
+        assert_eq!(words, vec!["x", "y"]);
+        assert_eq!(matrix.data(), &[1.5, -2.0]);
+
+        assert!(read_word2vec(&packed[..packed.len() - 1]).is_err());
+        assert!(read_word2vec(&b"3 1\nx \0\0\0\0"[..]).is_err());
+        let huge = format!("1 {}\nx ", MAX_WORD2VEC_DIM + 1);
+        assert!(read_word2vec(huge.as_bytes()).is_err());
+        let overflow = format!("{} 2\nx ", usize::MAX);
+        assert!(read_word2vec(overflow.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_read_glove() {
+        let text = "the 0.5 -1 2\n, 0 0 1e-2\n\n. . . 1 2 3\n";
+        let (words, matrix) = read_glove(text.as_bytes()).unwrap();
+
+        assert_eq!(words, vec!["the", ",", ". . ."]);
+        assert_eq!((matrix.rows(), matrix.cols()), (3, 3));
+        assert_eq!(matrix.row(0), &[0.5, -1.0, 2.0]);
+        assert_eq!(matrix.row(2), &[1.0, 2.0, 3.0]);
+
+        assert!(read_glove("the 1 2\nof 1\n".as_bytes()).is_err());
+        assert!(read_glove("the 1 2\nof 1 x\n".as_bytes()).is_err());
+        assert!(read_glove("".as_bytes()).is_err());
+        assert!(read_glove("lonely\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "compression")]
+    fn test_compressed_corpus() {
+        use crate::args::Args;
+        use crate::model::Model;
+        use std::io::Write;
+
+        let dir = std::env::temp_dir();
+        let name = |ext: &str| dir.join(format!("rusttext_corpus_{}.{}", std::process::id(), ext));
+        let mut text = String::new();
+        for i in 0..50 {
+            text.push_str(&format!("__label__fruit apple banana f{}\n", i % 7));
+            text.push_str(&format!("__label__car truck wheel c{}\n", i % 5));
+        }
+        let plain = name("txt");
+        std::fs::write(&plain, &text).unwrap();
+        let gzip = name("txt.gz");
+        let mut encoder =
+            flate2::write::GzEncoder::new(File::create(&gzip).unwrap(), Default::default());
+        encoder.write_all(text.as_bytes()).unwrap();
+        encoder.finish().unwrap();
+        let zstd = name("txt.zst");
+        std::fs::write(&zstd, zstd::encode_all(text.as_bytes(), 0).unwrap()).unwrap();
+
+        let args = Args {
+            dim: 5,
+            epoch: 3,
+            bucket: 100,
+            threads: 1,
+            ..Args::supervised()
+        };
+        let expected = Model::train_supervised(&plain, args.clone()).unwrap();
+        for path in [&gzip, &zstd].iter() {
+            let model = Model::train_supervised(path, args.clone()).unwrap();
+            assert_eq!(model.vocabulary().words(), expected.vocabulary().words());
+            // one thread reads the lines in the same order either way
+            assert_eq!(model.input(), expected.input());
+            let metadata = model.metadata().unwrap();
+            assert_eq!(
+                metadata.corpus_hash,
+                crate::metadata::hash_corpus(path).unwrap()
+            );
+
+            let mut lines = CorpusLines::open(path, 1, 2, &[]).unwrap();
+            let mut line = String::new();
+            lines.read_line(&mut line).unwrap();
+            assert!(line.starts_with("__label__car"));
+            let threaded = Model::train_supervised(
+                path,
+                Args {
+                    threads: 2,
+                    ..args.clone()
+                },
+            )
+            .unwrap();
+            assert_eq!(threaded.training_stats().unwrap().tokens, 3 * 5 * 100);
+        }
+        for path in [&plain, &gzip, &zstd].iter() {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+}
@@ -0,0 +1,149 @@
+use std::path::Path;
+
+use crate::model::Model;
+use crate::{Result, RustTextError};
+
+/// Language identification with a fastText language ID classifier such as
+/// `lid.176.bin` or its quantized `lid.176.ftz`, returning bare language
+/// codes (`en`, `de`, ...) instead of `__label__`-prefixed labels.
+pub struct LanguageIdentifier {
+    model: Model,
+    /// Languages returned per text, most probable first.
+    pub k: usize,
+    /// Languages less probable than this are left out.
+    pub threshold: f32,
+}
+
+impl LanguageIdentifier {
+    /// Wrap a trained or loaded classifier, returning the single most
+    /// probable language, as fastText's `predict` does by default.
+    pub fn new(model: Model) -> Result<LanguageIdentifier> {
+        if !model.supervised {
+            return Err(RustTextError::InvalidArgument(String::from(
+                "language identification needs a supervised model",
+            )));
+        }
+        Ok(LanguageIdentifier {
+            model,
+            k: 1,
+            threshold: 0.0,
+        })
+    }
+
+    /// Load a fastText `.bin` or `.ftz` language ID model; see
+    /// `Model::load_fasttext`.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<LanguageIdentifier> {
+        LanguageIdentifier::new(Model::load_fasttext(path)?)
+    }
+
+    pub fn model(&self) -> &Model {
+        &self.model
+    }
+
+    /// Every language the model knows, in label order.
+    pub fn languages(&self) -> Vec<&str> {
+        let vocab = self.model.vocabulary();
+        vocab.words()[vocab.n_words() as usize..]
+            .iter()
+            .map(|entry| self.strip(&entry.word))
+            .collect()
+    }
+
+    fn strip<'a>(&self, label: &'a str) -> &'a str {
+        let prefix = self.model.vocabulary().label_prefix();
+        label.strip_prefix(prefix).unwrap_or(label)
+    }
+
+    fn strip_all(&self, predictions: Vec<(String, f32)>) -> Vec<(String, f32)> {
+        predictions
+            .into_iter()
+            .map(|(label, p)| (self.strip(&label).to_string(), p))
+            .collect()
+    }
+
+    /// The `k` most probable languages of `text` with probability at least
+    /// `threshold`, best first. Line breaks are treated as spaces, so a
+    /// multi-line text is classified as a whole.
+    pub fn identify(&self, text: &str) -> Vec<(String, f32)> {
+        self.strip_all(self.model.predict(text, self.k, self.threshold))
+    }
+
+    /// `identify` for every text in `texts`, in parallel; results are in
+    /// input order.
+    pub fn identify_batch(&self, texts: &[&str]) -> Vec<Vec<(String, f32)>> {
+        self.model
+            .predict_batch(texts, self.k, self.threshold)
+            .into_iter()
+            .map(|predictions| self.strip_all(predictions))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args::{Args, LossKind};
+    use crate::quant::QuantArgs;
+
+    // A miniature lid.176: hierarchical softmax over character n-grams,
+    // quantized with a cutoff and saved as a fastText `.ftz`.
+    #[test]
+    fn test_identify_ftz() {
+        let dir = std::env::temp_dir();
+        let corpus = dir.join(format!("rusttext-langid-{}.txt", std::process::id()));
+        let mut text = String::new();
+        for _ in 0..60 {
+            text.push_str("__label__en the house is big and the cat is small\n");
+            text.push_str("__label__de das haus ist gross und die katze ist klein\n");
+            text.push_str("__label__fr la maison est grande et le chat est petit\n");
+        }
+        std::fs::write(&corpus, text).unwrap();
+        let args = Args {
+            dim: 16,
+            min_n: 2,
+            max_n: 4,
+            bucket: 2000,
+            epoch: 25,
+            lr: 1.0,
+            vocab_size: 1001,
+            threads: 1,
+            loss: LossKind::HierarchicalSoftmax,
+            ..Args::supervised()
+        };
+        let mut model = Model::train_supervised(&corpus, args).unwrap();
+        std::fs::remove_file(&corpus).unwrap();
+        model
+            .quantize(&QuantArgs {
+                cutoff: 500,
+                qnorm: true,
+                ..Default::default()
+            })
+            .unwrap();
+        let path = dir.join(format!("rusttext-langid-{}.ftz", std::process::id()));
+        model.save_fasttext(&path).unwrap();
+
+        let mut identifier = LanguageIdentifier::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let mut languages = identifier.languages();
+        languages.sort_unstable();
+        assert_eq!(languages, ["de", "en", "fr"]);
+
+        let best = identifier.identify("die katze\nist gross");
+        assert_eq!(best.len(), 1);
+        assert_eq!(best[0].0, "de");
+        identifier.k = 3;
+        let all = identifier.identify("le petit chat");
+        assert_eq!(all[0].0, "fr");
+        assert!((all.iter().map(|(_, p)| p).sum::<f32>() - 1.0).abs() < 1e-4);
+        assert_eq!(
+            identifier.identify_batch(&["the cats", "le petit chat"])[1],
+            all
+        );
+    }
+
+    #[test]
+    fn test_new_rejects_unsupervised() {
+        let model = crate::vectors::tests::fixed(&["a", "b"], vec![1.0, 0.0, 0.0, 1.0]);
+        assert!(LanguageIdentifier::new(model).is_err());
+    }
+}
@@ -1,6 +1,42 @@
+#[macro_use]
+mod trace;
+
+pub mod align;
+pub mod analysis;
+pub mod ann;
+pub mod args;
+#[cfg(feature = "arrow")]
+pub mod arrow;
+pub mod autotune;
+pub mod checkpoint;
+pub mod compact;
+pub mod diagnostics;
+pub mod error;
+pub mod eval;
+pub mod fasttext;
+pub mod io;
+pub mod langid;
 pub mod loader;
+pub mod loss;
+pub mod matrix;
+pub mod metadata;
+pub mod model;
+pub mod native;
+pub mod onnx;
+pub mod pca;
+pub mod predict;
+pub mod priors;
+pub mod quant;
+pub mod random;
+pub mod safetensors;
+pub mod simd;
+pub mod train;
+pub mod utils;
+pub mod vectors;
 pub mod vocabulary;
 pub mod word;
 
-pub type Error = Box<dyn std::error::Error + Send + Sync>;
+pub use error::RustTextError;
+
+pub type Error = RustTextError;
 pub type Result<T> = std::result::Result<T, Error>;
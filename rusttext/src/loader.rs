@@ -1,13 +1,693 @@
-use std::fs::File;
-use std::io::{BufReader, Read, Write};
+use std::collections::{HashMap, HashSet};
+use std::io::BufRead;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
 
-use crate::{vocabulary, word, Result};
+use regex::RegexSet;
 
-pub fn read_from_iter<'a, I>(vocab: &mut vocabulary::Vocabulary, words: I)
+use crate::args::Args;
+use crate::langid::LanguageIdentifier;
+use crate::random::Rng;
+use crate::{vocabulary, word, Result, RustTextError};
+
+pub fn read_from_iter<'a, I>(vocab: &mut vocabulary::Vocabulary, words: I) -> Result<()>
 where
     I: Iterator<Item = &'a String>,
 {
     for word in words {
-        vocab.add(word)
+        vocab.add(word)?;
+    }
+    Ok(())
+}
+
+/// A predicate applied to each raw line during ingestion; lines for which
+/// `keep` returns false never reach the vocabulary.
+pub trait LineFilter {
+    fn keep(&self, line: &str) -> bool;
+}
+
+/// Keep lines whose whitespace token count lies in `min..=max`.
+pub struct TokenCountFilter {
+    pub min: usize,
+    pub max: usize,
+}
+
+impl LineFilter for TokenCountFilter {
+    fn keep(&self, line: &str) -> bool {
+        let n_tokens = line.split_whitespace().count();
+        n_tokens >= self.min && n_tokens <= self.max
+    }
+}
+
+/// Drop lines matching any of a set of regular expressions.
+pub struct RegexFilter {
+    patterns: RegexSet,
+}
+
+impl RegexFilter {
+    pub fn new<I, S>(patterns: I) -> Result<RegexFilter>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        Ok(RegexFilter {
+            patterns: RegexSet::new(patterns)?,
+        })
+    }
+
+    /// Case-insensitive whole-word blocklist, e.g. for profanity. An empty
+    /// list keeps every line.
+    pub fn blocklist<I, S>(words: I) -> Result<RegexFilter>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let alternation: Vec<String> = words
+            .into_iter()
+            .map(|word| regex::escape(word.as_ref()))
+            .collect();
+        if alternation.is_empty() {
+            return Ok(RegexFilter {
+                patterns: RegexSet::empty(),
+            });
+        }
+        RegexFilter::new([format!(r"(?i)\b(?:{})\b", alternation.join("|"))])
+    }
+
+    /// Common PII shapes: email addresses, phone numbers, card-like digit runs.
+    pub fn pii() -> Result<RegexFilter> {
+        RegexFilter::new([
+            r"[A-Za-z0-9._%+-]+@[A-Za-z0-9.-]+\.[A-Za-z]{2,}",
+            r"(?:\+?\d{1,2}[\s.-]?)?\(?\d{3}\)?[\s.-]?\d{3}[\s.-]?\d{4}\b",
+            r"\b(?:\d[ -]?){13,16}\b",
+        ])
+    }
+}
+
+impl LineFilter for RegexFilter {
+    fn keep(&self, line: &str) -> bool {
+        !self.patterns.is_match(line)
+    }
+}
+
+impl<F: LineFilter + ?Sized> LineFilter for Arc<F> {
+    fn keep(&self, line: &str) -> bool {
+        (**self).keep(line)
+    }
+}
+
+/// How `DedupFilter` tells that two lines are copies of each other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DedupMode {
+    /// Identical lines, ignoring leading and trailing whitespace.
+    Exact,
+    /// Lines with mostly the same tokens, found with MinHash and
+    /// locality-sensitive hashing: each line gets a signature of
+    /// `bands * rows` hashes of its token set, and lines whose signatures
+    /// agree on all `rows` of any one band are copies. Two lines whose
+    /// token sets have Jaccard similarity `s` match with probability
+    /// `1 - (1 - s^rows)^bands`, which rises steeply around
+    /// `(1 / bands)^(1 / rows)`.
+    MinHash { bands: usize, rows: usize },
+}
+
+impl DedupMode {
+    /// MinHash that catches lines sharing about 80% of their tokens.
+    pub fn near() -> DedupMode {
+        DedupMode::MinHash {
+            bands: 10,
+            rows: 10,
+        }
+    }
+}
+
+/// Drop every line after the first `max_copies` copies of it, with
+/// near-duplicates counted as copies under `DedupMode::MinHash`; typically
+/// boilerplate of crawled text. Lines are matched against all lines the
+/// filter has seen, so it remembers a hash of every distinct line (one per
+/// band with MinHash). Share it through an `Arc` to read `dropped` after
+/// ingestion.
+pub struct DedupFilter {
+    mode: DedupMode,
+    max_copies: u32,
+    seeds: Vec<u64>,
+    state: Mutex<DedupState>,
+    dropped: AtomicU64,
+}
+
+#[derive(Default)]
+struct DedupState {
+    /// Lines seen of each group of copies.
+    counts: Vec<u32>,
+    /// Group of each line hash (exact) or band hash (MinHash).
+    groups: HashMap<u64, u32>,
+}
+
+impl DedupFilter {
+    pub fn new(mode: DedupMode, max_copies: u32) -> Result<DedupFilter> {
+        if max_copies == 0 {
+            return Err(RustTextError::InvalidArgument(String::from(
+                "max_copies must be positive",
+            )));
+        }
+        let seeds = match mode {
+            DedupMode::Exact => Vec::new(),
+            DedupMode::MinHash { bands, rows } => {
+                if bands == 0 || rows == 0 {
+                    return Err(RustTextError::InvalidArgument(format!(
+                        "MinHash needs at least one band and row, got {} x {}",
+                        bands, rows
+                    )));
+                }
+                let mut rng = Rng::new(0);
+                (0..bands * rows).map(|_| rng.next_u64()).collect()
+            }
+        };
+        Ok(DedupFilter {
+            mode,
+            max_copies,
+            seeds,
+            state: Mutex::new(DedupState::default()),
+            dropped: AtomicU64::new(0),
+        })
+    }
+
+    /// Lines dropped as copies so far.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    // The hashes under which copies of `line` are found.
+    fn keys(&self, line: &str) -> Vec<u64> {
+        let rows = match self.mode {
+            DedupMode::Exact => return vec![word::fnv_hash64(line.trim())],
+            DedupMode::MinHash { rows, .. } => rows,
+        };
+        let tokens: HashSet<u64> = line.split_whitespace().map(word::fnv_hash64).collect();
+        let signature: Vec<u64> = self
+            .seeds
+            .iter()
+            .map(|seed| {
+                tokens
+                    .iter()
+                    .map(|token| Rng::new(token ^ seed).next_u64())
+                    .min()
+                    .unwrap_or(u64::MAX)
+            })
+            .collect();
+        signature
+            .chunks(rows)
+            .enumerate()
+            .map(|(band, values)| {
+                values
+                    .iter()
+                    .fold(band as u64, |key, value| Rng::new(key ^ value).next_u64())
+            })
+            .collect()
+    }
+}
+
+impl LineFilter for DedupFilter {
+    fn keep(&self, line: &str) -> bool {
+        let keys = self.keys(line);
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let state = &mut *state;
+        let group = match keys.iter().find_map(|key| state.groups.get(key)) {
+            Some(&group) => group,
+            None => {
+                state.counts.push(0);
+                state.counts.len() as u32 - 1
+            }
+        };
+        for key in keys {
+            state.groups.entry(key).or_insert(group);
+        }
+        let count = &mut state.counts[group as usize];
+        *count = count.saturating_add(1);
+        if *count > self.max_copies {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        true
+    }
+}
+
+/// Keep lines whose most probable language, by a language ID model such
+/// as fastText's `lid.176.ftz`, is one of a set of languages with at least
+/// a minimum probability; for building monolingual vocabularies and models
+/// from mixed-language text. Lines with nothing to identify are dropped.
+pub struct LanguageFilter {
+    identifier: LanguageIdentifier,
+    languages: HashSet<String>,
+}
+
+impl LanguageFilter {
+    /// Keep `languages`, given as bare codes (`en`, `de`, ...) the model
+    /// knows, when identified with probability at least `min_confidence`.
+    pub fn new<I, S>(
+        mut identifier: LanguageIdentifier,
+        languages: I,
+        min_confidence: f32,
+    ) -> Result<LanguageFilter>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        if !(0.0..=1.0).contains(&min_confidence) {
+            return Err(RustTextError::InvalidArgument(format!(
+                "min_confidence must be in [0, 1], got {}",
+                min_confidence
+            )));
+        }
+        let languages: HashSet<String> = languages.into_iter().map(Into::into).collect();
+        if languages.is_empty() {
+            return Err(RustTextError::InvalidArgument(String::from(
+                "no languages to keep",
+            )));
+        }
+        let known = identifier.languages();
+        if let Some(language) = languages
+            .iter()
+            .find(|language| !known.contains(&language.as_str()))
+        {
+            return Err(RustTextError::UnknownLabel(language.clone()));
+        }
+        identifier.k = 1;
+        identifier.threshold = min_confidence;
+        Ok(LanguageFilter {
+            identifier,
+            languages,
+        })
+    }
+}
+
+impl LineFilter for LanguageFilter {
+    fn keep(&self, line: &str) -> bool {
+        // blank lines would still be scored, on the end-of-sentence token
+        if line.trim().is_empty() {
+            return false;
+        }
+        self.identifier
+            .identify(line)
+            .first()
+            .is_some_and(|(language, _)| self.languages.contains(language))
+    }
+}
+
+/// Ordered chain of line filters; a line is kept only if every filter keeps it.
+#[derive(Default)]
+pub struct Filters {
+    filters: Vec<Box<dyn LineFilter + Send + Sync>>,
+}
+
+impl Filters {
+    pub fn new() -> Filters {
+        Filters::default()
+    }
+
+    pub fn push<F: LineFilter + Send + Sync + 'static>(&mut self, filter: F) -> &mut Filters {
+        self.filters.push(Box::new(filter));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.filters.is_empty()
+    }
+
+    pub fn keep(&self, line: &str) -> bool {
+        self.filters.iter().all(|filter| filter.keep(line))
+    }
+}
+
+/// Line counts from an ingestion pass.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct IngestStats {
+    pub lines_read: u64,
+    pub lines_dropped: u64,
+}
+
+// The next line of `reader` without its line ending, read into `buffer`
+// so that a pass over a corpus allocates once rather than once a line.
+fn next_line<'a, R: BufRead>(reader: &mut R, buffer: &'a mut String) -> Result<Option<&'a str>> {
+    buffer.clear();
+    if reader.read_line(buffer)? == 0 {
+        return Ok(None);
+    }
+    Ok(Some(buffer.trim_end_matches(['\n', '\r'])))
+}
+
+/// Add every token of every line that passes `filters` to `vocab`.
+pub fn read_from_reader<R: BufRead>(
+    vocab: &mut vocabulary::Vocabulary,
+    mut reader: R,
+    filters: &Filters,
+) -> Result<IngestStats> {
+    let mut stats = IngestStats::default();
+
+    let mut buffer = String::new();
+    while let Some(line) = next_line(&mut reader, &mut buffer)? {
+        stats.lines_read += 1;
+        if !filters.keep(line) {
+            stats.lines_dropped += 1;
+            continue;
+        }
+        for token in line.split_whitespace() {
+            vocab.add(token)?;
+        }
+    }
+    Ok(stats)
+}
+
+/// Read a training corpus into a vocabulary configured from `args`, adding
+/// an end-of-sentence token per line, then drop entries below `min_count`
+/// (`min_count_label` for labels). As in fastText, if the lookup table gets
+/// more than 75% full while reading, rare entries are pruned with an
+/// increasing threshold to make room.
+pub fn build_vocabulary<R: BufRead>(reader: R, args: &Args) -> Result<vocabulary::Vocabulary> {
+    Ok(build_vocabulary_filtered(reader, args, &Filters::new())?.0)
+}
+
+/// `build_vocabulary` over only the lines `filters` keep, with the byte
+/// offsets of the dropped lines, in order, for training to skip.
+pub(crate) fn build_vocabulary_filtered<R: BufRead>(
+    mut reader: R,
+    args: &Args,
+    filters: &Filters,
+) -> Result<(vocabulary::Vocabulary, Vec<u64>)> {
+    trace_span!("build_vocabulary");
+    let mut vocab =
+        vocabulary::Vocabulary::new(args.vocab_size, args.min_n, args.max_n, args.bucket)?;
+    vocab.set_label_prefix(&args.label_prefix);
+    vocab.set_word_ngrams(args.word_ngrams);
+
+    let mut min_threshold = 1;
+    let prune_at = (args.vocab_size as f64 * 0.75) as u32;
+    let mut dropped = Vec::new();
+    let mut offset = 0;
+    let mut buffer = String::new();
+    loop {
+        buffer.clear();
+        let n = reader.read_line(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        let start = offset;
+        offset += n as u64;
+        let line = buffer.trim_end_matches(['\n', '\r']);
+        if !filters.keep(line) {
+            dropped.push(start);
+            continue;
+        }
+        for token in line.split_whitespace() {
+            vocab.add(token)?;
+            if vocab.size() > prune_at {
+                min_threshold += 1;
+                trace_info!(
+                    threshold = min_threshold,
+                    size = vocab.size(),
+                    "lookup table 75% full, pruning rare entries"
+                );
+                vocab.threshold(min_threshold, min_threshold);
+            }
+        }
+        vocab.add(word::EOS)?;
+    }
+    vocab.threshold(args.min_count, args.min_count_label);
+    trace_info!(
+        tokens = vocab.n_tokens(),
+        words = vocab.n_words(),
+        labels = vocab.n_labels(),
+        "built vocabulary"
+    );
+    Ok((vocab, dropped))
+}
+
+/// A vocabulary configured from `args` with the counts listed in the file
+/// at `path` (see `Vocabulary::load_counts`), thresholded as
+/// `build_vocabulary` does, for a corpus already counted by
+/// `Vocabulary::save_counts` or other tools. The token total is the sum of
+/// the counts, so it falls short of the corpus's if the list was
+/// thresholded.
+pub fn build_vocabulary_from_counts<P: AsRef<Path>>(
+    path: P,
+    args: &Args,
+) -> Result<vocabulary::Vocabulary> {
+    let mut vocab =
+        vocabulary::Vocabulary::new(args.vocab_size, args.min_n, args.max_n, args.bucket)?;
+    vocab.set_label_prefix(&args.label_prefix);
+    vocab.set_word_ngrams(args.word_ngrams);
+    vocab.load_counts(path)?;
+    vocab.threshold(args.min_count, args.min_count_label);
+    Ok(vocab)
+}
+
+/// Summary of a corpus gathered in a single pass, used to pick training
+/// defaults before committing to a vocabulary.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct CorpusStats {
+    pub n_lines: u64,
+    pub n_tokens: u64,
+    pub n_unique_words: u64,
+    pub n_labels: u64,
+}
+
+impl CorpusStats {
+    /// Mean number of non-label tokens per line.
+    pub fn avg_line_length(&self) -> f64 {
+        if self.n_lines == 0 {
+            return 0.0;
+        }
+        self.n_tokens as f64 / self.n_lines as f64
+    }
+
+    pub fn is_supervised(&self) -> bool {
+        self.n_labels > 0
+    }
+}
+
+/// Scan a corpus line by line, counting tokens, distinct words and distinct
+/// labels. Blank lines are skipped.
+pub fn analyze<R: BufRead>(reader: R, label_prefix: &str) -> Result<CorpusStats> {
+    let mut stats = CorpusStats::default();
+    let mut words: HashSet<String> = HashSet::new();
+    let mut labels: HashSet<String> = HashSet::new();
+
+    for line in reader.lines() {
+        let line = line?;
+        let mut tokens = line.split_whitespace().peekable();
+        if tokens.peek().is_none() {
+            continue;
+        }
+        stats.n_lines += 1;
+
+        for token in tokens {
+            if token.starts_with(label_prefix) {
+                if !labels.contains(token) {
+                    labels.insert(token.to_string());
+                }
+            } else {
+                stats.n_tokens += 1;
+                if !words.contains(token) {
+                    words.insert(token.to_string());
+                }
+            }
+        }
+    }
+
+    stats.n_unique_words = words.len() as u64;
+    stats.n_labels = labels.len() as u64;
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::tests::corpus_factory;
+
+    #[test]
+    fn test_analyze() {
+        let corpus = "__label__a foo bar\n\n__label__b foo baz qux\nbar\n";
+        let stats = analyze(corpus.as_bytes(), "__label__").unwrap();
+
+        assert_eq!(
+            stats,
+            CorpusStats {
+                n_lines: 3,
+                n_tokens: 6,
+                n_unique_words: 4,
+                n_labels: 2,
+            }
+        );
+        assert_eq!(stats.avg_line_length(), 2.0);
+        assert!(stats.is_supervised());
+    }
+
+    #[test]
+    fn test_token_count_filter() {
+        let filter = TokenCountFilter { min: 2, max: 3 };
+
+        assert!(!filter.keep("one"));
+        assert!(filter.keep("one two"));
+        assert!(filter.keep("one two three"));
+        assert!(!filter.keep("one two three four"));
+    }
+
+    #[test]
+    fn test_regex_filters() {
+        let blocklist = RegexFilter::blocklist(["darn"]).unwrap();
+        assert!(!blocklist.keep("well DARN it"));
+        assert!(blocklist.keep("darnell is fine"));
+        let empty = RegexFilter::blocklist(Vec::<&str>::new()).unwrap();
+        assert!(empty.keep("well darn it"));
+
+        let pii = RegexFilter::pii().unwrap();
+        assert!(!pii.keep("mail me at someone@example.com"));
+        assert!(!pii.keep("call 555-123-4567 today"));
+        assert!(pii.keep("nothing to see here"));
+    }
+
+    #[test]
+    fn test_dedup_exact() {
+        let filter = DedupFilter::new(DedupMode::Exact, 2).unwrap();
+        let kept: Vec<bool> = ["a b", "a b ", "c", " a b", "a b c"]
+            .iter()
+            .map(|line| filter.keep(line))
+            .collect();
+        assert_eq!(kept, vec![true, true, true, false, true]);
+        assert_eq!(filter.dropped(), 1);
+        assert!(DedupFilter::new(DedupMode::Exact, 0).is_err());
+    }
+
+    #[test]
+    fn test_dedup_minhash() {
+        let filter = DedupFilter::new(DedupMode::near(), 1).unwrap();
+        let boilerplate = "click here to subscribe to our newsletter for the latest deals and \
+                           offers from all of our partner stores";
+        assert!(filter.keep(boilerplate));
+        assert!(!filter.keep(&boilerplate.replace("latest", "newest")));
+        assert!(filter.keep("an unrelated sentence about rust and word vectors"));
+        assert_eq!(filter.dropped(), 1);
+        assert!(DedupFilter::new(DedupMode::MinHash { bands: 0, rows: 4 }, 1).is_err());
+    }
+
+    #[test]
+    fn test_language_filter() {
+        let corpus = corpus_factory(
+            &"__label__en the house is big and the cat is small\n\
+              __label__de das haus ist gross und die katze ist klein\n"
+                .repeat(50),
+        );
+        let args = Args {
+            dim: 10,
+            min_n: 2,
+            max_n: 4,
+            bucket: 1000,
+            epoch: 20,
+            lr: 0.5,
+            vocab_size: 1001,
+            threads: 1,
+            ..Args::supervised()
+        };
+        let model = crate::model::Model::train_supervised(corpus.path(), args).unwrap();
+        let mut bytes = Vec::new();
+        crate::native::write_model(&mut bytes, &model).unwrap();
+        let identifier =
+            || LanguageIdentifier::new(crate::native::read_model(&bytes[..]).unwrap()).unwrap();
+
+        let filter = LanguageFilter::new(identifier(), ["de"], 0.5).unwrap();
+        assert!(filter.keep("die katze ist klein"));
+        assert!(!filter.keep("the cat is small"));
+        assert!(!filter.keep(""));
+        // nothing is that certain
+        let filter = LanguageFilter::new(identifier(), ["de"], 1.0).unwrap();
+        assert!(!filter.keep("die katze ist klein"));
+
+        assert!(matches!(
+            LanguageFilter::new(identifier(), ["fr"], 0.5),
+            Err(RustTextError::UnknownLabel(_))
+        ));
+        assert!(LanguageFilter::new(identifier(), Vec::<String>::new(), 0.5).is_err());
+        assert!(LanguageFilter::new(identifier(), ["de"], 1.5).is_err());
+    }
+
+    #[test]
+    fn test_read_from_reader_filtered() {
+        let mut vocab = vocabulary::Vocabulary::new(101, 0, 0, 10).unwrap();
+        let mut filters = Filters::new();
+        filters.push(TokenCountFilter { min: 2, max: 10 });
+
+        let corpus = "foo bar\nlonely\nfoo baz\n";
+        let stats = read_from_reader(&mut vocab, corpus.as_bytes(), &filters).unwrap();
+
+        assert_eq!(
+            stats,
+            IngestStats {
+                lines_read: 3,
+                lines_dropped: 1,
+            }
+        );
+        assert_eq!(vocab.n_tokens(), 4);
+        assert_eq!(vocab.get_id("lonely"), None);
+    }
+
+    #[test]
+    fn test_read_from_reader_dedup() {
+        let mut vocab = vocabulary::Vocabulary::new(101, 0, 0, 10).unwrap();
+        let dedup = Arc::new(DedupFilter::new(DedupMode::Exact, 1).unwrap());
+        let mut filters = Filters::new();
+        filters.push(Arc::clone(&dedup));
+
+        let corpus = "foo bar\nfoo bar\nfoo baz\nfoo bar\n";
+        let stats = read_from_reader(&mut vocab, corpus.as_bytes(), &filters).unwrap();
+
+        assert_eq!(stats.lines_dropped, 2);
+        assert_eq!(dedup.dropped(), 2);
+        assert_eq!(vocab.n_tokens(), 4);
+    }
+
+    #[test]
+    fn test_build_vocabulary() {
+        let args = Args {
+            min_count: 2,
+            vocab_size: 101,
+            ..Args::default()
+        };
+        let corpus = "a b a\nb c\n";
+
+        let vocab = build_vocabulary(corpus.as_bytes(), &args).unwrap();
+
+        assert_eq!(vocab.n_tokens(), 7);
+        assert_eq!(vocab.n_words(), 3);
+        assert!(vocab.get_id("c").is_none());
+        assert!(vocab.words()[vocab.get_id(word::EOS).unwrap() as usize]
+            .subwords
+            .is_empty());
+    }
+
+    #[test]
+    fn test_build_vocabulary_from_counts() {
+        let args = Args {
+            min_count: 2,
+            vocab_size: 101,
+            ..Args::default()
+        };
+        let counted = build_vocabulary(
+            "a b a\nb c\na\n".as_bytes(),
+            &Args {
+                min_count: 1,
+                ..args.clone()
+            },
+        )
+        .unwrap();
+        let path = std::env::temp_dir().join("rusttext_loader_counts.tsv");
+        counted.save_counts(&path).unwrap();
+
+        let vocab = build_vocabulary_from_counts(&path, &args).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let expected = build_vocabulary("a b a\nb c\na\n".as_bytes(), &args).unwrap();
+        assert_eq!(vocab.words(), expected.words());
+        assert_eq!(vocab.n_tokens(), expected.n_tokens());
+        assert_eq!(vocab.get_id("c"), None);
     }
 }
@@ -0,0 +1,640 @@
+use std::sync::Arc;
+
+use crate::matrix::{Matrix, SharedMatrix};
+use crate::random::Rng;
+use crate::utils::{log, sigmoid};
+use crate::vocabulary::Vocabulary;
+use crate::{Result, RustTextError};
+
+const NEGATIVE_TABLE_SIZE: usize = 10_000_000;
+// small vocabularies don't need the full table to reproduce the distribution
+const NEGATIVE_SLOTS_PER_ID: usize = 1_000;
+
+/// Per-example scratch space shared between the training loop and a `Loss`:
+/// the hidden vector, the gradient with respect to it, and the scores over
+/// the output rows.
+pub struct State {
+    pub hidden: Vec<f32>,
+    pub grad: Vec<f32>,
+    pub output: Vec<f32>,
+}
+
+impl State {
+    pub fn new(dim: usize, output_size: usize) -> State {
+        State {
+            hidden: vec![0.0; dim],
+            grad: vec![0.0; dim],
+            output: vec![0.0; output_size],
+        }
+    }
+}
+
+/// An output layer objective. The training loop computes `state.hidden`,
+/// calls `forward`, and back-propagates `state.grad` into the input rows, so
+/// any implementation can be swapped in without changing the loop. Losses
+/// that sample keep their own generator, so each thread owns its instance.
+pub trait Loss: Send {
+    /// Score `state.hidden` against `targets[target_index]` and return the
+    /// loss. With `backprop`, also update the output rows with learning rate
+    /// `lr` and add the gradient with respect to the hidden vector into
+    /// `state.grad`. `targets` holds every positive row of the example.
+    /// `output` may be shared with other training threads.
+    fn forward(
+        &mut self,
+        output: &SharedMatrix,
+        targets: &[u32],
+        target_index: usize,
+        state: &mut State,
+        lr: f32,
+        backprop: bool,
+    ) -> f32;
+
+    /// Fill `state.output` with a probability for every output row.
+    fn compute_output(&self, output: &Matrix, state: &mut State);
+
+    /// An instance for another training thread, sharing any read-only
+    /// tables but drawing samples from a generator seeded with `seed`.
+    fn fork(&self, seed: u64) -> Box<dyn Loss>;
+}
+
+// Logistic regression of `state.hidden` against one output row, towards a
+// target probability `label` (1 or 0, or in between when smoothed).
+fn binary_logistic(
+    output: &SharedMatrix,
+    target: u32,
+    label: f32,
+    state: &mut State,
+    lr: f32,
+    backprop: bool,
+) -> f32 {
+    let score = sigmoid(output.dot_row(&state.hidden, target as usize));
+    if backprop {
+        let alpha = lr * (label - score);
+        output.add_row_to(&mut state.grad, target as usize, alpha);
+        output.add_row(&state.hidden, target as usize, alpha);
+    }
+    let mut loss = 0.0;
+    if label > 0.0 {
+        loss -= label * log(score);
+    }
+    if label < 1.0 {
+        loss -= (1.0 - label) * log(1.0 - score);
+    }
+    loss
+}
+
+// Turn `scores` into probabilities in place.
+fn softmax(scores: &mut [f32]) {
+    let max = scores.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let mut z = 0.0;
+    for score in scores.iter_mut() {
+        *score = (*score - max).exp();
+        z += *score;
+    }
+    for score in scores.iter_mut() {
+        *score /= z;
+    }
+}
+
+/// Full softmax over the output rows; the default for supervised training.
+///
+/// With `label_smoothing` ε the target distribution puts 1 - ε on the true
+/// label and spreads ε evenly over all labels.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Softmax {
+    pub label_smoothing: f32,
+}
+
+impl Loss for Softmax {
+    fn forward(
+        &mut self,
+        output: &SharedMatrix,
+        targets: &[u32],
+        target_index: usize,
+        state: &mut State,
+        lr: f32,
+        backprop: bool,
+    ) -> f32 {
+        for i in 0..output.rows() {
+            state.output[i] = output.dot_row(&state.hidden, i);
+        }
+        softmax(&mut state.output);
+        let target = targets[target_index] as usize;
+        let smoothing = self.label_smoothing / output.rows() as f32;
+        let mut loss = 0.0;
+        for i in 0..output.rows() {
+            let mut label = smoothing;
+            if i == target {
+                label += 1.0 - self.label_smoothing;
+            }
+            if label > 0.0 {
+                loss -= label * log(state.output[i]);
+            }
+            if backprop {
+                let alpha = lr * (label - state.output[i]);
+                output.add_row_to(&mut state.grad, i, alpha);
+                output.add_row(&state.hidden, i, alpha);
+            }
+        }
+        loss
+    }
+
+    fn compute_output(&self, output: &Matrix, state: &mut State) {
+        for i in 0..output.rows() {
+            state.output[i] = output.dot_row(&state.hidden, i);
+        }
+        softmax(&mut state.output);
+    }
+
+    fn fork(&self, _seed: u64) -> Box<dyn Loss> {
+        Box::new(*self)
+    }
+}
+
+/// An independent sigmoid per output row, for multi-label classification:
+/// every row in `targets` is pushed towards 1 and every other row towards 0,
+/// so `target_index` is ignored and probabilities need not sum to one.
+///
+/// With `label_smoothing` ε the targets become 1 - ε/2 and ε/2. With
+/// `class_weights`, each row in `targets` learns at `lr` scaled by its own
+/// weight; other rows, and all rows when it's empty, use `lr`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct OneVsAll {
+    pub label_smoothing: f32,
+    pub class_weights: Arc<[f32]>,
+}
+
+impl Loss for OneVsAll {
+    fn forward(
+        &mut self,
+        output: &SharedMatrix,
+        targets: &[u32],
+        _target_index: usize,
+        state: &mut State,
+        lr: f32,
+        backprop: bool,
+    ) -> f32 {
+        let (positive, negative) = (1.0 - self.label_smoothing / 2.0, self.label_smoothing / 2.0);
+        let mut loss = 0.0;
+        for i in 0..output.rows() as u32 {
+            let (label, lr) = if targets.contains(&i) {
+                let weight = self.class_weights.get(i as usize).copied();
+                (positive, lr * weight.unwrap_or(1.0))
+            } else {
+                (negative, lr)
+            };
+            loss += binary_logistic(output, i, label, state, lr, backprop);
+        }
+        loss
+    }
+
+    fn compute_output(&self, output: &Matrix, state: &mut State) {
+        for i in 0..output.rows() {
+            state.output[i] = sigmoid(output.dot_row(&state.hidden, i));
+        }
+    }
+
+    fn fork(&self, _seed: u64) -> Box<dyn Loss> {
+        Box::new(self.clone())
+    }
+}
+
+/// Huffman coding of the outputs by frequency, as in word2vec and fastText:
+/// output `i` is a leaf, frequent outputs sit close to the root, and each
+/// of the `n - 1` internal nodes owns one output row (the last row is
+/// unused). The construction matches fastText's, so trees built from the
+/// same counts are identical.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HuffmanTree {
+    /// Internal nodes from each leaf up to the root.
+    paths: Vec<Vec<u32>>,
+    /// Whether each step of `paths` comes from the node's right child.
+    codes: Vec<Vec<bool>>,
+}
+
+impl HuffmanTree {
+    /// `counts[i]` is the frequency of output `i`, sorted most frequent
+    /// first as in a thresholded vocabulary.
+    pub fn new(counts: &[u32]) -> HuffmanTree {
+        let n = counts.len();
+        if n == 0 {
+            return HuffmanTree {
+                paths: Vec::new(),
+                codes: Vec::new(),
+            };
+        }
+        let mut count: Vec<u64> = counts.iter().map(|&count| u64::from(count)).collect();
+        count.resize(2 * n - 1, 1_000_000_000_000_000);
+        let mut parent = vec![None; 2 * n - 1];
+        let mut right = vec![false; 2 * n - 1];
+
+        // leaves are taken from the end of `counts`, internal nodes in the
+        // order they are created, always merging the two smallest
+        let (mut leaf, mut node) = (n, n);
+        for i in n..2 * n - 1 {
+            let mut smallest = [0; 2];
+            for slot in smallest.iter_mut() {
+                if leaf > 0 && count[leaf - 1] < count[node] {
+                    leaf -= 1;
+                    *slot = leaf;
+                } else {
+                    *slot = node;
+                    node += 1;
+                }
+            }
+            count[i] = count[smallest[0]] + count[smallest[1]];
+            parent[smallest[0]] = Some(i);
+            parent[smallest[1]] = Some(i);
+            right[smallest[1]] = true;
+        }
+
+        let mut paths = Vec::with_capacity(n);
+        let mut codes = Vec::with_capacity(n);
+        for i in 0..n {
+            let (mut path, mut code) = (Vec::new(), Vec::new());
+            let mut node = i;
+            while let Some(up) = parent[node] {
+                path.push((up - n) as u32);
+                code.push(right[node]);
+                node = up;
+            }
+            paths.push(path);
+            codes.push(code);
+        }
+        HuffmanTree { paths, codes }
+    }
+
+    /// Number of leaves.
+    pub fn len(&self) -> usize {
+        self.paths.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.paths.is_empty()
+    }
+
+    /// The internal nodes above leaf `i`, and the branch taken at each.
+    pub fn path(&self, i: usize) -> (&[u32], &[bool]) {
+        (&self.paths[i], &self.codes[i])
+    }
+
+    /// Probability of every leaf given `score(node)`, the logit of taking
+    /// the right branch at each internal node.
+    pub fn probabilities<F: Fn(usize) -> f32>(&self, score: F, out: &mut [f32]) {
+        let nodes: Vec<f32> = (0..self.len().saturating_sub(1))
+            .map(|node| sigmoid(score(node)))
+            .collect();
+        for (p, (path, code)) in out.iter_mut().zip(self.paths.iter().zip(self.codes.iter())) {
+            *p = path
+                .iter()
+                .zip(code.iter())
+                .map(|(&node, &right)| {
+                    let p = nodes[node as usize];
+                    if right {
+                        p
+                    } else {
+                        1.0 - p
+                    }
+                })
+                .product();
+        }
+    }
+}
+
+/// Hierarchical softmax: a logistic loss at every internal node on the
+/// target's path in a `HuffmanTree`, so an update costs `O(log n)` rather
+/// than a pass over all outputs. The tree is shared between forks.
+#[derive(Debug, Clone)]
+pub struct HierarchicalSoftmax {
+    tree: Arc<HuffmanTree>,
+}
+
+impl HierarchicalSoftmax {
+    pub fn new(counts: &[u32]) -> HierarchicalSoftmax {
+        HierarchicalSoftmax {
+            tree: Arc::new(HuffmanTree::new(counts)),
+        }
+    }
+}
+
+impl Loss for HierarchicalSoftmax {
+    fn forward(
+        &mut self,
+        output: &SharedMatrix,
+        targets: &[u32],
+        target_index: usize,
+        state: &mut State,
+        lr: f32,
+        backprop: bool,
+    ) -> f32 {
+        let (path, code) = self.tree.path(targets[target_index] as usize);
+        let mut loss = 0.0;
+        for (&node, &right) in path.iter().zip(code.iter()) {
+            let label = if right { 1.0 } else { 0.0 };
+            loss += binary_logistic(output, node, label, state, lr, backprop);
+        }
+        loss
+    }
+
+    fn compute_output(&self, output: &Matrix, state: &mut State) {
+        let hidden = &state.hidden;
+        self.tree
+            .probabilities(|node| output.dot_row(hidden, node), &mut state.output);
+    }
+
+    fn fork(&self, _seed: u64) -> Box<dyn Loss> {
+        Box::new(self.clone())
+    }
+}
+
+/// Draws negative examples from the unigram distribution raised to the 3/4
+/// power, as in word2vec: ids are repeated in a large table in proportion to
+/// count^0.75 and sampled uniformly from it.
+///
+/// The table is shared, so `fork` gives each training thread its own
+/// independently seeded sampler without copying it.
+#[derive(Debug, Clone)]
+pub struct NegativeSampler {
+    table: Arc<[u32]>,
+    rng: Rng,
+}
+
+impl NegativeSampler {
+    /// `counts[i]` is the frequency of id `i`. At least two ids need a
+    /// nonzero count, or there would be nothing to draw besides the target.
+    pub fn new(counts: &[u32], seed: u64) -> Result<NegativeSampler> {
+        if counts.iter().filter(|&&count| count > 0).count() < 2 {
+            return Err(RustTextError::InvalidArgument(String::from(
+                "negative sampling needs at least two ids with nonzero counts",
+            )));
+        }
+        let weights: Vec<f64> = counts
+            .iter()
+            .map(|&count| f64::from(count).powf(0.75))
+            .collect();
+        let z: f64 = weights.iter().sum();
+        let size = usize::min(NEGATIVE_TABLE_SIZE, counts.len() * NEGATIVE_SLOTS_PER_ID);
+
+        let mut table = Vec::with_capacity(size);
+        for (id, weight) in weights.iter().enumerate() {
+            let n = (weight / z * size as f64).ceil() as usize;
+            table.extend(std::iter::repeat_n(id as u32, n));
+        }
+        Ok(NegativeSampler {
+            table: table.into(),
+            rng: Rng::new(seed),
+        })
+    }
+
+    /// Sample over the words of a thresholded vocabulary.
+    pub fn from_vocabulary(vocab: &Vocabulary, seed: u64) -> Result<NegativeSampler> {
+        let counts: Vec<u32> = vocab.words()[..vocab.n_words() as usize]
+            .iter()
+            .map(|entry| entry.count)
+            .collect();
+        NegativeSampler::new(&counts, seed)
+    }
+
+    /// A sampler over the same table with its own generator.
+    pub fn fork(&self, seed: u64) -> NegativeSampler {
+        NegativeSampler {
+            table: Arc::clone(&self.table),
+            rng: Rng::new(seed),
+        }
+    }
+
+    /// Draw an id other than `exclude_id`.
+    pub fn sample(&mut self, exclude_id: u32) -> u32 {
+        loop {
+            let negative = self.table[self.rng.below(self.table.len())];
+            if negative != exclude_id {
+                return negative;
+            }
+        }
+    }
+}
+
+/// Logistic loss on the target row plus `neg` rows drawn by a
+/// `NegativeSampler`.
+pub struct NegativeSampling {
+    neg: usize,
+    sampler: NegativeSampler,
+}
+
+impl NegativeSampling {
+    pub fn new(neg: usize, sampler: NegativeSampler) -> NegativeSampling {
+        NegativeSampling { neg, sampler }
+    }
+}
+
+impl Loss for NegativeSampling {
+    fn forward(
+        &mut self,
+        output: &SharedMatrix,
+        targets: &[u32],
+        target_index: usize,
+        state: &mut State,
+        lr: f32,
+        backprop: bool,
+    ) -> f32 {
+        let target = targets[target_index];
+        let mut loss = binary_logistic(output, target, 1.0, state, lr, backprop);
+        for _ in 0..self.neg {
+            let negative = self.sampler.sample(target);
+            loss += binary_logistic(output, negative, 0.0, state, lr, backprop);
+        }
+        loss
+    }
+
+    fn compute_output(&self, output: &Matrix, state: &mut State) {
+        for i in 0..output.rows() {
+            state.output[i] = sigmoid(output.dot_row(&state.hidden, i));
+        }
+    }
+
+    fn fork(&self, seed: u64) -> Box<dyn Loss> {
+        Box::new(NegativeSampling::new(self.neg, self.sampler.fork(seed)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_softmax() {
+        let mut output = Matrix::new(3, 2);
+        let mut state = State::new(2, 3);
+        state.hidden.copy_from_slice(&[1.0, -1.0]);
+
+        Softmax::default().compute_output(&output, &mut state);
+        assert!(state.output.iter().all(|&p| (p - 1.0 / 3.0).abs() < 1e-6));
+
+        let before = Softmax::default().forward(
+            &SharedMatrix::new(&mut output),
+            &[1],
+            0,
+            &mut state,
+            0.5,
+            true,
+        );
+        assert!((before - 3f32.ln()).abs() < 1e-2);
+        assert!(state.grad.iter().all(|&g| g == 0.0));
+
+        let after = Softmax::default().forward(
+            &SharedMatrix::new(&mut output),
+            &[1],
+            0,
+            &mut state,
+            0.0,
+            false,
+        );
+        assert!(after < before, "{} >= {}", after, before);
+        let total: f32 = state.output.iter().sum();
+        assert!((total - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_one_vs_all() {
+        let mut output = Matrix::new(3, 2);
+        let mut state = State::new(2, 3);
+        state.hidden.copy_from_slice(&[1.0, -1.0]);
+
+        for _ in 0..50 {
+            OneVsAll::default().forward(
+                &SharedMatrix::new(&mut output),
+                &[0, 2],
+                0,
+                &mut state,
+                0.5,
+                true,
+            );
+        }
+        OneVsAll::default().compute_output(&output, &mut state);
+        assert!(state.output[0] > 0.9 && state.output[2] > 0.9);
+        assert!(state.output[1] < 0.1);
+
+        // each positive row learns at its own weight
+        let mut weighted = OneVsAll {
+            class_weights: vec![1.0, 1.0, 0.0].into(),
+            ..Default::default()
+        };
+        let mut output = Matrix::new(3, 2);
+        for _ in 0..50 {
+            weighted.forward(
+                &SharedMatrix::new(&mut output),
+                &[0, 2],
+                0,
+                &mut state,
+                0.5,
+                true,
+            );
+        }
+        weighted.compute_output(&output, &mut state);
+        assert!(state.output[0] > 0.9 && state.output[1] < 0.1);
+        assert_eq!(state.output[2], 0.5);
+    }
+
+    #[test]
+    fn test_negative_sampler() {
+        let mut sampler = NegativeSampler::new(&[27, 8], 0).unwrap();
+
+        let draws: Vec<u32> = (0..1000).map(|_| sampler.sample(2)).collect();
+        let n_first = draws.iter().filter(|&&id| id == 0).count();
+        // 27^0.75 : 8^0.75 is roughly 71:29
+        assert!(n_first > 600 && n_first < 800);
+        assert!((0..100).all(|_| sampler.sample(0) == 1));
+
+        let mut left = sampler.fork(7);
+        let mut right = sampler.fork(7);
+        assert!(Arc::ptr_eq(&left.table, &sampler.table));
+        assert!((0..100).all(|_| left.sample(2) == right.sample(2)));
+
+        assert!(NegativeSampler::new(&[5, 0], 0).is_err());
+    }
+
+    #[test]
+    fn test_label_smoothing() {
+        let mut state = State::new(2, 4);
+        state.hidden.copy_from_slice(&[1.0, 0.5]);
+        let mut smoothed = Softmax {
+            label_smoothing: 0.2,
+        };
+
+        let mut output = Matrix::new(4, 2);
+        for _ in 0..2000 {
+            smoothed.forward(
+                &SharedMatrix::new(&mut output),
+                &[3],
+                0,
+                &mut state,
+                0.5,
+                true,
+            );
+        }
+        smoothed.compute_output(&output, &mut state);
+        // converges to the smoothed target 0.8 + 0.2 / 4 rather than 1
+        assert!((state.output[3] - 0.85).abs() < 0.02, "{:?}", state.output);
+        assert!((state.output[0] - 0.05).abs() < 0.02, "{:?}", state.output);
+
+        let mut smoothed = OneVsAll {
+            label_smoothing: 0.2,
+            ..Default::default()
+        };
+        let mut output = Matrix::new(4, 2);
+        for _ in 0..2000 {
+            smoothed.forward(
+                &SharedMatrix::new(&mut output),
+                &[3],
+                0,
+                &mut state,
+                0.5,
+                true,
+            );
+        }
+        smoothed.compute_output(&output, &mut state);
+        assert!((state.output[3] - 0.9).abs() < 0.02, "{:?}", state.output);
+        assert!((state.output[0] - 0.1).abs() < 0.02, "{:?}", state.output);
+    }
+
+    #[test]
+    fn test_huffman_tree() {
+        let tree = HuffmanTree::new(&[10, 5, 3, 1]);
+
+        // 1 + 3 merge first (node 0), then with 5 (node 1), then with 10
+        assert_eq!(tree.path(0), (&[2][..], &[true][..]));
+        assert_eq!(tree.path(1), (&[1, 2][..], &[true, false][..]));
+        assert_eq!(tree.path(3), (&[0, 1, 2][..], &[false, false, false][..]));
+
+        let mut probabilities = vec![0.0; 4];
+        tree.probabilities(|node| node as f32 - 1.0, &mut probabilities);
+        assert!((probabilities.iter().sum::<f32>() - 1.0).abs() < 1e-6);
+        assert!((probabilities[0] - sigmoid(1.0)).abs() < 1e-6);
+
+        let single = HuffmanTree::new(&[7]);
+        single.probabilities(|_| unreachable!(), &mut probabilities[..1]);
+        assert_eq!(probabilities[0], 1.0);
+    }
+
+    #[test]
+    fn test_hierarchical_softmax() {
+        let mut output = Matrix::new(3, 2);
+        let mut state = State::new(2, 3);
+        state.hidden.copy_from_slice(&[1.0, -1.0]);
+        let mut loss = HierarchicalSoftmax::new(&[4, 2, 1]);
+
+        for _ in 0..50 {
+            loss.forward(
+                &SharedMatrix::new(&mut output),
+                &[2],
+                0,
+                &mut state,
+                0.5,
+                true,
+            );
+        }
+        loss.compute_output(&output, &mut state);
+        assert!(state.output[2] > 0.9, "{:?}", state.output);
+        assert!((state.output.iter().sum::<f32>() - 1.0).abs() < 1e-5);
+    }
+}
@@ -0,0 +1,453 @@
+use std::ops::Range;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+
+use memmap2::Mmap;
+
+use crate::random::Rng;
+use crate::simd::{axpy, dot};
+use crate::{Result, RustTextError};
+
+/// Dense row-major `f32` matrix, used for input (word + subword) and output
+/// (label or context) embeddings. The values are either owned or borrowed
+/// from a memory-mapped model file (see `Model::load_mmap`); a mapped
+/// matrix is copied into memory the first time it is written to.
+#[derive(Debug, Clone)]
+pub struct Matrix {
+    rows: usize,
+    cols: usize,
+    data: Storage,
+}
+
+#[derive(Debug, Clone)]
+enum Storage {
+    Owned(Vec<f32>),
+    // `rows * cols` native-endian values starting `offset` bytes into the
+    // map, which `Matrix::from_mmap` has checked are in bounds and aligned.
+    Mapped { map: Arc<Mmap>, offset: usize },
+}
+
+/// Borrowed, zero-copy view over a contiguous range of a matrix's rows.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MatrixView<'a> {
+    rows: usize,
+    cols: usize,
+    data: &'a [f32],
+}
+
+/// Row access to a matrix shared between training threads without locks
+/// (Hogwild SGD). Values are stored as relaxed atomics, so concurrent
+/// updates to the same row may overwrite each other, as in fastText, but
+/// never race in the language's sense.
+#[derive(Debug)]
+pub struct SharedMatrix<'a> {
+    rows: usize,
+    cols: usize,
+    data: &'a [AtomicU32],
+}
+
+impl Matrix {
+    /// Zero-filled matrix.
+    pub fn new(rows: usize, cols: usize) -> Matrix {
+        Matrix {
+            rows,
+            cols,
+            data: Storage::Owned(vec![0.0; rows * cols]),
+        }
+    }
+
+    pub fn from_vec(rows: usize, cols: usize, data: Vec<f32>) -> Result<Matrix> {
+        if data.len() != rows * cols {
+            return Err(RustTextError::DimensionMismatch {
+                expected: rows * cols,
+                found: data.len(),
+            });
+        }
+        Ok(Matrix {
+            rows,
+            cols,
+            data: Storage::Owned(data),
+        })
+    }
+
+    /// Matrix over `rows * cols` native-endian `f32`s stored in `map`
+    /// starting at byte `offset`, without copying them. The range must lie
+    /// within the map and be aligned for `f32`.
+    pub fn from_mmap(rows: usize, cols: usize, map: Arc<Mmap>, offset: usize) -> Result<Matrix> {
+        let len = rows
+            .checked_mul(cols)
+            .and_then(|n| n.checked_mul(std::mem::size_of::<f32>()))
+            .and_then(|bytes| bytes.checked_add(offset));
+        match len {
+            Some(end) if end <= map.len() => {}
+            _ => {
+                return Err(RustTextError::ModelFormat(format!(
+                    "mapped matrix of {} x {} at byte {} overruns the {} byte file",
+                    rows,
+                    cols,
+                    offset,
+                    map.len()
+                )))
+            }
+        }
+        if !(map.as_ptr().wrapping_add(offset) as *const f32).is_aligned() {
+            return Err(RustTextError::ModelFormat(format!(
+                "mapped matrix at byte {} is not aligned",
+                offset
+            )));
+        }
+        Ok(Matrix {
+            rows,
+            cols,
+            data: Storage::Mapped { map, offset },
+        })
+    }
+
+    /// Whether the values are borrowed from a memory-mapped file.
+    pub fn is_mapped(&self) -> bool {
+        matches!(self.data, Storage::Mapped { .. })
+    }
+
+    /// Copy a mapped matrix's values into memory; a no-op when they are
+    /// already owned.
+    pub fn make_owned(&mut self) {
+        if let Storage::Mapped { .. } = self.data {
+            self.data = Storage::Owned(self.data().to_vec());
+        }
+    }
+
+    /// Fill with values drawn uniformly from `[-bound, bound)`. fastText
+    /// initialises input embeddings with `bound = 1 / cols`.
+    pub fn uniform(&mut self, bound: f32, rng: &mut Rng) {
+        for value in self.data_mut().iter_mut() {
+            *value = rng.uniform(-bound, bound);
+        }
+    }
+
+    /// Glorot/Xavier uniform initialisation, `bound = sqrt(6 / (rows + cols))`.
+    pub fn xavier(&mut self, rng: &mut Rng) {
+        let bound = (6.0 / (self.rows + self.cols) as f32).sqrt();
+        self.uniform(bound, rng);
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+
+    pub fn data(&self) -> &[f32] {
+        match &self.data {
+            Storage::Owned(data) => data,
+            // Safety: `from_mmap` checked the range and its alignment, and
+            // the map is read-only and lives as long as `self`.
+            Storage::Mapped { map, offset } => unsafe {
+                std::slice::from_raw_parts(
+                    map.as_ptr().add(*offset) as *const f32,
+                    self.rows * self.cols,
+                )
+            },
+        }
+    }
+
+    /// Mutable values, copying a mapped matrix into memory first.
+    pub fn data_mut(&mut self) -> &mut [f32] {
+        self.make_owned();
+        match &mut self.data {
+            Storage::Owned(data) => data,
+            Storage::Mapped { .. } => unreachable!("made owned above"),
+        }
+    }
+
+    pub fn row(&self, i: usize) -> &[f32] {
+        &self.data()[i * self.cols..(i + 1) * self.cols]
+    }
+
+    pub fn row_mut(&mut self, i: usize) -> &mut [f32] {
+        let cols = self.cols;
+        &mut self.data_mut()[i * cols..(i + 1) * cols]
+    }
+
+    /// Dot product of row `i` with `vector`.
+    pub fn dot_row(&self, vector: &[f32], i: usize) -> f32 {
+        dot(self.row(i), vector)
+    }
+
+    /// `row_i += scale * vector`.
+    pub fn add_row(&mut self, vector: &[f32], i: usize, scale: f32) {
+        axpy(self.row_mut(i), scale, vector);
+    }
+
+    /// `out += scale * row_i`.
+    pub fn add_row_to(&self, out: &mut [f32], i: usize, scale: f32) {
+        axpy(out, scale, self.row(i));
+    }
+
+    /// Mean of the rows listed in `rows` into `out`, zero if there are
+    /// none.
+    pub fn mean_rows(&self, rows: &[u32], out: &mut [f32]) {
+        out.iter_mut().for_each(|value| *value = 0.0);
+        let scale = 1.0 / rows.len() as f32;
+        for &row in rows.iter() {
+            self.add_row_to(out, row as usize, scale);
+        }
+    }
+
+    /// Multiply every value by `factor`.
+    pub fn scale(&mut self, factor: f32) {
+        for value in self.data_mut().iter_mut() {
+            *value *= factor;
+        }
+    }
+
+    pub fn scale_row(&mut self, i: usize, factor: f32) {
+        for value in self.row_mut(i).iter_mut() {
+            *value *= factor;
+        }
+    }
+
+    pub fn l2_norm_row(&self, i: usize) -> f32 {
+        dot(self.row(i), self.row(i)).sqrt()
+    }
+
+    /// New matrix made of the rows listed in `rows`, in that order.
+    pub fn select_rows(&self, rows: &[u32]) -> Matrix {
+        let mut data = Vec::with_capacity(rows.len() * self.cols);
+        for &row in rows.iter() {
+            data.extend_from_slice(self.row(row as usize));
+        }
+        Matrix {
+            rows: rows.len(),
+            cols: self.cols,
+            data: Storage::Owned(data),
+        }
+    }
+
+    pub fn view(&self) -> MatrixView<'_> {
+        self.slice_rows(0..self.rows)
+    }
+
+    /// Zero-copy view of rows in `range`.
+    pub fn slice_rows(&self, range: Range<usize>) -> MatrixView<'_> {
+        MatrixView {
+            rows: range.end - range.start,
+            cols: self.cols,
+            data: &self.data()[range.start * self.cols..range.end * self.cols],
+        }
+    }
+}
+
+impl PartialEq for Matrix {
+    fn eq(&self, other: &Matrix) -> bool {
+        (self.rows, self.cols) == (other.rows, other.cols) && self.data() == other.data()
+    }
+}
+
+impl<'a> MatrixView<'a> {
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+
+    pub fn data(&self) -> &'a [f32] {
+        self.data
+    }
+
+    pub fn row(&self, i: usize) -> &'a [f32] {
+        &self.data[i * self.cols..(i + 1) * self.cols]
+    }
+
+    pub fn dot_row(&self, vector: &[f32], i: usize) -> f32 {
+        dot(self.row(i), vector)
+    }
+
+    pub fn slice_rows(&self, range: Range<usize>) -> MatrixView<'a> {
+        MatrixView {
+            rows: range.end - range.start,
+            cols: self.cols,
+            data: &self.data[range.start * self.cols..range.end * self.cols],
+        }
+    }
+}
+
+impl<'a> SharedMatrix<'a> {
+    /// Share `matrix` for the lifetime of the borrow, copying a mapped
+    /// matrix into memory first.
+    pub fn new(matrix: &'a mut Matrix) -> SharedMatrix<'a> {
+        let (rows, cols) = (matrix.rows, matrix.cols);
+        let data = matrix.data_mut();
+        // Safety: `AtomicU32` has the size and alignment of `f32`, and the
+        // exclusive borrow guarantees no other access for `'a`.
+        let data = unsafe {
+            std::slice::from_raw_parts(data.as_mut_ptr() as *const AtomicU32, data.len())
+        };
+        SharedMatrix { rows, cols, data }
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+
+    fn row(&self, i: usize) -> &[AtomicU32] {
+        &self.data[i * self.cols..(i + 1) * self.cols]
+    }
+
+    /// Dot product of row `i` with `vector`.
+    pub fn dot_row(&self, vector: &[f32], i: usize) -> f32 {
+        self.row(i)
+            .iter()
+            .zip(vector.iter())
+            .map(|(value, v)| load(value) * v)
+            .sum()
+    }
+
+    /// `row_i += scale * vector`.
+    pub fn add_row(&self, vector: &[f32], i: usize, scale: f32) {
+        for (value, v) in self.row(i).iter().zip(vector.iter()) {
+            store(value, load(value) + scale * v);
+        }
+    }
+
+    /// `out += scale * row_i`.
+    pub fn add_row_to(&self, out: &mut [f32], i: usize, scale: f32) {
+        for (o, value) in out.iter_mut().zip(self.row(i).iter()) {
+            *o += scale * load(value);
+        }
+    }
+
+    /// Mean of the rows listed in `rows` into `out`, zero if there are
+    /// none.
+    pub fn mean_rows(&self, rows: &[u32], out: &mut [f32]) {
+        out.iter_mut().for_each(|value| *value = 0.0);
+        let scale = 1.0 / rows.len() as f32;
+        for &row in rows.iter() {
+            self.add_row_to(out, row as usize, scale);
+        }
+    }
+
+    /// Copy row `i` into `out`.
+    pub fn copy_row(&self, out: &mut [f32], i: usize) {
+        for (o, value) in out.iter_mut().zip(self.row(i).iter()) {
+            *o = load(value);
+        }
+    }
+}
+
+fn load(value: &AtomicU32) -> f32 {
+    f32::from_bits(value.load(Ordering::Relaxed))
+}
+
+fn store(value: &AtomicU32, x: f32) {
+    value.store(x.to_bits(), Ordering::Relaxed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_matrix() -> Matrix {
+        Matrix::from_vec(3, 2, vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0]).unwrap()
+    }
+
+    #[test]
+    fn test_from_vec() {
+        assert!(Matrix::from_vec(2, 2, vec![1.0; 3]).is_err());
+        assert_eq!(test_matrix().row(1), &[3.0, 4.0]);
+    }
+
+    #[test]
+    fn test_row_ops() {
+        let mut matrix = test_matrix();
+
+        assert_eq!(matrix.dot_row(&[1.0, 1.0], 2), 11.0);
+
+        matrix.add_row(&[1.0, -1.0], 0, 2.0);
+        assert_eq!(matrix.row(0), &[3.0, 0.0]);
+
+        let mut out = vec![1.0, 1.0];
+        matrix.add_row_to(&mut out, 1, 0.5);
+        assert_eq!(out, [2.5, 3.0]);
+
+        matrix.scale_row(1, 0.0);
+        assert_eq!(matrix.l2_norm_row(1), 0.0);
+        matrix.scale(2.0);
+        assert_eq!(matrix.row(2), &[10.0, 12.0]);
+    }
+
+    #[test]
+    fn test_slice_rows() {
+        let matrix = test_matrix();
+        let view = matrix.slice_rows(1..3);
+
+        assert_eq!(view.rows(), 2);
+        assert_eq!(view.row(0), &[3.0, 4.0]);
+        assert_eq!(view.slice_rows(1..2).row(0), &[5.0, 6.0]);
+        assert_eq!(view.dot_row(&[1.0, 0.0], 1), 5.0);
+        assert!(std::ptr::eq(view.row(0), matrix.row(1)));
+    }
+
+    #[test]
+    fn test_shared_matrix() {
+        let mut matrix = test_matrix();
+        let shared = SharedMatrix::new(&mut matrix);
+        std::thread::scope(|scope| {
+            for i in 0..3 {
+                let shared = &shared;
+                scope.spawn(move || shared.add_row(&[1.0, 1.0], i, 1.0));
+            }
+        });
+        assert_eq!(shared.dot_row(&[1.0, 0.0], 2), 6.0);
+        let mut out = vec![0.0; 2];
+        shared.mean_rows(&[0, 1], &mut out);
+        assert_eq!(out, [3.0, 4.0]);
+        assert_eq!(matrix.row(0), &[2.0, 3.0]);
+    }
+
+    #[test]
+    fn test_init() {
+        let mut matrix = Matrix::new(10, 20);
+        let mut rng = Rng::new(1);
+
+        matrix.uniform(0.05, &mut rng);
+        assert!(matrix.data().iter().all(|v| v.abs() <= 0.05));
+
+        matrix.xavier(&mut rng);
+        let bound = (6.0f32 / 30.0).sqrt();
+        assert!(matrix.data().iter().all(|v| v.abs() <= bound));
+        assert!(matrix.data().iter().any(|v| v.abs() > 0.05));
+    }
+
+    #[test]
+    fn test_from_mmap() {
+        let path = std::env::temp_dir().join(format!("rusttext-matrix-{}.bin", std::process::id()));
+        let bytes: Vec<u8> = test_matrix()
+            .data()
+            .iter()
+            .flat_map(|v| v.to_ne_bytes().to_vec())
+            .collect();
+        std::fs::write(&path, [&[0; 4][..], &bytes].concat()).unwrap();
+        let map = Arc::new(unsafe { Mmap::map(&std::fs::File::open(&path).unwrap()).unwrap() });
+        std::fs::remove_file(&path).unwrap();
+
+        let mut matrix = Matrix::from_mmap(3, 2, Arc::clone(&map), 4).unwrap();
+        assert!(matrix.is_mapped());
+        assert_eq!(matrix, test_matrix());
+        assert!(Matrix::from_mmap(4, 2, Arc::clone(&map), 4).is_err());
+        assert!(Matrix::from_mmap(1, 1, Arc::clone(&map), 2).is_err());
+
+        // writing copies the values out of the map
+        matrix.scale_row(0, 2.0);
+        assert!(!matrix.is_mapped());
+        assert_eq!(matrix.row(0), &[2.0, 4.0]);
+        assert_eq!(&map[4..8], &1.0f32.to_ne_bytes());
+    }
+}
@@ -0,0 +1,96 @@
+use std::collections::BTreeMap;
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::args::Args;
+use crate::io::HashingReader;
+use crate::train::TrainingStats;
+use crate::Result;
+
+/// Where a model came from: what trained it, on which data and with which
+/// settings. Kept by every model this crate trains and saved with it in
+/// the native format (`Model::save`); fastText's format has no room for
+/// it, so models loaded from fastText files have none.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Metadata {
+    /// Version of rusttext that trained the model.
+    pub version: String,
+    /// Arguments the model was trained with. `Model::args` can change
+    /// later, e.g. with `reduce_dim`; these don't.
+    pub args: Args,
+    /// The training corpus, as the path was given to training.
+    pub corpus: PathBuf,
+    /// 64-bit FNV-1a hash of the corpus file's bytes (see `hash_corpus`),
+    /// to check that a file is the one trained on. Not cryptographic.
+    pub corpus_hash: u64,
+    /// Tokens processed in training, over all epochs; a resumed run counts
+    /// only its own.
+    pub tokens: u64,
+    /// Wall time of training, the vocabulary pass included.
+    pub training_time: Duration,
+    /// Free-form labels set by the user, such as a dataset version or the
+    /// job that trained the model.
+    pub tags: BTreeMap<String, String>,
+}
+
+impl Metadata {
+    pub(crate) fn new(
+        args: &Args,
+        corpus: &Path,
+        corpus_hash: u64,
+        stats: &TrainingStats,
+        training_time: Duration,
+    ) -> Metadata {
+        Metadata {
+            version: String::from(env!("CARGO_PKG_VERSION")),
+            args: args.clone(),
+            corpus: corpus.to_path_buf(),
+            corpus_hash,
+            tokens: stats.tokens,
+            training_time,
+            tags: BTreeMap::new(),
+        }
+    }
+}
+
+/// The hash `Metadata::corpus_hash` records for the file at `path`.
+pub fn hash_corpus<P: AsRef<Path>>(path: P) -> Result<u64> {
+    let mut reader = HashingReader::new(BufReader::new(std::fs::File::open(path)?));
+    io::copy(&mut reader, &mut io::sink())?;
+    Ok(reader.hash())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::tests::corpus_factory;
+    use crate::model::Model;
+
+    #[test]
+    fn test_metadata() {
+        let text = "__label__a apple\n__label__b zulu\n".repeat(20);
+        let corpus = corpus_factory(&text);
+        let path = corpus.path();
+        let args = Args {
+            dim: 5,
+            epoch: 2,
+            bucket: 100,
+            threads: 1,
+            ..Args::supervised()
+        };
+        let model = Model::train_supervised(path, args.clone()).unwrap();
+        let hash = hash_corpus(path).unwrap();
+
+        let metadata = model.metadata().unwrap();
+        assert_eq!(metadata.version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(metadata.args, args);
+        assert_eq!(metadata.corpus, path);
+        assert_eq!(metadata.corpus_hash, hash);
+        assert_eq!(hash, crate::word::fnv_hash64(&text));
+        assert_eq!(metadata.tokens, 2 * 3 * 40);
+        assert!(metadata.tags.is_empty());
+    }
+}
@@ -0,0 +1,1046 @@
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+use std::sync::OnceLock;
+use std::time::Instant;
+
+use crate::ann::{self, HnswArgs, HnswIndex};
+use crate::args::Args;
+use crate::autotune::{self, Autotune};
+use crate::checkpoint::Checkpoint;
+use crate::eval::{self, CalibrationTarget, LabelReport, TestResult};
+use crate::fasttext;
+use crate::loader::Filters;
+use crate::loss::HuffmanTree;
+use crate::matrix::Matrix;
+use crate::metadata::Metadata;
+use crate::native;
+use crate::onnx;
+use crate::pca;
+use crate::predict::{self, Scratch};
+use crate::priors::LabelPriors;
+use crate::quant::{self, CompressedMatrix, Int8Matrix, QuantArgs, QuantMatrix};
+use crate::random::Rng;
+use crate::safetensors;
+use crate::train::{Job, Progress, TrainCallback, TrainingStats};
+use crate::vectors;
+use crate::vocabulary::Vocabulary;
+use crate::word;
+use crate::{io, loader, train, Result, RustTextError};
+
+fn silent(_: &Progress) {}
+
+// Whether the file at `path` starts like a model written by `Model::save`.
+fn is_native(path: &Path) -> Result<bool> {
+    let mut magic = [0; 4];
+    File::open(path)?.read_exact(&mut magic)?;
+    Ok(&magic == native::MAGIC)
+}
+
+// The vocabulary of the lines of the corpus at `path` that pass `filters`,
+// the offsets of those that don't, and the hash of its bytes for
+// `Metadata::corpus_hash`, in one pass. Compressed corpora are hashed as
+// stored, not as decompressed. Filtered runs can't checkpoint, as a
+// checkpoint doesn't record the filters to resume with.
+fn corpus_vocabulary(
+    corpus: &Path,
+    args: &Args,
+    filters: &Filters,
+) -> Result<(Vocabulary, Vec<u64>, u64)> {
+    if args.checkpoint.is_some() && !filters.is_empty() {
+        return Err(RustTextError::InvalidArgument(String::from(
+            "filtered training cannot write checkpoints",
+        )));
+    }
+    let (file, compression) = io::open_corpus(corpus)?;
+    let mut hashing = io::HashingReader::new(file);
+    let (vocab, dropped) = match compression {
+        Some(compression) => {
+            let reader = &mut compression.decode(&mut hashing)?;
+            loader::build_vocabulary_filtered(reader, args, filters)?
+        }
+        None => {
+            let reader = &mut BufReader::new(&mut hashing);
+            loader::build_vocabulary_filtered(reader, args, filters)?
+        }
+    };
+    // a decoder may stop short of trailing bytes
+    std::io::copy(&mut hashing, &mut std::io::sink())?;
+    Ok((vocab, dropped, hashing.hash()))
+}
+
+// Input matrix drawn uniformly from +-1/dim, with rows for words found in
+// `args.pretrained_vectors` overwritten by their pretrained vectors.
+fn init_input(vocab: &Vocabulary, args: &Args, rng: &mut Rng) -> Result<Matrix> {
+    let mut input = Matrix::new(vocab.n_input_rows(), args.dim);
+    input.uniform(1.0 / args.dim as f32, rng);
+
+    if let Some(path) = &args.pretrained_vectors {
+        let (words, vectors) = io::read_vec_file(path)?;
+        if vectors.cols() != args.dim {
+            return Err(RustTextError::DimensionMismatch {
+                expected: args.dim,
+                found: vectors.cols(),
+            });
+        }
+        for (i, word) in words.iter().enumerate() {
+            match vocab.get_id(word) {
+                Some(id) if id < vocab.n_words() && !vocab.char_only() => {
+                    input.row_mut(id as usize).copy_from_slice(vectors.row(i));
+                }
+                _ => {}
+            }
+        }
+    }
+    Ok(input)
+}
+
+/// How `Model::load_with` reads a model written by `Model::save`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LoadOptions {
+    /// Memory-map the matrices instead of reading them; see `load_mmap`.
+    pub mmap: bool,
+    /// Keep the vocabulary in its compact form for inference, several
+    /// times smaller for large vocabularies; see `Vocabulary::compact`.
+    pub compact_vocabulary: bool,
+    /// Quantize the matrices to int8 while reading them, as
+    /// `quantize_int8` would; see `native::read_model_int8`. Takes
+    /// precedence over `mmap`.
+    pub int8: bool,
+}
+
+/// A trained (or training) fastText-style model: the vocabulary, the input
+/// matrix of word and subword bucket embeddings, and the output matrix of
+/// context-word (or label) embeddings.
+pub struct Model {
+    pub(crate) args: Args,
+    pub(crate) vocab: Vocabulary,
+    pub(crate) input: Matrix,
+    pub(crate) output: Matrix,
+    /// Whether `output` holds labels (a classifier) rather than words.
+    pub(crate) supervised: bool,
+    /// Normalized word vectors for neighbour queries, built on first use
+    /// and reset whenever the input matrix changes.
+    pub(crate) word_vectors: OnceLock<Matrix>,
+    /// Approximate neighbour index over the normalized word vectors, if
+    /// one has been built or loaded; dropped along with them.
+    pub(crate) index: Option<HnswIndex>,
+    /// Per-label probability thresholds `predict` applies on top of its
+    /// own, as set by `calibrate`.
+    pub(crate) label_thresholds: Option<Vec<f32>>,
+    /// Per-label log-odds offsets `predict` adds before ranking.
+    pub(crate) label_priors: Option<LabelPriors>,
+    /// Huffman tree over the outputs for hierarchical softmax models,
+    /// built on first prediction.
+    pub(crate) output_tree: OnceLock<HuffmanTree>,
+    /// Replaces `input` (left empty) once the model is quantized.
+    pub(crate) quant_input: Option<CompressedMatrix>,
+    /// Replaces `output` (left empty) if it is quantized as well.
+    pub(crate) quant_output: Option<CompressedMatrix>,
+    /// Summary of the last training run in this process; not saved.
+    pub(crate) training_stats: Option<TrainingStats>,
+    /// Provenance of a model trained by this crate, saved with it.
+    pub(crate) metadata: Option<Metadata>,
+}
+
+impl Model {
+    /// Learn word embeddings from the text file at `corpus` with the
+    /// skipgram objective and negative sampling.
+    pub fn train_unsupervised<P: AsRef<Path>>(corpus: P, args: Args) -> Result<Model> {
+        Model::train_unsupervised_with(corpus, args, &silent)
+    }
+
+    /// `train_unsupervised`, reporting progress to `callback`.
+    pub fn train_unsupervised_with<P: AsRef<Path>>(
+        corpus: P,
+        args: Args,
+        callback: &dyn TrainCallback,
+    ) -> Result<Model> {
+        Model::train_unsupervised_from(corpus.as_ref(), args, &Filters::new(), callback)
+    }
+
+    /// `train_unsupervised` on only the lines that `filters` keep, in the
+    /// vocabulary and in training alike. Fails if `args.checkpoint` is
+    /// set, as a resumed run couldn't reapply the filters.
+    pub fn train_unsupervised_filtered<P: AsRef<Path>>(
+        corpus: P,
+        args: Args,
+        filters: &Filters,
+    ) -> Result<Model> {
+        Model::train_unsupervised_from(corpus.as_ref(), args, filters, &silent)
+    }
+
+    fn train_unsupervised_from(
+        corpus: &Path,
+        args: Args,
+        filters: &Filters,
+        callback: &dyn TrainCallback,
+    ) -> Result<Model> {
+        args.validate()?;
+        let started = Instant::now();
+        let (vocab, skip, corpus_hash) = corpus_vocabulary(corpus, &args, filters)?;
+        if vocab.n_words() == 0 {
+            return Err(RustTextError::Corpus(String::from(
+                "no words above min_count",
+            )));
+        }
+
+        let mut rng = Rng::new(args.seed);
+        let input = init_input(&vocab, &args, &mut rng)?;
+        let output = Matrix::new(vocab.n_words() as usize, args.dim);
+
+        let mut model = Model {
+            args,
+            vocab,
+            input,
+            output,
+            supervised: false,
+            word_vectors: OnceLock::new(),
+            index: None,
+            label_thresholds: None,
+            label_priors: None,
+            output_tree: OnceLock::new(),
+            quant_input: None,
+            quant_output: None,
+            training_stats: None,
+            metadata: None,
+        };
+        let job = Job {
+            corpus,
+            supervised: false,
+            start: 0,
+            skip: &skip,
+            callback,
+        };
+        let stats = train::unsupervised(&mut model, &job, &mut rng)?;
+        model.metadata = Some(Metadata::new(
+            &model.args,
+            corpus,
+            corpus_hash,
+            &stats,
+            started.elapsed(),
+        ));
+        model.training_stats = Some(stats);
+        Ok(model)
+    }
+
+    /// Learn a text classifier from the labelled file at `corpus`; use
+    /// `Args::supervised` for fastText's classification defaults.
+    pub fn train_supervised<P: AsRef<Path>>(corpus: P, args: Args) -> Result<Model> {
+        Model::train_supervised_with(corpus, args, &silent)
+    }
+
+    /// `train_supervised`, reporting progress to `callback`.
+    pub fn train_supervised_with<P: AsRef<Path>>(
+        corpus: P,
+        args: Args,
+        callback: &dyn TrainCallback,
+    ) -> Result<Model> {
+        Model::train_supervised_from(corpus.as_ref(), args, &Filters::new(), callback)
+    }
+
+    /// `train_supervised` on only the lines that `filters` keep, in the
+    /// vocabulary and in training alike. Fails if `args.checkpoint` is
+    /// set, as a resumed run couldn't reapply the filters.
+    pub fn train_supervised_filtered<P: AsRef<Path>>(
+        corpus: P,
+        args: Args,
+        filters: &Filters,
+    ) -> Result<Model> {
+        Model::train_supervised_from(corpus.as_ref(), args, filters, &silent)
+    }
+
+    fn train_supervised_from(
+        corpus: &Path,
+        args: Args,
+        filters: &Filters,
+        callback: &dyn TrainCallback,
+    ) -> Result<Model> {
+        args.validate()?;
+        let started = Instant::now();
+        let (vocab, skip, corpus_hash) = corpus_vocabulary(corpus, &args, filters)?;
+        if vocab.n_labels() == 0 {
+            return Err(RustTextError::Corpus(format!(
+                "no labels with prefix {}",
+                args.label_prefix
+            )));
+        }
+
+        let mut rng = Rng::new(args.seed);
+        let input = init_input(&vocab, &args, &mut rng)?;
+        let output = Matrix::new(vocab.n_labels() as usize, args.dim);
+
+        let mut model = Model {
+            args,
+            vocab,
+            input,
+            output,
+            supervised: true,
+            word_vectors: OnceLock::new(),
+            index: None,
+            label_thresholds: None,
+            label_priors: None,
+            output_tree: OnceLock::new(),
+            quant_input: None,
+            quant_output: None,
+            training_stats: None,
+            metadata: None,
+        };
+        let job = Job {
+            corpus,
+            supervised: true,
+            start: 0,
+            skip: &skip,
+            callback,
+        };
+        let stats = train::supervised(&mut model, &job, &mut rng)?;
+        model.metadata = Some(Metadata::new(
+            &model.args,
+            corpus,
+            corpus_hash,
+            &stats,
+            started.elapsed(),
+        ));
+        model.training_stats = Some(stats);
+        Ok(model)
+    }
+
+    /// Search for the supervised arguments that score best on
+    /// `autotune.validation_file`, starting from `args`, and return the
+    /// best model found (see `autotune`).
+    pub fn autotune<P: AsRef<Path>>(corpus: P, args: Args, autotune: &Autotune) -> Result<Model> {
+        autotune::search(corpus.as_ref(), args, autotune)
+    }
+
+    /// Continue the training run saved at `checkpoint` (see
+    /// `Args::checkpoint`) until its remaining epochs are done. The
+    /// vocabulary is rebuilt from the original corpus, which must not have
+    /// changed since.
+    pub fn resume<P: AsRef<Path>>(checkpoint: P) -> Result<Model> {
+        Model::resume_with(checkpoint, &silent)
+    }
+
+    /// `resume`, reporting progress to `callback`.
+    pub fn resume_with<P: AsRef<Path>>(
+        checkpoint: P,
+        callback: &dyn TrainCallback,
+    ) -> Result<Model> {
+        let Checkpoint {
+            args,
+            corpus,
+            supervised,
+            processed,
+            input,
+            output,
+        } = Checkpoint::load(checkpoint)?;
+        let started = Instant::now();
+        let (vocab, _, corpus_hash) = corpus_vocabulary(&corpus, &args, &Filters::new())?;
+        let n_outputs = if supervised {
+            vocab.n_labels()
+        } else {
+            vocab.n_words()
+        };
+        if input.rows() != vocab.n_input_rows() || output.rows() != n_outputs as usize {
+            return Err(RustTextError::ModelFormat(format!(
+                "checkpoint matrices do not match the vocabulary of {}",
+                corpus.display()
+            )));
+        }
+
+        let mut rng = Rng::new(args.seed ^ processed);
+        let mut model = Model {
+            args,
+            vocab,
+            input,
+            output,
+            supervised,
+            word_vectors: OnceLock::new(),
+            index: None,
+            label_thresholds: None,
+            label_priors: None,
+            output_tree: OnceLock::new(),
+            quant_input: None,
+            quant_output: None,
+            training_stats: None,
+            metadata: None,
+        };
+        let job = Job {
+            corpus: &corpus,
+            supervised,
+            start: processed,
+            skip: &[],
+            callback,
+        };
+        let stats = if supervised {
+            train::supervised(&mut model, &job, &mut rng)?
+        } else {
+            train::unsupervised(&mut model, &job, &mut rng)?
+        };
+        model.metadata = Some(Metadata::new(
+            &model.args,
+            &corpus,
+            corpus_hash,
+            &stats,
+            started.elapsed(),
+        ));
+        model.training_stats = Some(stats);
+        Ok(model)
+    }
+
+    /// A model from deserialized parts, each matrix given dense or (with
+    /// an empty dense placeholder) compressed, after checking that the
+    /// matrices fit the vocabulary and `args.dim`.
+    pub(crate) fn from_parts(
+        args: Args,
+        vocab: Vocabulary,
+        supervised: bool,
+        input: (Matrix, Option<CompressedMatrix>),
+        output: (Matrix, Option<CompressedMatrix>),
+    ) -> Result<Model> {
+        let n_outputs = if supervised {
+            vocab.n_labels()
+        } else {
+            vocab.n_words()
+        };
+        let expected = [
+            ("input", vocab.n_input_rows()),
+            ("output", n_outputs as usize),
+        ];
+        for ((what, rows), (dense, compressed)) in expected.iter().zip([&input, &output].iter()) {
+            let shape = match compressed {
+                Some(matrix) => (matrix.rows(), matrix.cols()),
+                None => (dense.rows(), dense.cols()),
+            };
+            if shape != (*rows, args.dim) {
+                return Err(RustTextError::ModelFormat(format!(
+                    "{} matrix is {} x {}, expected {} x {}",
+                    what, shape.0, shape.1, rows, args.dim
+                )));
+            }
+        }
+
+        Ok(Model {
+            args,
+            vocab,
+            input: input.0,
+            output: output.0,
+            supervised,
+            word_vectors: OnceLock::new(),
+            index: None,
+            label_thresholds: None,
+            label_priors: None,
+            output_tree: OnceLock::new(),
+            quant_input: input.1,
+            quant_output: output.1,
+            training_stats: None,
+            metadata: None,
+        })
+    }
+
+    /// Save the model in rusttext's native format, which keeps everything a
+    /// model holds (including int8 and cutoff-quantized matrices and
+    /// 64-bit hashing) and reads back exactly; see `native::write_model`.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        native::save(self, path)
+    }
+
+    /// Load a model written by `Model::save`.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Model> {
+        native::load(path)
+    }
+
+    /// Load a model written by `Model::save` with its matrices
+    /// memory-mapped instead of read into memory, for fast loading and
+    /// sharing between serving processes; see `native::load_mmap`.
+    pub fn load_mmap<P: AsRef<Path>>(path: P) -> Result<Model> {
+        native::load_mmap(path)
+    }
+
+    /// Load a model written by `Model::save` as `options` ask.
+    pub fn load_with<P: AsRef<Path>>(path: P, options: &LoadOptions) -> Result<Model> {
+        let mut model = if options.int8 {
+            native::load_int8(path)?
+        } else if options.mmap {
+            native::load_mmap(path)?
+        } else {
+            native::load(path)?
+        };
+        if options.compact_vocabulary {
+            model.vocab.compact();
+        }
+        Ok(model)
+    }
+
+    /// Load a model saved by rusttext (`Model::save`) or by fastText (`.bin`
+    /// or `.ftz`), telling them apart by their first bytes.
+    pub fn load_auto<P: AsRef<Path>>(path: P) -> Result<Model> {
+        if is_native(path.as_ref())? {
+            Model::load(path)
+        } else {
+            Model::load_fasttext(path)
+        }
+    }
+
+    /// `load_auto`, memory-mapping the matrices of a rusttext model as
+    /// `load_mmap` does. fastText files are always read into memory.
+    pub fn load_auto_mmap<P: AsRef<Path>>(path: P) -> Result<Model> {
+        if is_native(path.as_ref())? {
+            Model::load_mmap(path)
+        } else {
+            Model::load_fasttext(path)
+        }
+    }
+
+    /// Load a model saved by fastText in its `.bin` format; see
+    /// `fasttext::read_model`.
+    pub fn load_fasttext<P: AsRef<Path>>(path: P) -> Result<Model> {
+        fasttext::load(path)
+    }
+
+    /// Save the model in fastText's `.bin` format, for the fastText binary
+    /// and Python package; see `fasttext::write_model`.
+    pub fn save_fasttext<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        fasttext::save(self, path)
+    }
+
+    /// Export the input and output matrices as safetensors at `path`, with
+    /// the vocabulary alongside, for use as embeddings in PyTorch or JAX;
+    /// see `safetensors::write_safetensors`.
+    pub fn export_safetensors<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        safetensors::export(self, path)
+    }
+
+    /// Export a classifier as an ONNX graph at `path`, for serving with
+    /// ONNX Runtime; see `onnx::write_onnx`.
+    pub fn export_onnx<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        onnx::export(self, path)
+    }
+
+    /// Shrink the vectors to `target_dim` dimensions with PCA, updating
+    /// both matrices and `args.dim`; see `pca::reduce_dim`.
+    pub fn reduce_dim(&mut self, target_dim: usize) -> Result<()> {
+        pca::reduce_dim(self, target_dim)
+    }
+
+    /// Compress the model with product quantization (see `quant`), as
+    /// fastText's `quantize` does. The dense matrices are dropped, so a
+    /// quantized model can no longer be trained or quantized again.
+    pub fn quantize(&mut self, quant_args: &QuantArgs) -> Result<()> {
+        if self.quant_input.is_some() {
+            return Err(RustTextError::InvalidArgument(String::from(
+                "model is already quantized",
+            )));
+        }
+        let mut rng = Rng::new(self.args.seed);
+        let quant_input = if quant_args.cutoff > 0 && quant_args.cutoff < self.input.rows() {
+            let keep: Vec<u32> = self.vocab.get_id(word::EOS).into_iter().collect();
+            let rows = quant::select_rows(&self.input, quant_args.cutoff, &keep);
+            QuantMatrix::from_rows(
+                &self.input,
+                rows,
+                quant_args.dsub,
+                quant_args.qnorm,
+                &mut rng,
+            )?
+        } else {
+            QuantMatrix::new(&self.input, quant_args.dsub, quant_args.qnorm, &mut rng)?
+        };
+        // as in fastText, the output matrix always uses two dimensions per
+        // sub-quantizer
+        let quant_output = if quant_args.qout {
+            Some(CompressedMatrix::Product(QuantMatrix::new(
+                &self.output,
+                2,
+                quant_args.qnorm,
+                &mut rng,
+            )?))
+        } else {
+            None
+        };
+
+        self.input = Matrix::new(0, self.args.dim);
+        self.quant_input = Some(CompressedMatrix::Product(quant_input));
+        self.reset_word_vectors();
+        if quant_output.is_some() {
+            self.output = Matrix::new(0, self.args.dim);
+            self.quant_output = quant_output;
+        }
+        Ok(())
+    }
+
+    /// Keep only the `cutoff` input rows with the largest norms, words and
+    /// subword or word n-gram buckets alike (the end-of-sentence row is
+    /// always kept, and every label), as fastText's `quantize -cutoff`
+    /// does. The vocabulary is renumbered to match (see
+    /// `Vocabulary::prune`); follow with `retrain` to recover accuracy.
+    pub fn prune(&mut self, cutoff: usize) -> Result<()> {
+        if self.is_quantized() {
+            return Err(RustTextError::InvalidArgument(String::from(
+                "cannot prune a quantized model",
+            )));
+        }
+        if cutoff >= self.input.rows() {
+            return Ok(());
+        }
+        let keep: Vec<u32> = self.vocab.get_id(word::EOS).into_iter().collect();
+        let rows = quant::select_rows(&self.input, cutoff, &keep);
+        if !self.supervised && !self.vocab.char_only() {
+            let n_words = self.vocab.n_words();
+            let words: Vec<u32> = rows.iter().copied().filter(|&row| row < n_words).collect();
+            self.output = self.output.select_rows(&words);
+        }
+        self.input = self.input.select_rows(&rows);
+        self.reset_word_vectors();
+        self.vocab.prune(&rows);
+        Ok(())
+    }
+
+    /// Train the model for `epochs` more epochs on `corpus`, with its
+    /// current vocabulary and the learning rate schedule restarted; used
+    /// after `prune`. Checkpointing is off for the run.
+    pub fn retrain<P: AsRef<Path>>(&mut self, corpus: P, epochs: u32) -> Result<()> {
+        if self.is_quantized() {
+            return Err(RustTextError::InvalidArgument(String::from(
+                "cannot retrain a quantized model",
+            )));
+        }
+        let epoch = std::mem::replace(&mut self.args.epoch, epochs);
+        let checkpoint = self.args.checkpoint.take();
+        let job = Job {
+            corpus: corpus.as_ref(),
+            supervised: self.supervised,
+            start: 0,
+            skip: &[],
+            callback: &silent,
+        };
+        let mut rng = Rng::new(self.args.seed);
+        let result = if self.supervised {
+            train::supervised(self, &job, &mut rng)
+        } else {
+            train::unsupervised(self, &job, &mut rng)
+        };
+        self.args.epoch = epoch;
+        self.args.checkpoint = checkpoint;
+        self.reset_word_vectors();
+        self.training_stats = Some(result?);
+        Ok(())
+    }
+
+    /// Store both matrices as int8 with a scale per row (see
+    /// `Int8Matrix`): a 4x memory cut without codebook training, at the
+    /// cost of a little precision. Like `quantize`, this drops the dense
+    /// matrices.
+    pub fn quantize_int8(&mut self) -> Result<()> {
+        if self.quant_input.is_some() {
+            return Err(RustTextError::InvalidArgument(String::from(
+                "model is already quantized",
+            )));
+        }
+        self.quant_input = Some(CompressedMatrix::Int8(Int8Matrix::new(&self.input)));
+        self.quant_output = Some(CompressedMatrix::Int8(Int8Matrix::new(&self.output)));
+        self.input = Matrix::new(0, self.args.dim);
+        self.output = Matrix::new(0, self.args.dim);
+        self.reset_word_vectors();
+        Ok(())
+    }
+
+    pub fn is_quantized(&self) -> bool {
+        self.quant_input.is_some()
+    }
+
+    /// Whether this is a classifier, trained with `train_supervised`.
+    pub fn is_supervised(&self) -> bool {
+        self.supervised
+    }
+
+    /// Tokens, time, speed and loss of the run that trained (or last
+    /// retrained) this model; `None` for a loaded model.
+    pub fn training_stats(&self) -> Option<&TrainingStats> {
+        self.training_stats.as_ref()
+    }
+
+    /// Where the model came from (see `Metadata`); `None` for a model
+    /// loaded from a fastText file.
+    pub fn metadata(&self) -> Option<&Metadata> {
+        self.metadata.as_ref()
+    }
+
+    /// The metadata, for setting `Metadata::tags` before saving.
+    pub fn metadata_mut(&mut self) -> Option<&mut Metadata> {
+        self.metadata.as_mut()
+    }
+
+    /// The `k` most probable labels for `text` with their probabilities,
+    /// best first, keeping only those with probability at least
+    /// `threshold`. Pass `usize::MAX` as `k` to get every label above the
+    /// threshold, the usual way to query one-vs-all models. The text is
+    /// tokenized on whitespace and represented by the mean of its word,
+    /// subword and word n-gram vectors. Empty for a model trained without
+    /// labels, or text with no known features.
+    pub fn predict(&self, text: &str, k: usize, threshold: f32) -> Vec<(String, f32)> {
+        predict::predict(self, text, k, threshold, &mut Scratch::default())
+    }
+
+    /// Predict every line of the file at `input` and write the results to
+    /// `output` as JSON Lines, one record per input line; see
+    /// `predict::write_jsonl`. Returns the number of lines predicted.
+    pub fn predict_jsonl<P: AsRef<Path>, Q: AsRef<Path>>(
+        &self,
+        input: P,
+        output: Q,
+        k: usize,
+        threshold: f32,
+    ) -> Result<usize> {
+        predict::write_jsonl(
+            self,
+            BufReader::new(File::open(input)?),
+            File::create(output)?,
+            k,
+            threshold,
+        )
+    }
+
+    /// Precision and recall at `k` of the classifier on the labelled file at
+    /// `path`, as `fasttext test` reports them; see `eval::test`.
+    pub fn test<P: AsRef<Path>>(&self, path: P, k: usize, threshold: f32) -> Result<TestResult> {
+        eval::test(self, path, k, threshold)
+    }
+
+    /// Precision, recall and F1 of every label on the labelled file at
+    /// `path`, with a confusion matrix for single-label files; see
+    /// `eval::test_labels`.
+    pub fn test_labels<P: AsRef<Path>>(
+        &self,
+        path: P,
+        k: usize,
+        threshold: f32,
+    ) -> Result<LabelReport> {
+        eval::test_labels(self, path, k, threshold)
+    }
+
+    /// Sweep per-label probability thresholds of a one-vs-all classifier on
+    /// the labelled file at `path` (see `eval::calibrate`) and keep them:
+    /// `predict` then leaves out every label below its own threshold.
+    pub fn calibrate<P: AsRef<Path>>(&mut self, path: P, target: CalibrationTarget) -> Result<()> {
+        let thresholds = eval::calibrate(self, path, target)?;
+        self.label_thresholds = Some(thresholds);
+        Ok(())
+    }
+
+    pub fn label_thresholds(&self) -> Option<&[f32]> {
+        self.label_thresholds.as_deref()
+    }
+
+    /// Set the per-label thresholds `predict` applies, one per label in
+    /// label order, or remove them with `None`.
+    pub fn set_label_thresholds(&mut self, thresholds: Option<Vec<f32>>) -> Result<()> {
+        let n_labels = self.vocab.n_labels() as usize;
+        if let Some(thresholds) = &thresholds {
+            if thresholds.len() != n_labels {
+                return Err(RustTextError::DimensionMismatch {
+                    expected: n_labels,
+                    found: thresholds.len(),
+                });
+            }
+        }
+        self.label_thresholds = thresholds;
+        Ok(())
+    }
+
+    pub fn label_priors(&self) -> Option<&LabelPriors> {
+        self.label_priors.as_ref()
+    }
+
+    /// Set the label priors `predict` applies to every label's score before
+    /// ranking and thresholds, or remove them with `None`. They are saved
+    /// with the model.
+    pub fn set_label_priors(&mut self, priors: Option<LabelPriors>) -> Result<()> {
+        let n_labels = self.vocab.n_labels() as usize;
+        if let Some(priors) = &priors {
+            if priors.offsets().len() != n_labels {
+                return Err(RustTextError::DimensionMismatch {
+                    expected: n_labels,
+                    found: priors.offsets().len(),
+                });
+            }
+        }
+        self.label_priors = priors;
+        Ok(())
+    }
+
+    /// `predict` for many texts at once, spread over rayon's thread pool.
+    /// Returns one prediction list per text, in input order.
+    pub fn predict_batch(
+        &self,
+        texts: &[&str],
+        k: usize,
+        threshold: f32,
+    ) -> Vec<Vec<(String, f32)>> {
+        predict::predict_batch(self, texts, k, threshold)
+    }
+
+    /// Embedding of `word`: the mean of its own input row and its character
+    /// n-gram buckets if it is in the vocabulary, or of just the n-gram
+    /// buckets if it is not, so misspellings and unseen inflections still
+    /// get a meaningful vector. All zeros for labels, and for unknown words
+    /// when subwords are disabled.
+    pub fn word_vector(&self, word: &str) -> Vec<f32> {
+        let mut vector = Vec::new();
+        vectors::word_vector(self, word, &mut vector);
+        vector
+    }
+
+    /// The `k` words nearest to `word` by cosine similarity of normalized
+    /// word vectors, best first, as fastText's `nn` command lists them (the
+    /// word itself left out). Searches the approximate index when one is
+    /// present, every word otherwise.
+    pub fn nearest_neighbors(&self, word: &str, k: usize) -> Vec<(String, f32)> {
+        vectors::nearest(self, &self.word_vector(word), k, &[word])
+    }
+
+    /// Build an HNSW index over the normalized word vectors (see
+    /// `ann::HnswIndex`), which `nearest_neighbors`, `analogy` and other
+    /// neighbour queries then search instead of every word. The index is
+    /// dropped whenever the vectors change.
+    pub fn build_index(&mut self, args: &HnswArgs) -> Result<()> {
+        self.index = Some(HnswIndex::build(self.normalized_word_vectors(), args)?);
+        Ok(())
+    }
+
+    /// Compute the normalized word vectors that neighbour and analogy
+    /// queries search, which otherwise happens on the first query. They
+    /// stay cached until the model changes, so a long-lived process pays
+    /// for them once, up front.
+    pub fn cache_word_vectors(&self) {
+        self.normalized_word_vectors();
+    }
+
+    pub fn index(&self) -> Option<&HnswIndex> {
+        self.index.as_ref()
+    }
+
+    /// Use `index` for neighbour queries, or go back to exact search with
+    /// `None`. The index must cover every word of this model, in its
+    /// dimension.
+    pub fn set_index(&mut self, index: Option<HnswIndex>) -> Result<()> {
+        if let Some(index) = &index {
+            if index.len() != self.vocab.n_words() as usize {
+                return Err(RustTextError::DimensionMismatch {
+                    expected: self.vocab.n_words() as usize,
+                    found: index.len(),
+                });
+            }
+            if index.dim() != self.args.dim {
+                return Err(RustTextError::DimensionMismatch {
+                    expected: self.args.dim,
+                    found: index.dim(),
+                });
+            }
+        }
+        self.index = index;
+        Ok(())
+    }
+
+    /// Save the index to `path`, usually `ann::index_path` of the model
+    /// file so it travels alongside it.
+    pub fn save_index<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        match &self.index {
+            Some(index) => ann::save(index, path),
+            None => Err(RustTextError::InvalidArgument(String::from(
+                "model has no neighbour index",
+            ))),
+        }
+    }
+
+    /// Load an index saved by `save_index` and use it; see `set_index`.
+    pub fn load_index<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        self.set_index(Some(ann::load(path)?))
+    }
+
+    /// The `k` best answers to "`a` is to `b` as `c` is to ?", as fastText's
+    /// `analogies` command computes them: the words whose normalized
+    /// vectors are closest (by cosine) to `b - a + c`, leaving out the
+    /// three query words. Unknown query words are represented by their
+    /// subwords.
+    pub fn analogy(&self, a: &str, b: &str, c: &str, k: usize) -> Vec<(String, f32)> {
+        vectors::analogy(self, a, b, c, k)
+    }
+
+    /// Cosine similarity of the `word_vector`s of two words, unknown words
+    /// included; 0 if either vector is all zeros.
+    pub fn similarity(&self, first: &str, second: &str) -> f32 {
+        vectors::cosine(&self.word_vector(first), &self.word_vector(second))
+    }
+
+    /// Cosine similarity of the `sentence_vector`s of two texts.
+    pub fn sentence_similarity(&self, first: &str, second: &str) -> f32 {
+        vectors::cosine(&self.sentence_vector(first), &self.sentence_vector(second))
+    }
+
+    /// Drop the cached normalized word vectors and the neighbour index
+    /// built over them, after the input matrix changes.
+    pub(crate) fn reset_word_vectors(&mut self) {
+        self.word_vectors.take();
+        self.index = None;
+    }
+
+    pub(crate) fn normalized_word_vectors(&self) -> &Matrix {
+        self.word_vectors
+            .get_or_init(|| vectors::normalized_word_vectors(self))
+    }
+
+    /// A single vector for `text`, to feed clustering or retrieval. For a
+    /// classifier this is the averaged input the classifier sees; for word
+    /// embeddings it is the mean of the normalized word vectors.
+    pub fn sentence_vector(&self, text: &str) -> Vec<f32> {
+        let mut vector = Vec::new();
+        vectors::sentence_vector(self, text, &mut vector);
+        vector
+    }
+
+    /// `sentence_vector` of every text, computed in parallel, as the rows
+    /// of one contiguous matrix.
+    pub fn sentence_vectors(&self, texts: &[&str]) -> Matrix {
+        vectors::sentence_vectors(self, texts)
+    }
+
+    /// Write the vector of every word (subwords included, as `word_vector`
+    /// computes it) to `path` in `.vec` text format, most frequent first.
+    pub fn save_vectors<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        self.save_vectors_filtered(path, &io::ExportFilter::default())
+            .map(|_| ())
+    }
+
+    /// Write the vector of every word to `path` in word2vec's binary
+    /// format (see `io::write_word2vec`), most frequent first.
+    pub fn save_word2vec<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        io::write_word2vec(
+            File::create(path)?,
+            &self.vocab,
+            self.args.dim,
+            &io::ExportFilter::default(),
+            |id| self.word_vector(&self.vocab.words()[id as usize].word),
+        )
+        .map(|_| ())
+    }
+
+    /// `save_vectors` restricted to the entries `filter` selects. Returns
+    /// the number of vectors written.
+    pub fn save_vectors_filtered<P: AsRef<Path>>(
+        &self,
+        path: P,
+        filter: &io::ExportFilter,
+    ) -> Result<usize> {
+        io::write_vectors(
+            File::create(path)?,
+            &self.vocab,
+            self.args.dim,
+            filter,
+            |id| self.word_vector(&self.vocab.words()[id as usize].word),
+        )
+    }
+
+    /// Write `vectors.tsv` and `metadata.tsv` into directory `dir` (created
+    /// if needed) for the TensorBoard Embedding Projector, with every word;
+    /// see `io::write_projector`. Returns the number of vectors written.
+    pub fn export_projector<P: AsRef<Path>>(&self, dir: P) -> Result<usize> {
+        self.export_projector_filtered(dir, &io::ExportFilter::default())
+    }
+
+    /// `export_projector` restricted to the entries `filter` selects. A
+    /// classifier's labels are exported as their output matrix rows.
+    pub fn export_projector_filtered<P: AsRef<Path>>(
+        &self,
+        dir: P,
+        filter: &io::ExportFilter,
+    ) -> Result<usize> {
+        let dir = dir.as_ref();
+        std::fs::create_dir_all(dir)?;
+        let n_words = self.vocab.n_words();
+        io::write_projector(
+            File::create(dir.join("vectors.tsv"))?,
+            File::create(dir.join("metadata.tsv"))?,
+            &self.vocab,
+            self.args.dim,
+            filter,
+            |id| match id.checked_sub(n_words) {
+                Some(label) if self.supervised => self.output_row(label as usize),
+                _ => self.word_vector(&self.vocab.words()[id as usize].word),
+            },
+        )
+    }
+
+    // `out += scale * input_row`, from whichever form the input matrix is
+    // stored in.
+    pub(crate) fn add_input_row(&self, out: &mut [f32], row: u32, scale: f32) {
+        match &self.quant_input {
+            Some(quant) => quant.add_row_to(out, row as usize, scale),
+            None => self.input.add_row_to(out, row as usize, scale),
+        }
+    }
+
+    /// Row `row` of the output matrix, read through `output_dot` so that it
+    /// works for compressed matrices too.
+    pub(crate) fn output_row(&self, row: usize) -> Vec<f32> {
+        let mut unit = vec![0.0; self.args.dim];
+        (0..self.args.dim)
+            .map(|i| {
+                unit[i] = 1.0;
+                let value = self.output_dot(&unit, row);
+                unit[i] = 0.0;
+                value
+            })
+            .collect()
+    }
+
+    pub(crate) fn output_dot(&self, vector: &[f32], row: usize) -> f32 {
+        match &self.quant_output {
+            Some(quant) => quant.dot_row(vector, row),
+            None => self.output.dot_row(vector, row),
+        }
+    }
+
+    pub(crate) fn output_tree(&self) -> &HuffmanTree {
+        self.output_tree.get_or_init(|| {
+            let n_words = self.vocab.n_words();
+            let outputs = if self.supervised {
+                n_words..n_words + self.vocab.n_labels()
+            } else {
+                0..n_words
+            };
+            let counts: Vec<u32> = outputs.map(|id| self.vocab.count(id)).collect();
+            HuffmanTree::new(&counts)
+        })
+    }
+
+    pub(crate) fn n_output_rows(&self) -> usize {
+        match &self.quant_output {
+            Some(quant) => quant.rows(),
+            None => self.output.rows(),
+        }
+    }
+
+    pub fn args(&self) -> &Args {
+        &self.args
+    }
+
+    pub fn vocabulary(&self) -> &Vocabulary {
+        &self.vocab
+    }
+
+    pub fn input(&self) -> &Matrix {
+        &self.input
+    }
+
+    pub fn output(&self) -> &Matrix {
+        &self.output
+    }
+
+    pub fn quant_input(&self) -> Option<&CompressedMatrix> {
+        self.quant_input.as_ref()
+    }
+
+    pub fn quant_output(&self) -> Option<&CompressedMatrix> {
+        self.quant_output.as_ref()
+    }
+}
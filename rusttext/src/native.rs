@@ -0,0 +1,721 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use memmap2::Mmap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::args::{Args, ClassWeights, LossKind, LrSchedule, WordModel};
+use crate::io::read_f32s;
+use crate::matrix::Matrix;
+use crate::model::Model;
+use crate::priors::LabelPriors;
+use crate::quant::{CompressedMatrix, Int8Matrix};
+use crate::vocabulary::Vocabulary;
+use crate::{Result, RustTextError};
+
+/// First bytes of every file `write_model` writes, which tell a rusttext
+/// model apart from a fastText one.
+pub const MAGIC: &[u8; 4] = b"RTXM";
+/// Format version written by `write_model`. Readers accept any version up
+/// to their own; additions that older readers can skip go in the header's
+/// extension sections instead of bumping it. Version 2 added
+/// `Args::word_model`; version 1 models load as skipgram.
+pub const VERSION: u32 = 2;
+/// Byte alignment of dense matrix blocks within the file, so they can be
+/// memory-mapped and read in place as `f32`s (see `load_mmap`).
+pub const ALIGN: usize = 64;
+/// Extension section holding the model's calibrated per-label thresholds.
+const LABEL_THRESHOLDS: &str = "label_thresholds";
+/// Extension section holding the model's label prior offsets.
+const LABEL_PRIORS: &str = "label_priors";
+/// Extension section holding the model's `Metadata`, as JSON so that it
+/// still decodes after `Args` gains or loses fields.
+const METADATA: &str = "metadata";
+
+// How one model matrix is stored: dense data follows the header in its own
+// aligned block, compressed matrices live in the header.
+#[derive(Serialize, Deserialize)]
+enum Block<C> {
+    Dense { rows: u64, cols: u64 },
+    Compressed(C),
+}
+
+// Everything but the dense matrix data, bincode-encoded. Generic so that
+// saving can borrow what loading owns, and older versions can decode
+// their own arguments.
+#[derive(Serialize, Deserialize)]
+struct Header<A, V, C> {
+    args: A,
+    supervised: bool,
+    vocabulary: V,
+    input: Block<C>,
+    output: Block<C>,
+    /// Named optional sections; readers ignore those they don't know.
+    extensions: BTreeMap<String, Vec<u8>>,
+}
+
+// `Args` as version 1 stored them, without `word_model`.
+#[derive(Deserialize)]
+struct ArgsV1 {
+    dim: usize,
+    ws: usize,
+    epoch: u32,
+    lr: f32,
+    min_count: u32,
+    min_count_label: u32,
+    neg: usize,
+    word_ngrams: usize,
+    min_n: usize,
+    max_n: usize,
+    bucket: u32,
+    label_prefix: String,
+    t: f64,
+    vocab_size: usize,
+    loss: LossKind,
+    threads: usize,
+    lr_schedule: LrSchedule,
+    lr_update_rate: u64,
+    seed: u64,
+    checkpoint: Option<PathBuf>,
+    checkpoint_interval: u64,
+    validation_file: Option<PathBuf>,
+    patience: u32,
+    label_smoothing: f32,
+    class_weights: ClassWeights,
+    pretrained_vectors: Option<PathBuf>,
+    freeze_embeddings: bool,
+}
+
+impl From<ArgsV1> for Args {
+    fn from(args: ArgsV1) -> Args {
+        Args {
+            dim: args.dim,
+            ws: args.ws,
+            epoch: args.epoch,
+            lr: args.lr,
+            min_count: args.min_count,
+            min_count_label: args.min_count_label,
+            neg: args.neg,
+            word_ngrams: args.word_ngrams,
+            min_n: args.min_n,
+            max_n: args.max_n,
+            bucket: args.bucket,
+            label_prefix: args.label_prefix,
+            t: args.t,
+            vocab_size: args.vocab_size,
+            loss: args.loss,
+            // the only unsupervised objective before version 2
+            word_model: WordModel::Skipgram,
+            threads: args.threads,
+            lr_schedule: args.lr_schedule,
+            lr_update_rate: args.lr_update_rate,
+            seed: args.seed,
+            checkpoint: args.checkpoint,
+            checkpoint_interval: args.checkpoint_interval,
+            validation_file: args.validation_file,
+            patience: args.patience,
+            label_smoothing: args.label_smoothing,
+            class_weights: args.class_weights,
+            pretrained_vectors: args.pretrained_vectors,
+            freeze_embeddings: args.freeze_embeddings,
+        }
+    }
+}
+
+fn format_error(message: String) -> RustTextError {
+    RustTextError::ModelFormat(message)
+}
+
+fn padding(position: usize) -> usize {
+    (ALIGN - position % ALIGN) % ALIGN
+}
+
+fn block<'a>(
+    dense: &Matrix,
+    compressed: Option<&'a CompressedMatrix>,
+) -> Block<&'a CompressedMatrix> {
+    match compressed {
+        Some(matrix) => Block::Compressed(matrix),
+        None => Block::Dense {
+            rows: dense.rows() as u64,
+            cols: dense.cols() as u64,
+        },
+    }
+}
+
+/// Write `model` in rusttext's own format: a magic number and `VERSION`, a
+/// bincode header with the arguments, vocabulary and any compressed
+/// matrices, then each dense matrix as little-endian `f32`s starting on an
+/// `ALIGN`-byte boundary. Everything a model can hold round-trips, except
+/// a vocabulary with a custom token hasher, which can't be saved.
+pub fn write_model<W: Write>(writer: W, model: &Model) -> Result<()> {
+    if model.vocabulary().hasher().hash_mode().is_none() {
+        return Err(RustTextError::InvalidArgument(String::from(
+            "a vocabulary with a custom token hasher cannot be saved",
+        )));
+    }
+    let mut extensions = BTreeMap::new();
+    if let Some(thresholds) = model.label_thresholds() {
+        extensions.insert(
+            String::from(LABEL_THRESHOLDS),
+            bincode::serialize(thresholds)?,
+        );
+    }
+    if let Some(priors) = model.label_priors() {
+        extensions.insert(
+            String::from(LABEL_PRIORS),
+            bincode::serialize(priors.offsets())?,
+        );
+    }
+    if let Some(metadata) = model.metadata() {
+        let json = serde_json::to_vec(metadata)
+            .map_err(|err| RustTextError::InvalidArgument(err.to_string()))?;
+        extensions.insert(String::from(METADATA), json);
+    }
+    let header = Header {
+        args: model.args().clone(),
+        supervised: model.supervised,
+        vocabulary: model.vocabulary(),
+        input: block(model.input(), model.quant_input()),
+        output: block(model.output(), model.quant_output()),
+        extensions,
+    };
+    let header = bincode::serialize(&header)?;
+
+    let mut writer = BufWriter::new(writer);
+    writer.write_all(MAGIC)?;
+    writer.write_all(&VERSION.to_le_bytes())?;
+    writer.write_all(&(header.len() as u64).to_le_bytes())?;
+    writer.write_all(&header)?;
+    let mut position = 16 + header.len();
+    for (dense, compressed) in [
+        (model.input(), model.quant_input()),
+        (model.output(), model.quant_output()),
+    ]
+    .iter()
+    {
+        if compressed.is_some() {
+            continue;
+        }
+        writer.write_all(&[0; ALIGN][..padding(position)])?;
+        position += padding(position);
+        for value in dense.data().iter() {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+        position += 4 * dense.data().len();
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+// One model matrix: compressed ones come from the header, dense ones are
+// read from the next aligned block, or quantized to int8 row by row as they
+// are read.
+fn read_block<R: Read>(
+    reader: &mut R,
+    position: &mut usize,
+    block: Block<CompressedMatrix>,
+    dim: usize,
+    int8: bool,
+) -> Result<(Matrix, Option<CompressedMatrix>)> {
+    let (rows, cols) = match block {
+        Block::Dense { rows, cols } => (rows, cols),
+        Block::Compressed(matrix) => {
+            matrix.validate()?;
+            return Ok((Matrix::new(0, dim), Some(matrix)));
+        }
+    };
+    let skip = padding(*position);
+    std::io::copy(&mut reader.by_ref().take(skip as u64), &mut std::io::sink())?;
+    let (rows, cols) = (rows as usize, cols as usize);
+    let n = rows
+        .checked_mul(cols)
+        .ok_or_else(|| format_error(format!("matrix of {} x {} is too large", rows, cols)))?;
+    *position += skip + 4 * n;
+    if !int8 {
+        return Ok((Matrix::from_vec(rows, cols, read_f32s(reader, n)?)?, None));
+    }
+    let mut matrix = Int8Matrix::empty(cols);
+    for _ in 0..rows {
+        matrix.push_row(&read_f32s(reader, cols)?);
+    }
+    Ok((Matrix::new(0, dim), Some(CompressedMatrix::Int8(matrix))))
+}
+
+// The header, and the number of bytes read up to its end.
+fn read_header<R: Read>(
+    reader: &mut R,
+) -> Result<(Header<Args, Vocabulary, CompressedMatrix>, usize)> {
+    let mut magic = [0; 4];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(format_error(String::from("not a rusttext model")));
+    }
+    let mut word = [0; 4];
+    reader.read_exact(&mut word)?;
+    let version = u32::from_le_bytes(word);
+    if version == 0 || version > VERSION {
+        return Err(format_error(format!(
+            "model format version {} is newer than this rusttext supports ({})",
+            version, VERSION
+        )));
+    }
+    let mut word = [0; 8];
+    reader.read_exact(&mut word)?;
+    let header_len = u64::from_le_bytes(word);
+    let mut header = Vec::new();
+    reader.by_ref().take(header_len).read_to_end(&mut header)?;
+    if header.len() as u64 != header_len {
+        return Err(format_error(String::from("model file is truncated")));
+    }
+    let len = 16 + header.len();
+    if version == 1 {
+        let header: Header<ArgsV1, Vocabulary, CompressedMatrix> = bincode::deserialize(&header)?;
+        let header = Header {
+            args: Args::from(header.args),
+            supervised: header.supervised,
+            vocabulary: header.vocabulary,
+            input: header.input,
+            output: header.output,
+            extensions: header.extensions,
+        };
+        return Ok((header, len));
+    }
+    Ok((bincode::deserialize(&header)?, len))
+}
+
+// Apply the extension sections this version knows to `model`.
+fn read_extensions(mut model: Model, extensions: &BTreeMap<String, Vec<u8>>) -> Result<Model> {
+    if let Some(bytes) = extensions.get(LABEL_THRESHOLDS) {
+        model.set_label_thresholds(Some(bincode::deserialize(bytes)?))?;
+    }
+    if let Some(bytes) = extensions.get(LABEL_PRIORS) {
+        let offsets = bincode::deserialize(bytes)?;
+        model.set_label_priors(Some(LabelPriors::from_offsets(offsets)))?;
+    }
+    // metadata only describes the model, so an undecodable section is
+    // dropped rather than failing the load
+    if let Some(bytes) = extensions.get(METADATA) {
+        model.metadata = serde_json::from_slice(bytes).ok();
+    }
+    Ok(model)
+}
+
+/// Read a model written by `write_model`.
+pub fn read_model<R: Read>(reader: R) -> Result<Model> {
+    read_model_as(reader, false)
+}
+
+/// Read a model written by `write_model` with its dense matrices quantized
+/// to int8 as they are read, as `Model::quantize_int8` would leave them,
+/// without ever holding them as `f32`s. Fails on an already quantized
+/// model.
+pub fn read_model_int8<R: Read>(reader: R) -> Result<Model> {
+    read_model_as(reader, true)
+}
+
+fn read_model_as<R: Read>(mut reader: R, int8: bool) -> Result<Model> {
+    let (header, mut position) = read_header(&mut reader)?;
+    if int8 && matches!(header.input, Block::Compressed(_)) {
+        return Err(RustTextError::InvalidArgument(String::from(
+            "model is already quantized",
+        )));
+    }
+    let dim = header.args.dim;
+    let input = read_block(&mut reader, &mut position, header.input, dim, int8)?;
+    let output = read_block(&mut reader, &mut position, header.output, dim, int8)?;
+    let model = Model::from_parts(
+        header.args,
+        header.vocabulary,
+        header.supervised,
+        input,
+        output,
+    )?;
+    read_extensions(model, &header.extensions)
+}
+
+// One model matrix, with dense ones borrowed from the next aligned block of
+// `map`.
+fn map_block(
+    map: &Arc<Mmap>,
+    position: &mut usize,
+    block: Block<CompressedMatrix>,
+    dim: usize,
+) -> Result<(Matrix, Option<CompressedMatrix>)> {
+    let (rows, cols) = match block {
+        Block::Dense { rows, cols } => (rows as usize, cols as usize),
+        Block::Compressed(matrix) => {
+            matrix.validate()?;
+            return Ok((Matrix::new(0, dim), Some(matrix)));
+        }
+    };
+    *position += padding(*position);
+    let matrix = Matrix::from_mmap(rows, cols, Arc::clone(map), *position)?;
+    *position += 4 * matrix.data().len();
+    Ok((matrix, None))
+}
+
+/// Load a model saved by `write_model` with its dense matrices
+/// memory-mapped rather than read: loading costs little more than reading
+/// the vocabulary, and the page cache shares the matrices between every
+/// process that maps the same file. A mapped matrix is copied into memory
+/// if the model is trained further; compressed matrices are always read.
+///
+/// The file must not be modified while the model is alive. On big-endian
+/// targets, where the little-endian data can't be used in place, this
+/// falls back to `load`.
+pub fn load_mmap<P: AsRef<Path>>(path: P) -> Result<Model> {
+    if cfg!(target_endian = "big") {
+        return load(path);
+    }
+    let file = File::open(path)?;
+    // Safety: the map is only read, and the caller keeps the file
+    // unchanged while it is mapped.
+    let map = Arc::new(unsafe { Mmap::map(&file)? });
+    let (header, mut position) = read_header(&mut &map[..])?;
+    let dim = header.args.dim;
+    let input = map_block(&map, &mut position, header.input, dim)?;
+    let output = map_block(&map, &mut position, header.output, dim)?;
+    let model = Model::from_parts(
+        header.args,
+        header.vocabulary,
+        header.supervised,
+        input,
+        output,
+    )?;
+    read_extensions(model, &header.extensions)
+}
+
+/// `write_model` to a new file at `path`.
+pub fn save<P: AsRef<Path>>(model: &Model, path: P) -> Result<()> {
+    write_model(File::create(path)?, model)
+}
+
+/// `read_model` from the file at `path`.
+pub fn load<P: AsRef<Path>>(path: P) -> Result<Model> {
+    read_model(BufReader::new(File::open(path)?))
+}
+
+/// `read_model_int8` from the file at `path`.
+pub fn load_int8<P: AsRef<Path>>(path: P) -> Result<Model> {
+    read_model_int8(BufReader::new(File::open(path)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args::LossKind;
+    use crate::io::tests::corpus_factory;
+    use crate::model::LoadOptions;
+    use crate::quant::QuantArgs;
+    use crate::word::{HashMode, TokenHasher};
+
+    fn round_trip(model: &Model) -> Model {
+        let mut bytes = Vec::new();
+        write_model(&mut bytes, model).unwrap();
+        read_model(&bytes[..]).unwrap()
+    }
+
+    fn classifier() -> Model {
+        let mut text = String::new();
+        for i in 0..100 {
+            text.push_str(&format!("__label__fruit apple banana cherry f{}\n", i % 40));
+            text.push_str(&format!("__label__letter xray yankee zulu l{}\n", i % 40));
+        }
+        let corpus = corpus_factory(&text);
+        let args = Args {
+            dim: 10,
+            word_ngrams: 2,
+            bucket: 1000,
+            vocab_size: 1001,
+            threads: 1,
+            loss: LossKind::OneVsAll,
+            ..Args::supervised()
+        };
+        Model::train_supervised(corpus.path(), args).unwrap()
+    }
+
+    fn assert_same_predictions(loaded: &Model, model: &Model) {
+        for text in ["apple banana", "zulu f3", "unseen words"].iter() {
+            assert_eq!(loaded.predict(text, 2, 0.0), model.predict(text, 2, 0.0));
+        }
+    }
+
+    #[test]
+    fn test_round_trip_classifier() {
+        let mut model = classifier();
+        let loaded = round_trip(&model);
+        assert_eq!(loaded.args(), model.args());
+        assert_eq!(loaded.input(), model.input());
+        assert_eq!(loaded.output(), model.output());
+        assert_eq!(loaded.label_thresholds(), None);
+        assert_eq!(loaded.metadata(), model.metadata());
+        assert_same_predictions(&loaded, &model);
+
+        model
+            .metadata_mut()
+            .unwrap()
+            .tags
+            .insert(String::from("dataset"), String::from("fruit-v2"));
+        let loaded = round_trip(&model);
+        assert_eq!(loaded.metadata().unwrap().tags["dataset"], "fruit-v2");
+        let mut extensions = BTreeMap::new();
+        extensions.insert(String::from(METADATA), b"{\"version\":".to_vec());
+        let loaded = read_extensions(loaded, &extensions).unwrap();
+        assert_eq!(loaded.metadata(), None);
+
+        model.set_label_thresholds(Some(vec![0.9, 0.2])).unwrap();
+        let loaded = round_trip(&model);
+        assert_eq!(loaded.label_thresholds(), Some(&[0.9, 0.2][..]));
+        assert_same_predictions(&loaded, &model);
+
+        let priors = LabelPriors::from_offsets(vec![-0.5, 1.0]);
+        model.set_label_priors(Some(priors.clone())).unwrap();
+        let loaded = round_trip(&model);
+        assert_eq!(loaded.label_priors(), Some(&priors));
+        assert_same_predictions(&loaded, &model);
+    }
+
+    #[test]
+    fn test_round_trip_quantized() {
+        let mut model = classifier();
+        model
+            .quantize(&QuantArgs {
+                cutoff: 300,
+                qnorm: true,
+                qout: false,
+                ..Default::default()
+            })
+            .unwrap();
+        let loaded = round_trip(&model);
+        assert!(loaded.is_quantized());
+        assert_eq!(loaded.output(), model.output());
+        assert_same_predictions(&loaded, &model);
+
+        let mut model = classifier();
+        model.quantize_int8().unwrap();
+        assert_same_predictions(&round_trip(&model), &model);
+    }
+
+    #[test]
+    fn test_load_mmap() {
+        let mut model = classifier();
+        let file =
+            std::env::temp_dir().join(format!("rusttext-native-mmap-{}.bin", std::process::id()));
+        model.save(&file).unwrap();
+        let mut mapped = Model::load_mmap(&file).unwrap();
+        assert!(mapped.input().is_mapped() && mapped.output().is_mapped());
+        assert_eq!(mapped.input(), model.input());
+        assert_same_predictions(&mapped, &model);
+
+        // compression reads the mapped rows into new matrices
+        mapped.quantize_int8().unwrap();
+        model.quantize_int8().unwrap();
+        assert_same_predictions(&mapped, &model);
+        model.save(&file).unwrap();
+        let mapped = Model::load_mmap(&file).unwrap();
+        std::fs::remove_file(&file).unwrap();
+        assert_same_predictions(&mapped, &model);
+    }
+
+    #[test]
+    fn test_load_auto() {
+        let model = classifier();
+        let file =
+            std::env::temp_dir().join(format!("rusttext-native-auto-{}.bin", std::process::id()));
+        model.save(&file).unwrap();
+        assert_same_predictions(&Model::load_auto(&file).unwrap(), &model);
+        assert!(Model::load_auto_mmap(&file).unwrap().input().is_mapped());
+        model.save_fasttext(&file).unwrap();
+        assert_same_predictions(&Model::load_auto(&file).unwrap(), &model);
+        assert_same_predictions(&Model::load_auto_mmap(&file).unwrap(), &model);
+        std::fs::remove_file(&file).unwrap();
+    }
+
+    #[test]
+    fn test_load_int8() {
+        let mut model = classifier();
+        let file =
+            std::env::temp_dir().join(format!("rusttext-native-int8-{}.bin", std::process::id()));
+        model.save(&file).unwrap();
+        let options = LoadOptions {
+            int8: true,
+            ..Default::default()
+        };
+        let loaded = Model::load_with(&file, &options).unwrap();
+        model.quantize_int8().unwrap();
+        assert!(loaded.is_quantized() && loaded.input().data().is_empty());
+        assert_eq!(loaded.quant_input(), model.quant_input());
+        assert_eq!(loaded.quant_output(), model.quant_output());
+        assert_same_predictions(&loaded, &model);
+
+        model.save(&file).unwrap();
+        let result = Model::load_with(&file, &options);
+        std::fs::remove_file(&file).unwrap();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_load_compact_vocabulary() {
+        let model = classifier();
+        let file = std::env::temp_dir().join(format!(
+            "rusttext-native-compact-{}.bin",
+            std::process::id()
+        ));
+        model.save(&file).unwrap();
+        let options = LoadOptions {
+            mmap: true,
+            compact_vocabulary: true,
+            ..Default::default()
+        };
+        let compact = Model::load_with(&file, &options).unwrap();
+        assert!(compact.vocab.is_compact());
+
+        for (id, entry) in model.vocab.words().iter().enumerate() {
+            assert_eq!(compact.vocab.get_id(&entry.word), Some(id as u32));
+        }
+        let line = ["apple", "banana", "unseen", "__label__fruit"];
+        assert_eq!(
+            compact.vocab.line_features(&line),
+            model.vocab.line_features(&line)
+        );
+        assert_same_predictions(&compact, &model);
+        for word in ["apple", "zulu", "unseen"].iter() {
+            assert_eq!(compact.word_vector(word), model.word_vector(word));
+        }
+        assert_eq!(
+            compact.nearest_neighbors("apple", 3),
+            model.nearest_neighbors("apple", 3)
+        );
+
+        let mut saved = Vec::new();
+        write_model(&mut saved, &compact).unwrap();
+        let mut original = Vec::new();
+        write_model(&mut original, &model).unwrap();
+        std::fs::remove_file(&file).unwrap();
+        assert_eq!(saved, original);
+    }
+
+    #[test]
+    fn test_round_trip_word_vectors() {
+        let corpus = corpus_factory(&"the quick brown fox jumps over the lazy dog\n".repeat(20));
+        let args = Args {
+            dim: 8,
+            epoch: 1,
+            min_count: 1,
+            bucket: 500,
+            vocab_size: 1001,
+            threads: 1,
+            ..Args::default()
+        };
+        let mut model = Model::train_unsupervised(corpus.path(), args).unwrap();
+        model.vocab.set_hash_mode(HashMode::Fnv64);
+
+        let file = std::env::temp_dir().join(format!("rusttext-native-{}.bin", std::process::id()));
+        model.save(&file).unwrap();
+        let loaded = Model::load(&file).unwrap();
+        std::fs::remove_file(&file).unwrap();
+        assert!(!loaded.supervised);
+        assert_eq!(
+            loaded.vocabulary().hasher().hash_mode(),
+            Some(HashMode::Fnv64)
+        );
+        assert_eq!(loaded.vocabulary().words(), model.vocabulary().words());
+        for word in ["fox", "foxes", "</s>"].iter() {
+            assert_eq!(loaded.word_vector(word), model.word_vector(word));
+        }
+    }
+
+    struct Constant;
+
+    impl TokenHasher for Constant {
+        fn hash(&self, _: &str) -> u64 {
+            7
+        }
+    }
+
+    #[test]
+    fn test_read_model_errors() {
+        let mut model = classifier();
+        let mut bytes = Vec::new();
+        write_model(&mut bytes, &model).unwrap();
+        assert_eq!(bytes.len() % 4, 0);
+
+        let mut bad = bytes.clone();
+        bad[0] = b'X';
+        assert!(matches!(
+            read_model(&bad[..]),
+            Err(RustTextError::ModelFormat(_))
+        ));
+        let mut future = bytes.clone();
+        future[4..8].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        assert!(matches!(
+            read_model(&future[..]),
+            Err(RustTextError::ModelFormat(_))
+        ));
+        assert!(read_model(&bytes[..bytes.len() - 3]).is_err());
+        assert!(read_model(&bytes[..40]).is_err());
+
+        // an int8 input matrix with a row of values missing
+        #[derive(Serialize)]
+        enum Compressed {
+            _Product,
+            Int8(usize, usize, Vec<i8>, Vec<f32>),
+        }
+        let dim = model.args().dim;
+        let rows = model.input().rows();
+        let header = Header {
+            args: model.args(),
+            supervised: true,
+            vocabulary: model.vocabulary(),
+            input: Block::Compressed(Compressed::Int8(
+                rows,
+                dim,
+                vec![0; (rows - 1) * dim],
+                vec![1.0; rows],
+            )),
+            output: Block::Dense {
+                rows: 0,
+                cols: dim as u64,
+            },
+            extensions: BTreeMap::new(),
+        };
+        let header = bincode::serialize(&header).unwrap();
+        let mut corrupt = bytes[..8].to_vec();
+        corrupt.extend_from_slice(&(header.len() as u64).to_le_bytes());
+        corrupt.extend_from_slice(&header);
+        assert!(matches!(
+            read_model(&corrupt[..]),
+            Err(RustTextError::ModelFormat(_))
+        ));
+
+        model.vocab.set_hasher(Box::new(Constant));
+        assert!(matches!(
+            write_model(Vec::new(), &model),
+            Err(RustTextError::InvalidArgument(_))
+        ));
+    }
+
+    #[test]
+    fn test_read_version_1() {
+        // a classifier saved before `Args::word_model` existed, trained on
+        // "__label__fruit apple banana" and "__label__car truck wheel"
+        let bytes = include_bytes!("../tests/fixtures/model_v1.bin");
+        assert_eq!(bytes[4..8], 1u32.to_le_bytes());
+        let model = read_model(&bytes[..]).unwrap();
+
+        assert_eq!(model.args().dim, 4);
+        assert_eq!(model.args().word_model, WordModel::Skipgram);
+        assert_eq!(model.vocabulary().n_labels(), 2);
+        let predictions = model.predict("apple banana", 1, 0.0);
+        assert_eq!(predictions[0].0, "__label__fruit");
+        assert!((predictions[0].1 - 0.518_941_9).abs() < 1e-6);
+
+        let model = round_trip(&model);
+        assert_eq!(model.args().word_model, WordModel::Skipgram);
+    }
+}
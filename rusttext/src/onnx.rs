@@ -0,0 +1,521 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::args::LossKind;
+use crate::model::Model;
+use crate::word::{self, HashMode};
+use crate::{Result, RustTextError};
+
+/// ONNX IR version of the exported graph.
+pub const IR_VERSION: i64 = 7;
+/// Version of the default operator set the graph uses.
+pub const OPSET_VERSION: i64 = 13;
+
+// TensorProto.DataType values.
+const FLOAT: i64 = 1;
+const INT64: i64 = 7;
+// AttributeProto.AttributeType values.
+const ATTRIBUTE_INT: i64 = 2;
+
+// Protocol buffer wire types.
+const VARINT: u32 = 0;
+const LENGTH_DELIMITED: u32 = 2;
+
+fn varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn varint_len(value: u64) -> usize {
+    let mut out = Vec::with_capacity(10);
+    varint(&mut out, value);
+    out.len()
+}
+
+// An encoded protocol buffer message, built field by field. ONNX's schema
+// is small and fixed, so this stands in for generated code.
+#[derive(Debug, Default)]
+struct Proto(Vec<u8>);
+
+impl Proto {
+    fn key(&mut self, field: u32, wire_type: u32) {
+        varint(&mut self.0, u64::from(field << 3 | wire_type));
+    }
+
+    fn int(mut self, field: u32, value: i64) -> Proto {
+        self.key(field, VARINT);
+        varint(&mut self.0, value as u64);
+        self
+    }
+
+    fn ints(self, field: u32, values: &[i64]) -> Proto {
+        values
+            .iter()
+            .fold(self, |proto, &value| proto.int(field, value))
+    }
+
+    fn bytes(mut self, field: u32, value: &[u8]) -> Proto {
+        self.key(field, LENGTH_DELIMITED);
+        varint(&mut self.0, value.len() as u64);
+        self.0.extend_from_slice(value);
+        self
+    }
+
+    fn string(self, field: u32, value: &str) -> Proto {
+        self.bytes(field, value.as_bytes())
+    }
+
+    fn message(self, field: u32, value: &Proto) -> Proto {
+        self.bytes(field, &value.0)
+    }
+
+    // Length-delimited header for `len` bytes of field `field` that the
+    // caller writes itself.
+    fn header(mut self, field: u32, len: usize) -> Proto {
+        self.key(field, LENGTH_DELIMITED);
+        varint(&mut self.0, len as u64);
+        self
+    }
+}
+
+fn node(op_type: &str, inputs: &[&str], output: &str, attributes: &[(&str, i64)]) -> Proto {
+    let proto = inputs
+        .iter()
+        .fold(Proto::default(), |proto, input| proto.string(1, input))
+        .string(2, output)
+        .string(3, output)
+        .string(4, op_type);
+    attributes.iter().fold(proto, |proto, (name, value)| {
+        let attribute = Proto::default()
+            .string(1, name)
+            .int(3, *value)
+            .int(20, ATTRIBUTE_INT);
+        proto.message(5, &attribute)
+    })
+}
+
+// A ValueInfoProto for a graph input or output; `None` dimensions are
+// symbolic, named after the value.
+fn value_info(name: &str, elem_type: i64, dims: &[(Option<usize>, &str)]) -> Proto {
+    let shape = dims.iter().fold(Proto::default(), |shape, (dim, param)| {
+        let dim = match dim {
+            Some(value) => Proto::default().int(1, *value as i64),
+            None => Proto::default().string(2, param),
+        };
+        shape.message(1, &dim)
+    });
+    let tensor = Proto::default().int(1, elem_type).message(2, &shape);
+    let value_type = Proto::default().message(1, &tensor);
+    Proto::default().string(1, name).message(2, &value_type)
+}
+
+// A TensorProto without its raw_data, which is length-delimited field 9.
+fn tensor_head(name: &str, data_type: i64, dims: &[usize]) -> Proto {
+    let dims: Vec<i64> = dims.iter().map(|&dim| dim as i64).collect();
+    Proto::default()
+        .ints(1, &dims)
+        .int(2, data_type)
+        .string(8, name)
+}
+
+fn int64_tensor(name: &str, dims: &[usize], values: &[i64]) -> Proto {
+    let raw: Vec<u8> = values
+        .iter()
+        .flat_map(|v| v.to_le_bytes().to_vec())
+        .collect();
+    tensor_head(name, INT64, dims).bytes(9, &raw)
+}
+
+/// Row of the all-zero padding embedding in the exported graph, which
+/// `input_ids` pads shorter documents with.
+pub fn pad_id(model: &Model) -> usize {
+    model.vocabulary().n_input_rows()
+}
+
+/// Input ids for `texts` as the exported graph expects them: the feature
+/// rows `Model::predict` would use for each text (tokens split on
+/// whitespace, an end-of-sentence token appended, then words, subwords
+/// and word n-grams looked up as in `Vocabulary::line_features`), padded
+/// with `pad_id` to the longest. Returns the row-major `[texts.len(),
+/// length]` ids and `length`.
+pub fn input_ids(model: &Model, texts: &[&str]) -> (Vec<i64>, usize) {
+    let features: Vec<Vec<u32>> = texts
+        .iter()
+        .map(|text| {
+            let mut tokens: Vec<&str> = text.split_whitespace().collect();
+            tokens.push(word::EOS);
+            model.vocabulary().line_features(&tokens)
+        })
+        .collect();
+    let length = features.iter().map(Vec::len).max().unwrap_or(0);
+    let pad = pad_id(model) as i64;
+    let mut ids = Vec::with_capacity(texts.len() * length);
+    for row in features.iter() {
+        ids.extend(row.iter().map(|&id| i64::from(id)));
+        ids.resize(ids.len() + length - row.len(), pad);
+    }
+    (ids, length)
+}
+
+// Model metadata for computing input ids outside rusttext.
+fn metadata(model: &Model) -> Vec<(&'static str, String)> {
+    let vocab = model.vocabulary();
+    let n_words = vocab.n_words() as usize;
+    let hash = match vocab.hasher().hash_mode() {
+        Some(HashMode::Fnv32) => "fnv32",
+        Some(HashMode::Fnv64) => "fnv64",
+        None => "custom",
+    };
+    let names = |entries: &[word::WordEntry]| {
+        entries
+            .iter()
+            .map(|entry| entry.word.as_str())
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+    let mut metadata = vec![
+        ("producer", String::from("rusttext")),
+        ("words", names(&vocab.words()[..n_words])),
+        ("labels", names(&vocab.words()[n_words..])),
+        ("pad_id", pad_id(model).to_string()),
+        ("bucket", vocab.bucket().to_string()),
+        ("minn", vocab.min_n().to_string()),
+        ("maxn", vocab.max_n().to_string()),
+        ("word_ngrams", vocab.word_ngrams().to_string()),
+        ("char_only", vocab.char_only().to_string()),
+        ("hash", hash.to_string()),
+        ("bow", vocab.bow().to_string()),
+        ("eow", vocab.eow().to_string()),
+        ("label_prefix", vocab.label_prefix().to_string()),
+        ("eos", word::EOS.to_string()),
+    ];
+    if let Some(pruned) = vocab.pruned_buckets() {
+        let mut buckets: Vec<(u32, u32)> = pruned.iter().map(|(&b, &i)| (b, i)).collect();
+        buckets.sort_unstable();
+        let buckets: Vec<String> = buckets
+            .iter()
+            .map(|(b, i)| format!("{}:{}", b, i))
+            .collect();
+        metadata.push(("pruned_buckets", buckets.join(" ")));
+    }
+    metadata
+}
+
+/// Write a supervised model as an ONNX graph for ONNX Runtime and other
+/// serving stacks. The graph takes `input_ids`, an `int64` tensor of
+/// `[batch, length]` feature rows padded with `pad_id` (see `input_ids`),
+/// and returns `probabilities`, a `float` `[batch, n_labels]` tensor: the
+/// mean of the non-padding embedding rows times the output matrix, through
+/// a softmax for softmax-trained models and a sigmoid per label otherwise,
+/// matching `Model::predict`. Label names and everything needed to compute
+/// ids (words, bucket count, n-gram lengths, hash, markers, any pruned
+/// bucket map) are in the model's metadata properties, with lists
+/// newline-separated.
+///
+/// Hierarchical softmax and compressed models have no equivalent graph
+/// and can't be exported.
+pub fn write_onnx<W: Write>(writer: W, model: &Model) -> Result<()> {
+    if !model.supervised {
+        return Err(RustTextError::InvalidArgument(String::from(
+            "only supervised models can be exported to ONNX",
+        )));
+    }
+    if model.is_quantized() || model.args().loss == LossKind::HierarchicalSoftmax {
+        return Err(RustTextError::InvalidArgument(String::from(
+            "cannot export a quantized or hierarchical softmax model to ONNX",
+        )));
+    }
+    let input = model.input();
+    let output = model.output();
+    let dim = model.args().dim;
+    let n_labels = output.rows();
+    let padding = vec![0.0f32; dim];
+    // (head, data) of the float initializers, streamed rather than copied
+    // into the message since they can be large.
+    let floats = [
+        (
+            tensor_head("embedding", FLOAT, &[input.rows() + 1, dim]),
+            [input.data(), &padding[..]],
+        ),
+        (
+            tensor_head("output", FLOAT, &[n_labels, dim]),
+            [output.data(), &[][..]],
+        ),
+    ];
+
+    let final_op = match model.args().loss {
+        LossKind::Softmax => node("Softmax", &["scores"], "probabilities", &[("axis", 1)]),
+        _ => node("Sigmoid", &["scores"], "probabilities", &[]),
+    };
+    let nodes = [
+        node(
+            "Gather",
+            &["embedding", "input_ids"],
+            "embedded",
+            &[("axis", 0)],
+        ),
+        node("Equal", &["input_ids", "pad_id"], "is_padding", &[]),
+        node("Not", &["is_padding"], "is_token", &[]),
+        node("Cast", &["is_token"], "token_mask", &[("to", FLOAT)]),
+        node(
+            "ReduceSum",
+            &["token_mask", "axes"],
+            "counts",
+            &[("keepdims", 1)],
+        ),
+        node(
+            "ReduceSum",
+            &["embedded", "axes"],
+            "sums",
+            &[("keepdims", 0)],
+        ),
+        node("Div", &["sums", "counts"], "hidden", &[]),
+        node("Gemm", &["hidden", "output"], "scores", &[("transB", 1)]),
+        final_op,
+    ];
+    let graph_head = nodes
+        .iter()
+        .fold(Proto::default(), |graph, node| graph.message(1, node))
+        .string(2, "rusttext")
+        .message(5, &int64_tensor("pad_id", &[], &[pad_id(model) as i64]))
+        .message(5, &int64_tensor("axes", &[1], &[1]));
+    let graph_tail = Proto::default()
+        .message(
+            11,
+            &value_info("input_ids", INT64, &[(None, "batch"), (None, "length")]),
+        )
+        .message(
+            12,
+            &value_info(
+                "probabilities",
+                FLOAT,
+                &[(None, "batch"), (Some(n_labels), "")],
+            ),
+        );
+
+    let raw_len = |data: &[&[f32]; 2]| 4 * (data[0].len() + data[1].len());
+    let tensor_len =
+        |head: &Proto, raw_len: usize| head.0.len() + 1 + varint_len(raw_len as u64) + raw_len;
+    let mut graph_len = graph_head.0.len() + graph_tail.0.len();
+    for (head, data) in floats.iter() {
+        let tensor_len = tensor_len(head, raw_len(data));
+        graph_len += 1 + varint_len(tensor_len as u64) + tensor_len;
+    }
+    let opset = Proto::default().string(1, "").int(2, OPSET_VERSION);
+    let model_head = Proto::default()
+        .int(1, IR_VERSION)
+        .string(2, "rusttext")
+        .string(3, env!("CARGO_PKG_VERSION"))
+        .message(8, &opset)
+        .header(7, graph_len);
+    let model_tail = metadata(model)
+        .iter()
+        .fold(Proto::default(), |proto, (key, value)| {
+            let entry = Proto::default().string(1, key).string(2, value);
+            proto.message(14, &entry)
+        });
+
+    let mut writer = BufWriter::new(writer);
+    writer.write_all(&model_head.0)?;
+    writer.write_all(&graph_head.0)?;
+    for (head, data) in floats.iter() {
+        let raw_len = raw_len(data);
+        let initializer = Proto::default().header(5, tensor_len(head, raw_len));
+        writer.write_all(&initializer.0)?;
+        writer.write_all(&head.0)?;
+        writer.write_all(&Proto::default().header(9, raw_len).0)?;
+        for value in data.iter().flat_map(|chunk| chunk.iter()) {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+    }
+    writer.write_all(&graph_tail.0)?;
+    writer.write_all(&model_tail.0)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// `write_onnx` to a new file at `path`.
+pub fn export<P: AsRef<Path>>(model: &Model, path: P) -> Result<()> {
+    write_onnx(File::create(path)?, model)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args::Args;
+    use crate::io::tests::corpus_factory;
+
+    // (field, varint value or length-delimited bytes) pairs of a message.
+    fn decode(mut bytes: &[u8]) -> Vec<(u32, u64, &[u8])> {
+        fn read_varint(bytes: &mut &[u8]) -> u64 {
+            let mut value = 0;
+            for shift in (0..).step_by(7) {
+                let byte = bytes[0];
+                *bytes = &bytes[1..];
+                value |= u64::from(byte & 0x7f) << shift;
+                if byte < 0x80 {
+                    break;
+                }
+            }
+            value
+        }
+        let mut fields = Vec::new();
+        while !bytes.is_empty() {
+            let key = read_varint(&mut bytes);
+            let value = read_varint(&mut bytes);
+            if key & 7 == u64::from(LENGTH_DELIMITED) {
+                let (data, rest) = bytes.split_at(value as usize);
+                fields.push(((key >> 3) as u32, value, data));
+                bytes = rest;
+            } else {
+                fields.push(((key >> 3) as u32, value, &[][..]));
+            }
+        }
+        fields
+    }
+
+    fn field<'a>(fields: &[(u32, u64, &'a [u8])], number: u32) -> Vec<&'a [u8]> {
+        fields
+            .iter()
+            .filter(|(f, _, _)| *f == number)
+            .map(|(_, _, data)| *data)
+            .collect()
+    }
+
+    fn train(loss: LossKind) -> Model {
+        let mut text = String::new();
+        for _ in 0..50 {
+            text.push_str("__label__fruit apple banana cherry\n");
+            text.push_str("__label__letter xray yankee zulu\n");
+            text.push_str("__label__color red green blue\n");
+        }
+        let corpus = corpus_factory(&text);
+        let args = Args {
+            dim: 6,
+            bucket: 100,
+            word_ngrams: 2,
+            vocab_size: 101,
+            threads: 1,
+            loss,
+            ..Args::supervised()
+        };
+        Model::train_supervised(corpus.path(), args).unwrap()
+    }
+
+    #[test]
+    fn test_input_ids() {
+        let model = train(LossKind::Softmax);
+        let (ids, length) = input_ids(&model, &["apple", "xray zulu unseen", ""]);
+        assert_eq!(ids.len(), 3 * length);
+        let pad = pad_id(&model) as i64;
+        assert!(ids[..length].contains(&pad));
+        assert!(!ids[length..2 * length].contains(&pad));
+        assert!(ids.iter().all(|&id| id <= pad));
+    }
+
+    #[test]
+    fn test_write_onnx() {
+        let model = train(LossKind::Softmax);
+        let mut bytes = Vec::new();
+        write_onnx(&mut bytes, &model).unwrap();
+
+        let fields = decode(&bytes);
+        assert_eq!(fields[0], (1, IR_VERSION as u64, &[][..]));
+        let opset = decode(field(&fields, 8)[0]);
+        assert_eq!(opset[1], (2, OPSET_VERSION as u64, &[][..]));
+        let metadata: Vec<(String, String)> = field(&fields, 14)
+            .iter()
+            .map(|entry| {
+                let entry = decode(entry);
+                let text = |i: usize| String::from_utf8(entry[i].2.to_vec()).unwrap();
+                (text(0), text(1))
+            })
+            .collect();
+        let labels = &metadata.iter().find(|(key, _)| key == "labels").unwrap().1;
+        assert_eq!(labels.lines().count(), 3);
+
+        let graph = decode(field(&fields, 7)[0]);
+        let op_types: Vec<String> = field(&graph, 1)
+            .iter()
+            .map(|node| String::from_utf8(field(&decode(node), 4)[0].to_vec()).unwrap())
+            .collect();
+        assert_eq!(op_types.first().unwrap(), "Gather");
+        assert_eq!(op_types.last().unwrap(), "Softmax");
+
+        // evaluate the graph's arithmetic on its own initializers
+        let mut tensors = std::collections::HashMap::new();
+        for tensor in field(&graph, 5) {
+            let tensor = decode(tensor);
+            let name = String::from_utf8(field(&tensor, 8)[0].to_vec()).unwrap();
+            let dims: Vec<u64> = tensor
+                .iter()
+                .filter(|(f, _, _)| *f == 1)
+                .map(|(_, v, _)| *v)
+                .collect();
+            tensors.insert(name, (dims, field(&tensor, 9)[0].to_vec()));
+        }
+        let floats = |name: &str| -> Vec<f32> {
+            tensors[name]
+                .1
+                .chunks(4)
+                .map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+                .collect()
+        };
+        let (embedding, output) = (floats("embedding"), floats("output"));
+        assert_eq!(tensors["embedding"].0, [model.input().rows() as u64 + 1, 6]);
+        assert!(embedding[embedding.len() - 6..].iter().all(|&v| v == 0.0));
+
+        let texts = ["banana cherry", "zulu yankee xray"];
+        let (ids, length) = input_ids(&model, &texts);
+        let pad = pad_id(&model) as i64;
+        for (text, ids) in texts.iter().zip(ids.chunks(length)) {
+            let count = ids.iter().filter(|&&id| id != pad).count() as f32;
+            let mut hidden = [0.0f32; 6];
+            for &id in ids.iter() {
+                for (h, v) in hidden.iter_mut().zip(&embedding[6 * id as usize..]) {
+                    *h += v / count;
+                }
+            }
+            let scores: Vec<f32> = output
+                .chunks(6)
+                .map(|row| {
+                    row.iter()
+                        .zip(hidden.iter())
+                        .map(|(a, b)| a * b)
+                        .sum::<f32>()
+                        .exp()
+                })
+                .collect();
+            let z: f32 = scores.iter().sum();
+            let best = (0..3)
+                .max_by(|&a, &b| scores[a].total_cmp(&scores[b]))
+                .unwrap();
+            let labels: Vec<&str> = labels.lines().collect();
+            let predicted = &model.predict(text, 1, 0.0)[0];
+            assert_eq!(labels[best], predicted.0);
+            assert!((scores[best] / z - predicted.1).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn test_write_onnx_errors() {
+        let model = train(LossKind::HierarchicalSoftmax);
+        assert!(matches!(
+            write_onnx(Vec::new(), &model),
+            Err(RustTextError::InvalidArgument(_))
+        ));
+        let mut model = train(LossKind::OneVsAll);
+        let mut bytes = Vec::new();
+        write_onnx(&mut bytes, &model).unwrap();
+        let graph = decode(field(&decode(&bytes), 7)[0]);
+        let last = decode(field(&graph, 1).last().unwrap());
+        assert_eq!(field(&last, 4)[0], b"Sigmoid");
+
+        model.quantize_int8().unwrap();
+        assert!(write_onnx(Vec::new(), &model).is_err());
+    }
+}
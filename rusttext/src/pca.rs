@@ -0,0 +1,234 @@
+use rayon::prelude::*;
+
+use crate::matrix::Matrix;
+use crate::model::Model;
+use crate::utils::jacobi_svd;
+use crate::{Result, RustTextError};
+
+/// Rows sampled (evenly spaced) to estimate the covariance; enough for a
+/// stable estimate of a few hundred dimensions without a pass over every
+/// bucket of a large model.
+pub const MAX_SAMPLE_ROWS: usize = 100_000;
+
+/// Principal components of a set of vectors.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pca {
+    mean: Vec<f32>,
+    /// One component per row, by decreasing variance.
+    components: Matrix,
+    variances: Vec<f32>,
+}
+
+impl Pca {
+    /// Fit to the rows of `rows` (at most `MAX_SAMPLE_ROWS` of them, evenly
+    /// spaced), keeping `n_components` components after skipping the `skip`
+    /// of highest variance.
+    pub fn fit(rows: &Matrix, n_components: usize, skip: usize) -> Result<Pca> {
+        let dim = rows.cols();
+        if n_components == 0 || skip + n_components > dim {
+            return Err(RustTextError::InvalidArgument(format!(
+                "cannot keep {} of {} components after skipping {}",
+                n_components, dim, skip
+            )));
+        }
+        if rows.rows() == 0 {
+            return Err(RustTextError::InvalidArgument(String::from(
+                "cannot fit PCA to an empty matrix",
+            )));
+        }
+        let step = rows.rows().div_ceil(MAX_SAMPLE_ROWS);
+        let sample: Vec<&[f32]> = (0..rows.rows())
+            .step_by(step)
+            .map(|i| rows.row(i))
+            .collect();
+        let n = sample.len() as f64;
+
+        let mut mean = vec![0.0f64; dim];
+        for row in sample.iter() {
+            for (m, &x) in mean.iter_mut().zip(row.iter()) {
+                *m += f64::from(x) / n;
+            }
+        }
+        let covariance = sample
+            .par_iter()
+            .fold(
+                || vec![0.0f64; dim * dim],
+                |mut covariance, row| {
+                    let centered: Vec<f64> = row
+                        .iter()
+                        .zip(mean.iter())
+                        .map(|(&x, m)| f64::from(x) - m)
+                        .collect();
+                    for (i, x) in centered.iter().enumerate() {
+                        for (c, y) in covariance[i * dim..(i + 1) * dim].iter_mut().zip(&centered) {
+                            *c += x * y;
+                        }
+                    }
+                    covariance
+                },
+            )
+            .reduce(
+                || vec![0.0f64; dim * dim],
+                |mut left, right| {
+                    left.iter_mut().zip(right.iter()).for_each(|(l, r)| *l += r);
+                    left
+                },
+            );
+
+        // for a symmetric positive semi-definite matrix the singular values
+        // are the eigenvalues and `V` holds the eigenvectors
+        let (us, v) = jacobi_svd(covariance, dim);
+        let mut order: Vec<(usize, f64)> = (0..dim)
+            .map(|j| {
+                let norm = (0..dim)
+                    .map(|i| us[i * dim + j].powi(2))
+                    .sum::<f64>()
+                    .sqrt();
+                (j, norm / f64::max(n - 1.0, 1.0))
+            })
+            .collect();
+        order.sort_by(|left, right| right.1.total_cmp(&left.1));
+
+        let mut components = Matrix::new(n_components, dim);
+        let mut variances = Vec::with_capacity(n_components);
+        for (k, &(j, variance)) in order.iter().skip(skip).take(n_components).enumerate() {
+            for (i, value) in components.row_mut(k).iter_mut().enumerate() {
+                *value = v[i * dim + j] as f32;
+            }
+            variances.push(variance as f32);
+        }
+        Ok(Pca {
+            mean: mean.iter().map(|&m| m as f32).collect(),
+            components,
+            variances,
+        })
+    }
+
+    pub fn mean(&self) -> &[f32] {
+        &self.mean
+    }
+
+    pub fn components(&self) -> &Matrix {
+        &self.components
+    }
+
+    /// Variance of the fitted rows along each kept component.
+    pub fn variances(&self) -> &[f32] {
+        &self.variances
+    }
+
+    /// Coordinates of `vector` along the kept components, after
+    /// subtracting the mean when `center` is set.
+    pub fn project(&self, vector: &[f32], center: bool) -> Vec<f32> {
+        (0..self.components.rows())
+            .map(|k| {
+                let row = self.components.row(k);
+                if center {
+                    row.iter()
+                        .zip(vector.iter().zip(self.mean.iter()))
+                        .map(|(c, (x, m))| c * (x - m))
+                        .sum()
+                } else {
+                    self.components.dot_row(vector, k)
+                }
+            })
+            .collect()
+    }
+
+    /// `project` every row of `matrix`, in parallel.
+    pub fn project_rows(&self, matrix: &Matrix, center: bool) -> Matrix {
+        let mut projected = Matrix::new(matrix.rows(), self.components.rows());
+        if self.components.rows() > 0 {
+            projected
+                .data_mut()
+                .par_chunks_mut(self.components.rows())
+                .enumerate()
+                .for_each(|(i, out)| out.copy_from_slice(&self.project(matrix.row(i), center)));
+        }
+        projected
+    }
+}
+
+/// Shrink `model` to `target_dim` dimensions in place with PCA of its input
+/// matrix, in the spirit of fastText's `reduce_model` script: both matrices
+/// are projected onto the leading components of the input rows, and
+/// `args.dim` updated. For word vectors the rows are centered first and the
+/// `dim / 100` components of highest variance dropped before the kept
+/// ones ("all-but-the-top", Mu and Viswanath, 2018), since those
+/// directions are shared by nearly every word and carry little meaning. A
+/// classifier's rows are projected as they are, keeping its scores as close
+/// as the kept variance allows.
+pub fn reduce_dim(model: &mut Model, target_dim: usize) -> Result<()> {
+    let dim = model.args().dim;
+    if model.is_quantized() {
+        return Err(RustTextError::InvalidArgument(String::from(
+            "cannot reduce the dimension of a quantized model",
+        )));
+    }
+    if target_dim == 0 || target_dim >= dim {
+        return Err(RustTextError::InvalidArgument(format!(
+            "target dimension {} must be between 1 and {}",
+            target_dim,
+            dim - 1
+        )));
+    }
+    let center = !model.supervised;
+    let skip = if center {
+        usize::min(dim / 100, dim - target_dim)
+    } else {
+        0
+    };
+    let pca = Pca::fit(&model.input, target_dim, skip)?;
+    model.input = pca.project_rows(&model.input, center);
+    model.output = pca.project_rows(&model.output, false);
+    model.args.dim = target_dim;
+    model.reset_word_vectors();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vectors::tests::fixed;
+
+    #[test]
+    fn test_fit() {
+        // points along (1, 1, 0) with a little noise on the last axis
+        let mut data = Vec::new();
+        for i in 0..20 {
+            let t = i as f32 - 10.0;
+            data.extend_from_slice(&[t + 5.0, t + 5.0, 0.1 * (i % 2) as f32]);
+        }
+        let rows = Matrix::from_vec(20, 3, data).unwrap();
+
+        let pca = Pca::fit(&rows, 1, 0).unwrap();
+        let component = pca.components().row(0);
+        assert!((component[0].abs() - 0.5f32.sqrt()).abs() < 1e-4);
+        assert!((component[0] - component[1]).abs() < 1e-4);
+        assert!((pca.mean()[0] - 4.5).abs() < 1e-4);
+        let projected = pca.project(rows.row(0), true);
+        assert!((projected[0].abs() - 9.5 * 2.0f32.sqrt()).abs() < 1e-3);
+
+        let rest = Pca::fit(&rows, 2, 1).unwrap();
+        assert!(rest.variances()[0] >= rest.variances()[1]);
+        assert!(rest.components().row(0)[2].abs() > 0.99);
+        assert!(Pca::fit(&rows, 3, 1).is_err());
+    }
+
+    #[test]
+    fn test_reduce_dim() {
+        let words = ["a", "b", "c", "d", "e", "f"];
+        let vectors: Vec<f32> = (0..18).map(|i| ((i * 7) % 11) as f32 - 5.0).collect();
+        let mut model = fixed(&words, vectors);
+
+        assert!(reduce_dim(&mut model, 3).is_err());
+        reduce_dim(&mut model, 2).unwrap();
+        assert_eq!(model.args().dim, 2);
+        assert_eq!(model.input().cols(), 2);
+        assert_eq!(model.output().cols(), 2);
+        assert_eq!(model.word_vector("c").len(), 2);
+        // centered, so the reduced word vectors average to zero
+        let sum: f32 = words.iter().map(|word| model.word_vector(word)[0]).sum();
+        assert!(sum.abs() < 1e-4);
+    }
+}
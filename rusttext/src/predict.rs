@@ -0,0 +1,340 @@
+use std::io::{BufRead, BufWriter, Write};
+
+use rayon::prelude::*;
+
+use crate::args::LossKind;
+use crate::model::Model;
+use crate::utils::{json_string, sigmoid};
+use crate::word;
+use crate::Result;
+
+/// Lines predicted together, in parallel, by `write_jsonl`.
+const JSONL_BATCH: usize = 4096;
+
+/// Buffers for scoring one document, reused across calls to avoid
+/// allocating per prediction.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Scratch {
+    hidden: Vec<f32>,
+    scores: Vec<f32>,
+    ranking: Vec<usize>,
+}
+
+// Mean of the input rows of `features` into `hidden`, which is resized to
+// the model dimension.
+pub(crate) fn compute_hidden(model: &Model, features: &[u32], hidden: &mut Vec<f32>) {
+    hidden.clear();
+    hidden.resize(model.args.dim, 0.0);
+    let scale = 1.0 / features.len() as f32;
+    for &feature in features.iter() {
+        model.add_input_row(hidden, feature, scale);
+    }
+}
+
+// Probability of every label for `hidden`: a softmax for softmax-trained
+// models, the product of branch probabilities down the label tree for
+// hierarchical softmax, an independent sigmoid per label otherwise. Label
+// priors shift each label's logit, so they renormalise with the softmax
+// but move every one-vs-all probability independently.
+fn compute_scores(model: &Model, hidden: &[f32], scores: &mut Vec<f32>) {
+    scores.clear();
+    let priors = model.label_priors.as_ref();
+    if model.args.loss == LossKind::HierarchicalSoftmax {
+        let tree = model.output_tree();
+        scores.resize(tree.len(), 0.0);
+        tree.probabilities(|node| model.output_dot(hidden, node), scores);
+        if let Some(priors) = priors {
+            scores.iter_mut().for_each(|score| *score = score.ln());
+            priors.adjust(scores);
+            scores.iter_mut().for_each(|score| *score = score.exp());
+        }
+        return;
+    }
+    scores.extend((0..model.n_output_rows()).map(|i| model.output_dot(hidden, i)));
+    if let Some(priors) = priors {
+        for (score, offset) in scores.iter_mut().zip(priors.offsets().iter()) {
+            *score += offset;
+        }
+    }
+    if model.args.loss == LossKind::Softmax {
+        let max = scores.iter().fold(f32::NEG_INFINITY, |max, &s| max.max(s));
+        let mut z = 0.0;
+        for score in scores.iter_mut() {
+            *score = (*score - max).exp();
+            z += *score;
+        }
+        scores.iter_mut().for_each(|score| *score /= z);
+    } else {
+        scores.iter_mut().for_each(|score| *score = sigmoid(*score));
+    }
+}
+
+/// The `k` most probable labels of `text` with probability at least
+/// `threshold`, best first. Tokens are split on whitespace and an
+/// end-of-sentence token is appended, as in training.
+pub(crate) fn predict(
+    model: &Model,
+    text: &str,
+    k: usize,
+    threshold: f32,
+    scratch: &mut Scratch,
+) -> Vec<(String, f32)> {
+    if !model.supervised || k == 0 {
+        return Vec::new();
+    }
+    let mut tokens: Vec<&str> = text.split_whitespace().collect();
+    tokens.push(word::EOS);
+    let features = model.vocab.line_features(&tokens);
+    let n_words = model.vocab.n_words();
+    rank(model, &features, k, threshold, scratch)
+        .into_iter()
+        .map(|(i, p)| {
+            let label = model.vocab.word(n_words + i as u32).unwrap_or_default();
+            (label.into_owned(), p)
+        })
+        .collect()
+}
+
+/// Probability of every label for the input rows `features`, in label
+/// order, without any thresholds applied.
+pub(crate) fn label_scores(model: &Model, features: &[u32], scratch: &mut Scratch) -> Vec<f32> {
+    compute_hidden(model, features, &mut scratch.hidden);
+    compute_scores(model, &scratch.hidden, &mut scratch.scores);
+    scratch.scores.clone()
+}
+
+/// The `k` most probable label indices for the input rows `features`, after
+/// any label priors, with probability at least `threshold` and at least the
+/// label's own calibrated threshold, if the model has them, best first.
+pub(crate) fn rank(
+    model: &Model,
+    features: &[u32],
+    k: usize,
+    threshold: f32,
+    scratch: &mut Scratch,
+) -> Vec<(usize, f32)> {
+    if features.is_empty() || k == 0 {
+        return Vec::new();
+    }
+    compute_hidden(model, features, &mut scratch.hidden);
+    compute_scores(model, &scratch.hidden, &mut scratch.scores);
+    let scores = &scratch.scores;
+    let ranking = &mut scratch.ranking;
+    ranking.clear();
+    let thresholds = model.label_thresholds.as_deref();
+    ranking.extend((0..scores.len()).filter(|&i| {
+        scores[i] >= threshold && thresholds.is_none_or(|thresholds| scores[i] >= thresholds[i])
+    }));
+    ranking.sort_by(|&a, &b| scores[b].total_cmp(&scores[a]));
+    ranking.iter().take(k).map(|&i| (i, scores[i])).collect()
+}
+
+/// `predict` for every text in `texts`, in parallel across rayon's thread
+/// pool with one `Scratch` per worker. Results are in input order.
+pub(crate) fn predict_batch(
+    model: &Model,
+    texts: &[&str],
+    k: usize,
+    threshold: f32,
+) -> Vec<Vec<(String, f32)>> {
+    texts
+        .par_iter()
+        .map_init(Scratch::default, |scratch, text| {
+            predict(model, text, k, threshold, scratch)
+        })
+        .collect()
+}
+
+/// Predict every line of `reader` and write one JSON object per line to
+/// `writer`, in input order:
+/// `{"text_id":0,"labels":["__label__a",...],"probs":[0.9,...]}`, where
+/// `text_id` is the zero-based line number and the labels are the `k` most
+/// probable with probability at least `threshold`, best first. Lines are
+/// predicted in parallel batches, so input of any size streams through in
+/// bounded memory. Returns the number of lines written.
+pub fn write_jsonl<R: BufRead, W: Write>(
+    model: &Model,
+    reader: R,
+    writer: W,
+    k: usize,
+    threshold: f32,
+) -> Result<usize> {
+    let mut writer = BufWriter::new(writer);
+    let mut lines = reader.lines();
+    let mut batch = Vec::with_capacity(JSONL_BATCH);
+    let mut text_id = 0;
+    loop {
+        batch.clear();
+        for line in lines.by_ref().take(JSONL_BATCH) {
+            batch.push(line?);
+        }
+        if batch.is_empty() {
+            break;
+        }
+        let texts: Vec<&str> = batch.iter().map(String::as_str).collect();
+        for predictions in predict_batch(model, &texts, k, threshold) {
+            let labels: Vec<String> = predictions
+                .iter()
+                .map(|(label, _)| json_string(label))
+                .collect();
+            let probs: Vec<String> = predictions.iter().map(|(_, p)| p.to_string()).collect();
+            writeln!(
+                writer,
+                "{{\"text_id\":{},\"labels\":[{}],\"probs\":[{}]}}",
+                text_id,
+                labels.join(","),
+                probs.join(",")
+            )?;
+            text_id += 1;
+        }
+    }
+    writer.flush()?;
+    Ok(text_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args::Args;
+    use crate::io::tests::corpus_factory;
+    use crate::priors::LabelPriors;
+    use std::collections::HashMap;
+
+    fn train(loss: LossKind) -> Model {
+        let mut text = String::new();
+        for _ in 0..100 {
+            text.push_str("__label__fruit apple banana cherry\n");
+            text.push_str("__label__letter xray yankee zulu\n");
+            text.push_str("__label__color red green blue\n");
+        }
+        let corpus = corpus_factory(&text);
+        let args = Args {
+            dim: 10,
+            vocab_size: 101,
+            threads: 1,
+            loss,
+            ..Args::supervised()
+        };
+        Model::train_supervised(corpus.path(), args).unwrap()
+    }
+
+    #[test]
+    fn test_predict() {
+        let mut model = train(LossKind::Softmax);
+
+        let predictions = model.predict("banana cherry", 2, 0.0);
+        assert_eq!(predictions.len(), 2);
+        assert_eq!(predictions[0].0, "__label__fruit");
+        assert!(predictions[0].1 > 0.8 && predictions[0].1 >= predictions[1].1);
+        let all = model.predict("zulu", 5, 0.0);
+        assert_eq!(all.len(), 3);
+        assert!((all.iter().map(|(_, p)| p).sum::<f32>() - 1.0).abs() < 1e-5);
+        assert!(model.predict("zulu", 0, 0.0).is_empty());
+        assert!(model.predict("zulu", 5, 1.1).is_empty());
+
+        // int8 compression leaves the ranking intact
+        model.quantize_int8().unwrap();
+        assert_eq!(model.predict("blue green", 1, 0.0)[0].0, "__label__color");
+    }
+
+    #[test]
+    fn test_predict_one_vs_all() {
+        let model = train(LossKind::OneVsAll);
+
+        let predictions = model.predict("apple xray", 3, 0.0);
+        let fruit = predictions
+            .iter()
+            .find(|(label, _)| label == "__label__fruit");
+        assert!(fruit.unwrap().1 > 0.3);
+        assert!(predictions.iter().all(|(_, p)| (0.0..=1.0).contains(p)));
+
+        // a threshold with unbounded k keeps exactly the labels above it
+        let above = model.predict("apple xray", usize::MAX, 0.3);
+        assert_eq!(
+            above,
+            predictions
+                .into_iter()
+                .filter(|(_, p)| *p >= 0.3)
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_predict_hierarchical_softmax() {
+        let model = train(LossKind::HierarchicalSoftmax);
+
+        let predictions = model.predict("xray yankee", 3, 0.0);
+        assert_eq!(predictions.len(), 3);
+        assert_eq!(predictions[0].0, "__label__letter");
+        assert!((predictions.iter().map(|(_, p)| p).sum::<f32>() - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_predict_with_priors() {
+        let mut offsets = HashMap::new();
+        offsets.insert(String::from("__label__color"), 20.0);
+
+        // softmax renormalises the shifted logits
+        let mut model = train(LossKind::Softmax);
+        let priors = LabelPriors::new(model.vocabulary(), &offsets).unwrap();
+        model.set_label_priors(Some(priors.clone())).unwrap();
+        let predictions = model.predict("banana cherry", 3, 0.0);
+        assert_eq!(predictions[0].0, "__label__color");
+        assert!((predictions.iter().map(|(_, p)| p).sum::<f32>() - 1.0).abs() < 1e-5);
+
+        // one-vs-all moves only the shifted label
+        let mut model = train(LossKind::OneVsAll);
+        let before = model.predict("banana cherry", 3, 0.0);
+        model.set_label_priors(Some(priors)).unwrap();
+        let after = model.predict("banana cherry", 3, 0.0);
+        assert_eq!(after[0].0, "__label__color");
+        for (label, p) in before.iter().filter(|(label, _)| label != "__label__color") {
+            assert!(after.contains(&(label.clone(), *p)));
+        }
+
+        let short = LabelPriors::from_offsets(vec![0.0]);
+        assert!(model.set_label_priors(Some(short)).is_err());
+    }
+
+    #[test]
+    fn test_predict_batch() {
+        let model = train(LossKind::Softmax);
+        let texts: Vec<&str> = ["apple", "zulu xray", "", "green", "banana"]
+            .iter()
+            .cycle()
+            .take(200)
+            .copied()
+            .collect();
+
+        let batch = model.predict_batch(&texts, 2, 0.1);
+        assert_eq!(batch.len(), texts.len());
+        for (text, predictions) in texts.iter().zip(batch.iter()) {
+            assert_eq!(predictions, &model.predict(text, 2, 0.1));
+        }
+    }
+
+    #[test]
+    fn test_write_jsonl() {
+        let model = train(LossKind::Softmax);
+        let input = "banana cherry\n\nzulu xray\n";
+        let mut out = Vec::new();
+
+        assert_eq!(
+            write_jsonl(&model, input.as_bytes(), &mut out, 2, 0.0).unwrap(),
+            3
+        );
+        let out = String::from_utf8(out).unwrap();
+        let records: Vec<&str> = out.lines().collect();
+        assert_eq!(records.len(), 3);
+        let first = model.predict("banana cherry", 2, 0.0);
+        assert_eq!(
+            records[0],
+            format!(
+                "{{\"text_id\":0,\"labels\":[\"{}\",\"{}\"],\"probs\":[{},{}]}}",
+                first[0].0, first[1].0, first[0].1, first[1].1
+            )
+        );
+        assert!(records[1].starts_with("{\"text_id\":1,\"labels\":["));
+        assert!(records[2].starts_with("{\"text_id\":2,\"labels\":[\"__label__letter\""));
+    }
+}
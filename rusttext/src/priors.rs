@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+
+use crate::vocabulary::Vocabulary;
+use crate::word::EntryType;
+use crate::{Result, RustTextError};
+
+/// Per-label log-odds offsets applied to classifier output, used to correct
+/// for a known difference between the training and serving label
+/// distributions without retraining.
+///
+/// Offsets are indexed by label position: the order in which labels appear
+/// in the vocabulary, which is also the row order of the output matrix.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LabelPriors {
+    offsets: Vec<f32>,
+}
+
+fn label_names(vocab: &Vocabulary) -> Vec<&str> {
+    vocab
+        .words()
+        .iter()
+        .filter(|entry| entry.entry_type == EntryType::Label)
+        .map(|entry| entry.word.as_str())
+        .collect()
+}
+
+impl LabelPriors {
+    /// Explicit offsets keyed by full label (prefix included); labels not in
+    /// the map get no adjustment.
+    pub fn new(vocab: &Vocabulary, offsets: &HashMap<String, f32>) -> Result<LabelPriors> {
+        let labels = label_names(vocab);
+        for label in offsets.keys() {
+            if !labels.contains(&label.as_str()) {
+                return Err(RustTextError::UnknownLabel(label.clone()));
+            }
+        }
+
+        Ok(LabelPriors {
+            offsets: labels
+                .iter()
+                .map(|label| offsets.get(*label).copied().unwrap_or(0.0))
+                .collect(),
+        })
+    }
+
+    /// Offsets of `ln(p_serve / p_train)`, taking the training distribution
+    /// from the vocabulary's label counts. `serve` holds relative frequencies
+    /// (they need not sum to one); labels missing from it keep their
+    /// training prior.
+    pub fn from_distribution(
+        vocab: &Vocabulary,
+        serve: &HashMap<String, f32>,
+    ) -> Result<LabelPriors> {
+        let counts: Vec<(&str, u32)> = vocab
+            .words()
+            .iter()
+            .filter(|entry| entry.entry_type == EntryType::Label)
+            .map(|entry| (entry.word.as_str(), entry.count))
+            .collect();
+        let train_total: f32 = counts.iter().map(|(_, count)| *count as f32).sum();
+        let serve_total: f32 = serve.values().sum();
+        if serve_total <= 0.0 {
+            return Err(RustTextError::InvalidArgument(String::from(
+                "serving label frequencies must sum to a positive value",
+            )));
+        }
+
+        let mut offsets = HashMap::new();
+        for (label, count) in counts.iter() {
+            if let Some(freq) = serve.get(*label) {
+                let p_train = *count as f32 / train_total;
+                let p_serve = freq / serve_total;
+                offsets.insert(label.to_string(), p_serve.ln() - p_train.ln());
+            }
+        }
+        LabelPriors::new(vocab, &offsets)
+    }
+
+    // Offsets as saved with a model, already in label order.
+    pub(crate) fn from_offsets(offsets: Vec<f32>) -> LabelPriors {
+        LabelPriors { offsets }
+    }
+
+    pub fn offsets(&self) -> &[f32] {
+        &self.offsets
+    }
+
+    /// Shift per-label log-probabilities by their offsets and renormalise so
+    /// they again form a distribution.
+    pub fn adjust(&self, log_probs: &mut [f32]) {
+        for (log_prob, offset) in log_probs.iter_mut().zip(self.offsets.iter()) {
+            *log_prob += offset;
+        }
+        let max = log_probs.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+        let norm = max
+            + log_probs
+                .iter()
+                .map(|value| (value - max).exp())
+                .sum::<f32>()
+                .ln();
+        for log_prob in log_probs.iter_mut() {
+            *log_prob -= norm;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_vocab() -> Vocabulary {
+        let mut vocab = Vocabulary::new(101, 0, 0, 10).unwrap();
+        for (word, count) in [("__label__a", 3), ("word", 1), ("__label__b", 1)].iter() {
+            for _ in 0..*count {
+                vocab.add(word).unwrap();
+            }
+        }
+        vocab
+    }
+
+    #[test]
+    fn test_new() {
+        let vocab = test_vocab();
+        let mut offsets = HashMap::new();
+        offsets.insert(String::from("__label__b"), 1.5);
+
+        let priors = LabelPriors::new(&vocab, &offsets).unwrap();
+        assert_eq!(priors.offsets(), &[0.0, 1.5]);
+
+        offsets.insert(String::from("__label__c"), 1.0);
+        assert!(LabelPriors::new(&vocab, &offsets).is_err());
+    }
+
+    #[test]
+    fn test_from_distribution() {
+        // train is 3:1, serving is 1:1
+        let vocab = test_vocab();
+        let mut serve = HashMap::new();
+        serve.insert(String::from("__label__a"), 1.0);
+        serve.insert(String::from("__label__b"), 1.0);
+
+        let priors = LabelPriors::from_distribution(&vocab, &serve).unwrap();
+
+        // a classifier echoing the training prior becomes uniform
+        let mut log_probs = [0.75f32.ln(), 0.25f32.ln()];
+        priors.adjust(&mut log_probs);
+        assert!((log_probs[0].exp() - 0.5).abs() < 1e-6);
+        assert!((log_probs[1].exp() - 0.5).abs() < 1e-6);
+    }
+}
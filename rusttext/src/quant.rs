@@ -0,0 +1,898 @@
+use serde::{Deserialize, Serialize};
+
+use crate::matrix::Matrix;
+use crate::random::Rng;
+use crate::{Result, RustTextError};
+
+/// Centroids per sub-quantizer; codes are one byte each.
+pub const KSUB: usize = 256;
+const MAX_POINTS_PER_CLUSTER: usize = 256;
+const N_ITER: usize = 25;
+const EPS: f32 = 1e-7;
+
+/// Settings for `Model::quantize`. The defaults match fastText's
+/// `quantize` command.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QuantArgs {
+    /// Dimensions per sub-quantizer, so the input matrix is split into
+    /// `ceil(dim / dsub)` sub-quantizers of one byte each per row.
+    pub dsub: usize,
+    /// Quantize each row's norm separately and its direction with the
+    /// product quantizer, which preserves magnitudes better.
+    pub qnorm: bool,
+    /// Quantize the output matrix as well as the input; it needs at least
+    /// `KSUB` rows.
+    pub qout: bool,
+    /// Keep only this many input rows, those with the largest norms (plus
+    /// the end-of-sentence row); the rest read as zero. 0 keeps every row.
+    pub cutoff: usize,
+}
+
+impl Default for QuantArgs {
+    fn default() -> QuantArgs {
+        QuantArgs {
+            dsub: 2,
+            qnorm: false,
+            qout: false,
+            cutoff: 0,
+        }
+    }
+}
+
+fn distance(left: &[f32], right: &[f32]) -> f32 {
+    left.iter()
+        .zip(right.iter())
+        .map(|(l, r)| (l - r) * (l - r))
+        .sum()
+}
+
+fn shuffle(values: &mut [usize], rng: &mut Rng) {
+    for i in (1..values.len()).rev() {
+        values.swap(i, rng.below(i + 1));
+    }
+}
+
+/// Product quantizer: splits vectors into sub-vectors and encodes each as
+/// the index of its nearest of `KSUB` centroids learned by k-means. The
+/// centroid layout follows fastText, so the last sub-quantizer covers the
+/// remaining `dim % dsub` dimensions when `dsub` does not divide `dim`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProductQuantizer {
+    dim: usize,
+    nsubq: usize,
+    dsub: usize,
+    lastdsub: usize,
+    centroids: Vec<f32>,
+}
+
+impl ProductQuantizer {
+    pub fn new(dim: usize, dsub: usize) -> Result<ProductQuantizer> {
+        if dim == 0 || dsub == 0 {
+            return Err(RustTextError::InvalidArgument(format!(
+                "cannot quantize {} dimensions in groups of {}",
+                dim, dsub
+            )));
+        }
+        let lastdsub = match dim % dsub {
+            0 => dsub,
+            rest => rest,
+        };
+        Ok(ProductQuantizer {
+            dim,
+            nsubq: dim.div_ceil(dsub),
+            dsub,
+            lastdsub,
+            centroids: vec![0.0; dim * KSUB],
+        })
+    }
+
+    /// A quantizer with given centroids, laid out as `centroids()` returns
+    /// them, e.g. read from a saved model.
+    pub(crate) fn from_centroids(
+        dim: usize,
+        dsub: usize,
+        centroids: Vec<f32>,
+    ) -> Result<ProductQuantizer> {
+        let mut pq = ProductQuantizer::new(dim, dsub)?;
+        if centroids.len() != pq.centroids.len() {
+            return Err(RustTextError::DimensionMismatch {
+                expected: pq.centroids.len(),
+                found: centroids.len(),
+            });
+        }
+        pq.centroids = centroids;
+        Ok(pq)
+    }
+
+    // Check a deserialized quantizer against the layout `new` gives.
+    fn validate(&self) -> Result<()> {
+        let expected = ProductQuantizer::new(self.dim, self.dsub)
+            .map_err(|err| RustTextError::ModelFormat(err.to_string()))?;
+        if (self.nsubq, self.lastdsub, self.centroids.len())
+            != (expected.nsubq, expected.lastdsub, expected.centroids.len())
+        {
+            return Err(RustTextError::ModelFormat(format!(
+                "inconsistent quantizer: {} dimensions in {} groups of {} (last {})",
+                self.dim, self.nsubq, self.dsub, self.lastdsub
+            )));
+        }
+        Ok(())
+    }
+
+    pub fn dim(&self) -> usize {
+        self.dim
+    }
+
+    /// Number of sub-quantizers, which is also the code length in bytes.
+    pub fn nsubq(&self) -> usize {
+        self.nsubq
+    }
+
+    pub fn dsub(&self) -> usize {
+        self.dsub
+    }
+
+    /// Dimensions of the last sub-quantizer.
+    pub fn lastdsub(&self) -> usize {
+        self.lastdsub
+    }
+
+    pub fn centroids(&self) -> &[f32] {
+        &self.centroids
+    }
+
+    fn sub_dim(&self, m: usize) -> usize {
+        if m == self.nsubq - 1 {
+            self.lastdsub
+        } else {
+            self.dsub
+        }
+    }
+
+    fn centroid_offset(&self, m: usize, i: usize) -> usize {
+        if m == self.nsubq - 1 {
+            m * KSUB * self.dsub + i * self.lastdsub
+        } else {
+            (m * KSUB + i) * self.dsub
+        }
+    }
+
+    /// Centroid `i` of sub-quantizer `m`.
+    pub fn centroid(&self, m: usize, i: usize) -> &[f32] {
+        let offset = self.centroid_offset(m, i);
+        &self.centroids[offset..offset + self.sub_dim(m)]
+    }
+
+    fn nearest(&self, m: usize, x: &[f32]) -> u8 {
+        let mut best = (f32::INFINITY, 0);
+        for i in 0..KSUB {
+            let d = distance(x, self.centroid(m, i));
+            if d < best.0 {
+                best = (d, i);
+            }
+        }
+        best.1 as u8
+    }
+
+    /// Learn the centroids from the rows of `data` (at most
+    /// `256 * KSUB` of them, sampled), which must have `dim` columns and at
+    /// least `KSUB` rows.
+    pub fn train(&mut self, data: &Matrix, rng: &mut Rng) -> Result<()> {
+        if data.cols() != self.dim {
+            return Err(RustTextError::DimensionMismatch {
+                expected: self.dim,
+                found: data.cols(),
+            });
+        }
+        if data.rows() < KSUB {
+            return Err(RustTextError::InvalidArgument(format!(
+                "quantization needs at least {} rows, got {}",
+                KSUB,
+                data.rows()
+            )));
+        }
+        let mut perm: Vec<usize> = (0..data.rows()).collect();
+        let n_points = usize::min(data.rows(), MAX_POINTS_PER_CLUSTER * KSUB);
+
+        for m in 0..self.nsubq {
+            let d = self.sub_dim(m);
+            let start = m * self.dsub;
+            shuffle(&mut perm, rng);
+            let mut points = Vec::with_capacity(n_points * d);
+            for &row in perm[..n_points].iter() {
+                points.extend_from_slice(&data.row(row)[start..start + d]);
+            }
+            let offset = self.centroid_offset(m, 0);
+            kmeans(
+                &points,
+                &mut self.centroids[offset..offset + KSUB * d],
+                d,
+                rng,
+            );
+        }
+        Ok(())
+    }
+
+    /// Encode `x` into `code`, one byte per sub-quantizer.
+    pub fn compute_code(&self, x: &[f32], code: &mut [u8]) {
+        for (m, byte) in code.iter_mut().enumerate().take(self.nsubq) {
+            let start = m * self.dsub;
+            *byte = self.nearest(m, &x[start..start + self.sub_dim(m)]);
+        }
+    }
+
+    /// `alpha * dot(x, decode(code))`, without decoding.
+    pub fn dot_code(&self, x: &[f32], code: &[u8], alpha: f32) -> f32 {
+        let mut sum = 0.0;
+        for (m, &byte) in code.iter().enumerate().take(self.nsubq) {
+            let start = m * self.dsub;
+            let centroid = self.centroid(m, byte as usize);
+            sum += x[start..start + centroid.len()]
+                .iter()
+                .zip(centroid.iter())
+                .map(|(x, c)| x * c)
+                .sum::<f32>();
+        }
+        alpha * sum
+    }
+
+    /// `out += alpha * decode(code)`.
+    pub fn add_code(&self, out: &mut [f32], code: &[u8], alpha: f32) {
+        for (m, &byte) in code.iter().enumerate().take(self.nsubq) {
+            let start = m * self.dsub;
+            let centroid = self.centroid(m, byte as usize);
+            for (o, c) in out[start..start + centroid.len()]
+                .iter_mut()
+                .zip(centroid.iter())
+            {
+                *o += alpha * c;
+            }
+        }
+    }
+}
+
+// Lloyd's k-means over `points` (rows of `d` values) into `centroids`
+// (`KSUB` rows), initialised from distinct random points. Empty clusters
+// are refilled by splitting a populated one, as fastText does.
+fn kmeans(points: &[f32], centroids: &mut [f32], d: usize, rng: &mut Rng) {
+    let n = points.len() / d;
+    let mut perm: Vec<usize> = (0..n).collect();
+    shuffle(&mut perm, rng);
+    for (i, &point) in perm[..KSUB].iter().enumerate() {
+        centroids[i * d..(i + 1) * d].copy_from_slice(&points[point * d..(point + 1) * d]);
+    }
+
+    let mut assignments = vec![0; n];
+    let mut sizes = vec![0usize; KSUB];
+    for _ in 0..N_ITER {
+        for (point, assignment) in points.chunks(d).zip(assignments.iter_mut()) {
+            let mut best = (f32::INFINITY, 0);
+            for (i, centroid) in centroids.chunks(d).enumerate() {
+                let dist = distance(point, centroid);
+                if dist < best.0 {
+                    best = (dist, i);
+                }
+            }
+            *assignment = best.1;
+        }
+
+        centroids.iter_mut().for_each(|value| *value = 0.0);
+        sizes.iter_mut().for_each(|size| *size = 0);
+        for (point, &assignment) in points.chunks(d).zip(assignments.iter()) {
+            sizes[assignment] += 1;
+            for (c, p) in centroids[assignment * d..(assignment + 1) * d]
+                .iter_mut()
+                .zip(point.iter())
+            {
+                *c += p;
+            }
+        }
+        for (centroid, &size) in centroids.chunks_mut(d).zip(sizes.iter()) {
+            if size > 0 {
+                centroid.iter_mut().for_each(|c| *c /= size as f32);
+            }
+        }
+
+        for k in 0..KSUB {
+            if sizes[k] != 0 {
+                continue;
+            }
+            // pick a cluster to split with probability growing with its size
+            let mut m = 0;
+            while rng.next_f32() * (n - KSUB) as f32 >= sizes[m] as f32 - 1.0 {
+                m = (m + 1) % KSUB;
+            }
+            centroids.copy_within(m * d..(m + 1) * d, k * d);
+            for j in 0..d {
+                let sign = if j % 2 == 1 { 1.0 } else { -1.0 };
+                centroids[k * d + j] *= 1.0 + sign * EPS;
+                centroids[m * d + j] *= 1.0 - sign * EPS;
+            }
+            sizes[k] = sizes[m] / 2;
+            sizes[m] -= sizes[k];
+        }
+    }
+}
+
+/// Rows of `matrix` with the largest L2 norms, `cutoff` in total, always
+/// including those in `keep`. Returned in ascending order.
+pub fn select_rows(matrix: &Matrix, cutoff: usize, keep: &[u32]) -> Vec<u32> {
+    let norms: Vec<f32> = (0..matrix.rows()).map(|i| matrix.l2_norm_row(i)).collect();
+    let mut ids: Vec<u32> = (0..matrix.rows() as u32)
+        .filter(|id| !keep.contains(id))
+        .collect();
+    ids.sort_by(|&a, &b| norms[b as usize].total_cmp(&norms[a as usize]));
+    ids.truncate(cutoff.saturating_sub(keep.len()));
+    ids.extend_from_slice(keep);
+    ids.sort_unstable();
+    ids.dedup();
+    ids
+}
+
+/// A matrix stored as product-quantized codes, supporting the row dot
+/// products and accumulations used at inference.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QuantMatrix {
+    rows: usize,
+    cols: usize,
+    pq: ProductQuantizer,
+    codes: Vec<u8>,
+    /// Quantizer and one code per stored row for the norms, with `qnorm`.
+    norms: Option<(ProductQuantizer, Vec<u8>)>,
+    /// Original row ids of the stored rows, ascending, after a cutoff;
+    /// other rows read as zero.
+    kept: Option<Vec<u32>>,
+}
+
+impl QuantMatrix {
+    /// Quantize every row of `matrix` with sub-quantizers of `dsub`
+    /// dimensions, and the row norms separately if `qnorm` is set.
+    pub fn new(matrix: &Matrix, dsub: usize, qnorm: bool, rng: &mut Rng) -> Result<QuantMatrix> {
+        QuantMatrix::build(matrix, None, dsub, qnorm, rng)
+    }
+
+    /// Like `new`, but storing only the rows in `rows` (ascending, as
+    /// returned by `select_rows`).
+    pub fn from_rows(
+        matrix: &Matrix,
+        rows: Vec<u32>,
+        dsub: usize,
+        qnorm: bool,
+        rng: &mut Rng,
+    ) -> Result<QuantMatrix> {
+        QuantMatrix::build(matrix, Some(rows), dsub, qnorm, rng)
+    }
+
+    fn build(
+        matrix: &Matrix,
+        kept: Option<Vec<u32>>,
+        dsub: usize,
+        qnorm: bool,
+        rng: &mut Rng,
+    ) -> Result<QuantMatrix> {
+        let mut data = match &kept {
+            Some(rows) => matrix.select_rows(rows),
+            None => matrix.clone(),
+        };
+
+        let norms = if qnorm {
+            let values: Vec<f32> = (0..data.rows()).map(|i| data.l2_norm_row(i)).collect();
+            for (i, &norm) in values.iter().enumerate() {
+                if norm > 0.0 {
+                    data.scale_row(i, 1.0 / norm);
+                }
+            }
+            let mut npq = ProductQuantizer::new(1, 1)?;
+            let values = Matrix::from_vec(values.len(), 1, values)?;
+            npq.train(&values, rng)?;
+            let mut codes = vec![0; values.rows()];
+            for (i, code) in codes.iter_mut().enumerate() {
+                npq.compute_code(values.row(i), std::slice::from_mut(code));
+            }
+            Some((npq, codes))
+        } else {
+            None
+        };
+
+        let mut pq = ProductQuantizer::new(matrix.cols(), dsub)?;
+        pq.train(&data, rng)?;
+        let mut codes = vec![0; data.rows() * pq.nsubq()];
+        for (i, code) in codes.chunks_mut(pq.nsubq()).enumerate() {
+            pq.compute_code(data.row(i), code);
+        }
+
+        Ok(QuantMatrix {
+            rows: matrix.rows(),
+            cols: matrix.cols(),
+            pq,
+            codes,
+            norms,
+            kept,
+        })
+    }
+
+    /// A matrix of `rows` codes from an already trained quantizer, with
+    /// the norm quantizer and one norm code per row if `norms` is given.
+    pub(crate) fn from_codes(
+        rows: usize,
+        pq: ProductQuantizer,
+        codes: Vec<u8>,
+        norms: Option<(ProductQuantizer, Vec<u8>)>,
+    ) -> Result<QuantMatrix> {
+        let expected = rows.checked_mul(pq.nsubq()).ok_or_else(|| {
+            RustTextError::ModelFormat(format!("quantized matrix of {} rows is too large", rows))
+        })?;
+        if codes.len() != expected {
+            return Err(RustTextError::DimensionMismatch {
+                expected,
+                found: codes.len(),
+            });
+        }
+        if let Some((npq, norm_codes)) = &norms {
+            if npq.dim() != 1 || norm_codes.len() != rows {
+                return Err(RustTextError::InvalidArgument(String::from(
+                    "norm codes need a one-dimensional quantizer and one code per row",
+                )));
+            }
+        }
+        Ok(QuantMatrix {
+            rows,
+            cols: pq.dim(),
+            pq,
+            codes,
+            norms,
+            kept: None,
+        })
+    }
+
+    // Check that a deserialized matrix is consistent, so lookups stay in
+    // bounds.
+    fn validate(&self) -> Result<()> {
+        self.pq.validate()?;
+        let format_error = |message: &str| Err(RustTextError::ModelFormat(String::from(message)));
+        if self.pq.dim() != self.cols {
+            return format_error("quantizer dimension differs from the matrix's");
+        }
+        let stored = match &self.kept {
+            Some(kept) => {
+                let ascending = kept.windows(2).all(|pair| pair[0] < pair[1]);
+                if !ascending || kept.last().is_some_and(|&id| id as usize >= self.rows) {
+                    return format_error("kept rows are not ascending row ids");
+                }
+                kept.len()
+            }
+            None => self.rows,
+        };
+        if stored.checked_mul(self.pq.nsubq()) != Some(self.codes.len()) {
+            return format_error("code count differs from the stored rows");
+        }
+        if let Some((npq, codes)) = &self.norms {
+            npq.validate()?;
+            if npq.dim() != 1 || codes.len() != stored {
+                return format_error(
+                    "norm codes need a one-dimensional quantizer and one code per row",
+                );
+            }
+        }
+        Ok(())
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+
+    pub fn pq(&self) -> &ProductQuantizer {
+        &self.pq
+    }
+
+    pub fn codes(&self) -> &[u8] {
+        &self.codes
+    }
+
+    pub fn qnorm(&self) -> bool {
+        self.norms.is_some()
+    }
+
+    /// With `qnorm`, the norm quantizer and the norm code of each stored
+    /// row.
+    pub fn norm_codes(&self) -> Option<(&ProductQuantizer, &[u8])> {
+        self.norms
+            .as_ref()
+            .map(|(npq, codes)| (npq, codes.as_slice()))
+    }
+
+    /// Ids of the rows kept by a cutoff, or `None` if every row is stored.
+    pub fn kept_rows(&self) -> Option<&[u32]> {
+        self.kept.as_deref()
+    }
+
+    // Position of row `i` among the stored codes.
+    fn slot(&self, i: usize) -> Option<usize> {
+        match &self.kept {
+            Some(kept) => kept.binary_search(&(i as u32)).ok(),
+            None => Some(i),
+        }
+    }
+
+    fn code_and_norm(&self, slot: usize) -> (&[u8], f32) {
+        let nsubq = self.pq.nsubq();
+        let norm = match &self.norms {
+            Some((npq, codes)) => npq.centroid(0, codes[slot] as usize)[0],
+            None => 1.0,
+        };
+        (&self.codes[slot * nsubq..(slot + 1) * nsubq], norm)
+    }
+
+    /// Dot product of (the decoded) row `i` with `vector`.
+    pub fn dot_row(&self, vector: &[f32], i: usize) -> f32 {
+        match self.slot(i) {
+            Some(slot) => {
+                let (code, norm) = self.code_and_norm(slot);
+                self.pq.dot_code(vector, code, norm)
+            }
+            None => 0.0,
+        }
+    }
+
+    /// `out += scale * row_i`.
+    pub fn add_row_to(&self, out: &mut [f32], i: usize, scale: f32) {
+        if let Some(slot) = self.slot(i) {
+            let (code, norm) = self.code_and_norm(slot);
+            self.pq.add_code(out, code, scale * norm);
+        }
+    }
+}
+
+/// A matrix stored as one signed byte per value plus one scale per row,
+/// `value ~= scale * byte` with the scale mapping the row's largest
+/// magnitude to 127. Rows are dequantized on the fly.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Int8Matrix {
+    rows: usize,
+    cols: usize,
+    values: Vec<i8>,
+    scales: Vec<f32>,
+}
+
+impl Int8Matrix {
+    pub fn new(matrix: &Matrix) -> Int8Matrix {
+        let mut int8 = Int8Matrix::empty(matrix.cols());
+        int8.values.reserve(matrix.rows() * matrix.cols());
+        int8.scales.reserve(matrix.rows());
+        for i in 0..matrix.rows() {
+            int8.push_row(matrix.row(i));
+        }
+        int8
+    }
+
+    // no rows yet; see `push_row`
+    pub(crate) fn empty(cols: usize) -> Int8Matrix {
+        Int8Matrix {
+            rows: 0,
+            cols,
+            values: Vec::new(),
+            scales: Vec::new(),
+        }
+    }
+
+    // quantize `row` and append it
+    pub(crate) fn push_row(&mut self, row: &[f32]) {
+        debug_assert_eq!(row.len(), self.cols);
+        let max = row.iter().fold(0.0f32, |max, v| max.max(v.abs()));
+        let scale = if max > 0.0 { max / 127.0 } else { 1.0 };
+        self.values
+            .extend(row.iter().map(|v| (v / scale).round() as i8));
+        self.scales.push(scale);
+        self.rows += 1;
+    }
+
+    // Check that a deserialized matrix has a value per cell and a scale
+    // per row.
+    fn validate(&self) -> Result<()> {
+        if self.rows.checked_mul(self.cols) != Some(self.values.len())
+            || self.scales.len() != self.rows
+        {
+            return Err(RustTextError::ModelFormat(format!(
+                "int8 matrix of {} x {} has {} values and {} scales",
+                self.rows,
+                self.cols,
+                self.values.len(),
+                self.scales.len()
+            )));
+        }
+        Ok(())
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+
+    pub fn values(&self) -> &[i8] {
+        &self.values
+    }
+
+    pub fn scales(&self) -> &[f32] {
+        &self.scales
+    }
+
+    fn row(&self, i: usize) -> &[i8] {
+        &self.values[i * self.cols..(i + 1) * self.cols]
+    }
+
+    /// Dot product of (the dequantized) row `i` with `vector`.
+    pub fn dot_row(&self, vector: &[f32], i: usize) -> f32 {
+        let sum: f32 = self
+            .row(i)
+            .iter()
+            .zip(vector.iter())
+            .map(|(&q, v)| f32::from(q) * v)
+            .sum();
+        self.scales[i] * sum
+    }
+
+    /// `out += scale * row_i`.
+    pub fn add_row_to(&self, out: &mut [f32], i: usize, scale: f32) {
+        let scale = scale * self.scales[i];
+        for (o, &q) in out.iter_mut().zip(self.row(i).iter()) {
+            *o += scale * f32::from(q);
+        }
+    }
+}
+
+/// A compressed, read-only stand-in for a model matrix.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum CompressedMatrix {
+    Product(QuantMatrix),
+    Int8(Int8Matrix),
+}
+
+impl CompressedMatrix {
+    /// Check that a deserialized matrix is internally consistent.
+    pub(crate) fn validate(&self) -> Result<()> {
+        match self {
+            CompressedMatrix::Product(matrix) => matrix.validate(),
+            CompressedMatrix::Int8(matrix) => matrix.validate(),
+        }
+    }
+
+    pub fn rows(&self) -> usize {
+        match self {
+            CompressedMatrix::Product(matrix) => matrix.rows(),
+            CompressedMatrix::Int8(matrix) => matrix.rows(),
+        }
+    }
+
+    pub fn cols(&self) -> usize {
+        match self {
+            CompressedMatrix::Product(matrix) => matrix.cols(),
+            CompressedMatrix::Int8(matrix) => matrix.cols(),
+        }
+    }
+
+    pub fn dot_row(&self, vector: &[f32], i: usize) -> f32 {
+        match self {
+            CompressedMatrix::Product(matrix) => matrix.dot_row(vector, i),
+            CompressedMatrix::Int8(matrix) => matrix.dot_row(vector, i),
+        }
+    }
+
+    pub fn add_row_to(&self, out: &mut [f32], i: usize, scale: f32) {
+        match self {
+            CompressedMatrix::Product(matrix) => matrix.add_row_to(out, i, scale),
+            CompressedMatrix::Int8(matrix) => matrix.add_row_to(out, i, scale),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 512 rows scattered tightly around four points in 3 dimensions.
+    fn clustered() -> Matrix {
+        let centers = [
+            [1.0, 0.0, 2.0],
+            [-1.0, 3.0, 0.5],
+            [0.0, -2.0, -1.0],
+            [4.0, 4.0, 4.0],
+        ];
+        let mut rng = Rng::new(1);
+        let mut data = Vec::new();
+        for i in 0..512 {
+            for &c in centers[i % 4].iter() {
+                data.push(c + rng.uniform(-0.01, 0.01));
+            }
+        }
+        Matrix::from_vec(512, 3, data).unwrap()
+    }
+
+    #[test]
+    fn test_product_quantizer() {
+        let data = clustered();
+        let mut pq = ProductQuantizer::new(3, 2).unwrap();
+        assert_eq!((pq.nsubq(), pq.centroid(1, 0).len()), (2, 1));
+
+        pq.train(&data, &mut Rng::new(0)).unwrap();
+        let mut code = [0; 2];
+        for i in 0..data.rows() {
+            pq.compute_code(data.row(i), &mut code);
+            let mut decoded = [0.0; 3];
+            pq.add_code(&mut decoded, &code, 1.0);
+            assert!(distance(&decoded, data.row(i)) < 1e-3, "{:?}", decoded);
+            let dot = pq.dot_code(&[1.0, 1.0, 1.0], &code, 2.0);
+            assert!((dot - 2.0 * decoded.iter().sum::<f32>()).abs() < 1e-4);
+        }
+
+        assert!(pq.train(&Matrix::new(10, 3), &mut Rng::new(0)).is_err());
+        assert!(matches!(
+            pq.train(&Matrix::new(300, 4), &mut Rng::new(0)),
+            Err(RustTextError::DimensionMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_quant_matrix() {
+        let data = clustered();
+        let vector = [0.5, -1.0, 2.0];
+        for &qnorm in [false, true].iter() {
+            let quant = QuantMatrix::new(&data, 2, qnorm, &mut Rng::new(0)).unwrap();
+            assert_eq!(quant.codes().len(), 512 * 2);
+            for i in 0..data.rows() {
+                assert!((quant.dot_row(&vector, i) - data.dot_row(&vector, i)).abs() < 0.05);
+            }
+        }
+
+        let pq = || ProductQuantizer::new(3, 2).unwrap();
+        assert!(matches!(
+            QuantMatrix::from_codes(usize::MAX, pq(), vec![0; 4], None),
+            Err(RustTextError::ModelFormat(_))
+        ));
+        assert!(matches!(
+            QuantMatrix::from_codes(3, pq(), vec![0; 4], None),
+            Err(RustTextError::DimensionMismatch { .. })
+        ));
+
+        let valid = QuantMatrix::from_codes(3, pq(), vec![0; 6], None).unwrap();
+        assert!(valid.validate().is_ok());
+        let invalid = [
+            QuantMatrix {
+                codes: vec![0; 5],
+                ..valid.clone()
+            },
+            QuantMatrix {
+                kept: Some(vec![2, 1]),
+                codes: vec![0; 4],
+                ..valid.clone()
+            },
+            QuantMatrix {
+                kept: Some(vec![1, 3]),
+                codes: vec![0; 4],
+                ..valid.clone()
+            },
+            QuantMatrix {
+                norms: Some((pq(), vec![0; 3])),
+                ..valid.clone()
+            },
+            QuantMatrix {
+                pq: ProductQuantizer { nsubq: 3, ..pq() },
+                ..valid.clone()
+            },
+        ];
+        for matrix in invalid.iter() {
+            assert!(matches!(
+                matrix.validate(),
+                Err(RustTextError::ModelFormat(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_cutoff() {
+        let mut data = clustered();
+        data.row_mut(7).copy_from_slice(&[0.0, 0.0, 0.0]);
+        let rows = select_rows(&data, 300, &[7]);
+        assert_eq!(rows.len(), 300);
+        assert!(rows.contains(&7));
+        // the largest-norm cluster is always kept
+        assert!(rows.contains(&3) && rows.contains(&511));
+
+        let quant =
+            QuantMatrix::from_rows(&data, rows.clone(), 3, false, &mut Rng::new(0)).unwrap();
+        let dropped = (0..512).find(|i| !rows.contains(i)).unwrap() as usize;
+        let mut out = [1.0; 3];
+        quant.add_row_to(&mut out, dropped, 1.0);
+        assert_eq!(quant.dot_row(&[1.0; 3], dropped), 0.0);
+        assert_eq!(out, [1.0; 3]);
+        quant.add_row_to(&mut out, 3, 1.0);
+        assert!(distance(&out, &[5.0, 5.0, 5.0]) < 1e-3);
+    }
+
+    #[test]
+    fn test_quantize_model() {
+        use crate::args::Args;
+        use crate::model::Model;
+        use crate::vocabulary::Vocabulary;
+        use crate::word;
+
+        let mut vocab = Vocabulary::new(1001, 0, 0, 1).unwrap();
+        vocab.add(word::EOS).unwrap();
+        for i in 0..299 {
+            vocab.add(&format!("w{}", i)).unwrap();
+        }
+        vocab.threshold(1, 0);
+        let mut input = clustered();
+        input
+            .row_mut(vocab.get_id(word::EOS).unwrap() as usize)
+            .copy_from_slice(&[0.0; 3]);
+        let mut model = Model {
+            args: Args {
+                dim: 3,
+                ..Args::default()
+            },
+            vocab,
+            input: Matrix::from_vec(300, 3, input.data()[..900].to_vec()).unwrap(),
+            output: Matrix::new(2, 3),
+            supervised: false,
+            word_vectors: Default::default(),
+            index: None,
+            label_thresholds: None,
+            label_priors: None,
+            output_tree: Default::default(),
+            quant_input: None,
+            quant_output: None,
+            training_stats: None,
+            metadata: None,
+        };
+        let quant_args = QuantArgs {
+            dsub: 3,
+            qnorm: true,
+            cutoff: 256,
+            ..QuantArgs::default()
+        };
+
+        model.quantize(&quant_args).unwrap();
+        assert!(model.is_quantized());
+        assert_eq!(model.input().rows(), 0);
+        assert_eq!(model.output().rows(), 2);
+        let quant = match model.quant_input() {
+            Some(CompressedMatrix::Product(quant)) => quant,
+            other => panic!("{:?}", other),
+        };
+        assert_eq!(quant.rows(), 300);
+        let kept = quant.kept_rows().unwrap();
+        assert_eq!(kept.len(), 256);
+        assert!(kept.contains(&model.vocabulary().get_id(word::EOS).unwrap()));
+        assert!(model.quantize(&quant_args).is_err());
+        assert!(model.quantize_int8().is_err());
+    }
+
+    #[test]
+    fn test_int8_matrix() {
+        let data =
+            Matrix::from_vec(3, 3, vec![1.0, -0.5, 0.25, 0.0, 0.0, 0.0, 100.0, 3.0, -7.0]).unwrap();
+        let int8 = Int8Matrix::new(&data);
+        assert_eq!(int8.values()[..3], [127, -64, 32]);
+        assert_eq!(int8.row(1), &[0, 0, 0]);
+
+        // every value is within half a quantization step of the original
+        let vector = [0.3f32, -2.0, 1.5];
+        for i in 0..3 {
+            let step = int8.scales()[i];
+            let bound = 0.5 * step * vector.iter().map(|v| v.abs()).sum::<f32>() + 1e-5;
+            assert!((int8.dot_row(&vector, i) - data.dot_row(&vector, i)).abs() <= bound);
+            let mut out = [0.0; 3];
+            int8.add_row_to(&mut out, i, 2.0);
+            for (o, v) in out.iter().zip(data.row(i).iter()) {
+                assert!((o - 2.0 * v).abs() <= step + 1e-5);
+            }
+        }
+    }
+}
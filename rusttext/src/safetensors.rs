@@ -0,0 +1,171 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::matrix::Matrix;
+use crate::model::Model;
+use crate::vocabulary::Vocabulary;
+use crate::word::HashMode;
+use crate::{Result, RustTextError};
+
+// safetensors wants the data to start 8-byte aligned, with the header
+// padded by spaces to get there.
+const HEADER_ALIGN: usize = 8;
+
+fn tensor_entry(name: &str, matrix: &Matrix, offset: usize) -> String {
+    format!(
+        "\"{}\":{{\"dtype\":\"F32\",\"shape\":[{},{}],\"data_offsets\":[{},{}]}}",
+        name,
+        matrix.rows(),
+        matrix.cols(),
+        offset,
+        offset + 4 * matrix.data().len()
+    )
+}
+
+/// Write the model's matrices in the safetensors format, for loading with
+/// `safetensors.torch.load_file` and friends: an `F32` tensor `input` of
+/// shape `[n_input_rows, dim]` (word rows, then subword buckets) and
+/// `output` of `[n_labels or n_words, dim]`, plus string metadata with the
+/// settings needed to look up rows: `dim`, `n_words`, `n_labels`,
+/// `bucket`, `minn`, `maxn`, `hash` and `supervised`. Compressed models
+/// have no dense matrices to export.
+pub fn write_safetensors<W: Write>(writer: W, model: &Model) -> Result<()> {
+    if model.is_quantized() {
+        return Err(RustTextError::InvalidArgument(String::from(
+            "cannot export a quantized model to safetensors",
+        )));
+    }
+    let vocab = model.vocabulary();
+    let hash = match vocab.hasher().hash_mode() {
+        Some(HashMode::Fnv32) => "fnv32",
+        Some(HashMode::Fnv64) => "fnv64",
+        None => "custom",
+    };
+    let metadata = [
+        ("dim", model.args().dim.to_string()),
+        ("n_words", vocab.n_words().to_string()),
+        ("n_labels", vocab.n_labels().to_string()),
+        ("bucket", vocab.bucket().to_string()),
+        ("minn", vocab.min_n().to_string()),
+        ("maxn", vocab.max_n().to_string()),
+        ("hash", hash.to_string()),
+        ("supervised", model.supervised.to_string()),
+    ];
+    let metadata: Vec<String> = metadata
+        .iter()
+        .map(|(key, value)| format!("\"{}\":\"{}\"", key, value))
+        .collect();
+    let input = model.input();
+    let output = model.output();
+    let mut header = format!(
+        "{{\"__metadata__\":{{{}}},{},{}}}",
+        metadata.join(","),
+        tensor_entry("input", input, 0),
+        tensor_entry("output", output, 4 * input.data().len())
+    );
+    while header.len() % HEADER_ALIGN != 0 {
+        header.push(' ');
+    }
+
+    let mut writer = BufWriter::new(writer);
+    writer.write_all(&(header.len() as u64).to_le_bytes())?;
+    writer.write_all(header.as_bytes())?;
+    for value in input.data().iter().chain(output.data().iter()) {
+        writer.write_all(&value.to_le_bytes())?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Write the vocabulary as one entry per line, words then labels, each as
+/// `token<TAB>count`. Line `i` of the words names row `i` of the `input`
+/// tensor (and of `output` for unsupervised models); line `n_words + j`
+/// names label `j`, row `j` of a classifier's `output`.
+pub fn write_vocabulary<W: Write>(writer: W, vocab: &Vocabulary) -> Result<()> {
+    let mut writer = BufWriter::new(writer);
+    for entry in vocab.words().iter() {
+        writeln!(writer, "{}\t{}", entry.word, entry.count)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Where `export` puts the vocabulary for a safetensors file at `path`:
+/// the same name with a `.vocab.txt` extension.
+pub fn vocabulary_path<P: AsRef<Path>>(path: P) -> PathBuf {
+    path.as_ref().with_extension("vocab.txt")
+}
+
+/// `write_safetensors` to `path` and `write_vocabulary` to
+/// `vocabulary_path(path)`.
+pub fn export<P: AsRef<Path>>(model: &Model, path: P) -> Result<()> {
+    let path = path.as_ref();
+    write_safetensors(File::create(path)?, model)?;
+    write_vocabulary(File::create(vocabulary_path(path))?, model.vocabulary())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vectors::tests::fixed;
+
+    #[test]
+    fn test_write_safetensors() {
+        let model = fixed(&["ab", "cd"], vec![1.0, 2.0, -3.0, 0.5]);
+        let mut bytes = Vec::new();
+        write_safetensors(&mut bytes, &model).unwrap();
+
+        let mut word = [0; 8];
+        word.copy_from_slice(&bytes[..8]);
+        let header_len = u64::from_le_bytes(word) as usize;
+        assert_eq!((8 + header_len) % HEADER_ALIGN, 0);
+        let header = std::str::from_utf8(&bytes[8..8 + header_len]).unwrap();
+        let (rows, dim) = (model.input().rows(), model.input().cols());
+        assert!(header.contains(&format!(
+            "\"input\":{{\"dtype\":\"F32\",\"shape\":[{},{}],\"data_offsets\":[0,{}]}}",
+            rows,
+            dim,
+            4 * rows * dim
+        )));
+        assert!(header.contains("\"n_words\":\"2\""));
+        assert!(header.contains("\"hash\":\"fnv32\""));
+
+        let data: Vec<f32> = bytes[8 + header_len..]
+            .chunks(4)
+            .map(|chunk| f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+            .collect();
+        let expected: Vec<f32> = model
+            .input()
+            .data()
+            .iter()
+            .chain(model.output().data().iter())
+            .copied()
+            .collect();
+        assert_eq!(data, expected);
+    }
+
+    #[test]
+    fn test_export() {
+        let mut model = fixed(&["ab", "cd"], vec![1.0, 2.0, -3.0, 0.5]);
+        let path = std::env::temp_dir().join(format!(
+            "rusttext-export-{}.safetensors",
+            std::process::id()
+        ));
+        model.export_safetensors(&path).unwrap();
+        let vocabulary = std::fs::read_to_string(vocabulary_path(&path)).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(vocabulary_path(&path)).unwrap();
+        let tokens: Vec<&str> = vocabulary
+            .lines()
+            .map(|line| line.split('\t').next().unwrap())
+            .collect();
+        assert_eq!(tokens, ["ab", "cd"]);
+
+        model.quantize_int8().unwrap();
+        assert!(matches!(
+            model.export_safetensors(&path),
+            Err(RustTextError::InvalidArgument(_))
+        ));
+    }
+}
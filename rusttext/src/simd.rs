@@ -0,0 +1,231 @@
+//! Vector kernels behind training, prediction and neighbour search: the dot
+//! product and `y += a * x`, from which norms, cosines and row means are
+//! built. On x86-64 CPUs with AVX2 and FMA, detected at run time, and on
+//! AArch64 they work on 8 and 4 lanes at a time with fused multiply-adds;
+//! elsewhere a portable version with independent accumulators, which the
+//! compiler vectorizes, is used.
+//!
+//! The vector versions add up in a different order than a plain loop, so
+//! results can differ from the portable ones in the last bits; a given
+//! machine always gives the same results.
+//!
+//! Like `Iterator::zip`, every kernel stops at the shorter of its inputs.
+
+/// Dot product of `left` and `right`.
+pub fn dot(left: &[f32], right: &[f32]) -> f32 {
+    let n = left.len().min(right.len());
+    let (left, right) = (&left[..n], &right[..n]);
+    #[cfg(target_arch = "x86_64")]
+    if x86::available() {
+        return unsafe { x86::dot(left, right) };
+    }
+    #[cfg(target_arch = "aarch64")]
+    return unsafe { neon::dot(left, right) };
+    #[cfg(not(target_arch = "aarch64"))]
+    portable::dot(left, right)
+}
+
+/// `y += a * x`.
+pub fn axpy(y: &mut [f32], a: f32, x: &[f32]) {
+    let n = y.len().min(x.len());
+    let (y, x) = (&mut y[..n], &x[..n]);
+    #[cfg(target_arch = "x86_64")]
+    if x86::available() {
+        return unsafe { x86::axpy(y, a, x) };
+    }
+    #[cfg(target_arch = "aarch64")]
+    return unsafe { neon::axpy(y, a, x) };
+    #[cfg(not(target_arch = "aarch64"))]
+    portable::axpy(y, a, x)
+}
+
+/// Euclidean norm of `x`.
+pub fn norm(x: &[f32]) -> f32 {
+    dot(x, x).sqrt()
+}
+
+/// Cosine similarity of two vectors, 0 if either is all zeros.
+pub fn cosine(left: &[f32], right: &[f32]) -> f32 {
+    let norms = norm(left) * norm(right);
+    if norms == 0.0 {
+        return 0.0;
+    }
+    dot(left, right) / norms
+}
+
+#[cfg(not(target_arch = "aarch64"))]
+mod portable {
+    // Eight running sums break the dependency between additions, which
+    // lets the compiler keep them in one vector register.
+    pub(super) fn dot(left: &[f32], right: &[f32]) -> f32 {
+        let mut sums = [0.0f32; 8];
+        let (left, right) = (left.chunks_exact(8), right.chunks_exact(8));
+        let tail: f32 = left
+            .remainder()
+            .iter()
+            .zip(right.remainder())
+            .map(|(l, r)| l * r)
+            .sum();
+        for (l, r) in left.zip(right) {
+            for ((sum, l), r) in sums.iter_mut().zip(l).zip(r) {
+                *sum += l * r;
+            }
+        }
+        sums.iter().sum::<f32>() + tail
+    }
+
+    pub(super) fn axpy(y: &mut [f32], a: f32, x: &[f32]) {
+        for (y, x) in y.iter_mut().zip(x) {
+            *y += a * x;
+        }
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod x86 {
+    use std::arch::x86_64::*;
+
+    // `is_x86_feature_detected!` caches what it finds, so this is a load.
+    pub(super) fn available() -> bool {
+        is_x86_feature_detected!("avx2") && is_x86_feature_detected!("fma")
+    }
+
+    #[target_feature(enable = "avx2,fma")]
+    unsafe fn sum(v: __m256) -> f32 {
+        let half = _mm_add_ps(_mm256_castps256_ps128(v), _mm256_extractf128_ps(v, 1));
+        let quarter = _mm_add_ps(half, _mm_movehl_ps(half, half));
+        _mm_cvtss_f32(_mm_add_ss(quarter, _mm_shuffle_ps(quarter, quarter, 1)))
+    }
+
+    /// # Safety
+    ///
+    /// The CPU has AVX2 and FMA, and `left` and `right` are as long.
+    #[target_feature(enable = "avx2,fma")]
+    pub(super) unsafe fn dot(left: &[f32], right: &[f32]) -> f32 {
+        let n = left.len();
+        let (l, r) = (left.as_ptr(), right.as_ptr());
+        // two accumulators hide the latency of the fused multiply-add
+        let (mut even, mut odd) = (_mm256_setzero_ps(), _mm256_setzero_ps());
+        let mut i = 0;
+        while i + 16 <= n {
+            even = _mm256_fmadd_ps(_mm256_loadu_ps(l.add(i)), _mm256_loadu_ps(r.add(i)), even);
+            odd = _mm256_fmadd_ps(
+                _mm256_loadu_ps(l.add(i + 8)),
+                _mm256_loadu_ps(r.add(i + 8)),
+                odd,
+            );
+            i += 16;
+        }
+        if i + 8 <= n {
+            even = _mm256_fmadd_ps(_mm256_loadu_ps(l.add(i)), _mm256_loadu_ps(r.add(i)), even);
+            i += 8;
+        }
+        let tail: f32 = left[i..].iter().zip(&right[i..]).map(|(l, r)| l * r).sum();
+        sum(_mm256_add_ps(even, odd)) + tail
+    }
+
+    /// # Safety
+    ///
+    /// The CPU has AVX2 and FMA, and `y` and `x` are as long.
+    #[target_feature(enable = "avx2,fma")]
+    pub(super) unsafe fn axpy(y: &mut [f32], a: f32, x: &[f32]) {
+        let n = y.len();
+        let scale = _mm256_set1_ps(a);
+        let mut i = 0;
+        while i + 8 <= n {
+            let out = y.as_mut_ptr().add(i);
+            let sum = _mm256_fmadd_ps(
+                scale,
+                _mm256_loadu_ps(x.as_ptr().add(i)),
+                _mm256_loadu_ps(out),
+            );
+            _mm256_storeu_ps(out, sum);
+            i += 8;
+        }
+        for (y, x) in y[i..].iter_mut().zip(&x[i..]) {
+            *y += a * x;
+        }
+    }
+}
+
+// NEON is part of every AArch64 target, so needs no detection.
+#[cfg(target_arch = "aarch64")]
+mod neon {
+    use std::arch::aarch64::*;
+
+    /// # Safety
+    ///
+    /// `left` and `right` are as long.
+    pub(super) unsafe fn dot(left: &[f32], right: &[f32]) -> f32 {
+        let n = left.len();
+        let (l, r) = (left.as_ptr(), right.as_ptr());
+        let (mut even, mut odd) = (vdupq_n_f32(0.0), vdupq_n_f32(0.0));
+        let mut i = 0;
+        while i + 8 <= n {
+            even = vfmaq_f32(even, vld1q_f32(l.add(i)), vld1q_f32(r.add(i)));
+            odd = vfmaq_f32(odd, vld1q_f32(l.add(i + 4)), vld1q_f32(r.add(i + 4)));
+            i += 8;
+        }
+        let tail: f32 = left[i..].iter().zip(&right[i..]).map(|(l, r)| l * r).sum();
+        vaddvq_f32(vaddq_f32(even, odd)) + tail
+    }
+
+    /// # Safety
+    ///
+    /// `y` and `x` are as long.
+    pub(super) unsafe fn axpy(y: &mut [f32], a: f32, x: &[f32]) {
+        let n = y.len();
+        let scale = vdupq_n_f32(a);
+        let mut i = 0;
+        while i + 4 <= n {
+            let out = y.as_mut_ptr().add(i);
+            vst1q_f32(
+                out,
+                vfmaq_f32(vld1q_f32(out), vld1q_f32(x.as_ptr().add(i)), scale),
+            );
+            i += 4;
+        }
+        for (y, x) in y[i..].iter_mut().zip(&x[i..]) {
+            *y += a * x;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(n: usize, seed: f32) -> Vec<f32> {
+        (0..n).map(|i| ((i as f32 + seed) * 0.37).sin()).collect()
+    }
+
+    #[test]
+    fn test_kernels() {
+        // every length around the 4-, 8- and 16-lane boundaries
+        for n in 0..40 {
+            let (x, y) = (values(n, 1.0), values(n, 2.0));
+            let expected: f32 = x.iter().zip(&y).map(|(x, y)| x * y).sum();
+            assert!((dot(&x, &y) - expected).abs() < 1e-5, "dot of {}", n);
+
+            let mut out = y.clone();
+            axpy(&mut out, 0.5, &x);
+            for ((out, x), y) in out.iter().zip(&x).zip(&y) {
+                assert!((out - (y + 0.5 * x)).abs() < 1e-6, "axpy of {}", n);
+            }
+        }
+        assert_eq!(dot(&[1.0, 2.0, 3.0], &[4.0, 5.0]), 14.0);
+        assert_eq!(norm(&[3.0, 4.0]), 5.0);
+        assert_eq!(cosine(&[1.0, 0.0], &[0.0, 0.0]), 0.0);
+        assert!((cosine(&values(300, 1.0), &values(300, 1.0)) - 1.0).abs() < 1e-6);
+    }
+
+    #[cfg(not(target_arch = "aarch64"))]
+    #[test]
+    fn test_portable() {
+        // the dispatching functions skip it on CPUs with AVX2
+        for n in 0..20 {
+            let (x, y) = (values(n, 1.0), values(n, 2.0));
+            assert!((portable::dot(&x, &y) - dot(&x, &y)).abs() < 1e-5);
+        }
+    }
+}
@@ -0,0 +1,1190 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::args::{Args, LossKind, WordModel};
+use crate::checkpoint;
+use crate::loss::{
+    HierarchicalSoftmax, Loss, NegativeSampler, NegativeSampling, OneVsAll, Softmax, State,
+};
+use crate::matrix::{Matrix, SharedMatrix};
+use crate::model::Model;
+use crate::random::Rng;
+use crate::vocabulary::Vocabulary;
+use crate::word::{self, EntryType};
+use crate::{io, Result, RustTextError};
+
+// Probability of keeping each word under fastText's frequent-word
+// sub-sampling: sqrt(t / f) + t / f for relative frequency f.
+fn keep_probs(vocab: &Vocabulary, t: f64) -> Vec<f32> {
+    let n_tokens = vocab.n_tokens() as f64;
+    vocab.words()[..vocab.n_words() as usize]
+        .iter()
+        .map(|entry| {
+            let f = f64::from(entry.count) / n_tokens;
+            ((t / f).sqrt() + t / f) as f32
+        })
+        .collect()
+}
+
+/// The output layer objective selected by `args.loss`; `counts` gives the
+/// frequency of each output row for sampling-based losses.
+pub fn build_loss(args: &Args, counts: &[u32], rng: &mut Rng) -> Result<Box<dyn Loss>> {
+    Ok(match args.loss {
+        LossKind::NegativeSampling => {
+            let sampler = NegativeSampler::new(counts, rng.next_u64())?;
+            Box::new(NegativeSampling::new(args.neg, sampler))
+        }
+        LossKind::Softmax => Box::new(Softmax {
+            label_smoothing: args.label_smoothing,
+        }),
+        LossKind::OneVsAll => Box::new(OneVsAll {
+            label_smoothing: args.label_smoothing,
+            ..Default::default()
+        }),
+        LossKind::HierarchicalSoftmax => Box::new(HierarchicalSoftmax::new(counts)),
+    })
+}
+
+// How an update's hidden gradient is applied to the input rows.
+#[derive(Clone, Copy, PartialEq)]
+enum InputUpdate {
+    Full,
+    // scaled by the number of features, as fastText does for classifiers
+    Normalized,
+    Frozen,
+}
+
+// One SGD step: average the input rows of `features` into the hidden vector,
+// let `loss` update the output rows, then push the hidden gradient back into
+// each input row.
+#[allow(clippy::too_many_arguments)]
+fn update(
+    input: &SharedMatrix,
+    output: &SharedMatrix,
+    loss: &mut dyn Loss,
+    features: &[u32],
+    targets: &[u32],
+    target_index: usize,
+    state: &mut State,
+    lr: f32,
+    input_update: InputUpdate,
+) -> f32 {
+    if features.is_empty() {
+        return 0.0;
+    }
+    input.mean_rows(features, &mut state.hidden);
+    state.grad.iter_mut().for_each(|value| *value = 0.0);
+
+    let value = loss.forward(output, targets, target_index, state, lr, true);
+    let scale = match input_update {
+        InputUpdate::Full => 1.0,
+        InputUpdate::Normalized => 1.0 / features.len() as f32,
+        InputUpdate::Frozen => return value,
+    };
+    for &feature in features.iter() {
+        input.add_row(&state.grad, feature as usize, scale);
+    }
+    value
+}
+
+// Per-thread training state: the thread's own loss instance, scratch
+// buffers and random generator, and the loss summed over its updates.
+struct Worker {
+    loss: Box<dyn Loss>,
+    state: State,
+    rng: Rng,
+    features: Vec<u32>,
+    ids: Vec<u32>,
+    loss_sum: f64,
+    n_examples: u64,
+}
+
+impl Worker {
+    fn average_loss(&self) -> f32 {
+        if self.n_examples == 0 {
+            return 0.0;
+        }
+        (self.loss_sum / self.n_examples as f64) as f32
+    }
+}
+
+/// A snapshot of training progress passed to a `TrainCallback`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Progress {
+    /// 1-based epoch the token counter is currently in.
+    pub epoch: u32,
+    /// Percentage of all training tokens processed, 0 to 100.
+    pub percent: f32,
+    pub lr: f32,
+    /// Mean loss per update so far (from the first thread while training,
+    /// over all threads in the final report).
+    pub loss: f32,
+    pub words_per_sec: f64,
+    /// Tokens processed so far, counted over all threads and including
+    /// those before a resumed checkpoint, out of `total_tokens`.
+    pub tokens: u64,
+    pub total_tokens: u64,
+    /// Time since this run started.
+    pub elapsed: Duration,
+    /// Time left at the current speed, once there is one.
+    pub eta: Option<Duration>,
+}
+
+/// How a training run went, kept by the model it trained; see
+/// `Model::training_stats`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrainingStats {
+    /// Tokens processed by the run, over all threads; a resumed run counts
+    /// only its own.
+    pub tokens: u64,
+    /// Time spent in the training threads.
+    pub elapsed: Duration,
+    pub words_per_sec: f64,
+    /// Mean loss per update over all threads.
+    pub loss: f32,
+}
+
+impl TrainingStats {
+    fn new(tokens: u64, elapsed: Duration, loss: f32) -> TrainingStats {
+        TrainingStats {
+            tokens,
+            elapsed,
+            words_per_sec: tokens as f64 / f64::max(elapsed.as_secs_f64(), 1e-9),
+            loss,
+        }
+    }
+
+    // The stats of this run followed by `next`, the loss weighted by tokens.
+    fn merge(&self, next: &TrainingStats) -> TrainingStats {
+        let tokens = self.tokens + next.tokens;
+        let loss = (f64::from(self.loss) * self.tokens as f64
+            + f64::from(next.loss) * next.tokens as f64)
+            / u64::max(tokens, 1) as f64;
+        TrainingStats::new(tokens, self.elapsed + next.elapsed, loss as f32)
+    }
+}
+
+/// Receives periodic `Progress` reports during training, at most every
+/// `REPORT_INTERVAL` and once more when training ends. Closures taking
+/// `&Progress` implement it.
+pub trait TrainCallback: Sync {
+    fn on_progress(&self, progress: &Progress);
+}
+
+impl<F: Fn(&Progress) + Sync> TrainCallback for F {
+    fn on_progress(&self, progress: &Progress) {
+        self(progress)
+    }
+}
+
+pub const REPORT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Where a training run reads from, how far it has already got and who to
+/// tell about progress.
+#[derive(Clone, Copy)]
+pub(crate) struct Job<'a> {
+    pub corpus: &'a Path,
+    pub supervised: bool,
+    /// Tokens processed before this run, when resuming from a checkpoint.
+    pub start: u64,
+    /// Byte offsets of the corpus lines filtered out of the vocabulary,
+    /// sorted; training skips them too.
+    pub skip: &'a [u64],
+    pub callback: &'a dyn TrainCallback,
+}
+
+// Run `args.threads` workers over `job.corpus`, each reading its share of
+// the lines over and over (see `io::CorpusLines`), until the token counter
+// reaches `end`. `step` gets each line's tokens, EOS appended, and the
+// learning rate. Threads publish their counts every `args.lr_update_rate`
+// tokens; whichever crosses a multiple of `args.checkpoint_interval`
+// writes a checkpoint, and the first reports progress to `job.callback`.
+// Both matrices are shared between all threads.
+#[allow(clippy::too_many_arguments)]
+fn hogwild<F>(
+    job: &Job,
+    end: u64,
+    args: &Args,
+    n_tokens: u64,
+    input: &mut Matrix,
+    output: &mut Matrix,
+    loss: &dyn Loss,
+    rng: &mut Rng,
+    step: &F,
+) -> Result<TrainingStats>
+where
+    F: Fn(&SharedMatrix, &SharedMatrix, &mut Worker, &[&str], f32) + Sync,
+{
+    let threads = usize::max(args.threads, 1);
+    trace_span!("train", supervised = job.supervised, threads);
+    let total_tokens = u64::from(args.epoch) * n_tokens;
+    let corpus = job.corpus;
+    let processed = AtomicU64::new(job.start);
+    let interval = u64::max(args.checkpoint_interval, 1);
+    let next_checkpoint = AtomicU64::new((job.start / interval + 1) * interval);
+    let (rows, dim) = (output.rows(), args.dim);
+    // a thread must publish its count before the end can be reached
+    let update_rate = u64::min(
+        args.lr_update_rate,
+        end.saturating_sub(job.start) / threads as u64,
+    );
+
+    let workers: Vec<Worker> = (0..threads)
+        .map(|_| Worker {
+            loss: loss.fork(rng.next_u64()),
+            state: State::new(dim, rows),
+            rng: Rng::new(rng.next_u64()),
+            features: Vec::new(),
+            ids: Vec::new(),
+            loss_sum: 0.0,
+            n_examples: 0,
+        })
+        .collect();
+
+    let started = Instant::now();
+    #[cfg(feature = "tracing")]
+    let epochs_done = AtomicU64::new(job.start / u64::max(n_tokens, 1));
+    let report = |done: u64, lr: f32, loss: f32| {
+        let elapsed = started.elapsed();
+        let epoch = done
+            .div_ceil(u64::max(n_tokens, 1))
+            .clamp(1, u64::from(args.epoch));
+        let words_per_sec =
+            done.saturating_sub(job.start) as f64 / f64::max(elapsed.as_secs_f64(), 1e-9);
+        let eta = Some(end.saturating_sub(done) as f64 / words_per_sec)
+            .filter(|secs| secs.is_finite())
+            .map(Duration::from_secs_f64);
+        job.callback.on_progress(&Progress {
+            epoch: epoch as u32,
+            percent: f32::min(100.0 * done as f32 / total_tokens as f32, 100.0),
+            lr,
+            loss,
+            words_per_sec,
+            tokens: done,
+            total_tokens,
+            elapsed,
+            eta,
+        });
+    };
+
+    let shared_input = SharedMatrix::new(input);
+    let shared_output = SharedMatrix::new(output);
+
+    let result = thread::scope(|scope| {
+        let handles: Vec<_> = workers
+            .into_iter()
+            .enumerate()
+            .map(|(i, mut worker)| {
+                let (input, output) = (&shared_input, &shared_output);
+                let (processed, next_checkpoint, step) = (&processed, &next_checkpoint, &step);
+                let report = &report;
+                #[cfg(feature = "tracing")]
+                let epochs_done = &epochs_done;
+                scope.spawn(move || -> Result<Worker> {
+                    let mut lines = io::CorpusLines::open(corpus, i, threads, job.skip)?;
+                    let mut line = String::new();
+
+                    let progress = job.start as f32 / total_tokens as f32;
+                    let mut lr = args.lr_schedule.lr(args.lr, progress);
+                    let mut local_tokens = 0;
+                    let mut last_report = Instant::now();
+                    while processed.load(Ordering::Relaxed) < end {
+                        if lines.read_line(&mut line)? == 0 {
+                            continue;
+                        }
+                        let mut tokens: Vec<&str> = line.split_whitespace().collect();
+                        tokens.push(word::EOS);
+
+                        local_tokens += tokens.len() as u64;
+                        if local_tokens > update_rate {
+                            let done = processed.fetch_add(local_tokens, Ordering::Relaxed);
+                            #[cfg(feature = "tracing")]
+                            let local = local_tokens;
+                            local_tokens = 0;
+                            let progress = done as f32 / total_tokens as f32;
+                            lr = args.lr_schedule.lr(args.lr, progress);
+                            #[cfg(feature = "tracing")]
+                            {
+                                let epoch = (done + local) / u64::max(n_tokens, 1);
+                                let logged = epochs_done.load(Ordering::Relaxed);
+                                if epoch > logged
+                                    && epochs_done
+                                        .compare_exchange(
+                                            logged,
+                                            epoch,
+                                            Ordering::Relaxed,
+                                            Ordering::Relaxed,
+                                        )
+                                        .is_ok()
+                                {
+                                    tracing::info!(
+                                        epoch,
+                                        elapsed_secs = started.elapsed().as_secs_f64(),
+                                        loss = worker.average_loss(),
+                                        "finished epoch"
+                                    );
+                                }
+                            }
+                            if i == 0 && last_report.elapsed() >= REPORT_INTERVAL {
+                                report(done, lr, worker.average_loss());
+                                last_report = Instant::now();
+                            }
+
+                            if let Some(path) = &args.checkpoint {
+                                let due = next_checkpoint.load(Ordering::Relaxed);
+                                if done >= due
+                                    && next_checkpoint
+                                        .compare_exchange(
+                                            due,
+                                            (done / interval + 1) * interval,
+                                            Ordering::Relaxed,
+                                            Ordering::Relaxed,
+                                        )
+                                        .is_ok()
+                                {
+                                    checkpoint::write(
+                                        path,
+                                        args,
+                                        corpus,
+                                        job.supervised,
+                                        u64::min(done, end),
+                                        input,
+                                        output,
+                                    )?;
+                                    trace_info!(
+                                        path = %path.display(),
+                                        processed = done,
+                                        "wrote checkpoint"
+                                    );
+                                }
+                            }
+                        }
+                        step(input, output, &mut worker, &tokens, lr);
+                    }
+                    Ok(worker)
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| {
+                handle.join().unwrap_or_else(|_| {
+                    Err(RustTextError::Training(String::from(
+                        "a worker thread panicked",
+                    )))
+                })
+            })
+            .collect::<Result<Vec<Worker>>>()
+    });
+    let stats = result.map(|workers| {
+        let loss_sum: f64 = workers.iter().map(|worker| worker.loss_sum).sum();
+        let n_examples: u64 = workers.iter().map(|worker| worker.n_examples).sum();
+        let loss = (loss_sum / u64::max(n_examples, 1) as f64) as f32;
+        let done = u64::min(processed.load(Ordering::Relaxed), end);
+        report(
+            done,
+            args.lr_schedule
+                .lr(args.lr, done as f32 / total_tokens as f32),
+            loss,
+        );
+        trace_info!(
+            tokens = processed.load(Ordering::Relaxed).saturating_sub(job.start),
+            elapsed_secs = started.elapsed().as_secs_f64(),
+            loss,
+            "finished training"
+        );
+        TrainingStats::new(done.saturating_sub(job.start), started.elapsed(), loss)
+    });
+    stats
+}
+
+/// Train `model` in place with the objective `args.word_model` selects.
+pub(crate) fn unsupervised(model: &mut Model, job: &Job, rng: &mut Rng) -> Result<TrainingStats> {
+    match model.args.word_model {
+        WordModel::Skipgram => skipgram(model, job, rng),
+        WordModel::Cbow => cbow(model, job, rng),
+    }
+}
+
+// Ids of the words of `tokens` kept by frequent-word sub-sampling.
+fn subsample(vocab: &Vocabulary, tokens: &[&str], keep: &[f32], rng: &mut Rng, ids: &mut Vec<u32>) {
+    ids.clear();
+    for token in tokens.iter() {
+        if let Some(id) = vocab.get_id(token) {
+            let entry = &vocab.words()[id as usize];
+            if entry.entry_type == EntryType::Word && rng.next_f32() <= keep[id as usize] {
+                ids.push(id);
+            }
+        }
+    }
+}
+
+/// Train `model` in place with skipgram: every word predicts each word in a
+/// random window of up to `ws` around it, the word being represented by the
+/// mean of its own and its subword input rows.
+pub(crate) fn skipgram(model: &mut Model, job: &Job, rng: &mut Rng) -> Result<TrainingStats> {
+    let Model {
+        args,
+        vocab,
+        input,
+        output,
+        ..
+    } = model;
+    let counts: Vec<u32> = vocab.words()[..vocab.n_words() as usize]
+        .iter()
+        .map(|entry| entry.count)
+        .collect();
+    let loss = build_loss(args, &counts, rng)?;
+    let keep = keep_probs(vocab, args.t);
+    let vocab = &*vocab;
+    let n_tokens = vocab.n_tokens();
+    let end = u64::from(args.epoch) * n_tokens;
+    hogwild(
+        job,
+        end,
+        args,
+        n_tokens,
+        input,
+        output,
+        loss.as_ref(),
+        rng,
+        &|input, output, worker, tokens, lr| {
+            let Worker {
+                loss,
+                state,
+                rng,
+                features,
+                ids,
+                loss_sum,
+                n_examples,
+            } = worker;
+
+            subsample(vocab, tokens, &keep, rng, ids);
+            for (w, &center) in ids.iter().enumerate() {
+                features.clear();
+                vocab.word_features(center, features);
+
+                let boundary = 1 + rng.below(args.ws);
+                let start = w.saturating_sub(boundary);
+                let end = usize::min(w + boundary + 1, ids.len());
+                for (c, &context) in ids.iter().enumerate().take(end).skip(start) {
+                    if c != w {
+                        *loss_sum += f64::from(update(
+                            input,
+                            output,
+                            loss.as_mut(),
+                            features,
+                            &[context],
+                            0,
+                            state,
+                            lr,
+                            InputUpdate::Full,
+                        ));
+                        *n_examples += 1;
+                    }
+                }
+            }
+        },
+    )
+}
+
+/// Train `model` in place with CBOW: the mean of the input rows (own and
+/// subword) of every word in a random window of up to `ws` around each word
+/// predicts that word.
+pub(crate) fn cbow(model: &mut Model, job: &Job, rng: &mut Rng) -> Result<TrainingStats> {
+    let Model {
+        args,
+        vocab,
+        input,
+        output,
+        ..
+    } = model;
+    let counts: Vec<u32> = vocab.words()[..vocab.n_words() as usize]
+        .iter()
+        .map(|entry| entry.count)
+        .collect();
+    let loss = build_loss(args, &counts, rng)?;
+    let keep = keep_probs(vocab, args.t);
+    let vocab = &*vocab;
+    let n_tokens = vocab.n_tokens();
+    let end = u64::from(args.epoch) * n_tokens;
+    hogwild(
+        job,
+        end,
+        args,
+        n_tokens,
+        input,
+        output,
+        loss.as_ref(),
+        rng,
+        &|input, output, worker, tokens, lr| {
+            let Worker {
+                loss,
+                state,
+                rng,
+                features,
+                ids,
+                loss_sum,
+                n_examples,
+            } = worker;
+
+            subsample(vocab, tokens, &keep, rng, ids);
+            for (w, &center) in ids.iter().enumerate() {
+                let boundary = 1 + rng.below(args.ws);
+                let start = w.saturating_sub(boundary);
+                let end = usize::min(w + boundary + 1, ids.len());
+                features.clear();
+                for (c, &context) in ids.iter().enumerate().take(end).skip(start) {
+                    if c != w {
+                        vocab.word_features(context, features);
+                    }
+                }
+                if features.is_empty() {
+                    continue;
+                }
+                *loss_sum += f64::from(update(
+                    input,
+                    output,
+                    loss.as_mut(),
+                    features,
+                    &[center],
+                    0,
+                    state,
+                    lr,
+                    InputUpdate::Full,
+                ));
+                *n_examples += 1;
+            }
+        },
+    )
+}
+
+/// Train `model` in place as a classifier: each line's words and word
+/// n-grams predict one of its labels, picked at random when there are
+/// several (one-vs-all trains against all of them at once).
+///
+/// Updates are scaled by the class weight of the target label (of each
+/// positive label, with one-vs-all). With `args.freeze_embeddings` only
+/// the output matrix learns. With `args.validation_file`, each epoch is
+/// scored on it (see `validation_score`); training stops after
+/// `args.patience` epochs without improvement and keeps the best matrices.
+pub(crate) fn supervised(model: &mut Model, job: &Job, rng: &mut Rng) -> Result<TrainingStats> {
+    let Model {
+        args,
+        vocab,
+        input,
+        output,
+        ..
+    } = model;
+    let counts: Vec<u32> = vocab.words()[vocab.n_words() as usize..]
+        .iter()
+        .map(|entry| entry.count)
+        .collect();
+    let weights = args.class_weights.resolve(vocab)?;
+    let loss: Box<dyn Loss> = match args.loss {
+        // weighs each label's own term rather than the whole example
+        LossKind::OneVsAll => Box::new(OneVsAll {
+            label_smoothing: args.label_smoothing,
+            class_weights: weights.iter().copied().collect(),
+        }),
+        _ => build_loss(args, &counts, rng)?,
+    };
+    let per_label = args.loss == LossKind::OneVsAll;
+    let input_update = if args.freeze_embeddings {
+        InputUpdate::Frozen
+    } else {
+        InputUpdate::Normalized
+    };
+    let vocab = &*vocab;
+    let n_tokens = vocab.n_tokens();
+    let total_tokens = u64::from(args.epoch) * n_tokens;
+
+    let step =
+        |input: &SharedMatrix, output: &SharedMatrix, worker: &mut Worker, tokens: &[&str], lr| {
+            let labels = vocab.line_labels(tokens);
+            if labels.is_empty() {
+                return;
+            }
+            let features = vocab.line_features(tokens);
+            let target_index = worker.rng.below(labels.len());
+            let weight = if per_label {
+                1.0
+            } else {
+                weights[labels[target_index] as usize]
+            };
+            worker.loss_sum += f64::from(update(
+                input,
+                output,
+                worker.loss.as_mut(),
+                &features,
+                &labels,
+                target_index,
+                &mut worker.state,
+                lr * weight,
+                input_update,
+            ));
+            worker.n_examples += 1;
+        };
+
+    let validation_file = match &args.validation_file {
+        Some(path) => path,
+        None => {
+            return hogwild(
+                job,
+                total_tokens,
+                args,
+                n_tokens,
+                input,
+                output,
+                loss.as_ref(),
+                rng,
+                &step,
+            );
+        }
+    };
+    let validation = read_examples(vocab, validation_file)?;
+    let mut best: Option<(f32, Matrix, Matrix)> = None;
+    let mut epochs_since_best = 0;
+    let mut stats: Option<TrainingStats> = None;
+
+    let mut start = job.start;
+    while start < total_tokens {
+        let end = u64::min((start / n_tokens + 1) * n_tokens, total_tokens);
+        let epoch_job = Job { start, ..*job };
+        let epoch_stats = hogwild(
+            &epoch_job,
+            end,
+            args,
+            n_tokens,
+            input,
+            output,
+            loss.as_ref(),
+            rng,
+            &step,
+        )?;
+        stats = Some(match stats {
+            Some(stats) => stats.merge(&epoch_stats),
+            None => epoch_stats,
+        });
+        start = end;
+
+        let score = validation_score(input, output, loss.as_ref(), &validation);
+        trace_info!(epoch = end / n_tokens, score, "validation");
+        match &best {
+            Some((best_score, _, _)) if score <= *best_score => {
+                epochs_since_best += 1;
+                if args.patience > 0 && epochs_since_best >= args.patience {
+                    break;
+                }
+            }
+            _ => {
+                best = Some((score, input.clone(), output.clone()));
+                epochs_since_best = 0;
+            }
+        }
+    }
+
+    if let Some((_, best_input, best_output)) = best {
+        *input = best_input;
+        *output = best_output;
+    }
+    Ok(stats.unwrap_or_else(|| TrainingStats::new(0, Duration::ZERO, 0.0)))
+}
+
+// Features and label indices of every labelled line of `path`.
+pub(crate) fn read_examples(vocab: &Vocabulary, path: &Path) -> Result<Vec<(Vec<u32>, Vec<u32>)>> {
+    let mut examples = Vec::new();
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        let mut tokens: Vec<&str> = line.split_whitespace().collect();
+        tokens.push(word::EOS);
+        let labels = vocab.line_labels(&tokens);
+        if !labels.is_empty() {
+            examples.push((vocab.line_features(&tokens), labels));
+        }
+    }
+    Ok(examples)
+}
+
+/// Fraction of `examples` whose highest-scoring label is one of their
+/// labels (precision at one).
+pub(crate) fn validation_score(
+    input: &Matrix,
+    output: &Matrix,
+    loss: &dyn Loss,
+    examples: &[(Vec<u32>, Vec<u32>)],
+) -> f32 {
+    if examples.is_empty() {
+        return 0.0;
+    }
+    let mut state = State::new(input.cols(), output.rows());
+    let mut correct = 0;
+    for (features, labels) in examples.iter() {
+        let top = top_k(input, output, loss, features, 1, &mut state);
+        if top.first().is_some_and(|id| labels.contains(id)) {
+            correct += 1;
+        }
+    }
+    correct as f32 / examples.len() as f32
+}
+
+/// Indices of the `k` highest-scoring outputs for `features`, best first.
+pub(crate) fn top_k(
+    input: &Matrix,
+    output: &Matrix,
+    loss: &dyn Loss,
+    features: &[u32],
+    k: usize,
+    state: &mut State,
+) -> Vec<u32> {
+    input.mean_rows(features, &mut state.hidden);
+    loss.compute_output(output, state);
+    let mut indices: Vec<u32> = (0..state.output.len() as u32).collect();
+    indices.sort_by(|&a, &b| state.output[b as usize].total_cmp(&state.output[a as usize]));
+    indices.truncate(k);
+    indices
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args::ClassWeights;
+    use crate::io::tests::corpus_factory;
+
+    fn cosine(left: &[f32], right: &[f32]) -> f32 {
+        let dot: f32 = left.iter().zip(right.iter()).map(|(l, r)| l * r).sum();
+        let norm = |v: &[f32]| v.iter().map(|x| x * x).sum::<f32>().sqrt();
+        dot / (norm(left) * norm(right))
+    }
+
+    #[test]
+    fn test_skipgram() {
+        let mut text = String::new();
+        for _ in 0..200 {
+            text.push_str("apple banana cherry\nxray yankee zulu\n");
+        }
+        let corpus = corpus_factory(&text);
+        let args = Args {
+            dim: 10,
+            epoch: 5,
+            min_count: 1,
+            min_n: 0,
+            max_n: 0,
+            t: 1.0,
+            vocab_size: 101,
+            ..Args::default()
+        };
+
+        let model = Model::train_unsupervised(corpus.path(), args).unwrap();
+
+        let vector = |word: &str| {
+            let id = model.vocabulary().get_id(word).unwrap();
+            model.input().row(id as usize).to_vec()
+        };
+        let same = cosine(&vector("apple"), &vector("banana"));
+        let other = cosine(&vector("apple"), &vector("yankee"));
+        assert!(same > other, "{} <= {}", same, other);
+    }
+
+    #[test]
+    fn test_cbow() {
+        let mut text = String::new();
+        for _ in 0..200 {
+            text.push_str("apple banana cherry\napple kiwi cherry\n");
+            text.push_str("xray yankee zulu\nxray quebec zulu\n");
+        }
+        let corpus = corpus_factory(&text);
+        let args = Args {
+            dim: 10,
+            epoch: 10,
+            min_count: 1,
+            min_n: 0,
+            max_n: 0,
+            t: 1.0,
+            vocab_size: 101,
+            word_model: WordModel::Cbow,
+            ..Args::default()
+        };
+
+        let model = Model::train_unsupervised(corpus.path(), args).unwrap();
+
+        let vector = |word: &str| {
+            let id = model.vocabulary().get_id(word).unwrap();
+            model.input().row(id as usize).to_vec()
+        };
+        // words seen in the same contexts get similar vectors
+        let same = cosine(&vector("banana"), &vector("kiwi"));
+        let other = cosine(&vector("banana"), &vector("yankee"));
+        assert!(same > other, "{} <= {}", same, other);
+    }
+
+    #[test]
+    fn test_seeded_training_is_deterministic() {
+        let mut text = String::new();
+        for i in 0..50 {
+            text.push_str(&format!("__label__{} one two w{}\n", i % 3, i % 7));
+        }
+        let corpus = corpus_factory(&text);
+        let args = |seed| Args {
+            dim: 8,
+            vocab_size: 101,
+            threads: 1,
+            seed,
+            ..Args::supervised()
+        };
+
+        let first = Model::train_supervised(corpus.path(), args(42)).unwrap();
+        let second = Model::train_supervised(corpus.path(), args(42)).unwrap();
+        let other = Model::train_supervised(corpus.path(), args(7)).unwrap();
+
+        assert_eq!(first.input().data(), second.input().data());
+        assert_eq!(first.output().data(), second.output().data());
+        assert_ne!(first.input().data(), other.input().data());
+    }
+
+    #[test]
+    fn test_filtered_training() {
+        use crate::loader::{DedupFilter, DedupMode, Filters};
+        use std::sync::Arc;
+
+        let (mut unique, mut copied) = (String::new(), String::new());
+        for i in 0..60 {
+            let line = format!("__label__{} one two w{} x{}\n", i % 3, i % 7, i);
+            unique.push_str(&line);
+            copied.push_str(&line.repeat(1 + i % 3));
+        }
+        copied.push_str(&unique);
+        let unique_corpus = corpus_factory(&unique);
+        let copied_corpus = corpus_factory(&copied);
+        let args = Args {
+            dim: 8,
+            vocab_size: 1001,
+            threads: 1,
+            ..Args::supervised()
+        };
+
+        let dedup = Arc::new(DedupFilter::new(DedupMode::Exact, 1).unwrap());
+        let mut filters = Filters::new();
+        filters.push(Arc::clone(&dedup));
+        let filtered =
+            Model::train_supervised_filtered(copied_corpus.path(), args.clone(), &filters);
+        let checkpointed = Model::train_supervised_filtered(
+            copied_corpus.path(),
+            Args {
+                checkpoint: Some(std::env::temp_dir().join("rusttext-filtered.ckpt")),
+                ..args.clone()
+            },
+            &filters,
+        );
+        assert!(matches!(
+            checkpointed,
+            Err(RustTextError::InvalidArgument(_))
+        ));
+        let expected = Model::train_supervised(unique_corpus.path(), args);
+        let (filtered, expected) = (filtered.unwrap(), expected.unwrap());
+
+        assert_eq!(dedup.dropped(), 60 + 60);
+        assert_eq!(filtered.vocabulary().words(), expected.vocabulary().words());
+        let tokens = |model: &Model| model.training_stats().unwrap().tokens;
+        assert_eq!(tokens(&filtered), tokens(&expected));
+        // the same lines in the same order train the same model
+        assert_eq!(filtered.input().data(), expected.input().data());
+        assert_eq!(filtered.output().data(), expected.output().data());
+    }
+
+    #[test]
+    fn test_checkpoint_and_resume() {
+        let mut text = String::new();
+        for i in 0..100 {
+            text.push_str(&format!("__label__{} one two w{}\n", i % 3, i % 7));
+        }
+        let corpus = corpus_factory(&text);
+        let checkpoint =
+            std::env::temp_dir().join(format!("rusttext-resume-{}.ckpt", std::process::id()));
+        let args = Args {
+            dim: 8,
+            epoch: 2,
+            vocab_size: 101,
+            threads: 1,
+            checkpoint: Some(checkpoint.clone()),
+            checkpoint_interval: 500,
+            ..Args::supervised()
+        };
+
+        let model = Model::train_supervised(corpus.path(), args).unwrap();
+        let saved = crate::checkpoint::Checkpoint::load(&checkpoint).unwrap();
+        let total = 2 * model.vocabulary().n_tokens();
+        assert!(saved.supervised);
+        assert!(saved.processed >= 500 && saved.processed < total);
+        assert_eq!(saved.corpus, corpus.path());
+
+        let resumed = Model::resume(&checkpoint).unwrap();
+        std::fs::remove_file(&checkpoint).unwrap();
+
+        assert_eq!(resumed.input().rows(), model.input().rows());
+        assert_eq!(resumed.output().rows(), model.output().rows());
+        assert_ne!(resumed.input().data(), saved.input.data());
+    }
+
+    #[test]
+    fn test_progress_callback() {
+        let mut text = String::new();
+        for i in 0..100 {
+            text.push_str(&format!("__label__{} one two w{}\n", i % 3, i % 7));
+        }
+        let corpus = corpus_factory(&text);
+        let args = Args {
+            dim: 8,
+            epoch: 3,
+            vocab_size: 101,
+            threads: 2,
+            ..Args::supervised()
+        };
+        let reports = std::sync::Mutex::new(Vec::new());
+
+        let callback = |progress: &Progress| reports.lock().unwrap().push(progress.clone());
+        let model = Model::train_supervised_with(corpus.path(), args, &callback).unwrap();
+
+        let reports = reports.into_inner().unwrap();
+        let last = reports.last().unwrap();
+        assert_eq!(last.epoch, 3);
+        assert_eq!(last.percent, 100.0);
+        assert!(last.loss > 0.0 && last.loss.is_finite());
+        assert!(last.words_per_sec > 0.0);
+        assert_eq!(last.total_tokens, 3 * model.vocabulary().n_tokens());
+        assert_eq!(last.tokens, last.total_tokens);
+        assert_eq!(last.eta, Some(Duration::ZERO));
+
+        let stats = model.training_stats().unwrap();
+        assert_eq!(stats.tokens, last.tokens);
+        assert_eq!(stats.loss, last.loss);
+        assert!(stats.words_per_sec > 0.0 && stats.elapsed > Duration::ZERO);
+    }
+
+    #[test]
+    fn test_early_stopping() {
+        let mut text = String::new();
+        for _ in 0..100 {
+            text.push_str("__label__fruit apple banana cherry\n");
+            text.push_str("__label__letter xray yankee zulu\n");
+        }
+        let corpus = corpus_factory(&text);
+        // labels swapped, so validation accuracy can only get worse
+        let validation =
+            corpus_factory("__label__letter apple banana\n__label__fruit yankee zulu\n");
+        let args = Args {
+            dim: 10,
+            epoch: 10,
+            vocab_size: 101,
+            threads: 1,
+            validation_file: Some(validation.path().to_path_buf()),
+            patience: 2,
+            ..Args::supervised()
+        };
+        let reports = std::sync::Mutex::new(Vec::new());
+
+        let callback = |progress: &Progress| reports.lock().unwrap().push(progress.clone());
+        Model::train_supervised_with(corpus.path(), args, &callback).unwrap();
+
+        let last = reports.into_inner().unwrap().pop().unwrap();
+        assert_eq!(last.epoch, 3);
+        assert!(last.percent <= 30.0, "{}", last.percent);
+    }
+
+    #[test]
+    fn test_class_weights() {
+        let mut text = String::new();
+        for i in 0..100 {
+            let label = if i % 20 == 0 { "minor" } else { "major" };
+            text.push_str(&format!("__label__{} shared\n", label));
+        }
+        let corpus = corpus_factory(&text);
+        let minor_probability = |loss, class_weights| {
+            let args = Args {
+                dim: 4,
+                epoch: 20,
+                vocab_size: 101,
+                threads: 1,
+                loss,
+                class_weights,
+                ..Args::supervised()
+            };
+            let model = Model::train_supervised(corpus.path(), args).unwrap();
+            let vocab = model.vocabulary();
+            let mut state = State::new(4, 2);
+            let features = vocab.line_features(&["shared", word::EOS]);
+            model.input().mean_rows(&features, &mut state.hidden);
+            match loss {
+                LossKind::OneVsAll => {
+                    OneVsAll::default().compute_output(model.output(), &mut state)
+                }
+                _ => Softmax::default().compute_output(model.output(), &mut state),
+            }
+            state.output[vocab.line_labels(&["__label__minor"])[0] as usize]
+        };
+
+        for &loss in [LossKind::Softmax, LossKind::OneVsAll].iter() {
+            let uniform = minor_probability(loss, ClassWeights::Uniform);
+            let auto = minor_probability(loss, ClassWeights::Auto);
+            assert!(uniform < 0.2, "{:?} {}", loss, uniform);
+            assert!(auto > 0.3, "{:?} {}", loss, auto);
+        }
+    }
+
+    #[test]
+    fn test_pretrained_vectors() {
+        let corpus = corpus_factory("__label__a apple banana\n__label__b zulu\n");
+        let vectors = corpus_factory("2 3\napple 1 2 3\nunseen 4 5 6\n");
+        let args = |dim| Args {
+            dim,
+            lr: 0.0,
+            vocab_size: 101,
+            bucket: 10,
+            pretrained_vectors: Some(vectors.path().to_path_buf()),
+            ..Args::supervised()
+        };
+
+        let model = Model::train_supervised(corpus.path(), args(3)).unwrap();
+        let mismatch = Model::train_supervised(corpus.path(), args(4));
+
+        let apple = model.vocabulary().get_id("apple").unwrap();
+        assert_eq!(model.input().row(apple as usize), &[1.0, 2.0, 3.0]);
+        assert!(mismatch.is_err());
+    }
+
+    #[test]
+    fn test_freeze_embeddings() {
+        let corpus = corpus_factory("__label__a apple banana\n__label__b zulu\n");
+        let args = |freeze_embeddings| Args {
+            dim: 4,
+            vocab_size: 101,
+            bucket: 10,
+            threads: 1,
+            freeze_embeddings,
+            ..Args::supervised()
+        };
+
+        let frozen = Model::train_supervised(corpus.path(), args(true)).unwrap();
+        let trained = Model::train_supervised(corpus.path(), args(false)).unwrap();
+
+        let mut initial = Matrix::new(frozen.input().rows(), 4);
+        initial.uniform(1.0 / 4.0, &mut Rng::new(0));
+        assert_eq!(frozen.input().data(), initial.data());
+        assert_ne!(trained.input().data(), initial.data());
+        assert_ne!(frozen.output().data(), Matrix::new(2, 4).data());
+    }
+
+    #[test]
+    fn test_supervised_softmax() {
+        let mut text = String::new();
+        for _ in 0..100 {
+            text.push_str("__label__fruit apple banana cherry\n");
+            text.push_str("__label__letter xray yankee zulu\n");
+        }
+        let corpus = corpus_factory(&text);
+        let args = Args {
+            dim: 10,
+            vocab_size: 101,
+            ..Args::supervised()
+        };
+
+        let model = Model::train_supervised(corpus.path(), args).unwrap();
+        assert_eq!(model.output().rows(), 2);
+
+        let vocab = model.vocabulary();
+        let fruit = vocab.line_labels(&["__label__fruit"])[0] as usize;
+        let mut state = State::new(10, 2);
+        let features = vocab.line_features(&["banana", "cherry"]);
+        for &feature in features.iter() {
+            let scale = 1.0 / features.len() as f32;
+            model
+                .input()
+                .add_row_to(&mut state.hidden, feature as usize, scale);
+        }
+        Softmax::default().compute_output(model.output(), &mut state);
+        assert!(state.output[fruit] > 0.9, "{:?}", state.output);
+    }
+
+    #[test]
+    fn test_supervised_one_vs_all() {
+        let mut text = String::new();
+        for _ in 0..100 {
+            text.push_str("__label__red __label__round apple cherry\n");
+            text.push_str("__label__yellow banana lemon\n");
+        }
+        let corpus = corpus_factory(&text);
+        let args = Args {
+            dim: 10,
+            vocab_size: 101,
+            loss: LossKind::OneVsAll,
+            ..Args::supervised()
+        };
+
+        let model = Model::train_supervised(corpus.path(), args).unwrap();
+
+        let vocab = model.vocabulary();
+        let mut state = State::new(10, 3);
+        let features = vocab.line_features(&["apple"]);
+        for &feature in features.iter() {
+            let scale = 1.0 / features.len() as f32;
+            model
+                .input()
+                .add_row_to(&mut state.hidden, feature as usize, scale);
+        }
+        OneVsAll::default().compute_output(model.output(), &mut state);
+        let probability = |label: &str| state.output[vocab.line_labels(&[label])[0] as usize];
+        assert!(probability("__label__red") > 0.5);
+        assert!(probability("__label__round") > 0.5);
+        assert!(probability("__label__yellow") < 0.5);
+    }
+
+    #[test]
+    fn test_prune_and_retrain() {
+        let mut text = String::new();
+        for _ in 0..100 {
+            text.push_str("__label__fruit apple banana cherry\n");
+            text.push_str("__label__letter xray yankee zulu\n");
+        }
+        let corpus = corpus_factory(&text);
+        let args = Args {
+            dim: 10,
+            vocab_size: 101,
+            word_ngrams: 2,
+            bucket: 1000,
+            threads: 1,
+            ..Args::supervised()
+        };
+
+        let mut model = Model::train_supervised(corpus.path(), args).unwrap();
+        model.prune(5).unwrap();
+        assert_eq!(model.input().rows(), 5);
+        assert_eq!(model.vocabulary().n_input_rows(), 5);
+        assert_eq!(model.vocabulary().n_labels(), 2);
+        assert!(model.vocabulary().get_id(word::EOS).is_some());
+
+        model.retrain(corpus.path(), 2).unwrap();
+        assert_eq!(model.args().epoch, 5);
+        assert_eq!(model.input().rows(), 5);
+        let examples = vec![
+            (
+                model.vocabulary().line_features(&["apple", "banana"]),
+                model.vocabulary().line_labels(&["__label__fruit"]),
+            ),
+            (
+                model.vocabulary().line_features(&["yankee", "zulu"]),
+                model.vocabulary().line_labels(&["__label__letter"]),
+            ),
+        ];
+        let score = validation_score(
+            model.input(),
+            model.output(),
+            &Softmax::default(),
+            &examples,
+        );
+        assert_eq!(score, 1.0);
+    }
+}
@@ -0,0 +1,156 @@
+use std::sync::OnceLock;
+
+const SIGMOID_TABLE_SIZE: usize = 512;
+const MAX_SIGMOID: f32 = 8.0;
+const LOG_TABLE_SIZE: usize = 512;
+
+// Both tables are filled once in f64 and rounded, so every lookup returns the
+// same bits regardless of how often or from which thread it is called.
+fn sigmoid_table() -> &'static [f32] {
+    static TABLE: OnceLock<Vec<f32>> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        (0..=SIGMOID_TABLE_SIZE)
+            .map(|i| {
+                let x = (i as f64 * 2.0 * f64::from(MAX_SIGMOID)) / SIGMOID_TABLE_SIZE as f64
+                    - f64::from(MAX_SIGMOID);
+                (1.0 / (1.0 + (-x).exp())) as f32
+            })
+            .collect()
+    })
+}
+
+fn log_table() -> &'static [f32] {
+    static TABLE: OnceLock<Vec<f32>> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        (0..=LOG_TABLE_SIZE)
+            .map(|i| ((i as f64 + 1e-5) / LOG_TABLE_SIZE as f64).ln() as f32)
+            .collect()
+    })
+}
+
+/// Table-driven logistic function, as in fastText: exact to about 1e-2 and
+/// saturating to 0 or 1 outside `[-8, 8]`.
+pub fn sigmoid(x: f32) -> f32 {
+    if x < -MAX_SIGMOID {
+        0.0
+    } else if x > MAX_SIGMOID {
+        1.0
+    } else {
+        let i = ((x + MAX_SIGMOID) * SIGMOID_TABLE_SIZE as f32 / MAX_SIGMOID / 2.0) as usize;
+        sigmoid_table()[i]
+    }
+}
+
+/// Table-driven natural log for probabilities in `[0, 1]`; inputs above 1
+/// give 0 and 0 gives a large but finite negative value.
+pub fn log(x: f32) -> f32 {
+    if x > 1.0 {
+        return 0.0;
+    }
+    let i = (x * LOG_TABLE_SIZE as f32) as usize;
+    log_table()[i]
+}
+
+// Sweeps of one-sided Jacobi rotations before giving up on convergence;
+// a few dozen suffice in practice.
+/// `text` as a quoted JSON string, with quotes, backslashes and control
+/// characters escaped.
+pub(crate) fn json_string(text: &str) -> String {
+    let mut out = String::with_capacity(text.len() + 2);
+    out.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+const MAX_SWEEPS: usize = 60;
+
+// Singular value decomposition `a = U S V^T` of a square row-major matrix
+// by one-sided Jacobi rotations, returning `U S` (columns scaled by the
+// singular values) and `V`, both row-major.
+pub(crate) fn jacobi_svd(mut a: Vec<f64>, n: usize) -> (Vec<f64>, Vec<f64>) {
+    let mut v = vec![0.0; n * n];
+    for i in 0..n {
+        v[i * n + i] = 1.0;
+    }
+    for _ in 0..MAX_SWEEPS {
+        let mut rotated = false;
+        for p in 0..n {
+            for q in p + 1..n {
+                let (mut alpha, mut beta, mut gamma) = (0.0, 0.0, 0.0);
+                for i in 0..n {
+                    let (x, y) = (a[i * n + p], a[i * n + q]);
+                    alpha += x * x;
+                    beta += y * y;
+                    gamma += x * y;
+                }
+                if gamma.abs() <= 1e-12 * (alpha * beta).sqrt() {
+                    continue;
+                }
+                rotated = true;
+                let zeta = (beta - alpha) / (2.0 * gamma);
+                let t = zeta.signum() / (zeta.abs() + (1.0 + zeta * zeta).sqrt());
+                let c = 1.0 / (1.0 + t * t).sqrt();
+                let s = c * t;
+                for m in [&mut a, &mut v].iter_mut() {
+                    for i in 0..n {
+                        let (x, y) = (m[i * n + p], m[i * n + q]);
+                        m[i * n + p] = c * x - s * y;
+                        m[i * n + q] = s * x + c * y;
+                    }
+                }
+            }
+        }
+        if !rotated {
+            break;
+        }
+    }
+    (a, v)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sigmoid() {
+        assert_eq!(sigmoid(-100.0), 0.0);
+        assert_eq!(sigmoid(100.0), 1.0);
+        assert!((sigmoid(0.0) - 0.5).abs() < 1e-6);
+        for &x in [-7.5f32, -2.0, -0.3, 0.7, 3.0, 7.9].iter() {
+            assert!(
+                (sigmoid(x) - 1.0 / (1.0 + (-x).exp())).abs() < 2e-2,
+                "{}",
+                x
+            );
+        }
+    }
+
+    #[test]
+    fn test_log() {
+        assert_eq!(log(2.0), 0.0);
+        assert!(log(0.0).is_finite() && log(0.0) < -10.0);
+        for &x in [0.01f32, 0.2, 0.5, 0.9, 1.0].iter() {
+            assert!((log(x) - x.ln()).abs() < 0.2, "{}", x);
+        }
+    }
+
+    #[test]
+    fn test_json_string() {
+        assert_eq!(json_string("plain"), "\"plain\"");
+        assert_eq!(
+            json_string("a \"b\"\\c\nd\u{1}é"),
+            "\"a \\\"b\\\"\\\\c\\nd\\u0001é\""
+        );
+    }
+}
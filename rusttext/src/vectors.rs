@@ -0,0 +1,498 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::io::{BufRead, BufWriter, Write};
+
+use rayon::prelude::*;
+
+use crate::ann::Scored;
+use crate::matrix::Matrix;
+use crate::model::Model;
+use crate::predict;
+use crate::simd;
+use crate::word;
+use crate::Result;
+
+pub(crate) fn l2_norm(vector: &[f32]) -> f32 {
+    simd::norm(vector)
+}
+
+/// Cosine similarity of two vectors, 0 if either is all zeros.
+pub fn cosine(left: &[f32], right: &[f32]) -> f32 {
+    simd::cosine(left, right)
+}
+
+/// Mean of the input rows representing `token`: its own row and subword
+/// buckets if it is in the vocabulary, its hashed subword buckets
+/// otherwise. Zero if it has neither.
+pub(crate) fn word_vector(model: &Model, token: &str, out: &mut Vec<f32>) {
+    let features = model.vocab.line_features(&[token]);
+    predict::compute_hidden(model, &features, out);
+}
+
+/// Document vector of `text` into `out`. For a classifier it is the
+/// hidden layer the classifier sees: the mean of all line features,
+/// end-of-sentence and word n-grams included. Otherwise it is the mean of
+/// the unit-normalized word vectors, skipping zero vectors, as fastText's
+/// `print-sentence-vectors` does.
+pub(crate) fn sentence_vector(model: &Model, text: &str, out: &mut Vec<f32>) {
+    let mut tokens: Vec<&str> = text.split_whitespace().collect();
+    if model.supervised {
+        tokens.push(word::EOS);
+        let features = model.vocab.line_features(&tokens);
+        predict::compute_hidden(model, &features, out);
+        return;
+    }
+
+    out.clear();
+    out.resize(model.args.dim, 0.0);
+    let mut vector = Vec::with_capacity(model.args.dim);
+    let mut count = 0;
+    for token in tokens.iter() {
+        word_vector(model, token, &mut vector);
+        let norm = l2_norm(&vector);
+        if norm > 0.0 {
+            simd::axpy(out, 1.0 / norm, &vector);
+            count += 1;
+        }
+    }
+    if count > 0 {
+        out.iter_mut().for_each(|value| *value /= count as f32);
+    }
+}
+
+/// `sentence_vector` of every text, in parallel, as the rows of one
+/// matrix.
+pub(crate) fn sentence_vectors(model: &Model, texts: &[&str]) -> Matrix {
+    let dim = model.args.dim;
+    let mut matrix = Matrix::new(texts.len(), dim);
+    if dim == 0 {
+        return matrix;
+    }
+    matrix
+        .data_mut()
+        .par_chunks_mut(dim)
+        .zip(texts.par_iter())
+        .for_each_init(Vec::new, |vector, (row, text)| {
+            sentence_vector(model, text, vector);
+            row.copy_from_slice(vector);
+        });
+    matrix
+}
+
+/// Unit-normalized vector of every word, one row per word id, computed in
+/// parallel; the search space for analogy and neighbour queries.
+pub(crate) fn normalized_word_vectors(model: &Model) -> Matrix {
+    let dim = model.args.dim;
+    let n_words = model.vocab.n_words() as usize;
+    let mut matrix = Matrix::new(n_words, dim);
+    if dim == 0 {
+        return matrix;
+    }
+    matrix
+        .data_mut()
+        .par_chunks_mut(dim)
+        .enumerate()
+        .for_each_init(Vec::new, |vector, (id, row)| {
+            let word = model.vocab.word(id as u32).unwrap_or_default();
+            word_vector(model, &word, vector);
+            let norm = l2_norm(vector);
+            if norm > 0.0 {
+                for (r, v) in row.iter_mut().zip(vector.iter()) {
+                    *r = v / norm;
+                }
+            }
+        });
+    matrix
+}
+
+/// The `k` words whose normalized vectors have the highest cosine
+/// similarity with `query`, best first, leaving out those in `exclude`.
+/// With a neighbour index on the model the search is approximate.
+pub(crate) fn nearest(
+    model: &Model,
+    query: &[f32],
+    k: usize,
+    exclude: &[&str],
+) -> Vec<(String, f32)> {
+    let norm = l2_norm(query);
+    if norm == 0.0 {
+        return Vec::new();
+    }
+    let vectors = model.normalized_word_vectors();
+    let excluded: Vec<u32> = exclude
+        .iter()
+        .filter_map(|word| model.vocab.get_id(word))
+        .collect();
+    let name = |id: u32| model.vocab.word(id).unwrap_or_default().into_owned();
+    if let Some(index) = &model.index {
+        let query: Vec<f32> = query.iter().map(|x| x / norm).collect();
+        return index
+            .search(vectors, &query, k.saturating_add(exclude.len()))
+            .into_iter()
+            .filter(|&(i, _)| !excluded.contains(&(i as u32)))
+            .take(k)
+            .map(|(i, score)| (name(i as u32), score))
+            .collect();
+    }
+    let scores = (0..vectors.rows() as u32)
+        .into_par_iter()
+        .with_min_len(1024)
+        .filter(|i| !excluded.contains(i))
+        .map(|i| Scored(vectors.dot_row(query, i as usize) / norm, i));
+    best(scores, k)
+        .into_iter()
+        .map(|Scored(score, i)| (name(i), score))
+        .collect()
+}
+
+// Keep the `k` best of `heap` and `scored`, `heap` holding at most `k`.
+fn push_best(heap: &mut BinaryHeap<Reverse<Scored>>, scored: Scored, k: usize) {
+    if heap.len() < k {
+        heap.push(Reverse(scored));
+    } else if heap.peek().is_some_and(|worst| scored > worst.0) {
+        heap.pop();
+        heap.push(Reverse(scored));
+    }
+}
+
+/// The `k` best of `scores`, best first: each thread keeps a heap of its
+/// `k` best and the heaps are merged, so the scan needs `O(k)` memory per
+/// thread instead of a score per row.
+fn best<I: ParallelIterator<Item = Scored>>(scores: I, k: usize) -> Vec<Scored> {
+    if k == 0 {
+        return Vec::new();
+    }
+    let heap = scores
+        .fold(BinaryHeap::new, |mut heap, scored| {
+            push_best(&mut heap, scored, k);
+            heap
+        })
+        .reduce(BinaryHeap::new, |mut left, right| {
+            for Reverse(scored) in right {
+                push_best(&mut left, scored, k);
+            }
+            left
+        });
+    // ascending order of `Reverse` is best first
+    heap.into_sorted_vec()
+        .into_iter()
+        .map(|Reverse(scored)| scored)
+        .collect()
+}
+
+/// Answers to "`a` is to `b` as `c` is to ?": the words nearest to
+/// `b - a + c` over normalized word vectors, excluding the three inputs.
+pub(crate) fn analogy(model: &Model, a: &str, b: &str, c: &str, k: usize) -> Vec<(String, f32)> {
+    let mut query = vec![0.0; model.args.dim];
+    let mut vector = Vec::new();
+    for &(word, sign) in [(a, -1.0), (b, 1.0), (c, 1.0)].iter() {
+        word_vector(model, word, &mut vector);
+        let norm = l2_norm(&vector);
+        if norm > 0.0 {
+            simd::axpy(&mut query, sign / norm, &vector);
+        }
+    }
+    nearest(model, &query, k, &[a, b, c])
+}
+
+/// One row of space-separated values, ending the line.
+fn write_values<W: Write>(writer: &mut W, vector: &[f32]) -> Result<()> {
+    for (i, value) in vector.iter().enumerate() {
+        if i > 0 {
+            write!(writer, " ")?;
+        }
+        write!(writer, "{}", value)?;
+    }
+    writeln!(writer)?;
+    Ok(())
+}
+
+/// Write `word v1 v2 ...`, as in a `.vec` file, for every
+/// whitespace-separated word read from `reader`, as fastText's
+/// `print-word-vectors` does. Output is flushed after each input line, so
+/// the function can sit at either end of a pipe or serve one query at a
+/// time. Returns the number of rows written.
+pub fn write_word_vectors<R: BufRead, W: Write>(
+    model: &Model,
+    reader: R,
+    writer: W,
+) -> Result<usize> {
+    let mut writer = BufWriter::new(writer);
+    let mut vector = Vec::with_capacity(model.args.dim);
+    let mut rows = 0;
+    for line in reader.lines() {
+        for token in line?.split_whitespace() {
+            word_vector(model, token, &mut vector);
+            write!(writer, "{} ", token)?;
+            write_values(&mut writer, &vector)?;
+            rows += 1;
+        }
+        writer.flush()?;
+    }
+    Ok(rows)
+}
+
+/// Write the `sentence_vector` of every line read from `reader`, one row
+/// of space-separated values per line (without the text, as fastText's
+/// `print-sentence-vectors` prints them), flushing after each. Returns the
+/// number of rows written.
+pub fn write_sentence_vectors<R: BufRead, W: Write>(
+    model: &Model,
+    reader: R,
+    writer: W,
+) -> Result<usize> {
+    let mut writer = BufWriter::new(writer);
+    let mut vector = Vec::with_capacity(model.args.dim);
+    let mut rows = 0;
+    for line in reader.lines() {
+        sentence_vector(model, &line?, &mut vector);
+        write_values(&mut writer, &vector)?;
+        writer.flush()?;
+        rows += 1;
+    }
+    Ok(rows)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::args::Args;
+    use crate::io::tests::corpus_factory;
+
+    fn train(text: &str, args: Args) -> Model {
+        let corpus = corpus_factory(text);
+        let model = if args.loss == crate::args::LossKind::NegativeSampling {
+            Model::train_unsupervised(corpus.path(), args)
+        } else {
+            Model::train_supervised(corpus.path(), args)
+        };
+        model.unwrap()
+    }
+
+    fn skipgram() -> Model {
+        let text = "apple banana cherry\nxray yankee zulu\n".repeat(50);
+        train(
+            &text,
+            Args {
+                dim: 10,
+                min_count: 1,
+                bucket: 1000,
+                t: 1.0,
+                vocab_size: 101,
+                threads: 1,
+                ..Args::default()
+            },
+        )
+    }
+
+    #[test]
+    fn test_sentence_vector() {
+        let model = skipgram();
+        let mut expected = [0.0; 10];
+        for token in ["apple", "zulu"].iter() {
+            let mut vector = Vec::new();
+            word_vector(&model, token, &mut vector);
+            let norm = l2_norm(&vector);
+            for (e, v) in expected.iter_mut().zip(vector.iter()) {
+                *e += v / norm / 2.0;
+            }
+        }
+
+        let vector = model.sentence_vector("apple  zulu");
+        assert_eq!(vector.len(), 10);
+        for (v, e) in vector.iter().zip(expected.iter()) {
+            assert!((v - e).abs() < 1e-6);
+        }
+        assert_eq!(model.sentence_vector(""), vec![0.0; 10]);
+    }
+
+    #[test]
+    fn test_sentence_vectors() {
+        let model = skipgram();
+        let texts = ["apple banana", "", "yankee", "unknown words here"];
+
+        let matrix = model.sentence_vectors(&texts);
+        assert_eq!((matrix.rows(), matrix.cols()), (4, 10));
+        for (i, text) in texts.iter().enumerate() {
+            assert_eq!(matrix.row(i), &model.sentence_vector(text)[..]);
+        }
+    }
+
+    #[test]
+    fn test_sentence_vector_supervised() {
+        let text = "__label__a apple banana\n__label__b xray yankee\n".repeat(20);
+        let model = train(
+            &text,
+            Args {
+                dim: 5,
+                vocab_size: 101,
+                threads: 1,
+                ..Args::supervised()
+            },
+        );
+        let features = model
+            .vocabulary()
+            .line_features(&["apple", "xray", word::EOS]);
+        let mut hidden = vec![0.0; 5];
+        for &feature in features.iter() {
+            model
+                .input()
+                .add_row_to(&mut hidden, feature as usize, 1.0 / 3.0);
+        }
+
+        assert_eq!(model.sentence_vector("apple xray"), hidden);
+    }
+
+    #[test]
+    fn test_word_vector_oov() {
+        let model = skipgram();
+        let vocab = model.vocabulary();
+        let offset = vocab.bucket_offset() as usize;
+        let mean = |rows: &[usize]| {
+            let mut mean = vec![0.0; 10];
+            for &row in rows.iter() {
+                model
+                    .input()
+                    .add_row_to(&mut mean, row, 1.0 / rows.len() as f32);
+            }
+            mean
+        };
+
+        let buckets: Vec<usize> = vocab
+            .get_subwords("bananas")
+            .iter()
+            .map(|(_, bucket)| offset + *bucket as usize)
+            .collect();
+        assert!(vocab.get_id("bananas").is_none() && !buckets.is_empty());
+        assert_eq!(model.word_vector("bananas"), mean(&buckets));
+
+        let id = vocab.get_id("banana").unwrap();
+        let mut rows = vec![id as usize];
+        rows.extend(
+            vocab
+                .get_subwords("banana")
+                .iter()
+                .map(|(_, bucket)| offset + *bucket as usize),
+        );
+        assert_eq!(model.word_vector("banana"), mean(&rows));
+        assert_eq!(model.word_vector("__label__x"), vec![0.0; 10]);
+    }
+
+    // A model with one word per row of `vectors` and no subwords.
+    pub(crate) fn fixed(words: &[&str], vectors: Vec<f32>) -> Model {
+        let mut vocab = crate::vocabulary::Vocabulary::new(101, 0, 0, 0).unwrap();
+        for word in words.iter() {
+            vocab.add(word).unwrap();
+        }
+        vocab.threshold(1, 1);
+        let dim = vectors.len() / words.len();
+        let input = Matrix::from_vec(words.len(), dim, vectors).unwrap();
+        let order: Vec<u32> = vocab
+            .words()
+            .iter()
+            .map(|entry| words.iter().position(|w| *w == entry.word).unwrap() as u32)
+            .collect();
+        Model {
+            args: Args {
+                dim,
+                ..Args::default()
+            },
+            vocab,
+            input: input.select_rows(&order),
+            output: Matrix::new(words.len(), dim),
+            supervised: false,
+            word_vectors: Default::default(),
+            index: None,
+            label_thresholds: None,
+            label_priors: None,
+            output_tree: Default::default(),
+            quant_input: None,
+            quant_output: None,
+            training_stats: None,
+            metadata: None,
+        }
+    }
+
+    #[test]
+    fn test_analogy() {
+        let h = std::f32::consts::FRAC_1_SQRT_2;
+        #[rustfmt::skip]
+        let model = fixed(
+            &["man", "woman", "king", "queen", "apple"],
+            vec![
+                1.0, 0.0, 0.0, 0.0,
+                0.0, 1.0, 0.0, 0.0,
+                h, 0.0, h, 0.0,
+                0.0, h, h, 0.0,
+                0.0, 0.0, 0.0, 1.0,
+            ],
+        );
+        assert!(model.word_vectors.get().is_none());
+        model.cache_word_vectors();
+        assert_eq!(model.word_vectors.get().unwrap().rows(), 5);
+
+        let answers = model.analogy("man", "king", "woman", 2);
+        assert_eq!(answers[0].0, "queen");
+        // (king - man + woman) . queen / |king - man + woman|
+        let expected = (h + h * h) / ((h - 1.0).powi(2) + 1.0 + h * h).sqrt();
+        assert!((answers[0].1 - expected).abs() < 1e-5, "{:?}", answers);
+        assert_eq!(answers[1], (String::from("apple"), 0.0));
+        assert!(model.analogy("zzz", "zzz", "zzz", 3).is_empty());
+    }
+
+    #[test]
+    fn test_similarity() {
+        #[rustfmt::skip]
+        let model = fixed(
+            &["cat", "kitten", "car"],
+            vec![
+                1.0, 0.0,
+                3.0, 1.0,
+                0.0, -2.0,
+            ],
+        );
+
+        assert!((model.similarity("cat", "cat") - 1.0).abs() < 1e-6);
+        assert!((model.similarity("cat", "kitten") - 3.0 / 10f32.sqrt()).abs() < 1e-6);
+        assert_eq!(model.similarity("cat", "car"), 0.0);
+        assert_eq!(model.similarity("cat", "unknown"), 0.0);
+        // sentence vectors average the normalized word vectors
+        let expected = cosine(&[1.0, -1.0], &[3.0 / 10f32.sqrt(), 1.0 / 10f32.sqrt()]);
+        assert!((model.sentence_similarity("cat car", "kitten") - expected).abs() < 1e-6);
+        assert_eq!(cosine(&[0.0, 0.0], &[1.0, 0.0]), 0.0);
+    }
+
+    #[test]
+    fn test_write_vectors() {
+        let model = fixed(&["cat", "dog"], vec![1.0, 0.0, 0.0, 2.0]);
+
+        let mut out = Vec::new();
+        let rows = write_word_vectors(&model, "cat\ndog cat\n\n".as_bytes(), &mut out).unwrap();
+        assert_eq!(rows, 3);
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "cat 1 0\ndog 0 2\ncat 1 0\n"
+        );
+
+        let mut out = Vec::new();
+        let rows = write_sentence_vectors(&model, "cat dog\nbird\n".as_bytes(), &mut out).unwrap();
+        assert_eq!(rows, 2);
+        // the mean of the unit-normalized word vectors; zeros without any
+        assert_eq!(String::from_utf8(out).unwrap(), "0.5 0.5\n0 0\n");
+    }
+
+    #[test]
+    fn test_best() {
+        // ties every 7 rows, across several parallel splits
+        let scores: Vec<Scored> = (0..5000u32)
+            .map(|i| Scored(((i % 7) as f32 * 1.3).sin() + (i / 7) as f32 * 1e-3, i))
+            .collect();
+        let mut sorted = scores.clone();
+        sorted.sort_by(|left, right| right.cmp(left));
+        let rows = |found: &[Scored]| found.iter().map(|scored| scored.1).collect::<Vec<_>>();
+        for &k in [0, 1, 10, 5000, 6000].iter() {
+            let found = best(scores.par_iter().copied(), k);
+            assert_eq!(rows(&found), rows(&sorted[..k.min(5000)]));
+        }
+    }
+}
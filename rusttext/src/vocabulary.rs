@@ -1,11 +1,32 @@
-use crate::word;
-use std::iter::FromIterator;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::sync::OnceLock;
+use std::thread;
+
+use serde::de::Error as _;
+use serde::ser::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::compact::CompactVocabulary;
+use crate::{diagnostics, word, Result, RustTextError};
+
+/// Sentinel marking an unoccupied slot in the lookup table.
+const EMPTY: u32 = u32::MAX;
+
+/// Largest lookup table we can address. Entry ids are stored as `u32` with
+/// `u32::MAX` reserved for empty slots, so the table (and therefore the
+/// number of distinct entries) must stay strictly below that.
+pub const MAX_VOCAB_SIZE: usize = (u32::MAX - 1) as usize;
 
 pub struct Vocabulary {
     words: Vec<word::WordEntry>,
-    word_to_index: Vec<i32>,
+    word_hashes: Vec<u64>,
+    word_to_index: Vec<u32>,
     vocab_size: usize,
-    n_tokens: u32,
+    n_tokens: u64,
     n_words: u32,
     n_labels: u32,
     size: u32,
@@ -13,13 +34,149 @@ pub struct Vocabulary {
     min_n: usize,
     max_n: usize,
     bucket: u32,
+    bow: String,
+    eow: String,
+    lazy_subwords: bool,
+    hasher: Box<dyn word::TokenHasher>,
+    word_ngrams: usize,
+    char_only: bool,
+    label_separator: Option<String>,
+    /// After `prune`, the surviving buckets and their new indices; buckets
+    /// missing from the map are dropped from features.
+    pruned_buckets: Option<HashMap<u32, u32>>,
+    /// After `compact`, the entries in place of `words` and the lookup
+    /// table, which are left empty.
+    compact: Option<CompactVocabulary>,
+    /// Entries rebuilt from `compact` the first time `words` is called.
+    expanded: OnceLock<Vec<word::WordEntry>>,
+}
+
+// What a saved vocabulary holds: its entries and every setting that
+// shapes features. The lookup table and subwords are rebuilt on load.
+#[derive(Serialize)]
+struct SavedVocabulary<'a> {
+    words: &'a [word::WordEntry],
+    n_tokens: u64,
+    vocab_size: usize,
+    min_n: usize,
+    max_n: usize,
+    bucket: u32,
+    label_prefix: &'a str,
+    bow: &'a str,
+    eow: &'a str,
+    hash_mode: word::HashMode,
+    word_ngrams: usize,
+    char_only: bool,
+    label_separator: Option<&'a str>,
+    pruned_buckets: Option<&'a HashMap<u32, u32>>,
+}
+
+// The owned counterpart of `SavedVocabulary`, with fields in the same order.
+#[derive(Deserialize)]
+struct LoadedVocabulary {
+    words: Vec<word::WordEntry>,
+    n_tokens: u64,
+    vocab_size: usize,
+    min_n: usize,
+    max_n: usize,
+    bucket: u32,
+    label_prefix: String,
+    bow: String,
+    eow: String,
+    hash_mode: word::HashMode,
+    word_ngrams: usize,
+    char_only: bool,
+    label_separator: Option<String>,
+    pruned_buckets: Option<HashMap<u32, u32>>,
+}
+
+impl LoadedVocabulary {
+    fn into_vocabulary(self) -> Result<Vocabulary> {
+        let mut vocab = Vocabulary::new(self.vocab_size, self.min_n, self.max_n, self.bucket)?;
+        vocab.label_prefix = self.label_prefix;
+        vocab.bow = self.bow;
+        vocab.eow = self.eow;
+        vocab.hasher = Box::new(self.hash_mode);
+        vocab.set_word_ngrams(self.word_ngrams);
+        vocab.set_char_only(self.char_only)?;
+        vocab.label_separator = self.label_separator;
+        let threads = thread::available_parallelism().map_or(1, |n| n.get());
+        vocab.set_entries(self.words, self.n_tokens, threads)?;
+        vocab.pruned_buckets = self.pruned_buckets;
+        Ok(vocab)
+    }
+}
+
+// A line of a word count list: `word<TAB>count`, or `count word` as
+// `uniq -c` prints it.
+fn parse_count(line: &str) -> Option<(&str, u32)> {
+    let (word, count) = match line.rsplit_once('\t') {
+        Some((word, count)) => (word.trim(), count.trim()),
+        None => {
+            let mut fields = line.split_whitespace();
+            let count = fields.next()?;
+            (fields.next()?, count)
+        }
+    };
+    if word.is_empty() || word.contains(char::is_whitespace) {
+        return None;
+    }
+    Some((word, count.parse().ok()?))
+}
+
+/// Vocabularies serialize their entries and settings; the lookup table and
+/// subwords are rebuilt when deserializing. Only vocabularies using a
+/// built-in `HashMode` can be serialized.
+impl Serialize for Vocabulary {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let hash_mode = self
+            .hasher
+            .hash_mode()
+            .ok_or_else(|| S::Error::custom("a custom token hasher cannot be serialized"))?;
+        SavedVocabulary {
+            words: self.words(),
+            n_tokens: self.n_tokens,
+            vocab_size: self.vocab_size,
+            min_n: self.min_n,
+            max_n: self.max_n,
+            bucket: self.bucket,
+            label_prefix: &self.label_prefix,
+            bow: &self.bow,
+            eow: &self.eow,
+            hash_mode,
+            word_ngrams: self.word_ngrams,
+            char_only: self.char_only,
+            label_separator: self.label_separator.as_deref(),
+            pruned_buckets: self.pruned_buckets.as_ref(),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Vocabulary {
+    fn deserialize<D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Vocabulary, D::Error> {
+        LoadedVocabulary::deserialize(deserializer)?
+            .into_vocabulary()
+            .map_err(D::Error::custom)
+    }
 }
 
 impl Vocabulary {
-    pub fn new(vocab_size: usize, min_n: usize, max_n: usize, bucket: u32) -> Vocabulary {
-        Vocabulary {
+    pub fn new(vocab_size: usize, min_n: usize, max_n: usize, bucket: u32) -> Result<Vocabulary> {
+        if vocab_size == 0 || vocab_size > MAX_VOCAB_SIZE {
+            return Err(RustTextError::InvalidArgument(format!(
+                "vocab_size must be between 1 and {}, got {}",
+                MAX_VOCAB_SIZE, vocab_size
+            )));
+        }
+        word::validate_subword_params(min_n, max_n, bucket)?;
+
+        Ok(Vocabulary {
             words: Vec::new(),
-            word_to_index: vec![-1; vocab_size],
+            word_hashes: Vec::new(),
+            word_to_index: vec![EMPTY; vocab_size],
             vocab_size,
             n_tokens: 0,
             n_words: 0,
@@ -29,76 +186,770 @@ impl Vocabulary {
             min_n,
             max_n,
             bucket,
+            bow: String::from(word::BOW),
+            eow: String::from(word::EOW),
+            lazy_subwords: false,
+            hasher: Box::new(word::HashMode::default()),
+            word_ngrams: 1,
+            char_only: false,
+            label_separator: None,
+            pruned_buckets: None,
+            compact: None,
+            expanded: OnceLock::new(),
+        })
+    }
+
+    // The table uses Robin Hood probing: an entry being inserted takes the
+    // slot of any resident that sits closer to its own home slot, which keeps
+    // probe sequences short and lets lookups stop early on a miss.
+    fn probe_distance(&self, slot: usize, hash: u64) -> usize {
+        let home = (hash % self.vocab_size as u64) as usize;
+        (slot + self.vocab_size - home) % self.vocab_size
+    }
+
+    fn find_slot(&self, word: &str, hash: u64) -> Option<usize> {
+        let mut slot = (hash % self.vocab_size as u64) as usize;
+        let mut distance = 0;
+        loop {
+            let index = self.word_to_index[slot];
+            if index == EMPTY {
+                return None;
+            }
+            let index = index as usize;
+            if self.probe_distance(slot, self.word_hashes[index]) < distance {
+                return None;
+            }
+            if self.word_hashes[index] == hash && self.words[index].word == word {
+                return Some(slot);
+            }
+            slot = (slot + 1) % self.vocab_size;
+            distance += 1;
         }
     }
 
-    fn hash_lookup(&self, word: &String) -> usize {
-        let mut word_hash = word::fnv_hash(&word) as usize % self.vocab_size;
-        let mut word_index = self.word_to_index[word_hash];
+    fn insert_index(&mut self, mut index: u32) {
+        let mut slot = (self.word_hashes[index as usize] % self.vocab_size as u64) as usize;
+        let mut distance = 0;
         loop {
-            match (word_index, word) {
-                (-1, _) => return word_hash,
-                (_, word) if word == &self.words[word_index as usize].word => return word_hash,
-                _ => {
-                    word_hash = (word_hash + 1) % self.vocab_size;
-                    word_index = self.word_to_index[word_hash];
-                }
+            let resident = self.word_to_index[slot];
+            if resident == EMPTY {
+                self.word_to_index[slot] = index;
+                return;
+            }
+            let resident_distance = self.probe_distance(slot, self.word_hashes[resident as usize]);
+            if resident_distance < distance {
+                self.word_to_index[slot] = index;
+                index = resident;
+                distance = resident_distance;
             }
+            slot = (slot + 1) % self.vocab_size;
+            distance += 1;
+        }
+    }
+
+    pub fn get_id(&self, word: &str) -> Option<u32> {
+        self.lookup(word, self.hasher.hash(word))
+    }
+
+    // id of `word`, whose hash is `hash`
+    fn lookup(&self, word: &str, hash: u64) -> Option<u32> {
+        match &self.compact {
+            Some(compact) => compact.get_id(word),
+            None => self
+                .find_slot(word, hash)
+                .map(|slot| self.word_to_index[slot]),
+        }
+    }
+
+    /// The entry `id` stands for, if there is one.
+    pub fn word(&self, id: u32) -> Option<Cow<'_, str>> {
+        match &self.compact {
+            // words came from `String`s, so they always decode
+            Some(compact) => compact.get_word(id).ok().flatten().map(Cow::Owned),
+            None => self
+                .words
+                .get(id as usize)
+                .map(|entry| Cow::Borrowed(entry.word.as_str())),
+        }
+    }
+
+    pub fn count(&self, id: u32) -> u32 {
+        match &self.compact {
+            Some(compact) => compact.count(id),
+            None => self.words[id as usize].count,
+        }
+    }
+
+    pub fn entry_type(&self, id: u32) -> word::EntryType {
+        match &self.compact {
+            Some(compact) => compact.entry_type(id),
+            None => self.words[id as usize].entry_type.clone(),
+        }
+    }
+
+    /// Keep the entries as a `CompactVocabulary` instead, for inference
+    /// with large vocabularies: words are front-coded and looked up by
+    /// binary search, and the lookup table and stored subwords are dropped,
+    /// subwords being computed as words are looked up. Lookups and features
+    /// stay the same. Anything that changes the entries calls `expand`
+    /// first, and so does `words`, once.
+    pub fn compact(&mut self) {
+        if self.compact.is_some() {
+            return;
         }
+        self.compact = Some(CompactVocabulary::from_vocabulary(self));
+        self.words = Vec::new();
+        self.word_hashes = Vec::new();
+        self.word_to_index = Vec::new();
     }
 
-    fn get_id(&self, word: &String) -> i32 {
-        let hash = self.hash_lookup(word);
-        return self.word_to_index[hash];
+    pub fn is_compact(&self) -> bool {
+        self.compact.is_some()
     }
 
-    pub fn add(&mut self, word: &String) {
-        let hash = self.hash_lookup(word);
-        let index = self.word_to_index[hash];
+    /// Undo `compact`, rebuilding the entries, their subwords and the
+    /// lookup table.
+    pub fn expand(&mut self) {
+        let compact = match self.compact.take() {
+            Some(compact) => compact,
+            None => return,
+        };
+        self.words = match self.expanded.take() {
+            Some(words) => words,
+            None => compact.entries(),
+        };
+        self.rebuild_index();
+        if !self.lazy_subwords {
+            self.compute_all_subwords(1);
+        }
+    }
+
+    pub fn add(&mut self, word: &str) -> Result<()> {
+        if let Some(separator) = &self.label_separator {
+            for ancestor in word::label_ancestors(word, &self.label_prefix, separator) {
+                self.add_entry(&ancestor)?;
+            }
+        }
+        self.add_entry(word)?;
         self.n_tokens += 1;
+        Ok(())
+    }
+
+    // count one occurrence of `word` without touching the token total
+    fn add_entry(&mut self, word: &str) -> Result<()> {
+        self.add_count(word, 1)
+    }
 
-        match index {
-            -1 => {
+    // count `count` occurrences of `word` without touching the token total
+    fn add_count(&mut self, word: &str, count: u32) -> Result<()> {
+        self.expand();
+        let hash = self.hasher.hash(word);
+
+        match self.find_slot(word, hash) {
+            None => {
+                // keep at least one slot open so probing always terminates
+                if self.size as usize + 1 >= self.vocab_size {
+                    return Err(RustTextError::VocabularyFull {
+                        capacity: self.vocab_size,
+                    });
+                }
                 let mut word_entry = word::WordEntry::new(word, &self.label_prefix);
-                if word_entry.entry_type == word::EntryType::Word {
-                    word_entry.compute_subwords(self.min_n, self.max_n, self.bucket);
+                word_entry.count = count;
+                if word_entry.entry_type == word::EntryType::Word
+                    && !self.lazy_subwords
+                    && word != word::EOS
+                {
+                    word_entry.hash_subwords(
+                        self.min_n,
+                        self.max_n,
+                        self.bucket,
+                        &self.bow,
+                        &self.eow,
+                        self.hasher.as_ref(),
+                    );
                 }
                 self.words.push(word_entry);
-                self.word_to_index[hash] = self.size as i32;
+                self.word_hashes.push(hash);
+                self.insert_index(self.size);
                 self.size += 1;
             }
-            _ => {
-                self.words[index as usize].count += 1;
+            Some(slot) => {
+                let index = self.word_to_index[slot];
+                let entry = &mut self.words[index as usize];
+                entry.count = entry.count.saturating_add(count);
             }
         }
+        Ok(())
     }
 
-    fn threshold(&mut self, word_threshold: u32, label_threshold: u32) {
+    /// Write every entry as a `word<TAB>count` line, in vocabulary order,
+    /// for word count tooling or `load_counts`.
+    pub fn save_counts<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        for entry in self.words() {
+            writeln!(writer, "{}\t{}", entry.word, entry.count)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Add the counts listed in the file at `path`, one entry per line as
+    /// `word<TAB>count`, or as `count word` the way `uniq -c` prints them.
+    /// Entries listed twice are summed, and every count is added to the
+    /// token total. Like `add`, this doesn't threshold; see
+    /// `loader::build_vocabulary_from_counts`.
+    pub fn load_counts<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        let reader = BufReader::new(File::open(path)?);
+        for (i, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let (word, count) = parse_count(&line).ok_or_else(|| {
+                RustTextError::ModelFormat(format!(
+                    "line {} of word counts is not `word<TAB>count`: {}",
+                    i + 1,
+                    line
+                ))
+            })?;
+            self.add_count(word, count)?;
+            self.n_tokens += u64::from(count);
+        }
+        Ok(())
+    }
+
+    pub fn threshold(&mut self, word_threshold: u32, label_threshold: u32) {
+        self.expand();
         // prune words below threshold
         self.words.sort_by(word::compare);
 
+        #[cfg(feature = "tracing")]
+        let before = self.words.len();
         self.words.retain(|word| match word.entry_type {
             word::EntryType::Word => word.count >= word_threshold,
             word::EntryType::Label => word.count >= label_threshold,
         });
+        trace_debug!(
+            word_threshold,
+            label_threshold,
+            removed = before - self.words.len(),
+            kept = self.words.len(),
+            "thresholded vocabulary"
+        );
 
+        self.rebuild_index();
+    }
+
+    fn rebuild_index(&mut self) {
         // reset counters
         self.size = 0;
         self.n_words = 0;
         self.n_labels = 0;
-        self.word_to_index = vec![-1; self.vocab_size];
+        self.word_to_index = vec![EMPTY; self.vocab_size];
+        self.word_hashes = self
+            .words
+            .iter()
+            .map(|word| self.hasher.hash(&word.word))
+            .collect();
 
         // re-hydrate lookup
-        for word in self.words.iter() {
-            let hash = self.hash_lookup(&word.word);
-            self.word_to_index[hash] = self.size as i32;
+        for i in 0..self.words.len() {
+            self.insert_index(self.size);
             self.size += 1;
-            match word.entry_type {
+            match self.words[i].entry_type {
                 word::EntryType::Word => self.n_words += 1,
                 word::EntryType::Label => self.n_labels += 1,
             }
         }
     }
+
+    /// Replace the hash function, rebuilding the lookup table and
+    /// recomputing subwords of existing entries.
+    pub fn set_hasher(&mut self, hasher: Box<dyn word::TokenHasher>) {
+        self.expand();
+        self.hasher = hasher;
+        self.rebuild_index();
+        if !self.lazy_subwords {
+            self.compute_all_subwords(1);
+        }
+    }
+
+    /// Switch between the built-in 32- and 64-bit FNV hashers.
+    pub fn set_hash_mode(&mut self, hash_mode: word::HashMode) {
+        self.set_hasher(Box::new(hash_mode));
+    }
+
+    /// The built-in hasher in use. A vocabulary with a custom hasher
+    /// reports the default; `hasher().hash_mode()` tells them apart.
+    pub fn hash_mode(&self) -> word::HashMode {
+        self.hasher.hash_mode().unwrap_or_default()
+    }
+
+    pub fn hasher(&self) -> &dyn word::TokenHasher {
+        self.hasher.as_ref()
+    }
+
+    /// Skip subword computation in `add`, leaving it to a single
+    /// `compute_all_subwords` pass once the vocabulary has been pruned, so
+    /// entries about to be thresholded away are never hashed.
+    pub fn set_lazy_subwords(&mut self, lazy: bool) {
+        self.lazy_subwords = lazy;
+    }
+
+    /// Compute subwords for every word entry, splitting the work across
+    /// `threads` threads. With one thread, or on wasm where threads can't be
+    /// spawned, the work is done on the calling thread.
+    pub fn compute_all_subwords(&mut self, threads: usize) {
+        self.expand();
+        if self.words.is_empty() {
+            return;
+        }
+        let (min_n, max_n, bucket) = (self.min_n, self.max_n, self.bucket);
+        let (bow, eow) = (self.bow.as_str(), self.eow.as_str());
+        let hasher = self.hasher.as_ref();
+        let words = &mut self.words;
+        let hash_all = move |chunk: &mut [word::WordEntry]| {
+            for entry in chunk.iter_mut() {
+                if entry.entry_type == word::EntryType::Word && entry.word != word::EOS {
+                    entry.hash_subwords(min_n, max_n, bucket, bow, eow, hasher);
+                }
+            }
+        };
+        if threads <= 1 || cfg!(target_arch = "wasm32") {
+            hash_all(words);
+            return;
+        }
+
+        let chunk_size = words.len().div_ceil(threads);
+        thread::scope(|scope| {
+            for chunk in words.chunks_mut(chunk_size) {
+                scope.spawn(move || hash_all(chunk));
+            }
+        });
+    }
+
+    /// Character n-grams of `word` with their bucket ids, computed with this
+    /// vocabulary's settings whether or not `word` has been added. Labels
+    /// have no subwords.
+    pub fn get_subwords(&self, word: &str) -> Vec<(String, u32)> {
+        if word.starts_with(&self.label_prefix) || word == word::EOS {
+            return Vec::new();
+        }
+        word::char_ngrams(word, self.min_n, self.max_n, &self.bow, &self.eow)
+            .into_iter()
+            .map(|ngram| {
+                let id = self.hasher.bucket(&ngram, self.bucket);
+                (ngram, id)
+            })
+            .collect()
+    }
+
+    /// Length of the word n-grams added to line features; 1 disables them.
+    pub fn set_word_ngrams(&mut self, word_ngrams: usize) {
+        self.word_ngrams = usize::max(word_ngrams, 1);
+    }
+
+    pub fn word_ngrams(&self) -> usize {
+        self.word_ngrams
+    }
+
+    /// Represent words purely by their character n-grams: no input rows are
+    /// reserved for whole words, and features go straight to subword
+    /// buckets. Words are still counted, so pruning and sampling work as
+    /// usual. Requires subwords to be enabled.
+    pub fn set_char_only(&mut self, char_only: bool) -> Result<()> {
+        if char_only && (self.min_n == 0 || self.max_n == 0 || self.bucket == 0) {
+            return Err(RustTextError::InvalidSubwordParams(String::from(
+                "character-only mode needs non-zero min_n, max_n and bucket",
+            )));
+        }
+        self.char_only = char_only;
+        Ok(())
+    }
+
+    pub fn char_only(&self) -> bool {
+        self.char_only
+    }
+
+    /// Input-matrix row of bucket 0: buckets follow the word rows, or start
+    /// the matrix in character-only mode.
+    pub fn bucket_offset(&self) -> u32 {
+        if self.char_only {
+            0
+        } else {
+            self.n_words
+        }
+    }
+
+    /// Rows needed in the input matrix for this vocabulary.
+    pub fn n_input_rows(&self) -> usize {
+        let n_buckets = match &self.pruned_buckets {
+            Some(pruned) => pruned.len(),
+            None => self.bucket as usize,
+        };
+        self.bucket_offset() as usize + n_buckets
+    }
+
+    // Input-matrix row of hashed bucket `bucket`, if it survived pruning.
+    fn bucket_row(&self, bucket: u32) -> Option<u32> {
+        let offset = self.bucket_offset();
+        match &self.pruned_buckets {
+            Some(pruned) => pruned.get(&bucket).map(|index| offset + index),
+            None => Some(offset + bucket),
+        }
+    }
+
+    /// Keep only the input-matrix rows in `rows` (ascending): words whose
+    /// row is listed and the subword/word n-gram buckets whose row is
+    /// listed. Labels are always kept. Surviving words and buckets are
+    /// renumbered in their original order, so row `rows[i]` of the old
+    /// input matrix becomes row `i` of the new one. Dropped buckets no
+    /// longer contribute features.
+    pub fn prune(&mut self, rows: &[u32]) {
+        self.expand();
+        let offset = self.bucket_offset();
+        let n_words = self.n_words as usize;
+        let original_buckets: Vec<u32> = match &self.pruned_buckets {
+            Some(pruned) => {
+                let mut buckets = vec![0; pruned.len()];
+                for (&bucket, &index) in pruned.iter() {
+                    buckets[index as usize] = bucket;
+                }
+                buckets
+            }
+            None => (0..self.bucket).collect(),
+        };
+
+        if !self.char_only {
+            let kept: Vec<bool> = (0..n_words as u32)
+                .map(|id| rows.binary_search(&id).is_ok())
+                .collect();
+            let mut i = 0;
+            self.words.retain(|entry| {
+                let keep = entry.entry_type == word::EntryType::Label || kept[i];
+                i += 1;
+                keep
+            });
+        }
+        self.pruned_buckets = Some(
+            rows.iter()
+                .filter(|&&row| row >= offset)
+                .enumerate()
+                .map(|(index, &row)| (original_buckets[(row - offset) as usize], index as u32))
+                .collect(),
+        );
+        self.rebuild_index();
+    }
+
+    /// After `prune`, the original id of every surviving bucket mapped to
+    /// its index among the kept buckets.
+    pub fn pruned_buckets(&self) -> Option<&HashMap<u32, u32>> {
+        self.pruned_buckets.as_ref()
+    }
+
+    pub(crate) fn set_pruned_buckets(&mut self, pruned: Option<HashMap<u32, u32>>) {
+        self.pruned_buckets = pruned;
+    }
+
+    /// Replace every entry with `words`, e.g. read back from a saved model.
+    /// They must already be thresholded and in vocabulary order, words
+    /// before labels; subwords are recomputed with this vocabulary's
+    /// settings.
+    pub(crate) fn set_entries(
+        &mut self,
+        words: Vec<word::WordEntry>,
+        n_tokens: u64,
+        threads: usize,
+    ) -> Result<()> {
+        if words.len() >= self.vocab_size {
+            return Err(RustTextError::VocabularyFull {
+                capacity: self.vocab_size,
+            });
+        }
+        if words
+            .windows(2)
+            .any(|pair| pair[0].entry_type > pair[1].entry_type)
+        {
+            return Err(RustTextError::ModelFormat(String::from(
+                "vocabulary has labels before words",
+            )));
+        }
+        self.compact = None;
+        self.expanded = OnceLock::new();
+        self.words = words;
+        self.n_tokens = n_tokens;
+        self.rebuild_index();
+        self.compute_all_subwords(threads);
+        Ok(())
+    }
+
+    /// Append the input-matrix rows representing known word `id`: the word's
+    /// own row (unless in character-only mode) and its subword buckets.
+    pub fn word_features(&self, id: u32, features: &mut Vec<u32>) {
+        if !self.char_only {
+            features.push(id);
+        }
+        if let Some(compact) = &self.compact {
+            let word = compact.get_word(id).ok().flatten().unwrap_or_default();
+            features.extend(
+                self.get_subwords(&word)
+                    .into_iter()
+                    .filter_map(|(_, bucket)| self.bucket_row(bucket)),
+            );
+            return;
+        }
+        features.extend(
+            self.words[id as usize]
+                .subwords
+                .iter()
+                .filter_map(|&bucket| self.bucket_row(bucket)),
+        );
+    }
+
+    /// Input-matrix feature ids for a tokenized line: for each word, its
+    /// entry id (if known, and not in character-only mode) and its subword
+    /// buckets, followed by hashed word n-gram buckets. Bucket ids are offset
+    /// by `bucket_offset`, so this expects a thresholded vocabulary where
+    /// words occupy ids `0..n_words`. Labels are skipped; see `line_labels`.
+    pub fn line_features(&self, tokens: &[&str]) -> Vec<u32> {
+        let mut features = Vec::new();
+        let mut hashes = Vec::new();
+
+        for token in tokens.iter() {
+            let hash = self.hasher.hash(token);
+            match self.lookup(token, hash) {
+                Some(id) => {
+                    if self.entry_type(id) == word::EntryType::Label {
+                        continue;
+                    }
+                    self.word_features(id, &mut features);
+                }
+                None => {
+                    if token.starts_with(&self.label_prefix) {
+                        continue;
+                    }
+                    features.extend(
+                        self.get_subwords(token)
+                            .into_iter()
+                            .filter_map(|(_, bucket)| self.bucket_row(bucket)),
+                    );
+                }
+            }
+            hashes.push(hash);
+        }
+
+        self.add_word_ngrams(&mut features, &hashes);
+        features
+    }
+
+    // Mix each run of up to `word_ngrams` consecutive word hashes into a
+    // bucket id, with fastText's multiplier. fastText keeps 32-bit hashes as
+    // int32, so they are sign-extended before mixing to stay compatible.
+    fn add_word_ngrams(&self, features: &mut Vec<u32>, hashes: &[u64]) {
+        if self.bucket == 0 {
+            return;
+        }
+        let widen = |hash: u64| match self.hasher.width() {
+            32 => hash as u32 as i32 as i64 as u64,
+            _ => hash,
+        };
+        for i in 0..hashes.len() {
+            let mut h = widen(hashes[i]);
+            for &next in hashes.iter().take(i + self.word_ngrams).skip(i + 1) {
+                h = h.wrapping_mul(116049371).wrapping_add(widen(next));
+                if let Some(row) = self.bucket_row((h % u64::from(self.bucket)) as u32) {
+                    features.push(row);
+                }
+            }
+        }
+    }
+
+    /// Label indices (`0..n_labels`) of the known labels in a tokenized
+    /// line, for a thresholded vocabulary. With a label separator set, each
+    /// label's ancestors are included as well, once each.
+    pub fn line_labels(&self, tokens: &[&str]) -> Vec<u32> {
+        let mut labels = Vec::new();
+        for token in tokens.iter() {
+            if !token.starts_with(&self.label_prefix) {
+                continue;
+            }
+            let mut names = match &self.label_separator {
+                Some(separator) => word::label_ancestors(token, &self.label_prefix, separator),
+                None => Vec::new(),
+            };
+            names.push(token.to_string());
+            for name in names.iter() {
+                if let Some(id) = self.get_id(name) {
+                    if !labels.contains(&(id - self.n_words)) {
+                        labels.push(id - self.n_words);
+                    }
+                }
+            }
+        }
+        labels
+    }
+
+    /// Treat labels as paths split on `separator` (e.g. `/` for
+    /// `__label__sports/football`): every ancestor is registered as a label
+    /// of its own and counted each time a descendant is added.
+    pub fn set_label_separator(&mut self, separator: Option<&str>) {
+        self.label_separator = separator.map(String::from);
+    }
+
+    pub fn label_separator(&self) -> Option<&str> {
+        self.label_separator.as_deref()
+    }
+
+    /// Change the prefix marking label tokens. Only affects entries added
+    /// afterwards.
+    pub fn set_label_prefix(&mut self, label_prefix: &str) {
+        self.label_prefix = String::from(label_prefix);
+    }
+
+    pub fn label_prefix(&self) -> &str {
+        &self.label_prefix
+    }
+
+    /// Replace the begin/end-of-word markers used for subword extraction,
+    /// recomputing subwords of entries already added, or clearing them for
+    /// `compute_all_subwords` if subwords are lazy.
+    pub fn set_markers(&mut self, bow: &str, eow: &str) {
+        self.expand();
+        self.bow = String::from(bow);
+        self.eow = String::from(eow);
+        if self.lazy_subwords {
+            self.words
+                .iter_mut()
+                .for_each(|entry| entry.subwords.clear());
+        } else {
+            self.compute_all_subwords(1);
+        }
+    }
+
+    pub fn bow(&self) -> &str {
+        &self.bow
+    }
+
+    pub fn eow(&self) -> &str {
+        &self.eow
+    }
+
+    /// Every entry, words (by decreasing count) then labels. A compact
+    /// vocabulary rebuilds them the first time, without subwords; `word`,
+    /// `count` and `entry_type` read single entries without that.
+    pub fn words(&self) -> &[word::WordEntry] {
+        match &self.compact {
+            Some(compact) => self.expanded.get_or_init(|| compact.entries()),
+            None => &self.words,
+        }
+    }
+
+    pub fn min_n(&self) -> usize {
+        self.min_n
+    }
+
+    pub fn max_n(&self) -> usize {
+        self.max_n
+    }
+
+    pub fn bucket(&self) -> u32 {
+        self.bucket
+    }
+
+    /// Report how the vocabulary's character n-grams spread over the subword
+    /// buckets; see `diagnostics::bucket_stats`.
+    pub fn bucket_stats(&self, top_k: usize) -> diagnostics::BucketStats {
+        diagnostics::bucket_stats(self, top_k)
+    }
+
+    /// Summarise the word count distribution; see
+    /// `diagnostics::frequency_report`.
+    pub fn frequency_report(&self) -> diagnostics::FrequencyReport {
+        diagnostics::frequency_report(self)
+    }
+
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    pub fn n_tokens(&self) -> u64 {
+        self.n_tokens
+    }
+
+    pub fn n_words(&self) -> u32 {
+        self.n_words
+    }
+
+    pub fn n_labels(&self) -> u32 {
+        self.n_labels
+    }
+}
+
+/// Bounded cache of subword bucket ids for out-of-vocabulary words, so
+/// repeated lookups of the same unseen word skip n-gram extraction and
+/// hashing.
+///
+/// Approximates LRU with two generations: hits in the older generation are
+/// promoted, and once the young generation fills it replaces the old one,
+/// dropping whatever was not touched since the last swap.
+pub struct SubwordCache {
+    capacity: usize,
+    young: HashMap<String, Vec<u32>>,
+    old: HashMap<String, Vec<u32>>,
+}
+
+impl SubwordCache {
+    pub fn new(capacity: usize) -> SubwordCache {
+        SubwordCache {
+            capacity: usize::max(capacity / 2, 1),
+            young: HashMap::new(),
+            old: HashMap::new(),
+        }
+    }
+
+    /// Bucket ids of `word`'s subwords, from the vocabulary entry when it
+    /// has them and otherwise computed once and cached. Compact and lazy
+    /// vocabularies keep no subwords in their entries, so their words go
+    /// through the cache too.
+    pub fn get<'a>(&'a mut self, vocab: &'a Vocabulary, word: &str) -> &'a [u32] {
+        let disabled = vocab.max_n() == 0 || vocab.bucket() == 0;
+        let stored = match vocab.get_id(word) {
+            Some(id) if !vocab.is_compact() => Some(&vocab.words()[id as usize].subwords),
+            _ => None,
+        };
+        if let Some(subwords) = stored.filter(|subwords| !subwords.is_empty() || disabled) {
+            return subwords;
+        }
+
+        if !self.young.contains_key(word) {
+            let ids = match self.old.remove(word) {
+                Some(ids) => ids,
+                None => vocab
+                    .get_subwords(word)
+                    .into_iter()
+                    .map(|(_, id)| id)
+                    .collect(),
+            };
+            if self.young.len() >= self.capacity {
+                self.old = std::mem::take(&mut self.young);
+            }
+            self.young.insert(word.to_string(), ids);
+        }
+        &self.young[word]
+    }
+
+    pub fn len(&self) -> usize {
+        self.young.len() + self.old.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.young.is_empty() && self.old.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.young.clear();
+        self.old.clear();
+    }
 }
 
 #[cfg(test)]
@@ -106,53 +957,417 @@ mod tests {
     use super::*;
 
     fn test_vocab() -> Vocabulary {
-        let label_prefix = String::from("__label__");
-        let foo = word::WordEntry::new(&String::from("foo"), &label_prefix);
-        let bar = word::WordEntry::new(&String::from("bar"), &label_prefix);
-        let baz = word::WordEntry::new(&String::from("__label__baz"), &label_prefix);
-
-        Vocabulary {
-            words: vec![foo, bar, baz],
-            n_tokens: 3,
-            n_words: 2,
-            n_labels: 1,
-            size: 3,
-            label_prefix,
-            vocab_size: 5,
-            word_to_index: vec![-1, 2, 1, 0, -1],
-            min_n: 2,
-            max_n: 4,
-            bucket: 10,
-        }
+        let mut vocab = Vocabulary::new(5, 2, 4, 10).unwrap();
+        vocab.add("foo").unwrap();
+        vocab.add("bar").unwrap();
+        vocab.add("__label__baz").unwrap();
+        vocab
     }
 
     #[test]
-    fn test_hash_lookup() {
+    fn test_get_id() {
         let test_vocab = test_vocab();
 
-        assert_eq!(test_vocab.hash_lookup(&String::from("foo")), 3);
-        assert_eq!(test_vocab.hash_lookup(&String::from("bar")), 2);
-        assert_eq!(test_vocab.hash_lookup(&String::from("__label__baz")), 1);
+        assert_eq!(test_vocab.get_id("foo"), Some(0));
+        assert_eq!(test_vocab.get_id("bar"), Some(1));
+        assert_eq!(test_vocab.get_id("__label__baz"), Some(2));
+        assert_eq!(test_vocab.get_id("biff"), None);
     }
 
     #[test]
-    fn test_get_id() {
-        let test_vocab = test_vocab();
+    fn test_add() {
+        let mut test_vocab = test_vocab();
+        let test_word = "biff";
+
+        test_vocab.add(test_word).unwrap();
 
-        assert_eq!(test_vocab.get_id(&String::from("foo")), 0);
-        assert_eq!(test_vocab.get_id(&String::from("bar")), 1);
-        assert_eq!(test_vocab.get_id(&String::from("__label__baz")), 2);
+        assert_eq!(test_vocab.get_id(test_word), Some(3));
+        assert_eq!(test_vocab.n_tokens, 4);
     }
 
     #[test]
-    fn test_add() {
+    fn test_add_full() {
         let mut test_vocab = test_vocab();
-        let test_word = String::from("biff");
 
-        test_vocab.add(&test_word);
+        test_vocab.add("biff").unwrap();
 
-        assert_eq!(test_vocab.hash_lookup(&test_word), 0);
-        assert_eq!(test_vocab.get_id(&test_word), 3);
-        assert_eq!(test_vocab.n_tokens, 4);
+        assert!(matches!(
+            test_vocab.add("boom"),
+            Err(RustTextError::VocabularyFull { capacity: 5 })
+        ));
+        assert_eq!(test_vocab.size(), 4);
+        // existing entries are still counted
+        test_vocab.add("foo").unwrap();
+        assert_eq!(test_vocab.n_tokens(), 5);
+    }
+
+    #[test]
+    fn test_new_bad_size() {
+        assert!(matches!(
+            Vocabulary::new(0, 2, 4, 10),
+            Err(RustTextError::InvalidArgument(_))
+        ));
+        assert!(Vocabulary::new(MAX_VOCAB_SIZE + 1, 2, 4, 10).is_err());
+        assert!(matches!(
+            Vocabulary::new(5, 4, 2, 10),
+            Err(RustTextError::InvalidSubwordParams(_))
+        ));
+        assert!(Vocabulary::new(5, 2, 4, 0).is_err());
+        assert!(Vocabulary::new(5, 3, 3, 10).is_ok());
+    }
+
+    #[test]
+    fn test_high_load_factor() {
+        let mut vocab = Vocabulary::new(100, 0, 0, 10).unwrap();
+        for i in 0..99 {
+            vocab.add(&format!("word{}", i)).unwrap();
+        }
+
+        for i in 0..99 {
+            assert_eq!(vocab.get_id(&format!("word{}", i)), Some(i));
+        }
+        assert_eq!(vocab.get_id("missing"), None);
+    }
+
+    #[test]
+    fn test_probe_invariant() {
+        // every resident sits no further from home than its predecessor + 1
+        let mut vocab = Vocabulary::new(64, 0, 0, 10).unwrap();
+        for i in 0..60 {
+            vocab.add(&format!("w{}", i)).unwrap();
+        }
+
+        for slot in 0..vocab.vocab_size {
+            let index = vocab.word_to_index[slot];
+            let prev = vocab.word_to_index[(slot + vocab.vocab_size - 1) % vocab.vocab_size];
+            if index == EMPTY {
+                continue;
+            }
+            let distance = vocab.probe_distance(slot, vocab.word_hashes[index as usize]);
+            if prev == EMPTY {
+                assert_eq!(distance, 0);
+            } else {
+                let prev_slot = (slot + vocab.vocab_size - 1) % vocab.vocab_size;
+                let prev_distance =
+                    vocab.probe_distance(prev_slot, vocab.word_hashes[prev as usize]);
+                assert!(distance <= prev_distance + 1);
+            }
+        }
+    }
+
+    #[test]
+    fn test_get_subwords() {
+        let mut vocab = Vocabulary::new(11, 2, 3, 10).unwrap();
+        vocab.set_markers("", "");
+        vocab.add("rust").unwrap();
+
+        let expected = vec![
+            (String::from("ru"), 0),
+            (String::from("us"), 9),
+            (String::from("st"), 2),
+            (String::from("rus"), 7),
+            (String::from("ust"), 7),
+        ];
+        assert_eq!(vocab.get_subwords("rust"), expected);
+        // out-of-vocabulary words get subwords too
+        let oov = vocab.get_subwords("crust");
+        assert!(expected.iter().all(|subword| oov.contains(subword)));
+        assert!(vocab.get_subwords("__label__rust").is_empty());
+    }
+
+    #[test]
+    fn test_lazy_subwords() {
+        let mut eager = Vocabulary::new(101, 3, 6, 1000).unwrap();
+        let mut lazy = Vocabulary::new(101, 3, 6, 1000).unwrap();
+        lazy.set_lazy_subwords(true);
+        for word in ["foo", "bar", "baz", "qux", "foo", "__label__x"].iter() {
+            eager.add(word).unwrap();
+            lazy.add(word).unwrap();
+        }
+        assert!(lazy.words().iter().all(|entry| entry.subwords.is_empty()));
+
+        lazy.compute_all_subwords(3);
+        assert_eq!(lazy.words(), eager.words());
+    }
+
+    #[test]
+    fn test_subword_cache() {
+        let mut vocab = Vocabulary::new(11, 2, 3, 10).unwrap();
+        vocab.add("rust").unwrap();
+        let mut cache = SubwordCache::new(2);
+
+        assert_eq!(cache.get(&vocab, "rust"), &vocab.words()[0].subwords[..]);
+        assert!(cache.is_empty());
+
+        let crust: Vec<u32> = vocab
+            .get_subwords("crust")
+            .into_iter()
+            .map(|(_, id)| id)
+            .collect();
+        assert_eq!(cache.get(&vocab, "crust"), &crust[..]);
+        assert_eq!(cache.len(), 1);
+
+        cache.get(&vocab, "trust");
+        cache.get(&vocab, "crust");
+        cache.get(&vocab, "bust");
+        // "crust" was touched after "trust", so "trust" is the one evicted
+        assert_eq!(cache.len(), 2);
+        assert!(cache.old.contains_key("crust"));
+        assert!(!cache.old.contains_key("trust"));
+    }
+
+    #[test]
+    fn test_subword_cache_without_stored_subwords() {
+        let mut lazy = Vocabulary::new(11, 2, 3, 10).unwrap();
+        let mut compact = Vocabulary::new(11, 2, 3, 10).unwrap();
+        lazy.set_lazy_subwords(true);
+        lazy.add("rust").unwrap();
+        compact.add("rust").unwrap();
+        let expected = compact.words()[0].subwords.clone();
+        compact.compact();
+        assert!(!expected.is_empty());
+
+        for vocab in [&lazy, &compact].iter() {
+            let mut cache = SubwordCache::new(2);
+            assert_eq!(cache.get(vocab, "rust"), &expected[..]);
+            assert_eq!(cache.len(), 1);
+        }
+    }
+
+    #[test]
+    fn test_hash_mode() {
+        let mut vocab = Vocabulary::new(101, 2, 3, 1 << 30).unwrap();
+        vocab.add("rust").unwrap();
+        vocab.add("__label__x").unwrap();
+
+        assert_eq!(vocab.hash_mode(), word::HashMode::Fnv32);
+        vocab.set_hash_mode(word::HashMode::Fnv64);
+
+        assert_eq!(vocab.hash_mode(), word::HashMode::Fnv64);
+        assert_eq!(vocab.get_id("rust"), Some(0));
+        assert_eq!(vocab.get_id("__label__x"), Some(1));
+        let expected: Vec<u32> = vocab
+            .get_subwords("rust")
+            .iter()
+            .map(|(ngram, _)| (word::fnv_hash64(ngram) % (1 << 30)) as u32)
+            .collect();
+        assert_eq!(vocab.words()[0].subwords, expected);
+    }
+
+    fn features_vocab() -> Vocabulary {
+        let mut vocab = Vocabulary::new(101, 0, 0, 1000).unwrap();
+        for word in ["__label__pos", "good", "movie", "good"].iter() {
+            vocab.add(word).unwrap();
+        }
+        vocab.threshold(1, 1);
+        vocab
+    }
+
+    #[test]
+    fn test_line_features() {
+        let vocab = features_vocab();
+
+        // words only, unknown words dropped without subwords
+        assert_eq!(
+            vocab.line_features(&["__label__pos", "good", "unknown", "movie"]),
+            [0, 1]
+        );
+        assert_eq!(
+            vocab.line_labels(&["__label__pos", "good", "__label__neg"]),
+            [0]
+        );
+    }
+
+    #[test]
+    fn test_line_features_ngrams() {
+        let mut vocab = features_vocab();
+        vocab.set_word_ngrams(2);
+
+        let h = |token: &str| word::fnv_hash(token) as i32 as i64 as u64;
+        let bigram = |left: &str, right: &str| {
+            2 + (h(left).wrapping_mul(116049371).wrapping_add(h(right)) % 1000) as u32
+        };
+
+        assert_eq!(
+            vocab.line_features(&["good", "unknown", "movie"]),
+            [0, 1, bigram("good", "unknown"), bigram("unknown", "movie")]
+        );
+    }
+
+    #[test]
+    fn test_line_features_subwords() {
+        let mut vocab = Vocabulary::new(101, 2, 3, 10).unwrap();
+        vocab.set_markers("", "");
+        vocab.add("rust").unwrap();
+        vocab.threshold(1, 1);
+
+        let features = vocab.line_features(&["rust", "ru"]);
+        // "rust" id then its buckets [0, 9, 2, 7, 7]; OOV "ru" is bucket 0
+        assert_eq!(features, [0, 1, 10, 3, 8, 8, 1]);
+    }
+
+    #[test]
+    fn test_compact() {
+        let mut vocab = Vocabulary::new(101, 2, 3, 10).unwrap();
+        vocab.set_word_ngrams(2);
+        for token in "rust is fast and rust is safe __label__yes".split(' ') {
+            vocab.add(token).unwrap();
+        }
+        vocab.threshold(1, 1);
+        let words = vocab.words().to_vec();
+        let line = ["rust", "is", "rusty", "__label__yes", "__label__no"];
+        let features = vocab.line_features(&line);
+
+        vocab.compact();
+        assert!(vocab.is_compact() && vocab.word_to_index.is_empty());
+        for (id, entry) in words.iter().enumerate() {
+            let id = id as u32;
+            assert_eq!(vocab.get_id(&entry.word), Some(id));
+            assert_eq!(vocab.word(id).as_deref(), Some(entry.word.as_str()));
+            assert_eq!(vocab.count(id), entry.count);
+            assert_eq!(vocab.entry_type(id), entry.entry_type);
+        }
+        assert_eq!(vocab.get_id("rusty"), None);
+        assert_eq!(vocab.line_features(&line), features);
+        assert_eq!(vocab.line_labels(&line), [0]);
+        assert_eq!(vocab.words()[0].word, words[0].word);
+
+        // changing it goes back to the full entries
+        vocab.add("rusty").unwrap();
+        assert!(!vocab.is_compact());
+        assert_eq!(vocab.words()[..words.len()], words[..]);
+        assert_eq!(vocab.get_id("rusty"), Some(words.len() as u32));
+    }
+
+    #[test]
+    fn test_prune() {
+        let mut vocab = Vocabulary::new(101, 2, 3, 10).unwrap();
+        vocab.set_markers("", "");
+        for token in ["rust", "rust", "go", "__label__lang"].iter() {
+            vocab.add(token).unwrap();
+        }
+        vocab.threshold(1, 1);
+        // "rust" is row 0 with buckets [0, 9, 2, 7, 7] at rows 2 + bucket
+        assert_eq!(vocab.line_features(&["rust"]), [0, 2, 11, 4, 9, 9]);
+
+        vocab.prune(&[0, 4, 9]);
+        assert_eq!((vocab.n_words(), vocab.n_labels()), (1, 1));
+        assert_eq!(vocab.get_id("go"), None);
+        assert_eq!(vocab.line_labels(&["__label__lang"]), [0]);
+        assert_eq!(vocab.n_input_rows(), 3);
+        assert_eq!(vocab.line_features(&["rust"]), [0, 1, 2, 2]);
+
+        // pruning again maps through the earlier renumbering
+        vocab.prune(&[0, 2]);
+        assert_eq!(vocab.n_input_rows(), 2);
+        assert_eq!(vocab.line_features(&["rust"]), [0, 1, 1]);
+    }
+
+    #[test]
+    fn test_char_only() {
+        let mut vocab = Vocabulary::new(101, 2, 3, 10).unwrap();
+        vocab.set_markers("", "");
+        vocab.add("rust").unwrap();
+        vocab.threshold(1, 1);
+        assert_eq!(vocab.n_input_rows(), 11);
+
+        vocab.set_char_only(true).unwrap();
+
+        assert_eq!(vocab.n_input_rows(), 10);
+        assert_eq!(vocab.line_features(&["rust", "ru"]), [0, 9, 2, 7, 7, 0]);
+        assert_eq!(vocab.get_id("rust"), Some(0));
+
+        let mut no_subwords = Vocabulary::new(101, 0, 0, 10).unwrap();
+        assert!(no_subwords.set_char_only(true).is_err());
+    }
+
+    #[test]
+    fn test_hierarchical_labels() {
+        let mut vocab = Vocabulary::new(101, 0, 0, 10).unwrap();
+        vocab.set_label_separator(Some("/"));
+        for token in ["__label__sports/football", "__label__sports/tennis", "ball"].iter() {
+            vocab.add(token).unwrap();
+        }
+        assert_eq!(vocab.n_tokens(), 3);
+
+        vocab.threshold(1, 1);
+
+        let sports = vocab.get_id("__label__sports").unwrap();
+        assert_eq!(vocab.words()[sports as usize].count, 2);
+        assert_eq!(vocab.n_labels(), 3);
+        let labels = vocab.line_labels(&["__label__sports/football", "__label__sports/tennis"]);
+        assert_eq!(labels.len(), 3);
+        assert_eq!(labels[0], sports - vocab.n_words());
+    }
+
+    #[test]
+    fn test_set_markers() {
+        let mut vocab = Vocabulary::new(11, 2, 3, 10).unwrap();
+        vocab.add("rust").unwrap();
+        assert_eq!(vocab.words()[0].subwords.len(), 9);
+
+        vocab.add(word::EOS).unwrap();
+        vocab.set_markers("", "");
+        assert_eq!(vocab.words()[0].subwords, [0, 9, 2, 7, 7]);
+        assert!(vocab.words()[1].subwords.is_empty());
+
+        vocab.set_lazy_subwords(true);
+        vocab.set_markers("<", ">");
+        assert!(vocab.words().iter().all(|entry| entry.subwords.is_empty()));
+        vocab.compute_all_subwords(1);
+        assert_eq!(vocab.words()[0].subwords.len(), 9);
+    }
+
+    #[test]
+    fn test_threshold() {
+        let mut vocab = test_vocab();
+        vocab.add("foo").unwrap();
+        vocab.add("foo").unwrap();
+        vocab.add("bar").unwrap();
+
+        vocab.threshold(2, 1);
+
+        assert_eq!(vocab.get_id("foo"), Some(0));
+        assert_eq!(vocab.get_id("bar"), Some(1));
+        assert_eq!(vocab.get_id("__label__baz"), Some(2));
+        assert_eq!((vocab.n_words(), vocab.n_labels()), (2, 1));
+
+        vocab.threshold(3, 2);
+
+        assert_eq!(vocab.get_id("foo"), Some(0));
+        assert_eq!(vocab.get_id("bar"), None);
+        assert_eq!(vocab.get_id("__label__baz"), None);
+        assert_eq!(vocab.size(), 1);
+    }
+
+    #[test]
+    fn test_counts() {
+        let mut vocab = test_vocab();
+        vocab.add("foo").unwrap();
+        let path = std::env::temp_dir().join("rusttext_counts.tsv");
+        vocab.save_counts(&path).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "foo\t2\nbar\t1\n__label__baz\t1\n"
+        );
+
+        let mut loaded = Vocabulary::new(5, 2, 4, 10).unwrap();
+        loaded.load_counts(&path).unwrap();
+        assert_eq!(loaded.words(), vocab.words());
+        assert_eq!(loaded.n_tokens(), 4);
+
+        // `uniq -c` output, adding to what is there
+        std::fs::write(&path, "      3 foo\n\n      1 biff\n").unwrap();
+        loaded.load_counts(&path).unwrap();
+        assert_eq!(loaded.words()[0].count, 5);
+        assert_eq!(loaded.get_id("biff"), Some(3));
+        assert_eq!(loaded.n_tokens(), 8);
+
+        std::fs::write(&path, "foo\tmany\n").unwrap();
+        assert!(matches!(
+            loaded.load_counts(&path),
+            Err(RustTextError::ModelFormat(_))
+        ));
+        std::fs::remove_file(&path).unwrap();
     }
 }
@@ -1,83 +1,249 @@
 use std::cmp::Ordering;
+use std::hash::{BuildHasher, Hasher};
 
-#[derive(Debug, PartialOrd, Ord, PartialEq, Eq, Clone)]
+use serde::{Deserialize, Serialize};
+
+use crate::{Result, RustTextError};
+
+/// Default begin/end-of-word markers wrapped around a word before extracting
+/// character n-grams, as in fastText.
+pub const BOW: &str = "<";
+pub const EOW: &str = ">";
+
+/// End-of-sentence token appended to every line, as in fastText.
+pub const EOS: &str = "</s>";
+
+#[derive(Debug, PartialOrd, Ord, PartialEq, Eq, Clone, Serialize, Deserialize)]
 pub enum EntryType {
     Word,
     Label,
 }
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
 pub struct WordEntry {
     pub word: String,
     pub entry_type: EntryType,
     pub count: u32,
+    /// Derived from the word and the vocabulary's settings, so not saved.
+    #[serde(skip)]
     pub subwords: Vec<u32>,
 }
 
 impl WordEntry {
-    pub fn new(word: &String, label_prefix: &String) -> WordEntry {
+    pub fn new(word: &str, label_prefix: &str) -> WordEntry {
         let entry_type = get_type(word, label_prefix);
 
         WordEntry {
-            word: word.clone(),
+            word: word.to_string(),
             count: 1,
             entry_type,
             subwords: Vec::new(),
         }
     }
 
-    fn parse_subwords(&mut self, min_n: usize, max_n: usize) -> Vec<String> {
-        if (min_n == 0) | (max_n == 0) {
-            return Vec::new();
-        }
-        if min_n >= max_n {
-            panic!("invalid subword parameters")
-        }
+    /// Character n-grams of this entry's word; see `char_ngrams`.
+    pub fn parse_subwords(&self, min_n: usize, max_n: usize, bow: &str, eow: &str) -> Vec<String> {
+        char_ngrams(&self.word, min_n, max_n, bow, eow)
+    }
 
-        let mut subwords: Vec<String> = Vec::new();
+    /// Hash this entry's character n-grams into `0..bucket`, after checking
+    /// the parameters with `validate_subword_params`.
+    pub fn compute_subwords(
+        &mut self,
+        min_n: usize,
+        max_n: usize,
+        bucket: u32,
+        bow: &str,
+        eow: &str,
+        hasher: &dyn TokenHasher,
+    ) -> Result<()> {
+        validate_subword_params(min_n, max_n, bucket)?;
+        self.hash_subwords(min_n, max_n, bucket, bow, eow, hasher);
+        Ok(())
+    }
 
-        for width in min_n..max_n + 1 {
-            for (i, letter) in self.word.bytes().enumerate() {
-                if (letter & 0xC0) == 0x80 {
-                    continue;
-                }
+    // compute_subwords for parameters that are already known to be valid
+    pub(crate) fn hash_subwords(
+        &mut self,
+        min_n: usize,
+        max_n: usize,
+        bucket: u32,
+        bow: &str,
+        eow: &str,
+        hasher: &dyn TokenHasher,
+    ) {
+        let subword_chars = self.parse_subwords(min_n, max_n, bow, eow);
+        let mut hashed_subwords: Vec<u32> = Vec::new();
 
-                let ceil = i + width;
-                if ceil <= self.word.len() {
-                    let slice = &self.word[i..ceil];
-                    subwords.push(String::from(slice));
-                }
-            }
+        for subword in subword_chars.iter() {
+            hashed_subwords.push(hasher.bucket(subword, bucket));
         }
+        self.subwords = hashed_subwords;
+    }
+}
 
-        return subwords;
+/// Check subword settings: a zero `min_n` or `max_n` disables subwords,
+/// otherwise `min_n <= max_n` and a non-zero `bucket` are required.
+pub fn validate_subword_params(min_n: usize, max_n: usize, bucket: u32) -> Result<()> {
+    if min_n == 0 || max_n == 0 {
+        return Ok(());
+    }
+    if min_n > max_n {
+        return Err(RustTextError::InvalidSubwordParams(format!(
+            "min_n ({}) is greater than max_n ({})",
+            min_n, max_n
+        )));
+    }
+    if bucket == 0 {
+        return Err(RustTextError::InvalidSubwordParams(String::from(
+            "bucket must be non-zero when subwords are enabled",
+        )));
     }
+    Ok(())
+}
 
-    pub fn compute_subwords(&mut self, min_n: usize, max_n: usize, bucket: u32) {
-        let subword_chars = self.parse_subwords(min_n, max_n);
-        let mut hashed_subwords: Vec<u32> = Vec::new();
+/// Character n-grams of `word` wrapped in `bow`/`eow`, for widths
+/// `min_n..=max_n` counted in characters. Works for any string, in or out of
+/// a vocabulary; returns nothing if either width is zero or the range is
+/// empty.
+pub fn char_ngrams(word: &str, min_n: usize, max_n: usize, bow: &str, eow: &str) -> Vec<String> {
+    if (min_n == 0) | (max_n == 0) {
+        return Vec::new();
+    }
 
-        for subword in subword_chars.iter() {
-            hashed_subwords.push(fnv_hash(subword) % bucket);
+    let mut subwords: Vec<String> = Vec::new();
+
+    let word = format!("{}{}{}", bow, word, eow);
+
+    // n-gram widths count characters, so slice on char boundaries
+    let boundaries: Vec<usize> = word
+        .char_indices()
+        .map(|(i, _)| i)
+        .chain(std::iter::once(word.len()))
+        .collect();
+    let n_chars = boundaries.len() - 1;
+    let bow_chars = bow.chars().count();
+    let eow_start = n_chars - eow.chars().count();
+
+    for width in min_n..max_n + 1 {
+        for start in 0..n_chars {
+            let end = start + width;
+            // skip n-grams made up of nothing but a marker
+            if end > n_chars || end <= bow_chars || start >= eow_start {
+                continue;
+            }
+            let slice = &word[boundaries[start]..boundaries[end]];
+            subwords.push(String::from(slice));
         }
-        self.subwords = hashed_subwords;
     }
+
+    subwords
+}
+
+/// Ancestors of a hierarchical label, root first, excluding the label
+/// itself: `__label__a/b/c` with separator `/` gives `__label__a` and
+/// `__label__a/b`.
+pub fn label_ancestors(label: &str, label_prefix: &str, separator: &str) -> Vec<String> {
+    if separator.is_empty() || !label.starts_with(label_prefix) {
+        return Vec::new();
+    }
+    let name = &label[label_prefix.len()..];
+    name.match_indices(separator)
+        .map(|(i, _)| format!("{}{}", label_prefix, &name[..i]))
+        .collect()
 }
 
-fn get_type(word: &String, label_prefix: &String) -> EntryType {
+fn get_type(word: &str, label_prefix: &str) -> EntryType {
     match word {
         word if word.starts_with(label_prefix) => EntryType::Label,
         _ => EntryType::Word,
     }
 }
 
-pub fn fnv_hash(word: &String) -> u32 {
+/// Hash function for words (vocabulary lookup) and subwords (bucket ids).
+/// Implementations must be deterministic across runs and machines, since
+/// bucket ids index into trained model rows.
+pub trait TokenHasher: Send + Sync {
+    fn hash(&self, token: &str) -> u64;
+
+    /// Number of significant bits in `hash` output.
+    fn width(&self) -> u32 {
+        64
+    }
+
+    /// Bucket id of `token` in `0..bucket`.
+    fn bucket(&self, token: &str, bucket: u32) -> u32 {
+        (self.hash(token) % u64::from(bucket)) as u32
+    }
+
+    /// The built-in hasher this is, if any. Only vocabularies using a
+    /// built-in hasher can be saved, since a custom one can't be restored.
+    fn hash_mode(&self) -> Option<HashMode> {
+        None
+    }
+}
+
+/// The built-in FNV-1a hashers, and the default `TokenHasher`. `Fnv32` matches
+/// fastText; `Fnv64` spreads better over large tables and bucket counts,
+/// with the full 64-bit value only reduced by the final modulus.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum HashMode {
+    #[default]
+    Fnv32,
+    Fnv64,
+}
+
+impl TokenHasher for HashMode {
+    fn hash(&self, token: &str) -> u64 {
+        match self {
+            HashMode::Fnv32 => u64::from(fnv_hash(token)),
+            HashMode::Fnv64 => fnv_hash64(token),
+        }
+    }
+
+    fn width(&self) -> u32 {
+        match self {
+            HashMode::Fnv32 => 32,
+            HashMode::Fnv64 => 64,
+        }
+    }
+
+    fn hash_mode(&self) -> Option<HashMode> {
+        Some(*self)
+    }
+}
+
+/// Adapts any `std::hash::BuildHasher` (e.g. from the `ahash` or
+/// `twox-hash` crates) into a `TokenHasher`. The builder must be seeded
+/// deterministically; `std::collections::hash_map::RandomState` is not.
+pub struct BuildTokenHasher<S>(pub S);
+
+impl<S: BuildHasher + Send + Sync> TokenHasher for BuildTokenHasher<S> {
+    fn hash(&self, token: &str) -> u64 {
+        let mut hasher = self.0.build_hasher();
+        hasher.write(token.as_bytes());
+        hasher.finish()
+    }
+}
+
+/// 32-bit FNV-1a as fastText computes it, with each byte sign-extended
+/// (fastText hashes `char`s), so non-ASCII text gets the same buckets.
+pub fn fnv_hash(word: &str) -> u32 {
     let mut h: u32 = 2166136261;
     for char in word.bytes() {
-        h = h ^ u32::from(char);
+        h ^= char as i8 as u32;
         h = h.wrapping_mul(16777619);
     }
-    return h;
+    h
+}
+
+pub fn fnv_hash64(word: &str) -> u64 {
+    let mut h: u64 = 14695981039346656037;
+    for char in word.bytes() {
+        h ^= u64::from(char);
+        h = h.wrapping_mul(1099511628211);
+    }
+    h
 }
 
 pub fn compare(left: &WordEntry, right: &WordEntry) -> Ordering {
@@ -94,6 +260,30 @@ mod tests {
     #[test]
     fn test_hash() {
         assert_eq!(fnv_hash(&String::from("rust")), 490716647);
+        // bytes above 0x7f are sign-extended, as in fastText
+        assert_eq!(fnv_hash("é"), 1023043777);
+    }
+
+    #[test]
+    fn test_hash64() {
+        assert_eq!(fnv_hash64(""), 14695981039346656037);
+        assert_eq!(fnv_hash64("a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(HashMode::Fnv32.hash("rust"), 490716647);
+        assert_eq!(
+            HashMode::Fnv64.bucket("a", 10),
+            (0xaf63dc4c8601ec8cu64 % 10) as u32
+        );
+    }
+
+    #[test]
+    fn test_build_token_hasher() {
+        use std::collections::hash_map::DefaultHasher;
+        use std::hash::BuildHasherDefault;
+
+        let hasher = BuildTokenHasher(BuildHasherDefault::<DefaultHasher>::default());
+        assert_eq!(hasher.hash("rust"), hasher.hash("rust"));
+        assert_ne!(hasher.hash("rust"), hasher.hash("rusty"));
+        assert!(hasher.bucket("rust", 7) < 7);
     }
 
     fn data_factory() -> [WordEntry; 3] {
@@ -116,7 +306,7 @@ mod tests {
             subwords: Vec::new(),
         };
 
-        return [label_0, word_0, word_1];
+        [label_0, word_0, word_1]
     }
 
     #[test]
@@ -129,6 +319,16 @@ mod tests {
         assert_eq!(get_type(&test_word, &label_prefix), EntryType::Word);
     }
 
+    #[test]
+    fn test_label_ancestors() {
+        assert_eq!(
+            label_ancestors("__label__a/b/c", "__label__", "/"),
+            ["__label__a", "__label__a/b"]
+        );
+        assert!(label_ancestors("__label__a", "__label__", "/").is_empty());
+        assert!(label_ancestors("a/b", "__label__", "/").is_empty());
+    }
+
     #[test]
     fn test_compare_words_before_labels() {
         let [test_label, test_word, _] = data_factory();
@@ -156,30 +356,82 @@ mod tests {
     #[test]
     fn test_subwords() {
         let label_prefix = String::from("__label__");
-        let mut test_word = WordEntry::new(&String::from("rust"), &label_prefix);
+        let test_word = WordEntry::new(&String::from("rust"), &label_prefix);
 
-        let subwords = test_word.parse_subwords(2, 3);
+        let subwords = test_word.parse_subwords(2, 3, "", "");
         let expected_subwords = ["ru", "us", "st", "rus", "ust"];
         assert_eq!(subwords, expected_subwords)
     }
 
+    #[test]
+    fn test_subwords_markers() {
+        let label_prefix = String::from("__label__");
+        let test_word = WordEntry::new("rust", &label_prefix);
+
+        let subwords = test_word.parse_subwords(3, 6, BOW, EOW);
+        let expected_subwords = [
+            "<ru", "rus", "ust", "st>", "<rus", "rust", "ust>", "<rust", "rust>", "<rust>",
+        ];
+        assert_eq!(subwords, expected_subwords);
+
+        // bare markers are never n-grams on their own
+        let test_word = WordEntry::new("a", &label_prefix);
+        assert_eq!(test_word.parse_subwords(1, 2, BOW, EOW), ["a", "<a", "a>"]);
+    }
+
+    #[test]
+    fn test_subwords_multibyte() {
+        let label_prefix = String::from("__label__");
+
+        let test_word = WordEntry::new("naïve", &label_prefix);
+        let expected_subwords = ["na", "aï", "ïv", "ve", "naï", "aïv", "ïve"];
+        assert_eq!(test_word.parse_subwords(2, 3, "", ""), expected_subwords);
+
+        let test_word = WordEntry::new("東京都", &label_prefix);
+        let expected_subwords = ["東京", "京都", "東京都"];
+        assert_eq!(test_word.parse_subwords(2, 3, "", ""), expected_subwords);
+
+        let expected_subwords = ["«東京", "東京都", "京都»", "«東京都", "東京都»"];
+        assert_eq!(test_word.parse_subwords(3, 4, "«", "»"), expected_subwords);
+    }
+
     #[test]
     fn test_subwords_zero_param() {
         let label_prefix = String::from("__label__");
-        let mut test_word = WordEntry::new(&String::from("rust"), &label_prefix);
+        let test_word = WordEntry::new(&String::from("rust"), &label_prefix);
         let empty: Vec<String> = Vec::new();
 
-        assert_eq!(test_word.parse_subwords(0, 3), empty);
-        assert_eq!(test_word.parse_subwords(2, 0), empty);
+        assert_eq!(test_word.parse_subwords(0, 3, BOW, EOW), empty);
+        assert_eq!(test_word.parse_subwords(2, 0, BOW, EOW), empty);
     }
 
     #[test]
-    #[should_panic]
     fn test_subwords_bad_param() {
         let label_prefix = String::from("__label__");
         let mut test_word = WordEntry::new(&String::from("rust"), &label_prefix);
 
-        test_word.parse_subwords(2, 1);
+        assert!(test_word.parse_subwords(2, 1, BOW, EOW).is_empty());
+        assert!(test_word
+            .compute_subwords(2, 1, 10, BOW, EOW, &HashMode::Fnv32)
+            .is_err());
+        assert!(test_word
+            .compute_subwords(2, 3, 0, BOW, EOW, &HashMode::Fnv32)
+            .is_err());
+        assert!(test_word
+            .compute_subwords(0, 0, 0, BOW, EOW, &HashMode::Fnv32)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_subwords_single_width() {
+        let label_prefix = String::from("__label__");
+        let mut test_word = WordEntry::new(&String::from("rust"), &label_prefix);
+
+        assert_eq!(test_word.parse_subwords(3, 3, "", ""), ["rus", "ust"]);
+        test_word
+            .compute_subwords(3, 3, 10, "", "", &HashMode::Fnv32)
+            .unwrap();
+        assert_eq!(test_word.subwords, [7, 7]);
     }
 
     #[test]
@@ -188,7 +440,9 @@ mod tests {
         let mut test_word = WordEntry::new(&String::from("rust"), &label_prefix);
         let expected_hashes = [0, 9, 2, 7, 7];
 
-        test_word.compute_subwords(2, 3, 10);
+        test_word
+            .compute_subwords(2, 3, 10, "", "", &HashMode::Fnv32)
+            .unwrap();
         assert_eq!(test_word.subwords, expected_hashes);
     }
 }